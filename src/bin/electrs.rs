@@ -5,25 +5,73 @@ extern crate log;
 extern crate electrs;
 
 use error_chain::ChainedError;
+use std::collections::HashMap;
 use std::process;
 use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use electrs::{
     config::Config,
-    daemon::Daemon,
+    daemon::{Daemon, DaemonApi},
     electrum::RPC as ElectrumRPC,
     errors::*,
     metrics::Metrics,
-    new_index::{precache, ChainQuery, FetchFrom, Indexer, Mempool, Query, Store},
+    new_index::{precache, ChainQuery, FetchFrom, Indexer, Mempool, MempoolChange, Query, Store},
     rest,
     signal::Waiter,
+    zmq::{ZmqEvent, ZmqSubscriber},
 };
 
-fn fetch_from(_config: &Config, _store: &Store) -> FetchFrom {
+// With ZMQ notifications, the daemon is still polled at this interval in case some were missed
+const ZMQ_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+fn fetch_from(config: &Config, _store: &Store) -> FetchFrom {
     // Determine whether to fetch blocks from bitcoind or block files
-    // For now, default to bitcoind. This logic can be enhanced later.
-    FetchFrom::Bitcoind
+    // For now, default to bitcoind (over p2p if configured). This logic can be enhanced later.
+    match config.daemon_p2p_addr {
+        Some(addr) => FetchFrom::P2P(addr),
+        None => FetchFrom::Bitcoind,
+    }
+}
+
+// Apply the mempool changes announced over ZMQ, returns whether the daemon should be polled
+// instead (for new blocks, missed notifications or failures). `zmq_sequence` tracks the
+// daemon's mempool sequence from the notifications, it is unknown until the next change once
+// some were missed or a block silently removed transactions.
+fn apply_zmq_events(
+    events: Vec<ZmqEvent>,
+    daemon: &dyn DaemonApi,
+    mempool: &RwLock<Mempool>,
+    zmq_sequence: &mut Option<u64>,
+) -> bool {
+    let mut changes = vec![];
+    let mut txs = HashMap::new();
+    for event in events {
+        match event {
+            // polling updates the mempool too
+            ZmqEvent::Block(_) | ZmqEvent::Resync => {
+                *zmq_sequence = None;
+                return true;
+            }
+            ZmqEvent::RawTx(tx) => {
+                txs.insert(tx.txid(), tx);
+            }
+            ZmqEvent::TxAdded(txid, sequence) => {
+                *zmq_sequence = Some(sequence);
+                changes.push(MempoolChange::Added(txid, sequence))
+            }
+            ZmqEvent::TxRemoved(txid, sequence) => {
+                *zmq_sequence = Some(sequence);
+                changes.push(MempoolChange::Removed(txid, sequence))
+            }
+        }
+    }
+    if let Err(e) = Mempool::apply_changes(mempool, daemon, changes, txs) {
+        warn!("Error applying ZMQ mempool changes: {}", e.display_chain());
+        *zmq_sequence = None;
+        return true;
+    }
+    false
 }
 
 fn run_server(config: Arc<Config>) -> Result<()> {
@@ -31,24 +79,48 @@ fn run_server(config: Arc<Config>) -> Result<()> {
     let metrics = Metrics::new(config.monitoring_addr);
     metrics.start();
 
-    let daemon = Arc::new(Daemon::new(
+    let daemon: Arc<dyn DaemonApi> = Arc::new(Daemon::new(
         config.daemon_dir.clone(),
         config.blocks_dir.clone(),
-        config.daemon_rpc_addr,
+        config.daemon_rpc_addrs(),
         config.cookie_getter(),
         config.network_type,
         config.magic,
+        config.daemon_rest,
         signal.clone(),
         &metrics,
     )?);
-    let store = Arc::new(Store::open(&config.db_path.join("newindex"), &config));
-    let mut indexer = Indexer::open(
-        Arc::clone(&store),
-        fetch_from(&config, &store),
-        &config,
-        &metrics,
-    );
-    let mut tip = indexer.update(&daemon)?;
+    let db_path = config.db_path.join("newindex");
+    if let Some(ref checkpoint) = config.restore_db_checkpoint {
+        Store::restore(checkpoint, &db_path, &*daemon)?;
+    }
+    let store = Arc::new(match config.replica_of {
+        Some(ref primary_db_path) => {
+            let primary_path = primary_db_path.join("newindex");
+            if primary_path == db_path {
+                return Err("a replica cannot use the --db-dir of its primary".into());
+            }
+            Store::open_replica(&primary_path, &db_path, &config)
+        }
+        None => Store::open(&db_path, &config),
+    });
+    if config.create_db_checkpoint {
+        let dir = config.db_checkpoint_dir.as_ref().unwrap(); // required by the option
+        store.checkpoint(dir)?;
+        return Ok(());
+    }
+    // replicas follow the index of their primary, which also runs the migrations
+    let mut indexer = if config.replica_of.is_none() {
+        Store::start_migrations(&store, &metrics);
+        Some(Indexer::open(
+            Arc::clone(&store),
+            fetch_from(&config, &store),
+            &config,
+            &metrics,
+        ))
+    } else {
+        None
+    };
 
     let chain = Arc::new(ChainQuery::new(
         Arc::clone(&store),
@@ -62,18 +134,6 @@ fn run_server(config: Arc<Config>) -> Result<()> {
         &metrics,
         Arc::clone(&config),
     )));
-    loop {
-        match Mempool::update(&mempool, &daemon) {
-            Ok(_) => break,
-            Err(e) => {
-                warn!(
-                    "Error performing initial mempool update, trying again in 5 seconds: {}",
-                    e.display_chain()
-                );
-                signal.wait(Duration::from_secs(5), false)?;
-            }
-        }
-    }
 
     // #[cfg(feature = "opcat_layer")]
     // let asset_db = config.asset_db_path.as_ref().map(|db_dir| {
@@ -92,7 +152,39 @@ fn run_server(config: Arc<Config>) -> Result<()> {
     ));
 
     // TODO: configuration for which servers to start
+    // the REST server reports the sync progress while the initial sync runs
     let rest_server = rest::start(Arc::clone(&config), Arc::clone(&query), &metrics);
+
+    let mut tip = match indexer {
+        Some(ref mut indexer) => Some(indexer.update(&*daemon)?),
+        None => {
+            let interval = Duration::from_millis(config.main_loop_delay);
+            Store::start_replication(&store, interval, &metrics);
+            None
+        }
+    };
+
+    if let Some(ref snapshot) = config.mempool_snapshot {
+        if snapshot.exists() {
+            match mempool.write().unwrap().load_snapshot(snapshot) {
+                Ok(count) => info!("restored {} mempool transactions from snapshot", count),
+                Err(e) => warn!("failed to restore mempool snapshot: {}", e.display_chain()),
+            }
+        }
+    }
+    loop {
+        match Mempool::update(&mempool, &*daemon, None) {
+            Ok(_) => break,
+            Err(e) => {
+                warn!(
+                    "Error performing initial mempool update, trying again in 5 seconds: {}",
+                    e.display_chain()
+                );
+                signal.wait(Duration::from_secs(5), false)?;
+            }
+        }
+    }
+
     let electrum_server = ElectrumRPC::start(Arc::clone(&config), Arc::clone(&query), &metrics);
 
     if let Some(ref precache_file) = config.precache_scripts {
@@ -105,46 +197,107 @@ fn run_server(config: Arc<Config>) -> Result<()> {
         );
     }
 
+    let zmq = config.zmq_addr.map(ZmqSubscriber::start);
+    let mut last_snapshot = Instant::now();
+    let mut last_poll = Instant::now();
+    let mut notified = None;
+    let mut zmq_sequence = None;
     loop {
-        if let Err(err) = signal.wait(Duration::from_millis(config.main_loop_delay), true) {
-            info!("stopping server: {}", err);
-
-            electrs::util::spawn_thread("shutdown-thread-checker", || {
-                let mut counter = 40;
-                let interval_ms = 500;
-
-                while counter > 0 {
-                    electrs::util::with_spawned_threads(|threads| {
-                        debug!("Threads during shutdown: {:?}", threads);
-                    });
-                    std::thread::sleep(std::time::Duration::from_millis(interval_ms));
-                    counter -= 1;
+        let event = match zmq {
+            Some(ref zmq) => signal.wait_for(
+                ZMQ_POLL_INTERVAL.saturating_sub(last_poll.elapsed()),
+                true,
+                zmq.events(),
+            ),
+            None => signal
+                .wait(Duration::from_millis(config.main_loop_delay), true)
+                .map(|_| None),
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                info!("stopping server: {}", err);
+
+                if let Some(ref snapshot) = config.mempool_snapshot {
+                    if let Err(e) = mempool.read().unwrap().save_snapshot(snapshot) {
+                        warn!("failed to save mempool snapshot: {}", e.display_chain());
+                    }
                 }
-            });
 
-            rest_server.stop();
-            // the electrum server is stopped when dropped
-            break;
-        }
+                electrs::util::spawn_thread("shutdown-thread-checker", || {
+                    let mut counter = 40;
+                    let interval_ms = 500;
 
-        // Index new blocks
-        let current_tip = daemon.getbestblockhash()?;
-        if current_tip != tip {
-            indexer.update(&daemon)?;
-            tip = current_tip;
+                    while counter > 0 {
+                        electrs::util::with_spawned_threads(|threads| {
+                            debug!("Threads during shutdown: {:?}", threads);
+                        });
+                        std::thread::sleep(std::time::Duration::from_millis(interval_ms));
+                        counter -= 1;
+                    }
+                });
+
+                rest_server.stop();
+                // the electrum server is stopped when dropped
+                break;
+            }
+        };
+
+        // Apply the ZMQ notifications received so far
+        let poll = match (event, &zmq) {
+            (Some(event), Some(zmq)) => {
+                let events = std::iter::once(event)
+                    .chain(zmq.events().try_iter())
+                    .collect();
+                apply_zmq_events(events, &*daemon, &mempool, &mut zmq_sequence)
+            }
+            _ => true,
         };
 
-        // Update mempool
-        if let Err(e) = Mempool::update(&mempool, &daemon) {
-            // Log the error if the result is an Err
-            warn!(
-                "Error updating mempool, skipping mempool update: {}",
-                e.display_chain()
-            );
+        // Fail over to another daemon backend if needed
+        daemon.check_backends();
+
+        if poll {
+            // Index new blocks, replicas catch up with their primary in the background instead
+            if let Some(ref mut indexer) = indexer {
+                let current_tip = daemon.getbestblockhash()?;
+                if Some(current_tip) != tip {
+                    indexer.update(&*daemon)?;
+                    tip = Some(current_tip);
+                    zmq_sequence = None;
+                };
+            }
+
+            // Update mempool, unless the ZMQ notifications tell that it didn't change
+            if let Err(e) = Mempool::update(&mempool, &*daemon, zmq_sequence) {
+                // Log the error if the result is an Err
+                warn!(
+                    "Error updating mempool, skipping mempool update: {}",
+                    e.display_chain()
+                );
+            }
+            last_poll = Instant::now();
+        }
+
+        // Persist the mempool (if enabled and due)
+        if let Some(ref snapshot) = config.mempool_snapshot {
+            if config.mempool_snapshot_interval > 0
+                && last_snapshot.elapsed() > Duration::from_secs(config.mempool_snapshot_interval)
+            {
+                if let Err(e) = mempool.read().unwrap().save_snapshot(snapshot) {
+                    warn!("failed to save mempool snapshot: {}", e.display_chain());
+                }
+                last_snapshot = Instant::now();
+            }
         }
 
-        // Update subscribed clients
-        electrum_server.notify();
+        // Update subscribed clients, only when the chain or the mempool changed, as every
+        // update makes the connections recompute the status of all their subscriptions
+        let state = Some((chain.best_hash(), mempool.read().unwrap().sequence()));
+        if state != notified {
+            electrum_server.notify();
+            notified = state;
+        }
     }
     info!("server stopped");
     Ok(())
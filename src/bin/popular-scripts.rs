@@ -19,8 +19,9 @@ lazy_static! {
     static ref HISTORY_DB: DB = {
         let config = Config::from_args();
         open_raw_db(
-            &config.db_path.join("newindex").join("history"),
+            &config.db_path.join("newindex").join("db"),
             electrs::new_index::db::OpenMode::ReadOnly,
+            config.db_memory_mb,
         )
     };
 }
@@ -93,7 +94,11 @@ fn run_iterator(
     sender: crossbeam_channel::Sender<[u8; 32]>,
     now: Instant,
 ) {
-    let mut iter = db.raw_iterator();
+    // the history rows are stored in the tx_history column family
+    let cf = db
+        .cf_handle("tx_history")
+        .expect("missing tx_history family");
+    let mut iter = db.raw_iterator_cf(&cf);
     eprintln!(
         "Thread ({thread_id:?}) Seeking DB to beginning of tx histories for b'H' + {}",
         hex::encode([first_byte])
@@ -14,7 +14,7 @@ fn main() {
     use electrs::{
         chain::Transaction,
         config::Config,
-        daemon::Daemon,
+        daemon::{Daemon, DaemonApi},
         metrics::Metrics,
         new_index::{ChainQuery, FetchFrom, Indexer, Store},
         signal::Waiter,
@@ -28,14 +28,15 @@ fn main() {
     let metrics = Metrics::new(config.monitoring_addr);
     metrics.start();
 
-    let daemon = Arc::new(
+    let daemon: Arc<dyn DaemonApi> = Arc::new(
         Daemon::new(
             config.daemon_dir.clone(),
             config.blocks_dir.clone(),
-            config.daemon_rpc_addr,
+            config.daemon_rpc_addrs(),
             config.cookie_getter(),
             config.network_type,
             config.magic,
+            config.daemon_rest,
             signal,
             &metrics,
         )
@@ -45,27 +46,15 @@ fn main() {
     let chain = ChainQuery::new(Arc::clone(&store), Arc::clone(&daemon), &config, &metrics);
 
     let mut indexer = Indexer::open(Arc::clone(&store), FetchFrom::Bitcoind, &config, &metrics);
-    indexer.update(&daemon).unwrap();
-
-    let mut iter = store.txstore_db().raw_iterator();
-    iter.seek(b"T");
+    indexer.update(&*daemon).unwrap();
 
     let mut total = 0;
     let mut uih_totals = vec![0, 0, 0];
 
-    while iter.valid() {
-        let key = iter.key().unwrap();
-        let value = iter.value().unwrap();
-
-        if !key.starts_with(b"T") {
-            break;
-        }
-
-        let tx: Transaction = deserialize(value).expect("failed to parse Transaction");
+    for row in store.txstore_db().iter_scan(b"T") {
+        let tx: Transaction = deserialize(&row.value).expect("failed to parse Transaction");
         let txid = tx.txid();
 
-        iter.next();
-
         // only consider transactions of exactly two outputs
         if tx.output.len() != 2 {
             continue;
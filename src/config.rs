@@ -35,6 +35,10 @@ pub struct Config {
     pub daemon_dir: PathBuf,
     pub blocks_dir: PathBuf,
     pub daemon_rpc_addr: SocketAddr,
+    pub daemon_rpc_fallback_addrs: Vec<SocketAddr>,
+    pub daemon_p2p_addr: Option<SocketAddr>,
+    pub daemon_rest: bool,
+    pub zmq_addr: Option<SocketAddr>,
     pub cookie: Option<String>,
     pub electrum_rpc_addr: SocketAddr,
     pub http_addr: SocketAddr,
@@ -46,6 +50,9 @@ pub struct Config {
     pub main_loop_delay: u64,
     pub address_search: bool,
     pub index_unspendables: bool,
+    pub compress_txstore: bool,
+    pub import_legacy_db: bool,
+    pub db_memory_mb: usize,
     pub cors: Option<String>,
     pub precache_scripts: Option<String>,
     pub precache_threads: usize,
@@ -54,12 +61,19 @@ pub struct Config {
     pub electrum_banner: String,
     pub mempool_backlog_stats_ttl: u64,
     pub mempool_recent_txs_size: usize,
+    pub mempool_snapshot: Option<PathBuf>,
+    pub mempool_snapshot_interval: u64,
+    pub db_checkpoint_dir: Option<PathBuf>,
+    pub create_db_checkpoint: bool,
+    pub restore_db_checkpoint: Option<PathBuf>,
+    pub replica_of: Option<PathBuf>,
     pub rest_default_block_limit: usize,
     pub rest_default_chain_txs_per_page: usize,
     pub rest_default_max_mempool_txs: usize,
     pub rest_default_max_address_summary_txs: usize,
     pub rest_max_mempool_page_size: usize,
     pub rest_max_mempool_txid_page_size: usize,
+    pub rest_api_keys: Option<PathBuf>,
 
     #[cfg(feature = "electrum-discovery")]
     pub electrum_public_hosts: Option<crate::electrum::ServerHosts>,
@@ -154,6 +168,31 @@ impl Config {
                     .help("Bitcoin daemon JSONRPC 'addr:port' to connect (default: 127.0.0.1:8332 for mainnet, 127.0.0.1:18332 for testnet and 127.0.0.1:18443 for regtest)")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("daemon_rpc_fallback_addr")
+                    .long("daemon-rpc-fallback-addr")
+                    .help("Additional Bitcoin daemon JSONRPC 'addr:port' to fail over to when the main daemon is unreachable or behind, using the same credentials. Can be specified multiple times")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("daemon_p2p_addr")
+                    .long("daemon-p2p-addr")
+                    .help("Bitcoin daemon P2P 'addr:port' to download blocks from in binary form, instead of hex-encoded over JSONRPC (default disabled)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("daemon_rest")
+                    .long("daemon-rest")
+                    .help("Fetch blocks, headers and (in light mode) transactions in binary form from the Bitcoin daemon REST interface, on its JSONRPC port (requires bitcoind -rest). Falls back to JSONRPC on failure")
+            )
+            .arg(
+                Arg::with_name("zmq_addr")
+                    .long("zmq-addr")
+                    .help("Bitcoin daemon ZMQ 'addr:port' publishing the hashblock, rawtx and sequence topics (e.g. bitcoind -zmqpubsequence=tcp://127.0.0.1:28332). New blocks and mempool changes are then applied as they are announced, instead of polling the daemon every main loop (default disabled)")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("monitoring_addr")
                     .long("monitoring-addr")
@@ -186,6 +225,22 @@ impl Config {
                     .long("index-unspendables")
                     .help("Enable indexing of provably unspendable outputs")
             )
+            .arg(
+                Arg::with_name("compress_txstore")
+                    .long("compress-txstore")
+                    .help("Compress the stored transactions and scripts with zstd, using a dictionary trained on the first ones. Can only be enabled on a new index")
+            )
+            .arg(
+                Arg::with_name("import_legacy_db")
+                    .long("import-legacy-db")
+                    .help("Import an index stored in the legacy layout (a RocksDB per logical DB) into the column families of a single RocksDB. The index is unavailable until the import completes, which may take hours, and it needs as much free disk space as the legacy index")
+            )
+            .arg(
+                Arg::with_name("db_memory_mb")
+                    .long("db-memory-mb")
+                    .help("Memory budget of the index DB block caches and write buffers in MB, split between the column families in the proportions they are tuned for. The write buffers can use up to twice their size while flushing")
+                    .default_value("1680")
+            )
             .arg(
                 Arg::with_name("cors")
                     .long("cors")
@@ -222,6 +277,43 @@ impl Config {
                     .help("The number of transactions that mempool will keep in its recents queue. This is returned by mempool/recent endpoint.")
                     .default_value("10")
             )
+            .arg(
+                Arg::with_name("mempool_snapshot")
+                    .long("mempool-snapshot")
+                    .help("Path to a file the mempool is persisted to on shutdown and periodically, and restored from at startup (default disabled)")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("mempool_snapshot_interval")
+                    .long("mempool-snapshot-interval")
+                    .help("The number of seconds between periodic mempool snapshots, 0 to only persist the mempool on shutdown.")
+                    .default_value("600")
+            )
+            .arg(
+                Arg::with_name("db_checkpoint_dir")
+                    .long("db-checkpoint-dir")
+                    .help("Directory the index DB checkpoints are created in, enables the /internal/db/checkpoint endpoint (default disabled)")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("create_db_checkpoint")
+                    .long("create-db-checkpoint")
+                    .help("Create a checkpoint of the index DB in --db-checkpoint-dir and exit")
+                    .requires("db_checkpoint_dir")
+            )
+            .arg(
+                Arg::with_name("restore_db_checkpoint")
+                    .long("restore-db-checkpoint")
+                    .help("Path to a checkpoint created with --create-db-checkpoint, restored as the index DB at startup. The index DB must not exist yet.")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("replica_of")
+                    .long("replica-of")
+                    .help("The --db-dir of a primary electrs instance on the same host. Its index DB is followed as a read replica instead of indexing blocks (default disabled)")
+                    .takes_value(true)
+                    .conflicts_with_all(&["create_db_checkpoint", "restore_db_checkpoint"])
+            )
             .arg(
                 Arg::with_name("rest_default_block_limit")
                     .long("rest-default-block-limit")
@@ -263,6 +355,12 @@ impl Config {
                     .long("electrum-txs-limit")
                     .help("Maximum number of transactions returned by Electrum history queries. Lookups with more results will fail.")
                     .default_value("500")
+            )
+            .arg(
+                Arg::with_name("rest_api_keys")
+                    .long("rest-api-keys")
+                    .help("Path to a JSON file with REST API keys and access tiers. Enables API key authentication (keys are passed via the X-API-Key header or the api_key query parameter). The file is reloaded when it changes.")
+                    .takes_value(true)
             ).arg(
                 Arg::with_name("electrum_banner")
                     .long("electrum-banner")
@@ -401,6 +499,19 @@ impl Config {
                 .unwrap_or(&format!("127.0.0.1:{}", default_daemon_port)),
             "Bitcoin RPC",
         );
+        let daemon_rpc_fallback_addrs: Vec<SocketAddr> = m
+            .values_of("daemon_rpc_fallback_addr")
+            .map_or_else(Vec::new, |addrs| {
+                addrs
+                    .map(|addr| str_to_socketaddr(addr, "Bitcoin RPC fallback"))
+                    .collect()
+            });
+        let daemon_p2p_addr: Option<SocketAddr> = m
+            .value_of("daemon_p2p_addr")
+            .map(|addr| str_to_socketaddr(addr, "Bitcoin P2P"));
+        let zmq_addr: Option<SocketAddr> = m
+            .value_of("zmq_addr")
+            .map(|addr| str_to_socketaddr(addr, "ZMQ"));
         let electrum_rpc_addr: SocketAddr = str_to_socketaddr(
             m.value_of("electrum_rpc_addr")
                 .unwrap_or(&format!("127.0.0.1:{}", default_electrum_port)),
@@ -478,6 +589,10 @@ impl Config {
             daemon_dir,
             blocks_dir,
             daemon_rpc_addr,
+            daemon_rpc_fallback_addrs,
+            daemon_p2p_addr,
+            daemon_rest: m.is_present("daemon_rest"),
+            zmq_addr,
             cookie,
             utxos_limit: value_t_or_exit!(m, "utxos_limit", usize),
             electrum_rpc_addr,
@@ -489,6 +604,14 @@ impl Config {
             monitoring_addr,
             mempool_backlog_stats_ttl: value_t_or_exit!(m, "mempool_backlog_stats_ttl", u64),
             mempool_recent_txs_size: value_t_or_exit!(m, "mempool_recent_txs_size", usize),
+            mempool_snapshot: m.value_of("mempool_snapshot").map(PathBuf::from),
+            mempool_snapshot_interval: value_t_or_exit!(m, "mempool_snapshot_interval", u64),
+            db_checkpoint_dir: m.value_of("db_checkpoint_dir").map(PathBuf::from),
+            create_db_checkpoint: m.is_present("create_db_checkpoint"),
+            restore_db_checkpoint: m.value_of("restore_db_checkpoint").map(PathBuf::from),
+            replica_of: m
+                .value_of("replica_of")
+                .map(|dir| Path::new(dir).join(network_name)),
             rest_default_block_limit: value_t_or_exit!(m, "rest_default_block_limit", usize),
             rest_default_chain_txs_per_page: value_t_or_exit!(
                 m,
@@ -511,11 +634,15 @@ impl Config {
                 "rest_max_mempool_txid_page_size",
                 usize
             ),
+            rest_api_keys: m.value_of("rest_api_keys").map(PathBuf::from),
             jsonrpc_import: m.is_present("jsonrpc_import"),
             light_mode: m.is_present("light_mode"),
             main_loop_delay: value_t_or_exit!(m, "main_loop_delay", u64),
             address_search: m.is_present("address_search"),
             index_unspendables: m.is_present("index_unspendables"),
+            compress_txstore: m.is_present("compress_txstore"),
+            import_legacy_db: m.is_present("import_legacy_db"),
+            db_memory_mb: value_t_or_exit!(m, "db_memory_mb", usize),
             cors: m.value_of("cors").map(|s| s.to_string()),
             precache_scripts: m.value_of("precache_scripts").map(|s| s.to_string()),
             precache_threads: m.value_of("precache_threads").map_or_else(
@@ -546,6 +673,13 @@ impl Config {
         config
     }
 
+    /// The daemon JSONRPC addresses, the main one first
+    pub fn daemon_rpc_addrs(&self) -> Vec<SocketAddr> {
+        std::iter::once(self.daemon_rpc_addr)
+            .chain(self.daemon_rpc_fallback_addrs.iter().copied())
+            .collect()
+    }
+
     pub fn cookie_getter(&self) -> Arc<dyn CookieGetter> {
         if let Some(ref value) = self.cookie {
             Arc::new(StaticCookie {
@@ -1,12 +1,15 @@
 use std::collections::{HashMap, HashSet};
-use std::io::{BufRead, BufReader, Lines, Write};
+use std::io::{BufRead, BufReader, Lines, Read, Write};
 use std::net::{SocketAddr, TcpStream};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use base64;
 use bitcoin::hashes::hex::{FromHex, ToHex};
+use crossbeam_channel::{self as channel, Receiver, Sender, TrySendError};
 use glob;
 use hex;
 use itertools::Itertools;
@@ -19,9 +22,26 @@ use bitcoin::consensus::encode::{deserialize, serialize};
 
 use crate::chain::{Block, BlockHash, BlockHeader, Network, Transaction, Txid};
 use crate::errors::*;
-use crate::metrics::{HistogramOpts, HistogramVec, Metrics};
+use crate::metrics::{GaugeVec, HistogramOpts, HistogramVec, MetricOpts, Metrics};
 use crate::signal::Waiter;
-use crate::util::HeaderList;
+use crate::util::{spawn_thread, HeaderList};
+
+// How often the backends are health checked when several are configured
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// Timeout for the health checks and the requests fanned out to the inactive backends
+const BACKEND_TIMEOUT: Duration = Duration::from_secs(10);
+// How many requests may wait to be relayed to each backend, further ones are dropped
+const RELAY_QUEUE_SIZE: usize = 100;
+// How many blocks a backend may lag behind the best one and still be used
+const MAX_BACKEND_LAG: u64 = 1;
+// How long the active backend may stall before failing over, when several are configured
+const STALLED_BACKEND_TIMEOUT: Duration = Duration::from_secs(120);
+
+const REST_TIMEOUT: Duration = Duration::from_secs(60);
+// How many idle connections to the REST interface are kept open
+const REST_IDLE_CONNECTIONS: usize = 4;
+// The daemon's limit for the REST headers count
+const REST_MAX_HEADERS: usize = 2000;
 
 fn header_from_value(value: Value) -> Result<BlockHeader> {
     let header_hex = value
@@ -99,6 +119,12 @@ pub struct MempoolInfo {
     pub mempoolminfee: f64, // in BTC/kB
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct MempoolSequence {
+    txids: HashSet<Txid>,
+    mempool_sequence: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct NetworkInfo {
     version: u64,
@@ -158,24 +184,279 @@ pub trait CookieGetter: Send + Sync {
     fn get(&self) -> Result<Vec<u8>>;
 }
 
+// A keep-alive connection to the daemon's REST interface
+struct RestConnection {
+    addr: SocketAddr,
+    reader: BufReader<TcpStream>,
+    requests: usize, // sent over this connection
+}
+
+struct RestResponse {
+    status: u16,
+    body: Vec<u8>,
+    keep_alive: bool,
+}
+
+impl RestConnection {
+    fn new(addr: SocketAddr) -> Result<Self> {
+        let stream = TcpStream::connect_timeout(&addr, REST_TIMEOUT)
+            .chain_err(|| format!("failed to connect daemon REST at {}", addr))?;
+        stream
+            .set_read_timeout(Some(REST_TIMEOUT))
+            .chain_err(|| "failed to set daemon REST timeout")?;
+        Ok(RestConnection {
+            addr,
+            reader: BufReader::new(stream),
+            requests: 0,
+        })
+    }
+
+    fn get(&mut self, path: &str) -> Result<RestResponse> {
+        self.requests += 1;
+        write!(
+            self.reader.get_mut(),
+            "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+            path,
+            self.addr
+        )
+        .chain_err(|| "failed to send daemon REST request")?;
+
+        let mut status = String::new();
+        self.reader
+            .read_line(&mut status)
+            .chain_err(|| "failed to read daemon REST status")?;
+        let status = status
+            .split(' ')
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .chain_err(|| format!("invalid daemon REST status: {}", status.trim_end()))?;
+        let mut content_length: Option<usize> = None;
+        let mut keep_alive = true;
+        loop {
+            let mut line = String::new();
+            self.reader
+                .read_line(&mut line)
+                .chain_err(|| "failed to read daemon REST headers")?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    content_length = value.trim().parse().ok();
+                } else if name.eq_ignore_ascii_case("Connection") {
+                    keep_alive = !value.trim().eq_ignore_ascii_case("close");
+                }
+            }
+        }
+        let mut body = vec![];
+        match content_length {
+            Some(length) => {
+                body.resize(length, 0);
+                self.reader.read_exact(&mut body)
+            }
+            None => {
+                // the response ends with the connection
+                keep_alive = false;
+                self.reader.read_to_end(&mut body).map(|_| ())
+            }
+        }
+        .chain_err(|| "failed to read daemon REST response")?;
+        Ok(RestResponse {
+            status,
+            body,
+            keep_alive,
+        })
+    }
+}
+
 struct Connection {
     tx: TcpStream,
     rx: Lines<BufReader<TcpStream>>,
     cookie_getter: Arc<dyn CookieGetter>,
     addr: SocketAddr,
-    signal: Waiter,
 }
 
-fn tcp_connect(addr: SocketAddr, signal: &Waiter) -> Result<TcpStream> {
+#[derive(Clone, Copy, Debug)]
+struct BackendHealth {
+    height: u64,
+    latency: Duration,
+}
+
+// The daemon JSONRPC backends, along with their health at the last check
+struct Backends {
+    addrs: Vec<SocketAddr>,
+    health: Vec<Option<BackendHealth>>, // None if unreachable or not checked yet
+    gauges: GaugeVec,                   // active flag, tip height and latency of each backend
+}
+
+impl Backends {
+    fn new(addrs: Vec<SocketAddr>, gauges: GaugeVec) -> Self {
+        Backends {
+            health: vec![None; addrs.len()],
+            addrs,
+            gauges,
+        }
+    }
+
+    fn set_health(&mut self, addr: SocketAddr, health: Option<BackendHealth>) {
+        if let Some(index) = self.addrs.iter().position(|a| *a == addr) {
+            self.health[index] = health;
+        }
+        let addr = addr.to_string();
+        let (height, latency) =
+            health.map_or((0.0, 0.0), |h| (h.height as f64, h.latency.as_secs_f64()));
+        self.gauges
+            .with_label_values(&[&addr, "height"])
+            .set(height);
+        self.gauges
+            .with_label_values(&[&addr, "latency"])
+            .set(latency);
+    }
+
+    fn set_active(&self, active: SocketAddr) {
+        for addr in &self.addrs {
+            let value = if *addr == active { 1.0 } else { 0.0 };
+            self.gauges
+                .with_label_values(&[&addr.to_string(), "active"])
+                .set(value);
+        }
+    }
+
+    // The backends to use, best first, and whether they are healthy and not lagging behind
+    fn ranked(&self) -> Vec<(SocketAddr, bool)> {
+        rank_backends(&self.addrs, &self.health)
+    }
+
+    // Without fallbacks, wait for the daemon however long it takes
+    fn active_timeout(&self) -> Option<Duration> {
+        Some(STALLED_BACKEND_TIMEOUT).filter(|_| self.addrs.len() > 1)
+    }
+}
+
+// Healthy backends that are not lagging behind come first, from the fastest to the slowest
+// one, followed by the others in configuration order
+fn rank_backends(
+    addrs: &[SocketAddr],
+    health: &[Option<BackendHealth>],
+) -> Vec<(SocketAddr, bool)> {
+    let best_height = health.iter().flatten().map(|h| h.height).max();
+    let mut ranked: Vec<(Option<Duration>, SocketAddr)> = addrs
+        .iter()
+        .zip(health)
+        .map(|(addr, health)| {
+            let latency = health
+                .filter(|h| best_height.is_some_and(|best| h.height + MAX_BACKEND_LAG >= best))
+                .map(|h| h.latency);
+            (latency, *addr)
+        })
+        .collect();
+    // stable, so the others keep their order
+    ranked.sort_by_key(|(latency, _)| (latency.is_none(), *latency));
+    ranked
+        .into_iter()
+        .map(|(latency, addr)| (addr, latency.is_some()))
+        .collect()
+}
+
+// The backend to switch to from the active one, if any: the primary one as soon as it is usable
+// again, and otherwise the best one when the active backend isn't usable anymore
+fn switch_backend(
+    addrs: &[SocketAddr],
+    health: &[Option<BackendHealth>],
+    active: SocketAddr,
+) -> Option<SocketAddr> {
+    let ranked = rank_backends(addrs, health);
+    let usable = |addr: SocketAddr| ranked.iter().any(|(a, usable)| *a == addr && *usable);
+    let primary = addrs[0];
+    if usable(primary) {
+        Some(primary).filter(|primary| *primary != active)
+    } else if usable(active) {
+        None
+    } else {
+        ranked
+            .iter()
+            .find(|(_, usable)| *usable)
+            .map(|(addr, _)| *addr)
+    }
+}
+
+// Check the tip height and latency of each backend, every HEALTH_CHECK_INTERVAL
+fn check_backends_health(backends: &Mutex<Backends>, cookie_getter: &Arc<dyn CookieGetter>) {
     loop {
-        match TcpStream::connect(addr) {
-            Ok(conn) => return Ok(conn),
-            Err(err) => {
-                warn!("failed to connect daemon at {}: {}", addr, err);
-                signal.wait(Duration::from_secs(3), false)?;
-                continue;
+        let addrs = backends.lock().unwrap().addrs.clone();
+        for addr in addrs {
+            let start = Instant::now();
+            let health = Connection::new(addr, cookie_getter.clone(), Some(BACKEND_TIMEOUT))
+                .and_then(|mut conn| conn.request("getblockcount", json!([])))
+                .and_then(|height| height.as_u64().chain_err(|| "non-numeric block count"));
+            let health = match health {
+                Ok(height) => Some(BackendHealth {
+                    height,
+                    latency: start.elapsed(),
+                }),
+                Err(e) => {
+                    warn!("daemon backend {} is unhealthy: {}", addr, e);
+                    None
+                }
+            };
+            backends.lock().unwrap().set_health(addr, health);
+        }
+        thread::sleep(HEALTH_CHECK_INTERVAL);
+    }
+}
+
+// A request relayed to another backend, as a method name and its params
+type RelayRequest = (&'static str, Value);
+
+// Relay the requests to the backend, keeping the connection open between them
+fn relay_requests(
+    addr: SocketAddr,
+    cookie_getter: Arc<dyn CookieGetter>,
+    requests: Receiver<RelayRequest>,
+) {
+    let mut conn: Option<Connection> = None;
+    for (method, params) in requests.iter() {
+        let mut result = match conn.take() {
+            Some(mut c) => c.request(method, params.clone()).map(|_| c),
+            None => Err(ErrorKind::Connection("not connected".to_owned()).into()),
+        };
+        // the daemon may have closed the connection since the last request
+        if let Err(Error(ErrorKind::Connection(_), _)) = result {
+            result = Connection::new(addr, cookie_getter.clone(), Some(BACKEND_TIMEOUT))
+                .and_then(|mut c| c.request(method, params).map(|_| c));
+        }
+        match result {
+            Ok(c) => conn = Some(c),
+            Err(e) => debug!("{} to daemon backend {} failed: {}", method, addr, e),
+        }
+    }
+}
+
+// Connect to the best reachable backend, waiting until one is
+fn connect_backend(
+    backends: &Mutex<Backends>,
+    cookie_getter: &Arc<dyn CookieGetter>,
+    signal: &Waiter,
+) -> Result<Connection> {
+    loop {
+        let (candidates, timeout) = {
+            let backends = backends.lock().unwrap();
+            (backends.ranked(), backends.active_timeout())
+        };
+        for (addr, _) in candidates {
+            match Connection::new(addr, cookie_getter.clone(), timeout) {
+                Ok(conn) => {
+                    backends.lock().unwrap().set_active(addr);
+                    return Ok(conn);
+                }
+                Err(err) => {
+                    warn!("{}", err);
+                    backends.lock().unwrap().set_health(addr, None);
+                }
             }
         }
+        signal.wait(Duration::from_secs(3), false)?;
     }
 }
 
@@ -183,9 +464,16 @@ impl Connection {
     fn new(
         addr: SocketAddr,
         cookie_getter: Arc<dyn CookieGetter>,
-        signal: Waiter,
+        timeout: Option<Duration>,
     ) -> Result<Connection> {
-        let conn = tcp_connect(addr, &signal)?;
+        let conn = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        }
+        .chain_err(|| ErrorKind::Connection(format!("failed to connect daemon at {}", addr)))?;
+        conn.set_read_timeout(timeout)
+            .and_then(|_| conn.set_write_timeout(timeout))
+            .chain_err(|| format!("failed to set timeouts on {:?}", conn))?;
         let reader = BufReader::new(
             conn.try_clone()
                 .chain_err(|| format!("failed to clone {:?}", conn))?,
@@ -195,12 +483,14 @@ impl Connection {
             rx: reader.lines(),
             cookie_getter,
             addr,
-            signal,
         })
     }
 
-    fn reconnect(&self) -> Result<Connection> {
-        Connection::new(self.addr, self.cookie_getter.clone(), self.signal.clone())
+    // A single request, for the connections used besides the daemon's main one
+    fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.send(&json!({"method": method, "params": params, "id": 0}).to_string())?;
+        let reply = from_str(&self.recv()?).chain_err(|| "invalid JSON")?;
+        parse_jsonrpc_reply(reply, method, 0)
     }
 
     fn send(&mut self, request: &str) -> Result<()> {
@@ -297,14 +587,89 @@ impl Counter {
     }
 }
 
+/// The daemon operations used by the indexer, the mempool and the queries, implemented by
+/// `Daemon` over JSONRPC (and by an in-memory fake in the integration tests)
+pub trait DaemonApi: Send + Sync {
+    /// A new handle with its own connection, for use from another thread
+    fn reconnect(&self) -> Result<Box<dyn DaemonApi>>;
+
+    fn list_blk_files(&self) -> Result<Vec<PathBuf>>;
+
+    fn magic(&self) -> u32;
+
+    /// Switch to another backend (if several are configured) if the active one is unreachable
+    /// or behind, or back to the primary one once it is healthy again. The backends are
+    /// checked in the background, this only reads the results of the last check.
+    fn check_backends(&self) {}
+
+    #[cfg(feature = "opcat_layer")]
+    fn get_mempoolminfee(&self) -> Result<f64>;
+
+    fn getbestblockhash(&self) -> Result<BlockHash>;
+
+    fn getblock_raw(&self, blockhash: &BlockHash, verbose: u32) -> Result<Value>;
+
+    fn getblocks(&self, blockhashes: &[BlockHash]) -> Result<Vec<Block>>;
+
+    /// The transactions found, missing ones are skipped
+    fn gettransactions(&self, txhashes: &[&Txid]) -> Result<Vec<Transaction>>;
+
+    /// The serialized transaction, over REST if possible (the daemon needs -txindex), and
+    /// otherwise over JSONRPC from the given block
+    fn gettransaction_bin(&self, txid: &Txid, blockhash: &BlockHash) -> Result<Vec<u8>>;
+
+    fn getmempooltx(&self, txhash: &Txid) -> Result<Transaction>;
+
+    fn getmempooltxids(&self) -> Result<HashSet<Txid>>;
+
+    /// The mempool txids, along with the daemon's mempool sequence number they match
+    /// (requires bitcoind 0.21+)
+    fn getmempooltxids_sequence(&self) -> Result<(HashSet<Txid>, u64)>;
+
+    fn broadcast_raw(&self, txhex: &str) -> Result<Txid>;
+
+    fn test_mempool_accept(
+        &self,
+        txhex: Vec<String>,
+        maxfeerate: Option<f64>,
+    ) -> Result<Vec<MempoolAcceptResult>>;
+
+    fn submit_package(
+        &self,
+        txhex: Vec<String>,
+        maxfeerate: Option<f64>,
+        maxburnamount: Option<f64>,
+    ) -> Result<SubmitPackageResult>;
+
+    /// Feerates (in sat/vB) for the confirmation targets, skipping the missing estimates
+    fn estimatesmartfee_batch(&self, conf_targets: &[u16]) -> Result<HashMap<u16, f64>>;
+
+    /// The headers after the indexed ones up to `bestblockhash`, in ascending height (the
+    /// tip is last)
+    fn get_new_headers(
+        &self,
+        indexed_headers: &HeaderList,
+        bestblockhash: &BlockHash,
+    ) -> Result<Vec<BlockHeader>>;
+
+    /// The minimum relay feerate (in sat/vB)
+    fn get_relayfee(&self) -> Result<f64>;
+}
+
 pub struct Daemon {
     daemon_dir: PathBuf,
     blocks_dir: PathBuf,
     network: Network,
     magic: Option<u32>,
-    conn: Mutex<Connection>,
+    conn: Mutex<Connection>, // to the active backend
+    backends: Arc<Mutex<Backends>>,
+    relays: Arc<Vec<(SocketAddr, Sender<RelayRequest>)>>, // to each backend, if several
+    cookie_getter: Arc<dyn CookieGetter>,
     message_id: Counter, // for monotonic JSONRPC 'id'
     signal: Waiter,
+    rest: bool,          // fetch binary data from the REST interface (bitcoind -rest)
+    rest_tx: AtomicBool, // cleared if transaction lookups are refused (they require -txindex)
+    rest_conns: Mutex<Vec<RestConnection>>, // idle keep-alive connections
 
     // monitoring
     latency: HistogramVec,
@@ -316,25 +681,53 @@ impl Daemon {
     pub fn new(
         daemon_dir: PathBuf,
         blocks_dir: PathBuf,
-        daemon_rpc_addr: SocketAddr,
+        daemon_rpc_addrs: Vec<SocketAddr>,
         cookie_getter: Arc<dyn CookieGetter>,
         network: Network,
         magic: Option<u32>,
+        rest: bool,
         signal: Waiter,
         metrics: &Metrics,
     ) -> Result<Daemon> {
+        let relays = if daemon_rpc_addrs.len() > 1 {
+            daemon_rpc_addrs
+                .iter()
+                .map(|addr| {
+                    let (sender, requests) = channel::bounded(RELAY_QUEUE_SIZE);
+                    let (addr, cookie_getter) = (*addr, Arc::clone(&cookie_getter));
+                    spawn_thread("daemon-relay", move || {
+                        relay_requests(addr, cookie_getter, requests)
+                    });
+                    (addr, sender)
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        let backends = Mutex::new(Backends::new(
+            daemon_rpc_addrs,
+            metrics.gauge_vec(
+                MetricOpts::new(
+                    "daemon_backend",
+                    "Bitcoind backends status (active flag, tip height and latency in seconds)",
+                ),
+                &["addr", "type"],
+            ),
+        ));
         let daemon = Daemon {
             daemon_dir,
             blocks_dir,
             network,
             magic,
-            conn: Mutex::new(Connection::new(
-                daemon_rpc_addr,
-                cookie_getter,
-                signal.clone(),
-            )?),
+            conn: Mutex::new(connect_backend(&backends, &cookie_getter, &signal)?),
+            backends: Arc::new(backends),
+            relays: Arc::new(relays),
+            cookie_getter,
             message_id: Counter::new(),
             signal: signal.clone(),
+            rest,
+            rest_tx: AtomicBool::new(rest),
+            rest_conns: Mutex::new(vec![]),
             latency: metrics.histogram_vec(
                 HistogramOpts::new("daemon_rpc", "Bitcoind RPC latency (in seconds)"),
                 &["method"],
@@ -344,6 +737,13 @@ impl Daemon {
                 &["method", "dir"],
             ),
         };
+        if daemon.backends.lock().unwrap().addrs.len() > 1 {
+            let backends = Arc::clone(&daemon.backends);
+            let cookie_getter = Arc::clone(&daemon.cookie_getter);
+            spawn_thread("daemon-health", move || {
+                check_backends_health(&backends, &cookie_getter)
+            });
+        }
         let network_info = daemon.getnetworkinfo()?;
         info!("{:?}", network_info);
         #[cfg(not(feature = "opcat_layer"))]
@@ -392,33 +792,18 @@ impl Daemon {
         Ok(daemon)
     }
 
-    pub fn reconnect(&self) -> Result<Daemon> {
-        Ok(Daemon {
-            daemon_dir: self.daemon_dir.clone(),
-            blocks_dir: self.blocks_dir.clone(),
-            network: self.network,
-            magic: self.magic,
-            conn: Mutex::new(self.conn.lock().unwrap().reconnect()?),
-            message_id: Counter::new(),
-            signal: self.signal.clone(),
-            latency: self.latency.clone(),
-            size: self.size.clone(),
-        })
-    }
-
-    pub fn list_blk_files(&self) -> Result<Vec<PathBuf>> {
-        let path = self.blocks_dir.join("blk*.dat");
-        debug!("listing block files at {:?}", path);
-        let mut paths: Vec<PathBuf> = glob::glob(path.to_str().unwrap())
-            .chain_err(|| "failed to list blk*.dat files")?
-            .map(|res| res.unwrap())
-            .collect();
-        paths.sort();
-        Ok(paths)
-    }
-
-    pub fn magic(&self) -> u32 {
-        self.magic.unwrap_or_else(|| self.network.magic())
+    // Send the request to the inactive backends too, in the background and ignoring failures
+    // (e.g. to relay transactions through all of them)
+    fn fan_out(&self, method: &'static str, params: Value) {
+        let active = self.conn.lock().unwrap().addr;
+        for (addr, relay) in self.relays.iter().filter(|(addr, _)| *addr != active) {
+            if let Err(TrySendError::Full(_)) = relay.try_send((method, params.clone())) {
+                warn!(
+                    "daemon backend {} relay queue is full, dropping {}",
+                    addr, method
+                );
+            }
+        }
     }
 
     fn call_jsonrpc(&self, method: &str, request: &Value) -> Result<Value> {
@@ -499,7 +884,9 @@ impl Daemon {
                     warn!("reconnecting to bitcoind: {}", msg);
                     self.signal.wait(Duration::from_secs(3), false)?;
                     let mut conn = self.conn.lock().unwrap();
-                    *conn = conn.reconnect()?;
+                    // fail over to another backend, if any is healthy
+                    self.backends.lock().unwrap().set_health(conn.addr, None);
+                    *conn = connect_backend(&self.backends, &self.cookie_getter, &self.signal)?;
                     continue;
                 }
                 result => return result,
@@ -517,6 +904,57 @@ impl Daemon {
         self.retry_request_batch(method, params_list, 0.0)
     }
 
+    // A GET request to the REST interface of the active backend, over an idle keep-alive
+    // connection if there is one
+    fn rest_request(&self, name: &str, path: &str) -> Result<RestResponse> {
+        let addr = self.conn.lock().unwrap().addr;
+        let timer = self.latency.with_label_values(&[name]).start_timer();
+        let idle = {
+            let mut conns = self.rest_conns.lock().unwrap();
+            conns.retain(|conn| conn.addr == addr);
+            conns.pop()
+        };
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => RestConnection::new(addr)?,
+        };
+        let response = match conn.get(path) {
+            Ok(response) => response,
+            // the daemon may have closed the idle connection
+            Err(e) if conn.requests > 1 => {
+                debug!("daemon REST connection closed, reconnecting: {}", e);
+                conn = RestConnection::new(addr)?;
+                conn.get(path)?
+            }
+            Err(e) => return Err(e),
+        };
+        if response.keep_alive {
+            let mut conns = self.rest_conns.lock().unwrap();
+            if conns.len() < REST_IDLE_CONNECTIONS {
+                conns.push(conn);
+            }
+        }
+        timer.observe_duration();
+        self.size
+            .with_label_values(&[name, "recv"])
+            .observe(response.body.len() as f64);
+        Ok(response)
+    }
+
+    // A GET request to the REST interface of the active backend, returning the response body
+    fn rest_get(&self, name: &str, path: &str) -> Result<Vec<u8>> {
+        let response = self.rest_request(name, path)?;
+        if response.status != 200 {
+            bail!(
+                "daemon REST request {} failed: {} {}",
+                path,
+                response.status,
+                String::from_utf8_lossy(&response.body).trim_end()
+            );
+        }
+        Ok(response.body)
+    }
+
     // bitcoind JSONRPC API:
 
     pub fn getblockchaininfo(&self) -> Result<BlockchainInfo> {
@@ -529,23 +967,11 @@ impl Daemon {
         from_value(info).chain_err(|| "invalid mempool info")
     }
 
-    #[cfg(feature = "opcat_layer")]
-    pub fn get_mempoolminfee(&self) -> Result<f64> {
-        let info = self.getmempoolinfo()?;
-        Ok(info.mempoolminfee)
-    }
-
     fn getnetworkinfo(&self) -> Result<NetworkInfo> {
         let info: Value = self.request("getnetworkinfo", json!([]))?;
         from_value(info).chain_err(|| "invalid network info")
     }
 
-    pub fn getbestblockhash(&self) -> Result<BlockHash> {
-        let result = self.request("getbestblockhash", json!([]))?;
-        let hash_str = result.as_str().chain_err(|| "non-string blockhash")?;
-        BlockHash::from_hex(hash_str).chain_err(|| "invalid blockhash")
-    }
-
     pub fn getblockheader(&self, blockhash: &BlockHash) -> Result<BlockHeader> {
         header_from_value(self.request(
             "getblockheader",
@@ -575,11 +1001,188 @@ impl Daemon {
         Ok(block)
     }
 
-    pub fn getblock_raw(&self, blockhash: &BlockHash, verbose: u32) -> Result<Value> {
+    fn rest_getblocks(&self, blockhashes: &[BlockHash]) -> Result<Vec<Block>> {
+        blockhashes
+            .iter()
+            .map(|blockhash| {
+                let path = format!("/rest/block/{}.bin", blockhash.to_hex());
+                let block = self.rest_get("rest_block", &path)?;
+                deserialize(&block).chain_err(|| format!("failed to parse block {}", blockhash))
+            })
+            .collect()
+    }
+
+    pub fn gettransaction_raw(
+        &self,
+        txid: &Txid,
+        blockhash: &BlockHash,
+        verbose: bool,
+    ) -> Result<Value> {
+        self.request(
+            "getrawtransaction",
+            json!([txid.to_hex(), verbose, blockhash]),
+        )
+    }
+
+    pub fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
+        self.broadcast_raw(&hex::encode(serialize(tx)))
+    }
+
+    fn get_all_headers(&self, tip: &BlockHash) -> Result<Vec<BlockHeader>> {
+        let info: Value = self.request("getblockheader", json!([tip.to_hex()]))?;
+        let tip_height = info
+            .get("height")
+            .expect("missing height")
+            .as_u64()
+            .expect("non-numeric height") as usize;
+        let mut result = vec![];
+        if self.rest {
+            match self.rest_get_all_headers(tip, tip_height) {
+                Ok(headers) => result = headers,
+                Err(e) => warn!("failed to get headers over REST, using JSONRPC: {}", e),
+            }
+        }
+        if result.is_empty() {
+            let all_heights: Vec<usize> = (0..=tip_height).collect();
+            let chunk_size = 100_000;
+            for heights in all_heights.chunks(chunk_size) {
+                trace!("downloading {} block headers", heights.len());
+                let mut headers = self.getblockheaders(heights)?;
+                assert!(headers.len() == heights.len());
+                result.append(&mut headers);
+            }
+        }
+
+        let mut blockhash = BlockHash::default();
+        for header in &result {
+            assert_eq!(header.prev_blockhash, blockhash);
+            blockhash = header.block_hash();
+        }
+        assert_eq!(blockhash, *tip);
+        Ok(result)
+    }
+
+    // Download the headers from genesis to the tip, in batches following the daemon's best chain
+    fn rest_get_all_headers(&self, tip: &BlockHash, tip_height: usize) -> Result<Vec<BlockHeader>> {
+        let genesis = self.request("getblockhash", json!([0]))?;
+        let mut blockhash =
+            BlockHash::from_hex(genesis.as_str().chain_err(|| "non-string blockhash")?)
+                .chain_err(|| "invalid blockhash")?;
+        let mut result: Vec<BlockHeader> = vec![];
+        while result.len() <= tip_height {
+            trace!("downloading block headers from {}", blockhash);
+            let path = format!(
+                "/rest/headers/{}.bin?count={}",
+                blockhash.to_hex(),
+                REST_MAX_HEADERS
+            );
+            let headers = self.rest_get("rest_headers", &path)?;
+            // the batches overlap by one header, the one they start from
+            let skip = if result.is_empty() { 0 } else { 1 };
+            let headers = headers
+                .chunks(80)
+                .skip(skip)
+                .map(|header| deserialize(header).chain_err(|| "failed to parse header"))
+                .collect::<Result<Vec<BlockHeader>>>()?;
+            blockhash = headers
+                .last()
+                .chain_err(|| format!("no REST headers after {}", blockhash))?
+                .block_hash();
+            result.extend(headers);
+        }
+        result.truncate(tip_height + 1);
+        if result.last().map(|h| h.block_hash()) != Some(*tip) {
+            bail!("REST headers don't lead to {}", tip);
+        }
+        Ok(result)
+    }
+}
+
+impl DaemonApi for Daemon {
+    fn reconnect(&self) -> Result<Box<dyn DaemonApi>> {
+        Ok(Box::new(Daemon {
+            daemon_dir: self.daemon_dir.clone(),
+            blocks_dir: self.blocks_dir.clone(),
+            network: self.network,
+            magic: self.magic,
+            conn: Mutex::new(connect_backend(
+                &self.backends,
+                &self.cookie_getter,
+                &self.signal,
+            )?),
+            backends: Arc::clone(&self.backends),
+            relays: Arc::clone(&self.relays),
+            cookie_getter: Arc::clone(&self.cookie_getter),
+            message_id: Counter::new(),
+            signal: self.signal.clone(),
+            rest: self.rest,
+            rest_tx: AtomicBool::new(self.rest_tx.load(Ordering::Relaxed)),
+            rest_conns: Mutex::new(vec![]),
+            latency: self.latency.clone(),
+            size: self.size.clone(),
+        }))
+    }
+
+    fn list_blk_files(&self) -> Result<Vec<PathBuf>> {
+        let path = self.blocks_dir.join("blk*.dat");
+        debug!("listing block files at {:?}", path);
+        let mut paths: Vec<PathBuf> = glob::glob(path.to_str().unwrap())
+            .chain_err(|| "failed to list blk*.dat files")?
+            .map(|res| res.unwrap())
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn magic(&self) -> u32 {
+        self.magic.unwrap_or_else(|| self.network.magic())
+    }
+
+    fn check_backends(&self) {
+        let mut conn = self.conn.lock().unwrap();
+        let (addr, timeout) = {
+            let backends = self.backends.lock().unwrap();
+            if backends.addrs.len() < 2 {
+                return;
+            }
+            match switch_backend(&backends.addrs, &backends.health, conn.addr) {
+                Some(addr) => (addr, backends.active_timeout()),
+                None => return,
+            }
+        };
+        match Connection::new(addr, self.cookie_getter.clone(), timeout) {
+            Ok(new_conn) => {
+                info!("switching daemon backend from {} to {}", conn.addr, addr);
+                *conn = new_conn;
+                self.backends.lock().unwrap().set_active(addr);
+            }
+            Err(e) => warn!("failed to switch daemon backend: {}", e),
+        }
+    }
+
+    #[cfg(feature = "opcat_layer")]
+    fn get_mempoolminfee(&self) -> Result<f64> {
+        let info = self.getmempoolinfo()?;
+        Ok(info.mempoolminfee)
+    }
+
+    fn getbestblockhash(&self) -> Result<BlockHash> {
+        let result = self.request("getbestblockhash", json!([]))?;
+        let hash_str = result.as_str().chain_err(|| "non-string blockhash")?;
+        BlockHash::from_hex(hash_str).chain_err(|| "invalid blockhash")
+    }
+
+    fn getblock_raw(&self, blockhash: &BlockHash, verbose: u32) -> Result<Value> {
         self.request("getblock", json!([blockhash.to_hex(), verbose]))
     }
 
-    pub fn getblocks(&self, blockhashes: &[BlockHash]) -> Result<Vec<Block>> {
+    fn getblocks(&self, blockhashes: &[BlockHash]) -> Result<Vec<Block>> {
+        if self.rest {
+            match self.rest_getblocks(blockhashes) {
+                Ok(blocks) => return Ok(blocks),
+                Err(e) => warn!("failed to get blocks over REST, using JSONRPC: {}", e),
+            }
+        }
         let params_list: Vec<Value> = blockhashes
             .iter()
             .map(|hash| json!([hash.to_hex(), /*verbose=*/ false]))
@@ -592,7 +1195,7 @@ impl Daemon {
         Ok(blocks)
     }
 
-    pub fn gettransactions(&self, txhashes: &[&Txid]) -> Result<Vec<Transaction>> {
+    fn gettransactions(&self, txhashes: &[&Txid]) -> Result<Vec<Transaction>> {
         let params_list: Vec<Value> = txhashes
             .iter()
             .map(|txhash| json!([txhash.to_hex(), /*verbose=*/ false]))
@@ -606,19 +1209,33 @@ impl Daemon {
         Ok(txs)
     }
 
-    pub fn gettransaction_raw(
-        &self,
-        txid: &Txid,
-        blockhash: &BlockHash,
-        verbose: bool,
-    ) -> Result<Value> {
-        self.request(
-            "getrawtransaction",
-            json!([txid.to_hex(), verbose, blockhash]),
-        )
+    fn gettransaction_bin(&self, txid: &Txid, blockhash: &BlockHash) -> Result<Vec<u8>> {
+        if self.rest_tx.load(Ordering::Relaxed) {
+            let path = format!("/rest/tx/{}.bin", txid.to_hex());
+            match self.rest_request("rest_tx", &path) {
+                Ok(response) if response.status == 200 => return Ok(response.body),
+                // confirmed transactions are not found without -txindex
+                Ok(response) if (400..500).contains(&response.status) => {
+                    info!(
+                        "disabling REST transaction lookups, using JSONRPC: {} {}",
+                        response.status,
+                        String::from_utf8_lossy(&response.body).trim_end()
+                    );
+                    self.rest_tx.store(false, Ordering::Relaxed);
+                }
+                // transient failures, REST is used again for the next lookups
+                Ok(response) => warn!(
+                    "REST transaction lookup failed with status {}, using JSONRPC",
+                    response.status
+                ),
+                Err(e) => warn!("REST transaction lookup failed, using JSONRPC: {}", e),
+            }
+        }
+        let txhex = self.gettransaction_raw(txid, blockhash, false)?;
+        hex::decode(txhex.as_str().chain_err(|| "non-string tx")?).chain_err(|| "non-hex tx")
     }
 
-    pub fn getmempooltx(&self, txhash: &Txid) -> Result<Transaction> {
+    fn getmempooltx(&self, txhash: &Txid) -> Result<Transaction> {
         let value = self.request(
             "getrawtransaction",
             json!([txhash.to_hex(), /*verbose=*/ false]),
@@ -626,22 +1243,30 @@ impl Daemon {
         tx_from_value(value)
     }
 
-    pub fn getmempooltxids(&self) -> Result<HashSet<Txid>> {
+    fn getmempooltxids(&self) -> Result<HashSet<Txid>> {
         let res = self.request("getrawmempool", json!([/*verbose=*/ false]))?;
         serde_json::from_value(res).chain_err(|| "invalid getrawmempool reply")
     }
 
-    pub fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
-        self.broadcast_raw(&hex::encode(serialize(tx)))
+    fn getmempooltxids_sequence(&self) -> Result<(HashSet<Txid>, u64)> {
+        let res = self.request(
+            "getrawmempool",
+            json!([/*verbose=*/ false, /*mempool_sequence=*/ true]),
+        )?;
+        let res: MempoolSequence =
+            serde_json::from_value(res).chain_err(|| "invalid getrawmempool reply")?;
+        Ok((res.txids, res.mempool_sequence))
     }
 
-    pub fn broadcast_raw(&self, txhex: &str) -> Result<Txid> {
+    fn broadcast_raw(&self, txhex: &str) -> Result<Txid> {
         let txid = self.request("sendrawtransaction", json!([txhex]))?;
-        Txid::from_hex(txid.as_str().chain_err(|| "non-string txid")?)
-            .chain_err(|| "failed to parse txid")
+        let txid = Txid::from_hex(txid.as_str().chain_err(|| "non-string txid")?)
+            .chain_err(|| "failed to parse txid")?;
+        self.fan_out("sendrawtransaction", json!([txhex]));
+        Ok(txid)
     }
 
-    pub fn test_mempool_accept(
+    fn test_mempool_accept(
         &self,
         txhex: Vec<String>,
         maxfeerate: Option<f64>,
@@ -655,7 +1280,7 @@ impl Daemon {
             .chain_err(|| "invalid testmempoolaccept reply")
     }
 
-    pub fn submit_package(
+    fn submit_package(
         &self,
         txhex: Vec<String>,
         maxfeerate: Option<f64>,
@@ -669,15 +1294,17 @@ impl Daemon {
             (None, Some(burn)) => json!([txhex, null, format!("{:.8}", burn)]),
             (None, None) => json!([txhex]),
         };
-        let result = self.request("submitpackage", params)?;
-        serde_json::from_value::<SubmitPackageResult>(result)
-            .chain_err(|| "invalid submitpackage reply")
+        let result = self.request("submitpackage", params.clone())?;
+        let result = serde_json::from_value::<SubmitPackageResult>(result)
+            .chain_err(|| "invalid submitpackage reply")?;
+        self.fan_out("submitpackage", params);
+        Ok(result)
     }
 
     // Get estimated feerates for the provided confirmation targets using a batch RPC request
     // Missing estimates are logged but do not cause a failure, whatever is available is returned
     #[allow(clippy::float_cmp)]
-    pub fn estimatesmartfee_batch(&self, conf_targets: &[u16]) -> Result<HashMap<u16, f64>> {
+    fn estimatesmartfee_batch(&self, conf_targets: &[u16]) -> Result<HashMap<u16, f64>> {
         let params_list: Vec<Value> = conf_targets.iter().map(|t| json!([t])).collect();
 
         self.requests("estimatesmartfee", &params_list)?
@@ -711,34 +1338,7 @@ impl Daemon {
             .collect()
     }
 
-    fn get_all_headers(&self, tip: &BlockHash) -> Result<Vec<BlockHeader>> {
-        let info: Value = self.request("getblockheader", json!([tip.to_hex()]))?;
-        let tip_height = info
-            .get("height")
-            .expect("missing height")
-            .as_u64()
-            .expect("non-numeric height") as usize;
-        let all_heights: Vec<usize> = (0..=tip_height).collect();
-        let chunk_size = 100_000;
-        let mut result = vec![];
-        for heights in all_heights.chunks(chunk_size) {
-            trace!("downloading {} block headers", heights.len());
-            let mut headers = self.getblockheaders(heights)?;
-            assert!(headers.len() == heights.len());
-            result.append(&mut headers);
-        }
-
-        let mut blockhash = BlockHash::default();
-        for header in &result {
-            assert_eq!(header.prev_blockhash, blockhash);
-            blockhash = header.block_hash();
-        }
-        assert_eq!(blockhash, *tip);
-        Ok(result)
-    }
-
-    // Returns a list of BlockHeaders in ascending height (i.e. the tip is last).
-    pub fn get_new_headers(
+    fn get_new_headers(
         &self,
         indexed_headers: &HeaderList,
         bestblockhash: &BlockHash,
@@ -771,10 +1371,117 @@ impl Daemon {
         Ok(new_headers)
     }
 
-    pub fn get_relayfee(&self) -> Result<f64> {
+    fn get_relayfee(&self) -> Result<f64> {
         let relayfee = self.getnetworkinfo()?.relayfee;
 
         // from BTC/kB to sat/b
         Ok(relayfee * 100_000f64)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{rank_backends, switch_backend, BackendHealth, RestConnection};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rank_backends() {
+        let addrs: Vec<_> = (1..=4).map(|port| ([127, 0, 0, 1], port).into()).collect();
+        let health = |height, latency| {
+            Some(BackendHealth {
+                height,
+                latency: Duration::from_millis(latency),
+            })
+        };
+        // nothing checked yet: configuration order
+        let ranked = rank_backends(&addrs, &[None; 4]);
+        assert_eq!(ranked[0], (addrs[0], false));
+        assert_eq!(ranked[3], (addrs[3], false));
+
+        // lagging and unreachable backends come last, the others by latency
+        let ranked = rank_backends(
+            &addrs,
+            &[health(100, 5), None, health(102, 50), health(101, 10)],
+        );
+        assert_eq!(
+            ranked,
+            vec![
+                (addrs[3], true),
+                (addrs[2], true),
+                (addrs[0], false),
+                (addrs[1], false)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_switch_backend() {
+        let addrs: Vec<_> = (1..=3).map(|port| ([127, 0, 0, 1], port).into()).collect();
+        let healthy = Some(BackendHealth {
+            height: 100,
+            latency: Duration::from_millis(10),
+        });
+        let fast = Some(BackendHealth {
+            height: 100,
+            latency: Duration::from_millis(1),
+        });
+        // nothing checked yet
+        assert_eq!(switch_backend(&addrs, &[None; 3], addrs[0]), None);
+        // fail over to the fastest healthy backend
+        assert_eq!(
+            switch_backend(&addrs, &[None, healthy, fast], addrs[0]),
+            Some(addrs[2])
+        );
+        // stay on a healthy fallback while the primary is down
+        assert_eq!(
+            switch_backend(&addrs, &[None, healthy, fast], addrs[1]),
+            None
+        );
+        // and fail back to the primary once it is healthy again
+        assert_eq!(
+            switch_backend(&addrs, &[healthy, healthy, fast], addrs[2]),
+            Some(addrs[0])
+        );
+        assert_eq!(
+            switch_backend(&addrs, &[healthy, healthy, fast], addrs[0]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rest_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            for response in [
+                "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc",
+                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nde",
+                "HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Length: 9\r\n\r\nnot found",
+            ] {
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        // the requests share the same connection
+        let mut conn = RestConnection::new(addr).unwrap();
+        let response = conn.get("/rest/block/a.bin").unwrap();
+        assert_eq!((response.status, &response.body[..]), (200, &b"abc"[..]));
+        assert!(response.keep_alive);
+        let response = conn.get("/rest/block/b.bin").unwrap();
+        assert_eq!((response.status, &response.body[..]), (200, &b"de"[..]));
+        let response = conn.get("/rest/tx/c.bin").unwrap();
+        assert_eq!(response.status, 404);
+        assert!(!response.keep_alive);
+        assert_eq!(conn.requests, 3);
+        server.join().unwrap();
+    }
+}
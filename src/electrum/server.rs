@@ -23,11 +23,13 @@ use bitcoin::consensus::encode::serialize;
 
 const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::new(1, 4);
 const MAX_HEADERS: usize = 2016;
+// Max number of transactions a connection can subscribe to with each of the non-standard methods
+const MAX_TX_SUBSCRIPTIONS: usize = 1000;
 
 #[cfg(feature = "electrum-discovery")]
 use crate::electrum::{DiscoveryManager, ServerFeatures};
 
-use crate::chain::Txid;
+use crate::chain::{BlockHash, OutPoint, Txid};
 use crate::config::{Config, VERSION_STRING};
 use crate::electrum::{get_electrum_height, ProtocolVersion};
 use crate::errors::*;
@@ -35,7 +37,8 @@ use crate::metrics::{Gauge, HistogramOpts, HistogramVec, MetricOpts, Metrics};
 use crate::new_index::{Query, Utxo};
 use crate::util::electrum_merkle::{get_header_merkle_proof, get_id_from_pos, get_tx_merkle_proof};
 use crate::util::{
-    create_socket, full_hash, spawn_thread, BlockId, Channel, FullHash, HeaderEntry, SyncChannel,
+    create_socket, full_hash, has_prevout, spawn_thread, BlockId, Channel, FullHash, HeaderEntry,
+    SyncChannel,
 };
 
 // TODO: Sha256dHash should be a generic hash-container (since script hash is single SHA256)
@@ -97,6 +100,55 @@ fn get_status_hash(txs: Vec<(Txid, Option<BlockId>)>, query: &Query) -> Option<F
     }
 }
 
+/// A `blockchain.transaction.subscribe_conflicts` subscription. The conflicts only change
+/// with the spenders of the transaction's prevouts, so they are only looked up again then.
+struct ConflictsSubscription {
+    prevouts: Option<Vec<OutPoint>>, // None until the transaction is known
+    spenders: Option<Vec<Option<Txid>>>,
+    conflicts: Vec<Txid>,
+}
+
+impl ConflictsSubscription {
+    fn new(query: &Query, txid: &Txid) -> Self {
+        let mut subscription = ConflictsSubscription {
+            prevouts: None,
+            spenders: None,
+            conflicts: vec![],
+        };
+        subscription.update(query, txid);
+        subscription
+    }
+
+    /// Returns whether the conflicts changed
+    fn update(&mut self, query: &Query, txid: &Txid) -> bool {
+        if self.prevouts.is_none() {
+            self.prevouts = query.lookup_txn(txid).map(|tx| {
+                tx.input
+                    .iter()
+                    .filter(|txin| has_prevout(txin))
+                    .map(|txin| txin.previous_output)
+                    .collect()
+            });
+        }
+        if let Some(ref prevouts) = self.prevouts {
+            let spenders: Vec<Option<Txid>> = prevouts
+                .iter()
+                .map(|outpoint| query.lookup_spend(outpoint).map(|spend| spend.txid))
+                .collect();
+            if self.spenders.as_ref() == Some(&spenders) {
+                return false;
+            }
+            self.spenders = Some(spenders);
+        }
+        let conflicts = query.conflicting_txids(txid);
+        if conflicts == self.conflicts {
+            return false;
+        }
+        self.conflicts = conflicts;
+        true
+    }
+}
+
 #[repr(i16)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum JsonRpcV2Error {
@@ -116,6 +168,11 @@ struct Connection {
     query: Arc<Query>,
     last_header_entry: Option<HeaderEntry>,
     status_hashes: HashMap<Sha256dHash, Value>, // ScriptHash -> StatusHash
+    replacements: HashMap<Txid, Option<Txid>>,  // Txid -> replacing Txid
+    replacements_sequence: u64,                 // mempool sequence of the replacements
+    mempool_blocks: Option<Value>,              // last projected blocks sent
+    conflicts: HashMap<Txid, ConflictsSubscription>,
+    conflicts_state: Option<(BlockHash, u64)>, // chain tip and mempool sequence of the conflicts
     stream: ConnectionStream,
     chan: SyncChannel<Message>,
     stats: Arc<Stats>,
@@ -138,6 +195,11 @@ impl Connection {
             query,
             last_header_entry: None, // disable header subscription for now
             status_hashes: HashMap::new(),
+            replacements: HashMap::new(),
+            replacements_sequence: 0,
+            mempool_blocks: None,
+            conflicts: HashMap::new(),
+            conflicts_state: None,
             stream,
             chan: SyncChannel::new(10),
             stats,
@@ -215,6 +277,13 @@ impl Connection {
         Ok(json!(&self.query.mempool().backlog_stats().fee_histogram))
     }
 
+    // Non-standard: the next blocks projected from the mempool, notified when they change
+    fn mempool_projected_blocks_subscribe(&mut self) -> Result<Value> {
+        let blocks = json!(self.query.mempool().projected_blocks());
+        self.mempool_blocks = Some(blocks.clone());
+        Ok(blocks)
+    }
+
     fn blockchain_block_header(&self, params: &[Value]) -> Result<Value> {
         let height = usize_from_value(params.first(), "height")?;
         let cp_height = usize_from_value_or(params.get(1), "cp_height", 0)?;
@@ -398,6 +467,31 @@ impl Connection {
         Ok(json!(hex::encode(tx)))
     }
 
+    // Non-standard: notify when the transaction gets replaced (RBF) in the mempool
+    fn blockchain_transaction_replacement_subscribe(&mut self, params: &[Value]) -> Result<Value> {
+        let tx_hash = Txid::from(hash_from_value(params.first()).chain_err(|| "bad tx_hash")?);
+        if self.replacements.len() >= MAX_TX_SUBSCRIPTIONS
+            && !self.replacements.contains_key(&tx_hash)
+        {
+            bail!("too many replacement subscriptions");
+        }
+        let replaced_by = self.query.mempool().replaced_by(&tx_hash);
+        self.replacements.insert(tx_hash, replaced_by);
+        Ok(json!(replaced_by))
+    }
+
+    // Non-standard: notify when a transaction double spending the given one appears
+    fn blockchain_transaction_subscribe_conflicts(&mut self, params: &[Value]) -> Result<Value> {
+        let tx_hash = Txid::from(hash_from_value(params.first()).chain_err(|| "bad tx_hash")?);
+        if self.conflicts.len() >= MAX_TX_SUBSCRIPTIONS && !self.conflicts.contains_key(&tx_hash) {
+            bail!("too many conflicts subscriptions");
+        }
+        let subscription = ConflictsSubscription::new(&self.query, &tx_hash);
+        let conflicts = json!(subscription.conflicts);
+        self.conflicts.insert(tx_hash, subscription);
+        Ok(conflicts)
+    }
+
     fn blockchain_transaction_get_merkle(&self, params: &[Value]) -> Result<Value> {
         let txid = Txid::from(hash_from_value(params.first()).chain_err(|| "bad tx_hash")?);
         let height = usize_from_value(params.get(1), "height")?;
@@ -453,7 +547,14 @@ impl Connection {
             "blockchain.transaction.get" => self.blockchain_transaction_get(params),
             "blockchain.transaction.get_merkle" => self.blockchain_transaction_get_merkle(params),
             "blockchain.transaction.id_from_pos" => self.blockchain_transaction_id_from_pos(params),
+            "blockchain.transaction.subscribe_conflicts" => {
+                self.blockchain_transaction_subscribe_conflicts(params)
+            }
+            "blockchain.transaction.replacement.subscribe" => {
+                self.blockchain_transaction_replacement_subscribe(params)
+            }
             "mempool.get_fee_histogram" => self.mempool_get_fee_histogram(),
+            "mempool.projected_blocks.subscribe" => self.mempool_projected_blocks_subscribe(),
             "server.banner" => self.server_banner(),
             "server.donation_address" => self.server_donation_address(),
             "server.peers.subscribe" => self.server_peers_subscribe(),
@@ -523,6 +624,53 @@ impl Connection {
                 "params": [script_hash, new_status_hash]}));
             *status_hash = new_status_hash;
         }
+        if !self.replacements.is_empty() {
+            let mempool = self.query.mempool();
+            // replacements only change with the mempool
+            if mempool.sequence() != self.replacements_sequence {
+                self.replacements_sequence = mempool.sequence();
+                for (txid, replaced_by) in self.replacements.iter_mut() {
+                    let new_replaced_by = mempool.replaced_by(txid);
+                    if new_replaced_by == *replaced_by {
+                        continue;
+                    }
+                    result.push(json!({
+                        "jsonrpc": "2.0",
+                        "method": "blockchain.transaction.replacement.subscribe",
+                        "params": [txid, new_replaced_by]}));
+                    *replaced_by = new_replaced_by;
+                }
+            }
+        }
+        if !self.conflicts.is_empty() {
+            // conflicts only change with the chain and the mempool
+            let state = (
+                self.query.chain().best_hash(),
+                self.query.mempool().sequence(),
+            );
+            if self.conflicts_state != Some(state) {
+                self.conflicts_state = Some(state);
+                for (txid, subscription) in self.conflicts.iter_mut() {
+                    if !subscription.update(&self.query, txid) {
+                        continue;
+                    }
+                    result.push(json!({
+                        "jsonrpc": "2.0",
+                        "method": "blockchain.transaction.subscribe_conflicts",
+                        "params": [txid, subscription.conflicts]}));
+                }
+            }
+        }
+        if let Some(ref mut last_blocks) = self.mempool_blocks {
+            let blocks = json!(self.query.mempool().projected_blocks());
+            if *last_blocks != blocks {
+                result.push(json!({
+                    "jsonrpc": "2.0",
+                    "method": "mempool.projected_blocks.subscribe",
+                    "params": [blocks]}));
+                *last_blocks = blocks;
+            }
+        }
         timer.observe_duration();
         Ok(result)
     }
@@ -537,6 +685,16 @@ impl Connection {
         Ok(())
     }
 
+    // reads the chain from a single store snapshot while handling a request (or a batch),
+    // so that a block connected in the meantime can't mix two chain states into one reply
+    fn with_snapshot<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let pinned = Arc::new(self.query.with_snapshot());
+        let query = std::mem::replace(&mut self.query, pinned);
+        let result = f(self);
+        self.query = query;
+        result
+    }
+
     fn handle_replies(&mut self, shutdown: crossbeam_channel::Receiver<()>) -> Result<()> {
         loop {
             crossbeam_channel::select! {
@@ -545,12 +703,12 @@ impl Connection {
                     trace!("RPC {:?}", msg);
                     match msg {
                         Message::Request(line) => {
-                            let result = self.handle_line(&line);
+                            let result = self.with_snapshot(|conn| conn.handle_line(&line));
                             self.send_values(&[result])?
                         }
                         Message::PeriodicUpdate => {
                             let values = self
-                                .update_subscriptions()
+                                .with_snapshot(Self::update_subscriptions)
                                 .chain_err(|| "failed to update subscriptions")?;
                             self.send_values(&values)?
                         }
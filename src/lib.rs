@@ -25,9 +25,11 @@ pub mod electrum;
 pub mod errors;
 pub mod metrics;
 pub mod new_index;
+pub mod p2p;
 pub mod rest;
 pub mod signal;
 pub mod util;
+pub mod zmq;
 
 #[cfg(feature = "opcat_layer")]
 pub mod opcat_layer;
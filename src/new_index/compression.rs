@@ -0,0 +1,303 @@
+use rayon::prelude::*;
+use zstd::bulk::{Compressor, Decompressor};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::new_index::db::DBRow;
+use crate::util::{bincode_util, Bytes};
+
+/// Where the dictionary is stored. Its presence marks a DB whose compressed rows are encoded,
+/// it is empty until the dictionary gets trained.
+const DICTIONARY_KEY: &[u8] = b"Z";
+/// Where the `CompressionStats` are persisted, updated by every write
+const STATS_KEY: &[u8] = b"Zs";
+
+// the first byte of the encoded values, telling how the rest is stored
+const RAW: u8 = 0;
+const ZSTD: u8 = 1;
+
+const LEVEL: i32 = 3;
+const DICTIONARY_SIZE: usize = 110 << 10;
+/// The dictionary is trained on the first rows written, zstd recommends about 100 times its size
+const TRAINING_BYTES: usize = 100 * DICTIONARY_SIZE;
+/// Only the beginning of big values is sampled, like the common prefix of OPCAT data payloads
+const MAX_SAMPLE_SIZE: usize = 16 << 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Total size of the values of the compressed rows written, before encoding
+    pub raw_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+/// zstd compression of the values of the rows starting with one of `codes`, with a dictionary
+/// trained on the first of them.
+///
+/// Values are encoded by `DB::write` and decoded by the reads of the `DB` and its snapshots, so
+/// the callers only ever see the raw values.
+pub struct RowCompression {
+    db: Arc<rocksdb::DB>,
+    /// The family of the metadata rows of the DB, which stores the dictionary
+    family: &'static str,
+    codes: &'static [u8],
+    dictionary: RwLock<Option<Arc<Dictionary>>>,
+    state: Mutex<State>,
+    dictionary_size: usize,
+    training_bytes: usize,
+}
+
+struct Dictionary {
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+#[derive(Default)]
+struct State {
+    samples: Vec<u8>,
+    sample_sizes: Vec<usize>,
+    stats: CompressionStats,
+}
+
+impl RowCompression {
+    /// Returns the compression of the DB stored in `family`, if it was enabled with
+    /// `RowCompression::enable` when the DB was created.
+    pub fn load(db: &Arc<rocksdb::DB>, family: &'static str, codes: &'static [u8]) -> Option<Self> {
+        let compression = RowCompression {
+            db: Arc::clone(db),
+            family,
+            codes,
+            dictionary: RwLock::new(None),
+            state: Mutex::new(State::default()),
+            dictionary_size: DICTIONARY_SIZE,
+            training_bytes: TRAINING_BYTES,
+        };
+        compression.get(DICTIONARY_KEY)?;
+        if let Some(stats) = compression.get(STATS_KEY) {
+            compression.state.lock().unwrap().stats =
+                bincode_util::deserialize_little(&stats).expect("invalid compression stats");
+        }
+        Some(compression)
+    }
+
+    /// Marks the new DB stored in `family` as storing its compressed rows encoded
+    pub fn enable(db: &rocksdb::DB, family: &str) {
+        let cf = db.cf_handle(family).expect("missing column family");
+        db.put_cf(cf, DICTIONARY_KEY, b"").unwrap();
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        let cf = self
+            .db
+            .cf_handle(self.family)
+            .expect("missing column family");
+        self.db.get_cf(cf, key).unwrap()
+    }
+
+    fn compresses(&self, key: &[u8]) -> bool {
+        matches!(key.first(), Some(code) if self.codes.contains(code))
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Returns the dictionary, loading it if it was trained by another process, like the
+    /// primary of a secondary DB
+    fn dictionary(&self) -> Option<Arc<Dictionary>> {
+        if let Some(ref dictionary) = *self.dictionary.read().unwrap() {
+            return Some(Arc::clone(dictionary));
+        }
+        let bytes = self.get(DICTIONARY_KEY).filter(|bytes| !bytes.is_empty())?;
+        let dictionary = Arc::new(Dictionary::new(&bytes));
+        *self.dictionary.write().unwrap() = Some(Arc::clone(&dictionary));
+        Some(dictionary)
+    }
+
+    /// Encodes the values of the compressed `rows` in place, and adds the rows updating the
+    /// dictionary and the stats, which must be written in the same batch.
+    pub fn encode(&self, rows: &mut Vec<DBRow>) {
+        let mut state = self.state.lock().unwrap();
+        let mut dictionary = self.dictionary();
+        if dictionary.is_none() {
+            if let Some(bytes) = self.train(&mut state, rows) {
+                dictionary = Some(Arc::new(Dictionary::new(&bytes)));
+                *self.dictionary.write().unwrap() = dictionary.clone();
+                rows.push(DBRow {
+                    key: DICTIONARY_KEY.to_vec(),
+                    value: bytes,
+                });
+            }
+        }
+
+        let (raw_bytes, stored_bytes) = rows
+            .par_iter_mut()
+            .filter(|row| self.compresses(&row.key))
+            .map_init(
+                || {
+                    dictionary.as_ref().map(|d| {
+                        Compressor::with_prepared_dictionary(&d.encoder)
+                            .expect("failed to create a zstd compressor")
+                    })
+                },
+                |compressor, row| {
+                    let raw_len = row.value.len();
+                    row.value = encode(compressor.as_mut(), &row.value);
+                    (raw_len as u64, row.value.len() as u64)
+                },
+            )
+            .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+        if raw_bytes == 0 {
+            return;
+        }
+        state.stats.raw_bytes += raw_bytes;
+        state.stats.stored_bytes += stored_bytes;
+        rows.push(DBRow {
+            key: STATS_KEY.to_vec(),
+            value: bincode_util::serialize_little(&state.stats).unwrap(),
+        });
+    }
+
+    /// Samples the compressed `rows`, and trains the dictionary once enough were sampled
+    fn train(&self, state: &mut State, rows: &[DBRow]) -> Option<Bytes> {
+        for row in rows.iter().filter(|row| self.compresses(&row.key)) {
+            let sample = &row.value[..row.value.len().min(MAX_SAMPLE_SIZE)];
+            state.samples.extend_from_slice(sample);
+            state.sample_sizes.push(sample.len());
+        }
+        if state.samples.len() < self.training_bytes {
+            return None;
+        }
+
+        info!(
+            "training the compression dictionary on {} rows",
+            state.sample_sizes.len()
+        );
+        let result =
+            zstd::dict::from_continuous(&state.samples, &state.sample_sizes, self.dictionary_size);
+        state.samples = vec![];
+        state.sample_sizes = vec![];
+        // on failure, the next rows are sampled to try again
+        result
+            .map_err(|e| warn!("failed to train the compression dictionary: {}", e))
+            .ok()
+    }
+
+    /// Decodes the value of a row read from the DB, which may be compressed
+    pub fn decode(&self, key: &[u8], mut value: Bytes) -> Bytes {
+        if !self.compresses(key) {
+            return value;
+        }
+        match value.first() {
+            Some(&RAW) => {
+                value.remove(0);
+                value
+            }
+            Some(&ZSTD) => {
+                let dictionary = self
+                    .dictionary()
+                    .expect("missing the dictionary of a compressed row");
+                let frame = &value[1..];
+                let size = zstd::zstd_safe::get_frame_content_size(frame)
+                    .ok()
+                    .flatten()
+                    .expect("missing the size of a compressed row");
+                Decompressor::with_prepared_dictionary(&dictionary.decoder)
+                    .and_then(|mut decompressor| decompressor.decompress(frame, size as usize))
+                    .expect("failed to decompress a compressed row")
+            }
+            _ => panic!(
+                "invalid encoding of the compressed row {}",
+                hex::encode(key)
+            ),
+        }
+    }
+}
+
+impl Dictionary {
+    fn new(bytes: &[u8]) -> Self {
+        Dictionary {
+            encoder: EncoderDictionary::copy(bytes, LEVEL),
+            decoder: DecoderDictionary::copy(bytes),
+        }
+    }
+}
+
+/// Compresses `value` if there is a dictionary already and it makes it smaller
+fn encode(compressor: Option<&mut Compressor>, value: &[u8]) -> Bytes {
+    if let Some(compressor) = compressor {
+        let frame = compressor
+            .compress(value)
+            .expect("failed to compress a row");
+        if frame.len() < value.len() {
+            return [&[ZSTD][..], &frame].concat();
+        }
+    }
+    [&[RAW][..], value].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_index::db::{open_raw_db, OpenMode, DEFAULT_DB_MEMORY_MB};
+
+    fn tx_row(i: usize) -> DBRow {
+        // similar values, like transactions spending to the same kind of scripts
+        let value = format!("tx {} pays {} to script {:x}", i, i * 1000, i % 7).repeat(8);
+        DBRow {
+            key: [b"T", &i.to_le_bytes()[..]].concat(),
+            value: value.into_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(open_raw_db(
+            dir.path(),
+            OpenMode::ReadWrite,
+            DEFAULT_DB_MEMORY_MB,
+        ));
+        assert!(RowCompression::load(&db, "txstore", b"TS").is_none());
+        RowCompression::enable(&db, "txstore");
+        let mut compression = RowCompression::load(&db, "txstore", b"TS").unwrap();
+        compression.dictionary_size = 4 << 10;
+        compression.training_bytes = 200 << 10;
+
+        // stored raw until enough rows were sampled to train the dictionary
+        let mut rows: Vec<_> = (0..500).map(tx_row).collect();
+        rows.push(DBRow {
+            key: b"O1".to_vec(),
+            value: b"not compressed".to_vec(),
+        });
+        compression.encode(&mut rows);
+        assert_eq!(rows.len(), 502); // with the stats
+        assert_eq!(rows[0].value[0], RAW);
+        assert_eq!(rows[500].value, b"not compressed");
+
+        let mut rows: Vec<_> = (500..1000).map(tx_row).collect();
+        compression.encode(&mut rows);
+        assert_eq!(rows.len(), 502); // with the dictionary and the stats
+        assert_eq!(rows[0].value[0], ZSTD);
+        assert_eq!(rows[500].key, DICTIONARY_KEY);
+        for (i, row) in rows.iter().take(500).enumerate() {
+            assert_eq!(
+                compression.decode(&row.key, row.value.clone()),
+                tx_row(500 + i).value
+            );
+        }
+        let stats = compression.stats();
+        assert!(stats.stored_bytes < stats.raw_bytes);
+
+        // another process only loads the dictionary once it reads a compressed row
+        let cf = db.cf_handle("txstore").unwrap();
+        db.put_cf(cf, DICTIONARY_KEY, &rows[500].value).unwrap();
+        db.put_cf(cf, STATS_KEY, &rows[501].value).unwrap();
+        let reader = RowCompression::load(&db, "txstore", b"TS").unwrap();
+        assert_eq!(reader.stats(), stats);
+        assert_eq!(
+            reader.decode(&rows[0].key, rows[0].value.clone()),
+            tx_row(500).value
+        );
+    }
+}
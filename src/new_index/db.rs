@@ -1,15 +1,49 @@
 use rocksdb;
+use rocksdb::DBCompressionType as Compression;
 
+use std::fmt;
+use std::fs;
 use std::path::Path;
+use std::sync::Arc;
 
 use crate::config::Config;
+use crate::errors::*;
+use crate::metrics::Gauge;
+use crate::new_index::compression::{CompressionStats, RowCompression};
 use crate::util::{bincode_util, Bytes};
 
-/// Each version will break any running instance with a DB that has a differing version.
+/// Each version will break any running instance with a DB that has a differing version,
+/// unless the DB is recent enough to be brought up to date with `DB::migrate`.
 /// It will also break if light mode is enabled or disabled.
 // 1 = Original DB (since fork from Blockstream)
 // 2 = Add tx position to TxHistory rows and place Spending before Funding
-static DB_VERSION: u32 = 2;
+// 3 = TxOut rows are all stored as HybridTxOut, without the legacy TxOut encoding
+pub static DB_VERSION: u32 = 3;
+
+/// The oldest version that can still be migrated, older DBs require a reindex.
+static MIN_MIGRATABLE_VERSION: u32 = 2;
+
+/// Where the progress of the running migration is persisted, so that it resumes after a restart
+const MIGRATION_CURSOR_KEY: &[u8] = b"v";
+const MIGRATION_BATCH_SIZE: usize = 10_000;
+
+/// Where the progress of the running import from the legacy layout is persisted
+const LEGACY_IMPORT_CURSOR_KEY: &[u8] = b"L";
+
+/// Rewrites the rows of a DB from the format of version `version - 1` to the format of `version`.
+///
+/// Migrations run in the background while the server keeps serving queries, so the readers of
+/// the rows being rewritten must accept both the old and the new format until they complete.
+/// A DB gets at most one migration per version.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    /// Only the rows with this key prefix are visited
+    pub prefix: &'static [u8],
+    /// Returns the rows to write in place of `row`, or `None` if it is already up to date.
+    /// Must be idempotent, as rows may be visited again when resuming an interrupted migration.
+    pub rewrite: fn(&DBRow) -> Option<Vec<DBRow>>,
+}
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct DBRow {
@@ -21,6 +55,27 @@ pub struct ScanIterator<'a> {
     prefix: Vec<u8>,
     iter: rocksdb::DBIterator<'a>,
     done: bool,
+    compression: Option<Arc<RowCompression>>,
+    // keeps the snapshot being iterated alive, must stay after `iter` so that it is dropped
+    // last, see `DBSnapshot::iter_scan_from`
+    _snapshot: Option<DBSnapshot>,
+}
+
+impl<'a> ScanIterator<'a> {
+    fn new(
+        prefix: &[u8],
+        iter: rocksdb::DBIterator<'a>,
+        compression: Option<Arc<RowCompression>>,
+        snapshot: Option<DBSnapshot>,
+    ) -> Self {
+        ScanIterator {
+            prefix: prefix.to_vec(),
+            iter,
+            done: false,
+            compression,
+            _snapshot: snapshot,
+        }
+    }
 }
 
 impl Iterator for ScanIterator<'_> {
@@ -30,14 +85,14 @@ impl Iterator for ScanIterator<'_> {
         if self.done {
             return None;
         }
-        let (key, value) = self.iter.next().map(Result::ok)??;
+        let (key, value) = self.iter.next().map(std::result::Result::ok)??;
         if !key.starts_with(&self.prefix) {
             self.done = true;
             return None;
         }
         Some(DBRow {
             key: key.to_vec(),
-            value: value.to_vec(),
+            value: decode(&self.compression, &key, value.to_vec()),
         })
     }
 }
@@ -46,6 +101,29 @@ pub struct ReverseScanIterator<'a> {
     prefix: Vec<u8>,
     iter: rocksdb::DBRawIterator<'a>,
     done: bool,
+    compression: Option<Arc<RowCompression>>,
+    // keeps the snapshot being iterated alive, must stay after `iter` so that it is dropped
+    // last, see `DBSnapshot::iter_scan_from`
+    _snapshot: Option<DBSnapshot>,
+}
+
+impl<'a> ReverseScanIterator<'a> {
+    fn new(
+        prefix: &[u8],
+        prefix_max: &[u8],
+        mut iter: rocksdb::DBRawIterator<'a>,
+        compression: Option<Arc<RowCompression>>,
+        snapshot: Option<DBSnapshot>,
+    ) -> Self {
+        iter.seek_for_prev(prefix_max);
+        ReverseScanIterator {
+            prefix: prefix.to_vec(),
+            iter,
+            done: false,
+            compression,
+            _snapshot: snapshot,
+        }
+    }
 }
 
 impl Iterator for ReverseScanIterator<'_> {
@@ -64,7 +142,7 @@ impl Iterator for ReverseScanIterator<'_> {
 
         let row = DBRow {
             key: key.into(),
-            value: self.iter.value().unwrap().into(),
+            value: decode(&self.compression, key, self.iter.value().unwrap().into()),
         };
 
         self.iter.prev();
@@ -134,9 +212,108 @@ impl Iterator for ReverseScanGroupIterator<'_> {
     }
 }
 
-#[derive(Debug)]
+/// Storage settings of a column family, tuned for the rows stored in it
+pub struct Family {
+    pub name: &'static str,
+    compression: rocksdb::DBCompressionType,
+    /// Bits per key of the bloom filter, for the families read by point lookups.
+    /// Whole key bloom filters are of no use to prefix scans.
+    bloom_bits: Option<f64>,
+    block_size: usize,
+    /// 0 to not cache the blocks of this family
+    block_cache_mb: usize,
+    write_buffer_mb: usize,
+}
+
+/// The memory used by the block caches and write buffers of `FAMILIES` as tuned, which
+/// `--db-memory-mb` scales them from
+pub const DEFAULT_DB_MEMORY_MB: usize = 1680;
+
+/// The column families of the index RocksDB, which the rows of each `DBLayout` are stored in
+pub static FAMILIES: &[Family] = &[
+    // txstore: metadata and done markers, then T, O, S, C and B/X/M rows
+    Family::new("txstore", Compression::None, Some(10.0), 4 << 10, 8, 16),
+    Family::new("txs", Compression::Lz4, Some(10.0), 16 << 10, 128, 256),
+    Family::new("txouts", Compression::Lz4, Some(10.0), 4 << 10, 128, 128),
+    Family::new("scripts", Compression::Lz4, Some(10.0), 4 << 10, 32, 64),
+    Family::new("tx_confs", Compression::Lz4, None, 4 << 10, 32, 64),
+    Family::new("blocks", Compression::Lz4, Some(10.0), 4 << 10, 32, 32),
+    // history: metadata and done markers, then H, S and address search rows
+    Family::new("history", Compression::None, Some(10.0), 4 << 10, 8, 16),
+    Family::new("tx_history", Compression::Lz4, None, 16 << 10, 128, 256),
+    Family::new("tx_edges", Compression::Lz4, None, 4 << 10, 64, 128),
+    Family::new("address_search", Compression::Lz4, None, 4 << 10, 0, 64),
+    Family::new("cache", Compression::Lz4, Some(10.0), 4 << 10, 32, 64),
+];
+
+/// Where the rows of a logical DB are stored in the index RocksDB. The rows are routed to a
+/// family by the code byte their key starts with, so a prefix scan never spans families.
+pub struct DBLayout {
+    /// Also the family of the rows without a family of their own, like the metadata rows
+    pub name: &'static str,
+    row_families: &'static [(u8, &'static str)],
+    /// The codes of the rows whose values are compressed, when enabled, see `RowCompression`
+    compressed_rows: &'static [u8],
+}
+
+pub static TXSTORE: DBLayout = DBLayout {
+    name: "txstore",
+    row_families: &[
+        (b'T', "txs"),
+        (b'O', "txouts"),
+        (b'S', "scripts"),
+        (b'C', "tx_confs"),
+        (b'B', "blocks"),
+        (b'X', "blocks"),
+        (b'M', "blocks"),
+    ],
+    compressed_rows: b"TS",
+};
+
+pub static HISTORY: DBLayout = DBLayout {
+    name: "history",
+    row_families: &[
+        (b'H', "tx_history"),
+        (b'S', "tx_edges"),
+        (b'a', "address_search"),
+    ],
+    compressed_rows: b"",
+};
+
+pub static CACHE: DBLayout = DBLayout {
+    name: "cache",
+    row_families: &[],
+    compressed_rows: b"",
+};
+
+/// One of the logical DBs (txstore, history or cache) stored in the index RocksDB
 pub struct DB {
-    db: rocksdb::DB,
+    db: Arc<rocksdb::DB>,
+    layout: &'static DBLayout,
+    // RocksDB secondaries only follow their primary, and don't support snapshots
+    secondary: bool,
+    compression: Option<Arc<RowCompression>>,
+}
+
+/// A consistent point-in-time view of a `DB`, unaffected by writes made after it was taken.
+/// Cloning it is cheap, and the iterators it returns keep it alive on their own.
+///
+/// On a secondary DB, it reads the state of the last `DB::catch_up` instead, which only
+/// changes when the secondary catches up again.
+#[derive(Clone)]
+pub struct DBSnapshot(Arc<SnapshotHandle>);
+
+struct SnapshotHandle {
+    // declared first so that it is dropped before the DB it points into
+    snapshot: Option<rocksdb::Snapshot<'static>>,
+    db: Arc<rocksdb::DB>,
+    layout: &'static DBLayout,
+    compression: Option<Arc<RowCompression>>,
+}
+
+/// Point reads that work the same on the live `DB` and on a `DBSnapshot`.
+pub trait DBRead: fmt::Debug + Sync {
+    fn get(&self, key: &[u8]) -> Option<Bytes>;
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -145,60 +322,228 @@ pub enum DBFlush {
     Enable,
 }
 
+impl Family {
+    const fn new(
+        name: &'static str,
+        compression: rocksdb::DBCompressionType,
+        bloom_bits: Option<f64>,
+        block_size: usize,
+        block_cache_mb: usize,
+        write_buffer_mb: usize,
+    ) -> Self {
+        Family {
+            name,
+            compression,
+            bloom_bits,
+            block_size,
+            block_cache_mb,
+            write_buffer_mb,
+        }
+    }
+
+    /// The options of the family, with its memory scaled to a budget of `memory_mb` for all
+    /// the families
+    fn options(&self, memory_mb: usize) -> rocksdb::Options {
+        // at least 1MB each, except for the disabled caches
+        let scale = |mb: usize| match mb {
+            0 => 0,
+            mb => (mb * memory_mb / DEFAULT_DB_MEMORY_MB).max(1),
+        };
+
+        let mut opts = rocksdb::Options::default();
+        opts.set_compaction_style(rocksdb::DBCompactionStyle::Level);
+        opts.set_compression_type(self.compression);
+        opts.set_target_file_size_base(1_073_741_824);
+        opts.set_write_buffer_size(scale(self.write_buffer_mb) << 20);
+        opts.set_disable_auto_compactions(true); // for initial bulk load
+
+        let mut block_opts = rocksdb::BlockBasedOptions::default();
+        block_opts.set_block_size(self.block_size);
+        if let Some(bits) = self.bloom_bits {
+            block_opts.set_bloom_filter(bits, false);
+        }
+        match scale(self.block_cache_mb) {
+            0 => block_opts.disable_cache(),
+            mb => block_opts.set_block_cache(&rocksdb::Cache::new_lru_cache(mb << 20)),
+        }
+        opts.set_block_based_table_factory(&block_opts);
+        opts
+    }
+}
+
+impl DBLayout {
+    fn family(&self, key: &[u8]) -> &'static str {
+        key.first()
+            .and_then(|code| self.row_families.iter().find(|(c, _)| c == code))
+            .map_or(self.name, |(_, family)| family)
+    }
+
+    /// The families of the rows first, then the family of the metadata rows
+    fn families(&self) -> Vec<&'static str> {
+        let mut families: Vec<_> = self.row_families.iter().map(|(_, f)| *f).collect();
+        families.dedup();
+        families.push(self.name);
+        families
+    }
+}
+
+fn cf_handle<'a>(db: &'a rocksdb::DB, family: &str) -> &'a rocksdb::ColumnFamily {
+    db.cf_handle(family)
+        .unwrap_or_else(|| panic!("missing column family {}", family))
+}
+
 impl DB {
-    pub fn open(path: &Path, config: &Config) -> DB {
-        let db = DB {
-            db: open_raw_db(path, OpenMode::ReadWrite),
+    /// Opens the logical DB stored in `db` with `layout`. If it is still stored in the legacy
+    /// layout, as a separate RocksDB in the subdirectory of `path` named after it, it is
+    /// imported first when `--import-legacy-db` is set. The import blocks the startup until
+    /// it completes, and temporarily needs twice the disk space of the legacy DB.
+    pub fn open(
+        db: &Arc<rocksdb::DB>,
+        layout: &'static DBLayout,
+        path: &Path,
+        config: &Config,
+    ) -> DB {
+        let mut db = DB::new(db, layout);
+        let legacy_path = path.join(layout.name);
+        if legacy_path.exists() {
+            if !config.import_legacy_db {
+                panic!(
+                    "{:?} is stored in the legacy layout. Restart with --import-legacy-db \
+                     to import it, which takes the index offline until it completes and \
+                     needs as much free disk space as the legacy DB, or reindex.",
+                    legacy_path
+                )
+            }
+            db.import_legacy(&legacy_path);
+        }
+        let created = db.get(b"V").is_none();
+        db.verify_compatibility(config);
+
+        if !layout.compressed_rows.is_empty() {
+            // the rows are encoded differently, so this can only be chosen for a new DB
+            if created && config.compress_txstore {
+                RowCompression::enable(&db.db, layout.name);
+            }
+            db.load_compression();
+            if db.compression.is_some() != config.compress_txstore {
+                panic!("Incompatible database found (compression was toggled). Please reindex.")
+            }
+        }
+        db
+    }
+
+    pub fn open_read_only(db: &Arc<rocksdb::DB>, layout: &'static DBLayout) -> DB {
+        let mut db = DB::new(db, layout);
+        db.load_compression();
+        db
+    }
+
+    /// Opens a logical DB of `db`, a RocksDB secondary which follows the writes of its primary
+    /// with `DB::catch_up`.
+    pub fn open_secondary(db: &Arc<rocksdb::DB>, layout: &'static DBLayout, config: &Config) -> DB {
+        let mut db = DB {
+            secondary: true,
+            ..DB::new(db, layout)
         };
+        db.load_compression();
+        // checked before `verify_compatibility`, which would initialize the version
+        if db.get(b"V").is_none() {
+            panic!("{:?} is not an index DB, it cannot be replicated", db);
+        }
         db.verify_compatibility(config);
         db
     }
 
+    fn new(db: &Arc<rocksdb::DB>, layout: &'static DBLayout) -> DB {
+        DB {
+            db: Arc::clone(db),
+            layout,
+            secondary: false,
+            compression: None,
+        }
+    }
+
+    fn load_compression(&mut self) {
+        self.compression =
+            RowCompression::load(&self.db, self.layout.name, self.layout.compressed_rows)
+                .map(Arc::new);
+    }
+
+    /// The sizes of the compressed rows written so far, if compression is enabled
+    pub fn compression_stats(&self) -> Option<CompressionStats> {
+        self.compression.as_ref().map(|c| c.stats())
+    }
+
+    fn cf(&self, key: &[u8]) -> &rocksdb::ColumnFamily {
+        cf_handle(&self.db, self.layout.family(key))
+    }
+
+    /// Applies the writes made by the primary since the last catch up, on a secondary DB.
+    /// This catches up all the logical DBs stored in the same RocksDB at once.
+    pub fn catch_up(&self) -> Result<()> {
+        self.db
+            .try_catch_up_with_primary()
+            .map_err(|e| format!("failed to catch up {:?} with its primary: {}", self, e).into())
+    }
+
+    /// The directory of the underlying RocksDB, shared by the DBs opened on it.
+    pub fn path(&self) -> &Path {
+        self.db.path()
+    }
+
+    /// Creates a consistent copy of the whole RocksDB at `path`, including the other logical
+    /// DBs stored in it, hard linking its files when possible
+    pub fn checkpoint(&self, path: &Path) -> Result<()> {
+        rocksdb::checkpoint::Checkpoint::new(&self.db)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(path))
+            .map_err(|e| format!("failed to checkpoint {:?} to {:?}: {}", self, path, e).into())
+    }
+
     pub fn full_compaction(&self) {
         // TODO: make sure this doesn't fail silently
-        debug!("starting full compaction on {:?}", self.db);
-        self.db.compact_range(None::<&[u8]>, None::<&[u8]>);
-        debug!("finished full compaction on {:?}", self.db);
+        debug!("starting full compaction on {:?}", self);
+        for family in self.layout.families() {
+            self.db
+                .compact_range_cf(cf_handle(&self.db, family), None::<&[u8]>, None::<&[u8]>);
+        }
+        debug!("finished full compaction on {:?}", self);
     }
 
     pub fn enable_auto_compaction(&self) {
         let opts = [("disable_auto_compactions", "false")];
-        self.db.set_options(&opts).unwrap();
+        for family in self.layout.families() {
+            self.db
+                .set_options_cf(cf_handle(&self.db, family), &opts)
+                .unwrap();
+        }
     }
 
-    pub fn raw_iterator(&self) -> rocksdb::DBRawIterator {
-        self.db.raw_iterator()
+    /// Iterates over the family storing the rows that start with `prefix`. The values of
+    /// compressed rows are returned as stored, use `DB::iter_scan` to read them.
+    pub fn raw_iterator(&self, prefix: &[u8]) -> rocksdb::DBRawIterator {
+        self.db.raw_iterator_cf(self.cf(prefix))
     }
 
     pub fn iter_scan(&self, prefix: &[u8]) -> ScanIterator {
-        ScanIterator {
-            prefix: prefix.to_vec(),
-            iter: self.db.prefix_iterator(prefix),
-            done: false,
-        }
+        self.iter_scan_from(prefix, prefix)
     }
 
     pub fn iter_scan_from(&self, prefix: &[u8], start_at: &[u8]) -> ScanIterator {
-        let iter = self.db.iterator(rocksdb::IteratorMode::From(
-            start_at,
-            rocksdb::Direction::Forward,
-        ));
-        ScanIterator {
-            prefix: prefix.to_vec(),
-            iter,
-            done: false,
-        }
+        let iter = self.db.iterator_cf(
+            self.cf(prefix),
+            rocksdb::IteratorMode::From(start_at, rocksdb::Direction::Forward),
+        );
+        ScanIterator::new(prefix, iter, self.compression.clone(), None)
     }
 
     pub fn iter_scan_reverse(&self, prefix: &[u8], prefix_max: &[u8]) -> ReverseScanIterator {
-        let mut iter = self.db.raw_iterator();
-        iter.seek_for_prev(prefix_max);
-
-        ReverseScanIterator {
-            prefix: prefix.to_vec(),
-            iter,
-            done: false,
-        }
+        ReverseScanIterator::new(
+            prefix,
+            prefix_max,
+            self.raw_iterator(prefix),
+            self.compression.clone(),
+            None,
+        )
     }
 
     pub fn iter_scan_group_reverse(
@@ -208,13 +553,13 @@ impl DB {
     ) -> ReverseScanGroupIterator {
         let iters = prefixes
             .map(|(prefix, prefix_max)| {
-                let mut iter = self.db.raw_iterator();
-                iter.seek_for_prev(prefix_max);
-                ReverseScanIterator {
-                    prefix: prefix.to_vec(),
-                    iter,
-                    done: false,
-                }
+                ReverseScanIterator::new(
+                    &prefix,
+                    &prefix_max,
+                    self.raw_iterator(&prefix),
+                    self.compression.clone(),
+                    None,
+                )
             })
             .collect();
         ReverseScanGroupIterator::new(iters, value_offset)
@@ -224,13 +569,16 @@ impl DB {
         debug!(
             "writing {} rows to {:?}, flush={:?}",
             rows.len(),
-            self.db,
+            self,
             flush
         );
+        if let Some(ref compression) = self.compression {
+            compression.encode(&mut rows);
+        }
         rows.sort_unstable_by(|a, b| a.key.cmp(&b.key));
         let mut batch = rocksdb::WriteBatch::default();
         for row in rows {
-            batch.put(&row.key, &row.value);
+            batch.put_cf(self.cf(&row.key), &row.key, &row.value);
         }
         let do_flush = match flush {
             DBFlush::Enable => true,
@@ -243,30 +591,180 @@ impl DB {
     }
 
     pub fn delete(&self, keys: Vec<Vec<u8>>) {
-        debug!("deleting {} rows from {:?}", keys.len(), self.db);
+        debug!("deleting {} rows from {:?}", keys.len(), self);
         for key in keys {
-            let _ = self.db.delete(key).inspect_err(|err| {
+            let _ = self.db.delete_cf(self.cf(&key), &key).inspect_err(|err| {
                 warn!("Error while deleting DB row: {err}");
             });
         }
     }
 
     pub fn flush(&self) {
-        self.db.flush().unwrap();
+        // the metadata family holds the done markers, flushing it last ensures that they never
+        // get ahead of the rows they mark as done
+        for family in self.layout.families() {
+            self.db.flush_cf(cf_handle(&self.db, family)).unwrap();
+        }
     }
 
     pub fn put(&self, key: &[u8], value: &[u8]) {
-        self.db.put(key, value).unwrap();
+        self.db.put_cf(self.cf(key), key, value).unwrap();
     }
 
     pub fn put_sync(&self, key: &[u8], value: &[u8]) {
         let mut opts = rocksdb::WriteOptions::new();
         opts.set_sync(true);
-        self.db.put_opt(key, value, &opts).unwrap();
+        self.db.put_cf_opt(self.cf(key), key, value, &opts).unwrap();
     }
 
     pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.db.get(key).unwrap().map(|v| v.to_vec())
+        self.db
+            .get_cf(self.cf(key), key)
+            .unwrap()
+            .map(|v| decode(&self.compression, key, v))
+    }
+
+    pub fn snapshot(&self) -> DBSnapshot {
+        let snapshot = (!self.secondary).then(|| self.db.snapshot());
+        // SAFETY: the snapshot borrows the `rocksdb::DB` behind `self.db`, whose heap address
+        // doesn't change while any `Arc` to it is alive. `SnapshotHandle` stores the snapshot
+        // with a clone of that `Arc`, and fields are dropped in declaration order, so the
+        // snapshot is always released before the `Arc` and thus before the DB can be closed.
+        // The `'static` snapshot is private to `SnapshotHandle` and never handed out.
+        let snapshot = unsafe {
+            std::mem::transmute::<Option<rocksdb::Snapshot<'_>>, Option<rocksdb::Snapshot<'static>>>(
+                snapshot,
+            )
+        };
+        DBSnapshot(Arc::new(SnapshotHandle {
+            snapshot,
+            db: Arc::clone(&self.db),
+            layout: self.layout,
+            compression: self.compression.clone(),
+        }))
+    }
+
+    /// Copies the rows of the legacy standalone RocksDB at `legacy_path` into the families of
+    /// this DB, then deletes it. Resumes where it stopped if interrupted.
+    fn import_legacy(&self, legacy_path: &Path) {
+        let legacy =
+            rocksdb::DB::open_for_read_only(&rocksdb::Options::default(), legacy_path, false)
+                .expect("failed to open the legacy DB");
+        let mut cursor = self.get(LEGACY_IMPORT_CURSOR_KEY).unwrap_or_default();
+        info!(
+            "importing {:?} into the column families of {:?}, this may take a while",
+            legacy_path, self
+        );
+
+        let mut imported = 0;
+        loop {
+            let mut rows = vec![];
+            let iter = legacy.iterator(rocksdb::IteratorMode::From(
+                &cursor,
+                rocksdb::Direction::Forward,
+            ));
+            for item in iter.take(MIGRATION_BATCH_SIZE) {
+                let (key, value) = item.expect("failed to read the legacy DB");
+                // resume right after the last imported key
+                cursor = [&key[..], &[0]].concat();
+                rows.push(DBRow {
+                    key: key.to_vec(),
+                    value: value.to_vec(),
+                });
+            }
+            let done = rows.len() < MIGRATION_BATCH_SIZE;
+            imported += rows.len();
+            // not imported, so that the indexer compacts the imported rows before it enables
+            // the auto compactions
+            rows.retain(|row| row.key != b"F");
+            if !done {
+                rows.push(DBRow {
+                    key: LEGACY_IMPORT_CURSOR_KEY.to_vec(),
+                    value: cursor.clone(),
+                });
+            }
+            self.write(rows, DBFlush::Enable);
+            if done {
+                break;
+            }
+            debug!("imported {} rows from {:?}", imported, legacy_path);
+        }
+
+        drop(legacy);
+        fs::remove_dir_all(legacy_path).expect("failed to delete the legacy DB");
+        self.delete(vec![LEGACY_IMPORT_CURSOR_KEY.to_vec()]);
+        info!("imported {} rows from {:?}", imported, legacy_path);
+    }
+
+    /// Runs the `migrations` needed to bring this DB to `DB_VERSION`, blocking until done.
+    pub fn migrate(&self, migrations: &[Migration], rows_migrated: &Gauge) {
+        loop {
+            let version = self.version();
+            if version >= DB_VERSION {
+                break;
+            }
+            if let Some(migration) = migrations.iter().find(|m| m.version == version + 1) {
+                info!(
+                    "migrating {:?} to version {}: {}",
+                    self, migration.version, migration.description
+                );
+                self.run_migration(migration, rows_migrated);
+            }
+            self.set_version(version + 1);
+            info!("{:?} migrated to version {}", self, version + 1);
+        }
+    }
+
+    fn run_migration(&self, migration: &Migration, rows_migrated: &Gauge) {
+        let version_bytes = bincode_util::serialize_little(&migration.version).unwrap();
+        let mut cursor = match self.get(MIGRATION_CURSOR_KEY) {
+            Some(value) if value.starts_with(&version_bytes) => {
+                value[version_bytes.len()..].to_vec()
+            }
+            _ => migration.prefix.to_vec(),
+        };
+        rows_migrated.set(0);
+
+        loop {
+            let mut rows = vec![];
+            let mut visited = 0;
+            for row in self
+                .iter_scan_from(migration.prefix, &cursor)
+                .take(MIGRATION_BATCH_SIZE)
+            {
+                if let Some(new_rows) = (migration.rewrite)(&row) {
+                    rows_migrated.inc();
+                    rows.extend(new_rows);
+                }
+                visited += 1;
+                // resume right after the last visited key
+                cursor = [&row.key[..], &[0]].concat();
+            }
+            if visited < MIGRATION_BATCH_SIZE {
+                self.write(rows, DBFlush::Enable);
+                break;
+            }
+            rows.push(DBRow {
+                key: MIGRATION_CURSOR_KEY.to_vec(),
+                value: [&version_bytes[..], &cursor].concat(),
+            });
+            self.write(rows, DBFlush::Enable);
+        }
+
+        // cleared before the version is bumped, a crash in between only restarts this migration
+        self.delete(vec![MIGRATION_CURSOR_KEY.to_vec()]);
+    }
+
+    pub fn version(&self) -> u32 {
+        let compatibility_bytes = self.get(b"V").expect("missing DB version");
+        parse_version(&compatibility_bytes).expect("invalid DB version")
+    }
+
+    fn set_version(&self, version: u32) {
+        // keep the light mode marker byte, if any
+        let mut compatibility_bytes = self.get(b"V").expect("missing DB version");
+        compatibility_bytes.splice(..4, bincode_util::serialize_little(&version).unwrap());
+        self.put_sync(b"V", &compatibility_bytes);
     }
 
     fn verify_compatibility(&self, config: &Config) {
@@ -282,49 +780,331 @@ impl DB {
 
         match self.get(b"V") {
             None => self.put(b"V", &compatibility_bytes),
-            Some(ref x) if x != &compatibility_bytes => {
-                panic!("Incompatible database found. Please reindex.")
+            Some(ref x) if x.get(4..) != compatibility_bytes.get(4..) => {
+                panic!("Incompatible database found (light mode was toggled). Please reindex.")
             }
-            Some(_) => (),
+            Some(ref x) => match parse_version(x) {
+                Some(version) if version > DB_VERSION => panic!(
+                    "Database version {} is newer than the supported version {}.",
+                    version, DB_VERSION
+                ),
+                Some(version) if version >= MIN_MIGRATABLE_VERSION => {
+                    if version < DB_VERSION {
+                        info!(
+                            "{:?} is at version {}, it will be migrated to version {} in the background",
+                            self, version, DB_VERSION
+                        );
+                    }
+                }
+                _ => panic!("Incompatible database found. Please reindex."),
+            },
+        }
+    }
+}
+
+fn decode(compression: &Option<Arc<RowCompression>>, key: &[u8], value: Bytes) -> Bytes {
+    match compression {
+        Some(compression) => compression.decode(key, value),
+        None => value,
+    }
+}
+
+fn parse_version(compatibility_bytes: &[u8]) -> Option<u32> {
+    bincode_util::deserialize_little(compatibility_bytes.get(..4)?).ok()
+}
+
+impl DBRead for DB {
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        DB::get(self, key)
+    }
+}
+
+impl DBSnapshot {
+    pub fn iter_scan(&self, prefix: &[u8]) -> ScanIterator<'static> {
+        self.iter_scan_from(prefix, prefix)
+    }
+
+    pub fn iter_scan_from(&self, prefix: &[u8], start_at: &[u8]) -> ScanIterator<'static> {
+        let cf = cf_handle(&self.0.db, self.0.layout.family(prefix));
+        let mode = rocksdb::IteratorMode::From(start_at, rocksdb::Direction::Forward);
+        let iter = match self.0.snapshot {
+            Some(ref snapshot) => snapshot.iterator_cf(cf, mode),
+            None => self.0.db.iterator_cf(cf, mode),
+        };
+        // SAFETY: the iterator borrows the snapshot (or the DB, on a secondary) owned by
+        // `self.0`. `ScanIterator` keeps the iterator private and stores it with a clone of
+        // `self` in `_snapshot`, declared after `iter`, so the iterator is dropped first and
+        // the `Arc<SnapshotHandle>` keeps the snapshot and its DB alive until then.
+        let iter = unsafe {
+            std::mem::transmute::<rocksdb::DBIterator<'_>, rocksdb::DBIterator<'static>>(iter)
+        };
+        ScanIterator::new(prefix, iter, self.0.compression.clone(), Some(self.clone()))
+    }
+
+    pub fn iter_scan_reverse(
+        &self,
+        prefix: &[u8],
+        prefix_max: &[u8],
+    ) -> ReverseScanIterator<'static> {
+        ReverseScanIterator::new(
+            prefix,
+            prefix_max,
+            self.raw_iterator(prefix),
+            self.0.compression.clone(),
+            Some(self.clone()),
+        )
+    }
+
+    pub fn iter_scan_group_reverse(
+        &self,
+        prefixes: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+        value_offset: usize,
+    ) -> ReverseScanGroupIterator<'static> {
+        let iters = prefixes
+            .map(|(prefix, prefix_max)| {
+                ReverseScanIterator::new(
+                    &prefix,
+                    &prefix_max,
+                    self.raw_iterator(&prefix),
+                    self.0.compression.clone(),
+                    Some(self.clone()),
+                )
+            })
+            .collect();
+        ReverseScanGroupIterator::new(iters, value_offset)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        let cf = cf_handle(&self.0.db, self.0.layout.family(key));
+        let value = match self.0.snapshot {
+            Some(ref snapshot) => snapshot.get_cf(cf, key).unwrap(),
+            None => self.0.db.get_cf(cf, key).unwrap(),
+        };
+        value.map(|v| decode(&self.0.compression, key, v))
+    }
+
+    fn raw_iterator(&self, prefix: &[u8]) -> rocksdb::DBRawIterator<'static> {
+        let cf = cf_handle(&self.0.db, self.0.layout.family(prefix));
+        let iter = match self.0.snapshot {
+            Some(ref snapshot) => snapshot.raw_iterator_cf(cf),
+            None => self.0.db.raw_iterator_cf(cf),
+        };
+        // SAFETY: as in `iter_scan_from`. This is private, and both callers move the iterator
+        // into a `ReverseScanIterator` together with a clone of `self` in `_snapshot`, declared
+        // after `iter`, so the snapshot and its DB outlive the iterator.
+        unsafe {
+            std::mem::transmute::<rocksdb::DBRawIterator<'_>, rocksdb::DBRawIterator<'static>>(iter)
         }
     }
 }
 
+impl DBRead for DBSnapshot {
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        DBSnapshot::get(self, key)
+    }
+}
+
+impl fmt::Debug for DB {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DB({:?}, {})", self.db.path(), self.layout.name)
+    }
+}
+
+impl fmt::Debug for DBSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DBSnapshot({:?}, {})",
+            self.0.db.path(),
+            self.0.layout.name
+        )
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-#[repr(u8)]
-pub enum OpenMode {
+pub enum OpenMode<'a> {
     ReadOnly,
     ReadWrite,
+    /// Follows the DB of another process, keeping its own info logs in the given directory
+    Secondary(&'a Path),
 }
 
+/// Opens the index RocksDB at `path`, with the block caches and write buffers of its families
+/// sized to a total of `memory_mb`, see `DEFAULT_DB_MEMORY_MB`.
 pub fn open_raw_db<T: rocksdb::ThreadMode>(
     path: &Path,
     read_mode: OpenMode,
+    memory_mb: usize,
 ) -> rocksdb::DBWithThreadMode<T> {
     debug!("opening DB at {:?}", path);
     let mut db_opts = rocksdb::Options::default();
     db_opts.create_if_missing(true);
+    db_opts.create_missing_column_families(true);
     db_opts.set_max_open_files(100_000); // TODO: make sure to `ulimit -n` this process correctly
-    db_opts.set_compaction_style(rocksdb::DBCompactionStyle::Level);
-    db_opts.set_compression_type(rocksdb::DBCompressionType::None);
-    db_opts.set_target_file_size_base(1_073_741_824);
-    db_opts.set_write_buffer_size(256 << 20);
-    db_opts.set_disable_auto_compactions(true); // for initial bulk load
+
+    // the rows of a block are spread over several families. they must be flushed together,
+    // so that when writing without WAL the done markers never get ahead of the rows
+    db_opts.set_atomic_flush(true);
 
     // db_opts.set_advise_random_on_open(???);
     db_opts.set_compaction_readahead_size(1 << 20);
     db_opts.increase_parallelism(2);
 
-    // let mut block_opts = rocksdb::BlockBasedOptions::default();
-    // block_opts.set_block_size(???);
+    let families = FAMILIES
+        .iter()
+        .map(|family| rocksdb::ColumnFamilyDescriptor::new(family.name, family.options(memory_mb)));
 
     match read_mode {
-        OpenMode::ReadOnly => {
-            rocksdb::DBWithThreadMode::<T>::open_for_read_only(&db_opts, path, false)
-                .expect("failed to open RocksDB (READ ONLY)")
-        }
+        OpenMode::ReadOnly => rocksdb::DBWithThreadMode::<T>::open_cf_descriptors_read_only(
+            &db_opts, path, families, false,
+        )
+        .expect("failed to open RocksDB (READ ONLY)"),
         OpenMode::ReadWrite => {
-            rocksdb::DBWithThreadMode::<T>::open(&db_opts, path).expect("failed to open RocksDB")
+            rocksdb::DBWithThreadMode::<T>::open_cf_descriptors(&db_opts, path, families)
+                .expect("failed to open RocksDB")
+        }
+        OpenMode::Secondary(secondary_path) => {
+            // secondaries keep all the files of the primary open, so that they aren't deleted
+            // from under them by its compactions
+            db_opts.set_max_open_files(-1);
+            fs::create_dir_all(secondary_path).expect("failed to create the secondary DB dir");
+            rocksdb::DBWithThreadMode::<T>::open_cf_descriptors_as_secondary(
+                &db_opts,
+                path,
+                secondary_path,
+                families,
+            )
+            .expect("failed to open RocksDB (SECONDARY)")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_memory() {
+        let memory_mb: usize = FAMILIES
+            .iter()
+            .map(|family| family.block_cache_mb + family.write_buffer_mb)
+            .sum();
+        assert_eq!(memory_mb, DEFAULT_DB_MEMORY_MB);
+    }
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::new(
+            &Arc::new(open_raw_db(
+                dir.path(),
+                OpenMode::ReadWrite,
+                DEFAULT_DB_MEMORY_MB,
+            )),
+            &TXSTORE,
+        );
+        db.put(b"a1", b"old");
+        let snapshot = db.snapshot();
+        db.put(b"a1", b"new");
+        db.put(b"a2", b"new");
+
+        assert_eq!(snapshot.get(b"a1"), Some(b"old".to_vec()));
+        assert_eq!(snapshot.get(b"a2"), None);
+        assert_eq!(snapshot.iter_scan(b"a").count(), 1);
+        assert_eq!(snapshot.iter_scan_reverse(b"a", b"b").count(), 1);
+
+        // iterators keep the snapshot alive on their own
+        let iter = snapshot.iter_scan(b"a");
+        drop(snapshot);
+        assert_eq!(iter.map(|row| row.value).collect::<Vec<_>>(), vec![b"old"]);
+
+        assert_eq!(db.get(b"a1"), Some(b"new".to_vec()));
+        assert_eq!(db.iter_scan(b"a").count(), 2);
+    }
+
+    fn rewrite_to_new(row: &DBRow) -> Option<Vec<DBRow>> {
+        if row.value == b"new" {
+            return None;
         }
+        Some(vec![DBRow {
+            key: row.key.clone(),
+            value: b"new".to_vec(),
+        }])
+    }
+
+    #[test]
+    fn test_migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::new(
+            &Arc::new(open_raw_db(
+                dir.path(),
+                OpenMode::ReadWrite,
+                DEFAULT_DB_MEMORY_MB,
+            )),
+            &TXSTORE,
+        );
+        let mut compatibility_bytes = bincode_util::serialize_little(&2u32).unwrap();
+        compatibility_bytes.push(1); // light mode
+        db.put(b"V", &compatibility_bytes);
+        for key in [&b"a1"[..], b"a2", b"a3", b"b1"] {
+            db.put(key, b"old");
+        }
+        // resume an interrupted migration, that already went past a1
+        let cursor = [&bincode_util::serialize_little(&3u32).unwrap()[..], b"a1\0"].concat();
+        db.put(MIGRATION_CURSOR_KEY, &cursor);
+
+        let migrations = [Migration {
+            version: 3,
+            description: "test",
+            prefix: b"a",
+            rewrite: rewrite_to_new,
+        }];
+        let rows_migrated = Gauge::new("rows_migrated", "test").unwrap();
+        db.migrate(&migrations, &rows_migrated);
+
+        assert_eq!(db.version(), DB_VERSION);
+        assert_eq!(db.get(b"V").unwrap()[4..], [1]);
+        assert_eq!(db.get(MIGRATION_CURSOR_KEY), None);
+        assert_eq!(rows_migrated.get(), 2);
+        assert_eq!(db.get(b"a1"), Some(b"old".to_vec()));
+        assert_eq!(db.get(b"a2"), Some(b"new".to_vec()));
+        assert_eq!(db.get(b"a3"), Some(b"new".to_vec()));
+        assert_eq!(db.get(b"b1"), Some(b"old".to_vec()));
+    }
+
+    #[test]
+    fn test_import_legacy() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_path = dir.path().join("txstore");
+        {
+            let mut opts = rocksdb::Options::default();
+            opts.create_if_missing(true);
+            let legacy = rocksdb::DB::open(&opts, &legacy_path).unwrap();
+            for key in [&b"T1"[..], b"O1", b"B1", b"V", b"F"] {
+                legacy.put(key, b"legacy").unwrap();
+            }
+        }
+        let raw = Arc::new(open_raw_db(
+            &dir.path().join("db"),
+            OpenMode::ReadWrite,
+            DEFAULT_DB_MEMORY_MB,
+        ));
+        let db = DB::new(&raw, &TXSTORE);
+        db.import_legacy(&legacy_path);
+
+        assert!(!legacy_path.exists());
+        assert_eq!(db.get(LEGACY_IMPORT_CURSOR_KEY), None);
+        assert_eq!(db.get(b"F"), None);
+        for (key, family) in [(&b"T1"[..], "txs"), (b"O1", "txouts"), (b"B1", "blocks")] {
+            assert_eq!(db.get(key), Some(b"legacy".to_vec()));
+            assert_eq!(
+                raw.get_cf(cf_handle(&raw, family), key).unwrap(),
+                Some(b"legacy".to_vec())
+            );
+        }
+        assert_eq!(
+            raw.get_cf(cf_handle(&raw, "txstore"), b"V").unwrap(),
+            Some(b"legacy".to_vec())
+        );
     }
 }
@@ -2,12 +2,14 @@ use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fs;
 use std::io::Cursor;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::thread;
 
 use crate::chain::{Block, BlockHash};
-use crate::daemon::Daemon;
+use crate::daemon::DaemonApi;
 use crate::errors::*;
+use crate::p2p::Peer;
 use crate::util::HeaderEntry;
 use crate::util::{spawn_thread, SyncChannel};
 
@@ -17,18 +19,19 @@ use bitcoin::consensus::encode::{deserialize, Decodable};
 pub enum FetchFrom {
     Bitcoind,
     BlkFiles,
+    P2P(SocketAddr),
 }
 
 pub fn start_fetcher(
     from: FetchFrom,
-    daemon: &Daemon,
+    daemon: &dyn DaemonApi,
     new_headers: Vec<HeaderEntry>,
 ) -> Result<Fetcher<Vec<BlockEntry>>> {
-    let fetcher = match from {
-        FetchFrom::Bitcoind => bitcoind_fetcher,
-        FetchFrom::BlkFiles => blkfiles_fetcher,
-    };
-    fetcher(daemon, new_headers)
+    match from {
+        FetchFrom::Bitcoind => bitcoind_fetcher(daemon, new_headers),
+        FetchFrom::BlkFiles => blkfiles_fetcher(daemon, new_headers),
+        FetchFrom::P2P(addr) => p2p_fetcher(addr, daemon, new_headers),
+    }
 }
 
 pub struct BlockEntry {
@@ -61,7 +64,7 @@ impl<T> SequentialFetcher<T> {
 }
 
 pub fn bitcoind_sequential_fetcher(
-    daemon: &Daemon,
+    daemon: &dyn DaemonApi,
     new_headers: Vec<HeaderEntry>,
 ) -> Result<SequentialFetcher<BlockEntry>> {
     let daemon = daemon.reconnect()?;
@@ -112,7 +115,7 @@ impl<T> Fetcher<T> {
 }
 
 fn bitcoind_fetcher(
-    daemon: &Daemon,
+    daemon: &dyn DaemonApi,
     new_headers: Vec<HeaderEntry>,
 ) -> Result<Fetcher<Vec<BlockEntry>>> {
     if let Some(tip) = new_headers.last() {
@@ -148,8 +151,64 @@ fn bitcoind_fetcher(
     ))
 }
 
+// Falls back to JSONRPC if the connection to the p2p peer or the download from it fails
+fn p2p_fetcher(
+    addr: SocketAddr,
+    daemon: &dyn DaemonApi,
+    new_headers: Vec<HeaderEntry>,
+) -> Result<Fetcher<Vec<BlockEntry>>> {
+    if let Some(tip) = new_headers.last() {
+        debug!("{:?} ({} left to index)", tip, new_headers.len());
+    };
+    let mut peer = Peer::connect(addr, daemon.magic())
+        .map_err(|e| warn!("p2p connection failed, using JSONRPC instead: {}", e))
+        .ok();
+    let daemon = daemon.reconnect()?;
+    let chan = SyncChannel::new(1);
+    let sender = chan.sender();
+    Ok(Fetcher::from(
+        chan.into_receiver(),
+        spawn_thread("p2p_fetcher", move || {
+            for entries in new_headers.chunks(10) {
+                let blockhashes: Vec<BlockHash> = entries.iter().map(|e| *e.hash()).collect();
+                let blocks = match peer.as_mut().map(|peer| peer.get_blocks(&blockhashes)) {
+                    Some(Ok(blocks)) => blocks,
+                    result => {
+                        if let Some(Err(e)) = result {
+                            warn!("p2p block download failed, using JSONRPC instead: {}", e);
+                            peer = None;
+                        }
+                        daemon
+                            .getblocks(&blockhashes)
+                            .expect("failed to get blocks from bitcoind")
+                            .into_iter()
+                            .map(|block| {
+                                let size = block.size() as u32;
+                                (block, size)
+                            })
+                            .collect()
+                    }
+                };
+                assert_eq!(blocks.len(), entries.len());
+                let block_entries: Vec<BlockEntry> = blocks
+                    .into_iter()
+                    .zip(entries)
+                    .map(|((block, size), entry)| BlockEntry {
+                        entry: entry.clone(),
+                        size,
+                        block,
+                    })
+                    .collect();
+                sender
+                    .send(block_entries)
+                    .expect("failed to send fetched blocks");
+            }
+        }),
+    ))
+}
+
 fn blkfiles_fetcher(
-    daemon: &Daemon,
+    daemon: &dyn DaemonApi,
     new_headers: Vec<HeaderEntry>,
 ) -> Result<Fetcher<Vec<BlockEntry>>> {
     let magic = daemon.magic();
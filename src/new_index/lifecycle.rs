@@ -0,0 +1,151 @@
+use bounded_vec_deque::BoundedVecDeque;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::chain::{BlockHash, Txid};
+
+// Number of removed transactions to remember the removal reason of
+const MAX_REMOVED_TXS: usize = 100_000;
+
+// Transactions are expired from bitcoind's mempool after two weeks by default (-mempoolexpiry)
+const MEMPOOL_EXPIRY: u64 = 14 * 24 * 60 * 60;
+
+/// Why a transaction left the mempool
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum RemovalReason {
+    Confirmed {
+        block_hash: BlockHash,
+        block_height: usize,
+    },
+    Replaced {
+        replaced_by: Txid,
+    },
+    /// Removed without being confirmed or replaced after the mempool expiry time
+    Expired,
+    /// Removed without being confirmed or replaced, e.g. for mempool size limits
+    Evicted,
+}
+
+impl RemovalReason {
+    /// The reason for a transaction that was neither confirmed nor replaced
+    pub fn dropped(first_seen: u64, time: u64) -> Self {
+        if time.saturating_sub(first_seen) >= MEMPOOL_EXPIRY {
+            RemovalReason::Expired
+        } else {
+            RemovalReason::Evicted
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct TxRemoval {
+    pub time: u64,
+    #[serde(flatten)]
+    pub reason: RemovalReason,
+}
+
+/// The lifecycle of a transaction seen in the mempool
+#[derive(Serialize)]
+pub struct TxLifecycle {
+    pub txid: Txid,
+    pub first_seen: u64,
+    pub in_mempool: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<TxRemoval>,
+}
+
+/// Remembers when and why transactions were removed from the mempool
+pub(super) struct LifecycleTracker {
+    // removed txid -> (first seen time, removal)
+    removed: HashMap<Txid, (u64, TxRemoval)>,
+    // removed txids, most recent first
+    order: BoundedVecDeque<Txid>,
+}
+
+impl LifecycleTracker {
+    pub(super) fn new() -> Self {
+        LifecycleTracker {
+            removed: HashMap::new(),
+            order: BoundedVecDeque::new(MAX_REMOVED_TXS),
+        }
+    }
+
+    pub fn removed(&mut self, txid: Txid, first_seen: u64, removal: TxRemoval) {
+        if self.removed.insert(txid, (first_seen, removal)).is_some() {
+            return;
+        }
+        if let Some(evicted) = self.order.push_front(txid) {
+            self.removed.remove(&evicted);
+        }
+    }
+
+    /// Reclassify a removed transaction found to be replaced by a later one
+    pub fn replaced(&mut self, txid: &Txid, replaced_by: Txid) {
+        if let Some((_, removal)) = self.removed.get_mut(txid) {
+            if !matches!(removal.reason, RemovalReason::Confirmed { .. }) {
+                removal.reason = RemovalReason::Replaced { replaced_by };
+            }
+        }
+    }
+
+    /// Forget the removal of a transaction that re-entered the mempool
+    pub fn readded(&mut self, txid: &Txid) {
+        if self.removed.remove(txid).is_some() {
+            if let Some(pos) = self.order.iter().position(|removed| removed == txid) {
+                self.order.remove(pos);
+            }
+        }
+    }
+
+    pub fn get(&self, txid: &Txid) -> Option<TxLifecycle> {
+        self.removed
+            .get(txid)
+            .map(|(first_seen, removal)| TxLifecycle {
+                txid: *txid,
+                first_seen: *first_seen,
+                in_mempool: false,
+                removed: Some(*removal),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LifecycleTracker, RemovalReason, TxRemoval, MEMPOOL_EXPIRY};
+    use crate::chain::Txid;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_lifecycle() {
+        let txid = |n: u8| Txid::from_slice(&[n; 32]).unwrap();
+        let mut tracker = LifecycleTracker::new();
+        for n in 1..=2 {
+            let removal = TxRemoval {
+                time: MEMPOOL_EXPIRY + 1000,
+                reason: RemovalReason::dropped(1000 * n as u64, MEMPOOL_EXPIRY + 1000),
+            };
+            tracker.removed(txid(n), 1000 * n as u64, removal);
+        }
+        assert_eq!(
+            tracker.get(&txid(1)).unwrap().removed.unwrap().reason,
+            RemovalReason::Expired
+        );
+        assert_eq!(
+            tracker.get(&txid(2)).unwrap().removed.unwrap().reason,
+            RemovalReason::Evicted
+        );
+
+        tracker.replaced(&txid(2), txid(3));
+        assert_eq!(
+            tracker.get(&txid(2)).unwrap().removed.unwrap().reason,
+            RemovalReason::Replaced {
+                replaced_by: txid(3)
+            }
+        );
+
+        tracker.readded(&txid(1));
+        assert!(tracker.get(&txid(1)).is_none());
+        assert!(tracker.get(&txid(3)).is_none());
+    }
+}
@@ -1,11 +1,14 @@
 use crate::metrics::MetricOpts;
 use bounded_vec_deque::BoundedVecDeque;
+use error_chain::ChainedError;
 use itertools::Itertools;
 use prometheus::{HistogramOpts, HistogramVec};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, Bound::Excluded, Bound::Unbounded, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[cfg(feature = "opcat_layer")]
 use crate::opcat_layer::consensus::encode::{deserialize, serialize};
@@ -14,34 +17,88 @@ use bitcoin::consensus::encode::{deserialize, serialize};
 
 use crate::chain::{Network, OutPoint, Transaction, TxOut, Txid};
 use crate::config::Config;
-use crate::daemon::Daemon;
+use crate::daemon::DaemonApi;
 use crate::errors::*;
 use crate::metrics::Metrics;
+use crate::new_index::lifecycle::{LifecycleTracker, RemovalReason, TxLifecycle, TxRemoval};
+use crate::new_index::package::{TxLinks, TxPackage};
+use crate::new_index::projection::{project_blocks, MempoolBlock};
+use crate::new_index::rbf::{ReplacementTracker, ReplacementTree, ReplacementTx};
 use crate::new_index::{
     compute_script_hash, ChainQuery, FundingInfo, ScriptStats, SpendingInfo, SpendingInput,
     TxHistoryInfo, Utxo,
 };
 use crate::util::fees::{make_fee_histogram, TxFeeInfo};
-use crate::util::{extract_tx_prevouts, full_hash, has_prevout, is_spendable, Bytes};
+use crate::util::{
+    bincode_util, extract_tx_prevouts, full_hash, has_prevout, is_spendable, BlockId, Bytes,
+};
+
+// Number of projected blocks to build from the mempool
+const MEMPOOL_BLOCKS: usize = 8;
+
+// Bumped whenever the snapshot format changes, older snapshots are ignored
+const SNAPSHOT_VERSION: u32 = 2;
 
 // A simplified transaction view used for the list of most recent transactions
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct TxOverview {
     txid: Txid,
     fee: u64,
     vsize: u32,
     #[cfg(not(feature = "opcat_layer"))]
     value: u64,
+    first_seen: u64,
+    // filled in when listing, as it changes with the transaction's descendants
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effective_fee_rate: Option<f64>,
+}
+
+/// A mempool change announced by the daemon, with the daemon's mempool sequence number
+/// following it
+pub enum MempoolChange {
+    Added(Txid, u64),
+    Removed(Txid, u64),
+}
+
+// The mempool contents persisted across restarts
+#[derive(Serialize, Deserialize)]
+struct MempoolSnapshot {
+    version: u32,
+    // raw transactions, with their first seen time
+    txs: Vec<(Bytes, u64)>,
+    // the recent transactions, most recent first
+    recent: Vec<RecentTx>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecentTx {
+    txid: Txid,
+    fee: u64,
+    vsize: u32,
+    #[cfg(not(feature = "opcat_layer"))]
+    value: u64,
+    first_seen: u64,
 }
 
 pub struct Mempool {
     chain: Arc<ChainQuery>,
     txstore: BTreeMap<Txid, Transaction>,
     feeinfo: HashMap<Txid, TxFeeInfo>,
+    first_seen: HashMap<Txid, u64>,
     history: HashMap<[u8; 32], Vec<TxHistoryInfo>>,
     edges: HashMap<OutPoint, (Txid, u32)>,
     recent: BoundedVecDeque<TxOverview>,
     backlog_stats: (BacklogStats, Instant),
+    sequence: u64, // bumped whenever transactions are added or removed
+    replacements: ReplacementTracker,
+    removals: LifecycleTracker,
+    links: TxLinks,
+    // the projected blocks and the mempool sequence they were built at
+    projected_blocks: (Vec<MempoolBlock>, u64),
+    // the daemon's mempool sequence number the mempool is in sync with, if known
+    daemon_sequence: Option<u64>,
+    // cleared if the daemon doesn't report its mempool sequence number (before bitcoind 0.21)
+    daemon_sequence_supported: bool,
 
     // Metrics
     latency: HistogramVec,
@@ -57,6 +114,7 @@ impl Mempool {
             chain,
             txstore: BTreeMap::new(),
             feeinfo: HashMap::new(),
+            first_seen: HashMap::new(),
             history: HashMap::new(),
             edges: HashMap::new(),
             recent: BoundedVecDeque::new(config.mempool_recent_txs_size),
@@ -64,6 +122,13 @@ impl Mempool {
                 BacklogStats::default(),
                 Instant::now() - Duration::from_secs(config.mempool_backlog_stats_ttl),
             ),
+            sequence: 0,
+            replacements: ReplacementTracker::new(),
+            removals: LifecycleTracker::new(),
+            links: TxLinks::new(),
+            projected_blocks: (vec![], 0),
+            daemon_sequence: None,
+            daemon_sequence_supported: true,
             latency: metrics.histogram_vec(
                 HistogramOpts::new("mempool_latency", "Mempool requests latency (in seconds)"),
                 &["part"],
@@ -85,6 +150,11 @@ impl Mempool {
         self.config.network_type
     }
 
+    /// A counter that changes every time the mempool contents change
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
     pub fn lookup_txn(&self, txid: &Txid) -> Option<Transaction> {
         self.txstore.get(txid).cloned()
     }
@@ -105,10 +175,57 @@ impl Mempool {
         self.edges.contains_key(outpoint)
     }
 
+    /// When the transaction was first seen, if it is in the mempool
+    pub fn first_seen(&self, txid: &Txid) -> Option<u64> {
+        self.first_seen.get(txid).copied()
+    }
+
+    /// When the transaction was first seen, and when and why it left the mempool
+    pub fn lifecycle(&self, txid: &Txid) -> Option<TxLifecycle> {
+        match self.first_seen.get(txid) {
+            Some(first_seen) => Some(TxLifecycle {
+                txid: *txid,
+                first_seen: *first_seen,
+                in_mempool: true,
+                removed: None,
+            }),
+            None => self.removals.get(txid),
+        }
+    }
+
+    /// The transaction that replaced the given one, if it was replaced
+    pub fn replaced_by(&self, txid: &Txid) -> Option<Txid> {
+        self.replacements.replaced_by(txid)
+    }
+
+    /// The transactions directly replaced by the given one
+    pub fn replaces(&self, txid: &Txid) -> Vec<Txid> {
+        self.replacements.replaces(txid)
+    }
+
+    /// The replacement tree the transaction belongs to, if it replaced or was replaced
+    pub fn replacement_tree(&self, txid: &Txid) -> Option<ReplacementTree> {
+        self.replacements.tree(txid)
+    }
+
+    pub fn recent_replacements(&self) -> Vec<ReplacementTree> {
+        self.replacements.recent_trees()
+    }
+
     pub fn get_tx_fee(&self, txid: &Txid) -> Option<u64> {
         Some(self.feeinfo.get(txid)?.fee)
     }
 
+    /// The transaction with its in-mempool ancestors and descendants (CPFP package)
+    pub fn package(&self, txid: &Txid) -> Option<TxPackage> {
+        self.links.package(txid, &self.feeinfo)
+    }
+
+    /// The fee rate the transaction is expected to be mined at, accounting for CPFP
+    pub fn effective_fee_rate(&self, txid: &Txid) -> Option<f64> {
+        self.links.effective_fee_rate(txid, &self.feeinfo)
+    }
+
     pub fn has_unconfirmed_parents(&self, txid: &Txid) -> bool {
         let tx = match self.txstore.get(txid) {
             Some(tx) => tx,
@@ -361,81 +478,242 @@ impl Mempool {
     }
 
     // Get an overview of the most recent transactions
-    pub fn recent_txs_overview(&self) -> Vec<&TxOverview> {
+    pub fn recent_txs_overview(&self) -> Vec<TxOverview> {
         // We don't bother ever deleting elements from the recent list.
         // It may contain outdated txs that are no longer in the mempool,
         // until they get pushed out by newer transactions.
-        self.recent.iter().collect()
+        self.recent
+            .iter()
+            .map(|overview| TxOverview {
+                effective_fee_rate: self.effective_fee_rate(&overview.txid),
+                ..overview.clone()
+            })
+            .collect()
     }
 
     pub fn backlog_stats(&self) -> &BacklogStats {
         &self.backlog_stats.0
     }
 
+    /// The next blocks expected to be mined from the mempool, as of the last update
+    pub fn projected_blocks(&self) -> &[MempoolBlock] {
+        &self.projected_blocks.0
+    }
+
     pub fn unique_txids(&self) -> HashSet<Txid> {
         self.txstore.keys().cloned().collect()
     }
 
-    pub fn update(mempool: &RwLock<Mempool>, daemon: &Daemon) -> Result<()> {
-        // 1. Start the metrics timer and get the current mempool txids
+    /// Sync with the daemon's mempool, by diffing its txids against ours.
+    ///
+    /// `daemon_sequence` is the daemon's current mempool sequence, when it is known without
+    /// asking the daemon (e.g. from its ZMQ notifications). bitcoind only returns it along with
+    /// the whole txid list, which isn't fetched at all when it matches the synced sequence.
+    pub fn update(
+        mempool: &RwLock<Mempool>,
+        daemon: &dyn DaemonApi,
+        daemon_sequence: Option<u64>,
+    ) -> Result<()> {
+        // 1. Start the metrics timer and get the daemon's mempool sequence we're in sync with
         // [LOCK] Takes read lock for whole scope.
-        let (_timer, old_txids) = {
+        let (_timer, synced_sequence, sequence_supported) = {
             let mempool = mempool.read().unwrap();
             (
                 mempool.latency.with_label_values(&["update"]).start_timer(),
-                mempool.unique_txids(),
+                mempool.daemon_sequence,
+                mempool.daemon_sequence_supported,
             )
         };
+        if daemon_sequence.is_some() && daemon_sequence == synced_sequence {
+            return Ok(());
+        }
+
+        // 2. Get all the mempool txids from the RPC, along with the daemon's mempool sequence
+        // number when supported.
+        // [LOCK] No lock taken. Wait for RPC request.
+        let all_txids = if sequence_supported {
+            daemon
+                .getmempooltxids_sequence()
+                .map(|(txids, sequence)| (txids, Some(sequence)))
+                .or_else(|e| {
+                    warn!(
+                        "daemon mempool sequence unavailable, diffing full mempools: {}",
+                        e.display_chain()
+                    );
+                    mempool.write().unwrap().daemon_sequence_supported = false;
+                    daemon.getmempooltxids().map(|txids| (txids, None))
+                })
+        } else {
+            daemon.getmempooltxids().map(|txids| (txids, None))
+        };
+        let (all_txids, sequence) =
+            all_txids.chain_err(|| "failed to update mempool from daemon")?;
+
+        // The daemon's mempool didn't change since we last synced with it
+        if sequence.is_some() && sequence == synced_sequence {
+            return Ok(());
+        }
 
-        // 2. Get all the mempool txids from the RPC.
-        // [LOCK] No lock taken. Wait for RPC request. Get lists of remove/add txes.
-        let all_txids = daemon
-            .getmempooltxids()
-            .chain_err(|| "failed to update mempool from daemon")?;
+        // 3. Get lists of remove/add txes.
+        // [LOCK] Takes read lock for whole scope.
+        let (old_txids, chain) = {
+            let mempool = mempool.read().unwrap();
+            (mempool.unique_txids(), Arc::clone(&mempool.chain))
+        };
         let txids_to_remove: HashSet<&Txid> = old_txids.difference(&all_txids).collect();
         let txids_to_add: Vec<&Txid> = all_txids.difference(&old_txids).collect();
 
-        // 3. Remove missing transactions. Even if we are unable to download new transactions from
+        // Look up the blocks that confirmed the removed transactions, to classify the removals.
+        // [LOCK] No lock taken, the lookups read the DB.
+        let confirmed = confirming_blocks(&chain, txids_to_remove.iter().copied());
+
+        // 4. Remove missing transactions. Even if we are unable to download new transactions from
         // the daemon, we still want to remove the transactions that are no longer in the mempool.
-        // [LOCK] Write lock is released at the end of the call to remove().
-        mempool.write().unwrap().remove(txids_to_remove);
+        // [LOCK] Write lock is released at the end of the scope.
+        {
+            let mut mempool = mempool.write().unwrap();
+            mempool.daemon_sequence = None;
+            mempool.remove(txids_to_remove, &confirmed);
+        }
 
-        // 4. Download the new transactions from the daemon's mempool
+        // 5. Download the new transactions from the daemon's mempool
         // [LOCK] No lock taken, waiting for RPC response.
         let txs_to_add = daemon
             .gettransactions(&txids_to_add)
             .chain_err(|| format!("failed to get {} transactions", txids_to_add.len()))?;
 
-        // 4. Update local mempool to match daemon's state
+        // 6. Update local mempool to match daemon's state
         // [LOCK] Takes Write lock for whole scope.
         {
             let mut mempool = mempool.write().unwrap();
             // Add new transactions
             if txs_to_add.len() > mempool.add(txs_to_add) {
                 debug!("Mempool update added less transactions than expected");
+            } else {
+                mempool.daemon_sequence = sequence;
             }
+            mempool.update_stats();
 
-            mempool
-                .count
-                .with_label_values(&["txs"])
-                .set(mempool.txstore.len() as f64);
+            Ok(())
+        }
+    }
 
-            // Update cached backlog stats (if expired)
-            if mempool.backlog_stats.1.elapsed()
-                > Duration::from_secs(mempool.config.mempool_backlog_stats_ttl)
-            {
-                let _timer = mempool
-                    .latency
-                    .with_label_values(&["update_backlog_stats"])
-                    .start_timer();
-                mempool.backlog_stats = (BacklogStats::new(&mempool.feeinfo), Instant::now());
+    /// Apply mempool changes announced by the daemon (e.g. over ZMQ), without listing its
+    /// whole mempool.
+    ///
+    /// Changes are applied in order. Added transactions are fetched from the daemon, unless
+    /// they are found in `known_txs`. Once in sync with the daemon's mempool sequence, changes
+    /// it already covers are skipped and a gap in the sequence fails, requiring an `update()`.
+    pub fn apply_changes(
+        mempool: &RwLock<Mempool>,
+        daemon: &dyn DaemonApi,
+        changes: Vec<MempoolChange>,
+        mut known_txs: HashMap<Txid, Transaction>,
+    ) -> Result<()> {
+        // 1. Reduce the changes to the last one of each transaction
+        // [LOCK] Takes read lock to skip the changes already applied.
+        let (_timer, to_add, to_remove, sequence, chain) = {
+            let mempool = mempool.read().unwrap();
+            let mut sequence = mempool.daemon_sequence;
+            let mut last_changes = HashMap::new();
+            for change in changes {
+                let (txid, added, change_sequence) = match change {
+                    MempoolChange::Added(txid, sequence) => (txid, true, sequence),
+                    MempoolChange::Removed(txid, sequence) => (txid, false, sequence),
+                };
+                if let Some(ref mut sequence) = sequence {
+                    if change_sequence <= *sequence {
+                        continue;
+                    }
+                    ensure!(
+                        change_sequence == *sequence + 1,
+                        "mempool sequence gap, expected {} but got {}",
+                        *sequence + 1,
+                        change_sequence
+                    );
+                    *sequence = change_sequence;
+                }
+                last_changes.insert(txid, added);
             }
+            let (to_add, to_remove): (Vec<_>, Vec<_>) = last_changes
+                .into_iter()
+                .filter(|(txid, added)| mempool.txstore.contains_key(txid) != *added)
+                .partition(|(_, added)| *added);
+            (
+                mempool
+                    .latency
+                    .with_label_values(&["apply_changes"])
+                    .start_timer(),
+                to_add.into_iter().map(|(txid, _)| txid).collect::<Vec<_>>(),
+                to_remove
+                    .into_iter()
+                    .map(|(txid, _)| txid)
+                    .collect::<Vec<_>>(),
+                sequence,
+                Arc::clone(&mempool.chain),
+            )
+        };
 
-            Ok(())
+        // 2. Download the added transactions that weren't announced with their body
+        // [LOCK] No lock taken, waiting for RPC response.
+        let missing: Vec<&Txid> = to_add
+            .iter()
+            .filter(|txid| !known_txs.contains_key(*txid))
+            .collect();
+        let mut txs_to_add = daemon
+            .gettransactions(&missing)
+            .chain_err(|| format!("failed to get {} transactions", missing.len()))?;
+        txs_to_add.extend(to_add.iter().filter_map(|txid| known_txs.remove(txid)));
+        // [LOCK] No lock taken, the lookups read the DB.
+        let confirmed = confirming_blocks(&chain, to_remove.iter());
+
+        // 3. Apply the changes
+        // [LOCK] Takes Write lock for whole scope.
+        let mut mempool = mempool.write().unwrap();
+        let to_remove: HashSet<&Txid> = to_remove
+            .iter()
+            .filter(|txid| mempool.txstore.contains_key(*txid))
+            .collect();
+        mempool.remove(to_remove, &confirmed);
+        if txs_to_add.len() > mempool.add(txs_to_add) {
+            debug!("Mempool changes added less transactions than expected");
+            mempool.daemon_sequence = None;
+        } else {
+            mempool.daemon_sequence = sequence;
         }
+        mempool.update_stats();
+        Ok(())
     }
 
-    pub fn add_by_txid(&mut self, daemon: &Daemon, txid: &Txid) -> Result<()> {
+    // Update the transaction count gauge, the backlog stats and the projected blocks
+    fn update_stats(&mut self) {
+        self.count
+            .with_label_values(&["txs"])
+            .set(self.txstore.len() as f64);
+
+        // Update cached backlog stats (if expired)
+        if self.backlog_stats.1.elapsed()
+            > Duration::from_secs(self.config.mempool_backlog_stats_ttl)
+        {
+            let _timer = self
+                .latency
+                .with_label_values(&["update_backlog_stats"])
+                .start_timer();
+            self.backlog_stats = (BacklogStats::new(&self.feeinfo), Instant::now());
+        }
+
+        // Rebuild the projected blocks (if the mempool changed)
+        if self.projected_blocks.1 != self.sequence {
+            let _timer = self
+                .latency
+                .with_label_values(&["update_projected_blocks"])
+                .start_timer();
+            let blocks = project_blocks(&self.feeinfo, &self.links, MEMPOOL_BLOCKS);
+            self.projected_blocks = (blocks, self.sequence);
+        }
+    }
+
+    pub fn add_by_txid(&mut self, daemon: &dyn DaemonApi, txid: &Txid) -> Result<()> {
         if !self.txstore.contains_key(txid) {
             if let Ok(tx) = daemon.getmempooltx(txid) {
                 if self.add(vec![tx]) == 0 {
@@ -449,6 +727,97 @@ impl Mempool {
         Ok(())
     }
 
+    /// Persist the mempool transactions, their first seen time and the recent transactions.
+    ///
+    /// The snapshot is written to a temporary file first, so that a crash never leaves
+    /// a truncated snapshot behind.
+    pub fn save_snapshot(&self, path: &Path) -> Result<()> {
+        let _timer = self
+            .latency
+            .with_label_values(&["save_snapshot"])
+            .start_timer();
+        let snapshot = MempoolSnapshot {
+            version: SNAPSHOT_VERSION,
+            txs: self
+                .txstore
+                .iter()
+                .map(|(txid, tx)| {
+                    (
+                        serialize(tx),
+                        self.first_seen.get(txid).copied().unwrap_or(0),
+                    )
+                })
+                .collect(),
+            recent: self
+                .recent
+                .iter()
+                .map(|overview| RecentTx {
+                    txid: overview.txid,
+                    fee: overview.fee,
+                    vsize: overview.vsize,
+                    #[cfg(not(feature = "opcat_layer"))]
+                    value: overview.value,
+                    first_seen: overview.first_seen,
+                })
+                .collect(),
+        };
+        let data = bincode_util::serialize_little(&snapshot)
+            .chain_err(|| "failed to serialize mempool snapshot")?;
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .chain_err(|| format!("failed to write mempool snapshot to {:?}", path))?;
+        debug!(
+            "saved {} mempool transactions to {:?}",
+            snapshot.txs.len(),
+            path
+        );
+        Ok(())
+    }
+
+    /// Restore the mempool from a snapshot, returning the number of restored transactions.
+    ///
+    /// The restored transactions are re-indexed (their prevouts are looked up again). The next
+    /// `update()` reconciles them with the daemon's mempool, only fetching the missing ones.
+    pub fn load_snapshot(&mut self, path: &Path) -> Result<usize> {
+        let data = fs::read(path)
+            .chain_err(|| format!("failed to read mempool snapshot from {:?}", path))?;
+        let snapshot: MempoolSnapshot = bincode_util::deserialize_little(&data)
+            .chain_err(|| "failed to parse mempool snapshot")?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            bail!("unsupported mempool snapshot version {}", snapshot.version);
+        }
+
+        let txs = snapshot
+            .txs
+            .iter()
+            .map(|(raw, first_seen)| {
+                let tx: Transaction =
+                    deserialize(raw).chain_err(|| "invalid mempool snapshot transaction")?;
+                Ok((tx, *first_seen))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (tx, first_seen) in &txs {
+            self.first_seen.insert(tx.txid(), *first_seen);
+        }
+        let restored = self.add(txs.into_iter().map(|(tx, _)| tx).collect());
+
+        self.recent.clear();
+        for recent in snapshot.recent {
+            self.recent.push_back(TxOverview {
+                txid: recent.txid,
+                fee: recent.fee,
+                vsize: recent.vsize,
+                #[cfg(not(feature = "opcat_layer"))]
+                value: recent.value,
+                first_seen: recent.first_seen,
+                effective_fee_rate: None,
+            });
+        }
+        Ok(restored)
+    }
+
     /// Add transactions to the mempool.
     ///
     /// The return value is the number of transactions processed.
@@ -464,6 +833,7 @@ impl Mempool {
         }
         debug!("Adding {} transactions to Mempool", txlen);
 
+        let now = unix_time();
         let mut txids = Vec::with_capacity(txs.len());
         // Phase 1: add to txstore
         for tx in txs {
@@ -472,9 +842,15 @@ impl Mempool {
             // This is important now that update doesn't lock during
             // the entire function body.
             if self.txstore.insert(txid, tx).is_none() {
+                // restored transactions keep their first seen time from the snapshot
+                self.first_seen.entry(txid).or_insert(now);
+                self.removals.readded(&txid);
                 txids.push(txid);
             }
         }
+        if !txids.is_empty() {
+            self.sequence += 1;
+        }
 
         // Phase 2: index history and spend edges (some txos can be missing)
         let txos = self.lookup_txos(&self.get_prevouts(&txids));
@@ -490,6 +866,7 @@ impl Mempool {
         // 5. Create the Spend and Fund TxHistory structs for inputs + outputs
         // 6. Insert all TxHistory into history.
         // 7. Insert the tx edges into edges (HashMap of (Outpoint, (Txid, vin)))
+        //    and link the tx to its mempool parents
         // 8. (Liquid only) Parse assets of tx.
         for txid in txids {
             let tx = self.txstore.get(&txid).expect("missing tx from txstore");
@@ -513,8 +890,50 @@ impl Mempool {
                 vsize: feeinfo.vsize,
                 #[cfg(not(feature = "opcat_layer"))]
                 value: prevouts.values().map(|prevout| prevout.value).sum(),
+                first_seen: self.first_seen.get(&txid).copied().unwrap_or(now),
+                effective_fee_rate: None,
             });
 
+            // Detect replaced transactions, either still in the mempool or removed recently
+            let mut replaced: Vec<ReplacementTx> = vec![];
+            for txi in &tx.input {
+                let conflict = match self.edges.get(&txi.previous_output) {
+                    Some((spending_txid, _)) if *spending_txid != txid => self
+                        .txstore
+                        .get(spending_txid)
+                        .zip(self.feeinfo.get(spending_txid))
+                        .map(|(spending_tx, info)| {
+                            ReplacementTx::new(*spending_txid, spending_tx, info)
+                        }),
+                    Some(_) => None,
+                    // a removed transaction re-entering the mempool doesn't replace itself
+                    None => self
+                        .replacements
+                        .removed_spend(&txi.previous_output)
+                        .filter(|removed| removed.txid != txid),
+                };
+                if let Some(conflict) = conflict {
+                    if !replaced.iter().any(|r| r.txid == conflict.txid) {
+                        replaced.push(conflict);
+                    }
+                }
+            }
+            if !replaced.is_empty() {
+                debug!(
+                    "{} replaced {} mempool transaction(s)",
+                    txid,
+                    replaced.len()
+                );
+                self.delta
+                    .with_label_values(&["replaced"])
+                    .observe(replaced.len() as f64);
+                for replaced_tx in &replaced {
+                    self.removals.replaced(&replaced_tx.txid, txid);
+                }
+                self.replacements
+                    .add(ReplacementTx::new(txid, tx, &feeinfo), replaced);
+            }
+
             self.feeinfo.insert(txid, feeinfo);
 
             // An iterator over (ScriptHash, TxHistoryInfo)
@@ -558,6 +977,13 @@ impl Mempool {
             for (i, txi) in tx.input.iter().enumerate() {
                 self.edges.insert(txi.previous_output, (txid, i as u32));
             }
+            let txstore = &self.txstore;
+            let parents = tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output.txid)
+                .filter(|parent| txstore.contains_key(parent));
+            self.links.add(txid, parents);
 
             processed_count += 1;
         }
@@ -623,22 +1049,52 @@ impl Mempool {
             .collect()
     }
 
-    fn remove(&mut self, to_remove: HashSet<&Txid>) {
+    /// Removes the transactions, `confirmed` has the blocks confirming those that were mined,
+    /// see `confirming_blocks`.
+    fn remove(&mut self, to_remove: HashSet<&Txid>, confirmed: &HashMap<Txid, BlockId>) {
         self.delta
             .with_label_values(&["remove"])
             .observe(to_remove.len() as f64);
         let _timer = self.latency.with_label_values(&["remove"]).start_timer();
+        if !to_remove.is_empty() {
+            self.sequence += 1;
+        }
 
+        let time = unix_time();
+        let mut removed = Vec::with_capacity(to_remove.len());
         for txid in &to_remove {
-            self.txstore
+            let tx = self
+                .txstore
                 .remove(*txid)
                 .unwrap_or_else(|| panic!("missing mempool tx {}", txid));
 
-            self.feeinfo.remove(*txid).or_else(|| {
-                warn!("missing mempool tx feeinfo {}", txid);
-                None
-            });
+            match self.feeinfo.remove(*txid) {
+                // Keep the spends, to detect the transactions replacing this one
+                Some(feeinfo) => removed.push((
+                    ReplacementTx::new(**txid, &tx, &feeinfo),
+                    tx.input.iter().map(|txin| txin.previous_output).collect(),
+                )),
+                None => warn!("missing mempool tx feeinfo {}", txid),
+            }
+            self.links.remove(txid);
+
+            // Classify the removal, replacements by transactions not seen yet are
+            // reclassified when they get added
+            let first_seen = self.first_seen.remove(*txid).unwrap_or(time);
+            let reason = match confirmed.get(*txid) {
+                Some(block) => RemovalReason::Confirmed {
+                    block_hash: block.hash,
+                    block_height: block.height,
+                },
+                None => match self.replacements.replaced_by(txid) {
+                    Some(replaced_by) => RemovalReason::Replaced { replaced_by },
+                    None => RemovalReason::dropped(first_seen, time),
+                },
+            };
+            self.removals
+                .removed(**txid, first_seen, TxRemoval { time, reason });
         }
+        self.replacements.add_removed(removed, time);
 
         // TODO: make it more efficient (currently it takes O(|mempool|) time)
         self.history.retain(|_scripthash, entries| {
@@ -651,6 +1107,17 @@ impl Mempool {
     }
 }
 
+// The blocks confirming the transactions that were mined. Each lookup reads the DB, so this
+// runs before taking the mempool write lock, to not stall the readers after a block.
+fn confirming_blocks<'a>(
+    chain: &ChainQuery,
+    txids: impl Iterator<Item = &'a Txid>,
+) -> HashMap<Txid, BlockId> {
+    txids
+        .filter_map(|txid| Some((*txid, chain.tx_confirming_block(txid)?)))
+        .collect()
+}
+
 #[derive(Serialize)]
 pub struct BacklogStats {
     pub count: u32,
@@ -684,3 +1151,9 @@ impl BacklogStats {
         }
     }
 }
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -1,15 +1,26 @@
+mod compression;
 pub mod db;
 mod fetch;
+mod lifecycle;
 mod mempool;
+mod package;
 pub mod precache;
+mod projection;
 mod query;
+mod rbf;
 pub mod schema;
+mod sync;
 
 pub use self::db::{DBRow, DB};
 pub use self::fetch::{BlockEntry, FetchFrom};
-pub use self::mempool::Mempool;
+pub use self::lifecycle::{RemovalReason, TxLifecycle, TxRemoval};
+pub use self::mempool::{Mempool, MempoolChange};
+pub use self::package::TxPackage;
+pub use self::projection::MempoolBlock;
 pub use self::query::Query;
+pub use self::rbf::ReplacementTree;
 pub use self::schema::{
     compute_script_hash, parse_hash, ChainQuery, FundingInfo, Indexer, ScriptStats, SpendingInfo,
     SpendingInput, Store, TxHistoryInfo, TxHistoryKey, TxHistoryRow, Utxo,
 };
+pub use self::sync::{SyncPhase, SyncStatus};
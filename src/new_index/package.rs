@@ -0,0 +1,205 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::chain::Txid;
+use crate::util::fees::TxFeeInfo;
+
+/// A mempool transaction with its in-mempool ancestors and descendants
+#[derive(Serialize)]
+pub struct TxPackage {
+    pub txid: Txid,
+    pub fee: u64,
+    pub vsize: u32,
+    pub fee_rate: f64,
+    pub ancestors: Vec<Txid>,
+    pub descendants: Vec<Txid>,
+    /// Totals of the transaction and its ancestors
+    pub ancestor_fee: u64,
+    pub ancestor_vsize: u64,
+    pub ancestor_fee_rate: f64,
+    /// Totals of the transaction and its descendants
+    pub descendant_fee: u64,
+    pub descendant_vsize: u64,
+    pub descendant_fee_rate: f64,
+    pub effective_fee_rate: f64,
+}
+
+/// Parent/child links between mempool transactions spending each other's outputs
+pub(super) struct TxLinks {
+    parents: HashMap<Txid, HashSet<Txid>>,
+    children: HashMap<Txid, HashSet<Txid>>,
+}
+
+impl TxLinks {
+    pub(super) fn new() -> Self {
+        TxLinks {
+            parents: HashMap::new(),
+            children: HashMap::new(),
+        }
+    }
+
+    /// Link a transaction to the mempool transactions it spends from
+    pub fn add(&mut self, txid: Txid, parents: impl IntoIterator<Item = Txid>) {
+        for parent in parents {
+            self.parents.entry(txid).or_default().insert(parent);
+            self.children.entry(parent).or_default().insert(txid);
+        }
+    }
+
+    /// Unlink a transaction removed from the mempool
+    pub fn remove(&mut self, txid: &Txid) {
+        for parent in self.parents.remove(txid).unwrap_or_default() {
+            unlink(&mut self.children, &parent, txid);
+        }
+        for child in self.children.remove(txid).unwrap_or_default() {
+            unlink(&mut self.parents, &child, txid);
+        }
+    }
+
+    pub fn ancestors(&self, txid: &Txid) -> HashSet<Txid> {
+        walk(&self.parents, txid)
+    }
+
+    pub fn descendants(&self, txid: &Txid) -> HashSet<Txid> {
+        walk(&self.children, txid)
+    }
+
+    pub fn package(&self, txid: &Txid, feeinfo: &HashMap<Txid, TxFeeInfo>) -> Option<TxPackage> {
+        let info = feeinfo.get(txid)?;
+        let mut ancestors: Vec<Txid> = self.ancestors(txid).into_iter().collect();
+        let mut descendants: Vec<Txid> = self.descendants(txid).into_iter().collect();
+        ancestors.sort();
+        descendants.sort();
+
+        let (ancestor_fee, ancestor_vsize) = totals(ancestors.iter().chain(Some(txid)), feeinfo);
+        let (descendant_fee, descendant_vsize) =
+            totals(descendants.iter().chain(Some(txid)), feeinfo);
+
+        Some(TxPackage {
+            txid: *txid,
+            fee: info.fee,
+            vsize: info.vsize,
+            fee_rate: fee_rate(info.fee, info.vsize as u64),
+            ancestors,
+            descendants,
+            ancestor_fee,
+            ancestor_vsize,
+            ancestor_fee_rate: fee_rate(ancestor_fee, ancestor_vsize),
+            descendant_fee,
+            descendant_vsize,
+            descendant_fee_rate: fee_rate(descendant_fee, descendant_vsize),
+            effective_fee_rate: self.effective_fee_rate(txid, feeinfo)?,
+        })
+    }
+
+    /// The fee rate the transaction is expected to be mined at.
+    ///
+    /// This is the fee rate of the transaction with its ancestors, or the fee rate of
+    /// a descendant with its ancestors (including this transaction) when that is higher,
+    /// as a miner would include the transaction to get to the descendant (CPFP).
+    pub fn effective_fee_rate(
+        &self,
+        txid: &Txid,
+        feeinfo: &HashMap<Txid, TxFeeInfo>,
+    ) -> Option<f64> {
+        feeinfo.get(txid)?;
+        let ancestor_fee_rate = |txid: &Txid| {
+            let (fee, vsize) = totals(self.ancestors(txid).iter().chain(Some(txid)), feeinfo);
+            fee_rate(fee, vsize)
+        };
+        Some(
+            self.descendants(txid)
+                .iter()
+                .map(ancestor_fee_rate)
+                .fold(ancestor_fee_rate(txid), f64::max),
+        )
+    }
+}
+
+fn unlink(links: &mut HashMap<Txid, HashSet<Txid>>, from: &Txid, to: &Txid) {
+    if let Some(txids) = links.get_mut(from) {
+        txids.remove(to);
+        if txids.is_empty() {
+            links.remove(from);
+        }
+    }
+}
+
+// All the transactions reachable from txid, excluding itself
+fn walk(links: &HashMap<Txid, HashSet<Txid>>, txid: &Txid) -> HashSet<Txid> {
+    let mut found = HashSet::new();
+    let mut queue = vec![*txid];
+    while let Some(next) = queue.pop() {
+        for linked in links.get(&next).into_iter().flatten() {
+            if linked != txid && found.insert(*linked) {
+                queue.push(*linked);
+            }
+        }
+    }
+    found
+}
+
+// Total fee and vsize of the transactions, skipping those without fee info
+fn totals<'a>(
+    txids: impl Iterator<Item = &'a Txid>,
+    feeinfo: &HashMap<Txid, TxFeeInfo>,
+) -> (u64, u64) {
+    txids
+        .filter_map(|txid| feeinfo.get(txid))
+        .fold((0, 0), |(fee, vsize), info| {
+            (fee + info.fee, vsize + info.vsize as u64)
+        })
+}
+
+fn fee_rate(fee: u64, vsize: u64) -> f64 {
+    if vsize == 0 {
+        return 0.0;
+    }
+    fee as f64 / vsize as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TxLinks;
+    use crate::chain::Txid;
+    use crate::util::fees::TxFeeInfo;
+    use bitcoin::hashes::Hash;
+    use std::collections::HashMap;
+
+    fn txid(n: u8) -> Txid {
+        Txid::from_slice(&[n; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_tx_package() {
+        // 1 <- 2 <- 3, with a low fee parent bumped by its child
+        let mut feeinfo = HashMap::new();
+        for (n, fee) in [(1, 100), (2, 1000), (3, 400)] {
+            let info = TxFeeInfo {
+                fee,
+                vsize: 100,
+                fee_per_vbyte: fee as f32 / 100.0,
+            };
+            feeinfo.insert(txid(n), info);
+        }
+        let mut links = TxLinks::new();
+        links.add(txid(2), vec![txid(1)]);
+        links.add(txid(3), vec![txid(2)]);
+
+        let package = links.package(&txid(2), &feeinfo).unwrap();
+        assert_eq!(package.ancestors, vec![txid(1)]);
+        assert_eq!(package.descendants, vec![txid(3)]);
+        assert_eq!(package.ancestor_fee, 1100);
+        assert_eq!(package.ancestor_vsize, 200);
+        assert_eq!(package.descendant_fee_rate, 7.0);
+        assert_eq!(package.effective_fee_rate, 5.5);
+
+        assert_eq!(links.effective_fee_rate(&txid(1), &feeinfo), Some(5.5));
+        assert_eq!(links.effective_fee_rate(&txid(3), &feeinfo), Some(5.0));
+
+        links.remove(&txid(1));
+        assert!(links.ancestors(&txid(3)) == vec![txid(2)].into_iter().collect());
+        assert_eq!(links.effective_fee_rate(&txid(2), &feeinfo), Some(10.0));
+        assert!(links.package(&txid(4), &feeinfo).is_none());
+    }
+}
@@ -0,0 +1,239 @@
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::chain::Txid;
+use crate::new_index::package::TxLinks;
+use crate::util::fees::TxFeeInfo;
+
+// Block size limit, in the same virtual size unit as `TxFeeInfo::vsize`
+#[cfg(not(feature = "opcat_layer"))]
+const BLOCK_MAX_VSIZE: u64 = 1_000_000;
+// OPCAT layer transactions have no witness, so vsize == weight
+#[cfg(feature = "opcat_layer")]
+const BLOCK_MAX_VSIZE: u64 = crate::opcat_layer::blockdata::constants::MAX_BLOCK_WEIGHT as u64;
+
+// Fee rate percentiles reported in `MempoolBlock::fee_range`, besides the min and max
+const FEE_RANGE_PERCENTILES: [u64; 5] = [10, 25, 50, 75, 90];
+
+/// A block projected from the mempool contents, in mining order
+#[derive(Serialize, Clone)]
+pub struct MempoolBlock {
+    pub block_vsize: u64,
+    pub n_tx: usize,
+    pub total_fees: u64,
+    /// Vsize-weighted median of the effective fee rates
+    pub median_fee: f64,
+    /// Min, 10th, 25th, 50th, 75th and 90th percentiles and max effective fee rates
+    pub fee_range: Vec<f64>,
+}
+
+// A transaction selected into a projected block, with the fee rate of the package it came with
+struct Selected {
+    fee: u64,
+    vsize: u64,
+    fee_rate: f64,
+}
+
+// A transaction candidate, ordered by the fee rate of its not yet selected ancestor package
+#[derive(PartialEq, Eq)]
+struct Candidate {
+    fee: u64,
+    vsize: u64,
+    txid: Txid,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // compare fee rates without rounding: fee1 / vsize1 vs fee2 / vsize2
+        (self.fee as u128 * other.vsize as u128)
+            .cmp(&(other.fee as u128 * self.vsize as u128))
+            .then_with(|| other.txid.cmp(&self.txid))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Project the next `max_blocks` blocks from the mempool.
+///
+/// Transactions are selected by ancestor package fee rate, similar to bitcoind's block
+/// template assembly, so that low fee parents are mined along with their high fee children.
+/// The last block holds all the remaining transactions and may exceed the block size limit.
+pub(super) fn project_blocks(
+    feeinfo: &HashMap<Txid, TxFeeInfo>,
+    links: &TxLinks,
+    max_blocks: usize,
+) -> Vec<MempoolBlock> {
+    if max_blocks == 0 {
+        return vec![];
+    }
+    let mut selected = HashSet::new();
+    // the not yet selected ancestors of a transaction, including itself
+    let package = |txid: &Txid, selected: &HashSet<Txid>| -> Vec<Txid> {
+        links
+            .ancestors(txid)
+            .into_iter()
+            .chain(Some(*txid))
+            .filter(|txid| feeinfo.contains_key(txid) && !selected.contains(txid))
+            .collect()
+    };
+    let candidate = |txid: &Txid, package: &[Txid]| {
+        let (fee, vsize) = package.iter().fold((0, 0), |(fee, vsize), txid| {
+            let info = &feeinfo[txid];
+            (fee + info.fee, vsize + info.vsize as u64)
+        });
+        Candidate {
+            fee,
+            vsize,
+            txid: *txid,
+        }
+    };
+
+    // current package totals of the candidates, to skip outdated heap entries
+    let mut scores: HashMap<Txid, (u64, u64)> = HashMap::with_capacity(feeinfo.len());
+    let mut heap = BinaryHeap::with_capacity(feeinfo.len());
+    for txid in feeinfo.keys() {
+        let entry = candidate(txid, &package(txid, &selected));
+        scores.insert(*txid, (entry.fee, entry.vsize));
+        heap.push(entry);
+    }
+
+    let mut blocks = vec![];
+    let mut block: Vec<Selected> = vec![];
+    let mut block_vsize = 0;
+    while let Some(entry) = heap.pop() {
+        if scores.get(&entry.txid) != Some(&(entry.fee, entry.vsize)) {
+            continue;
+        }
+        let mut txids = package(&entry.txid, &selected);
+        let fee_rate = entry.fee as f64 / entry.vsize as f64;
+
+        if !block.is_empty()
+            && block_vsize + entry.vsize > BLOCK_MAX_VSIZE
+            && blocks.len() + 1 < max_blocks
+        {
+            blocks.push(MempoolBlock::new(std::mem::take(&mut block)));
+            block_vsize = 0;
+        }
+
+        // parents have less ancestors than their children
+        txids.sort_by_cached_key(|txid| links.ancestors(txid).len());
+        for txid in &txids {
+            let info = &feeinfo[txid];
+            block.push(Selected {
+                fee: info.fee,
+                vsize: info.vsize as u64,
+                fee_rate,
+            });
+            scores.remove(txid);
+            selected.insert(*txid);
+        }
+        block_vsize += entry.vsize;
+
+        // the descendants' packages no longer include the selected transactions
+        let descendants: HashSet<Txid> = txids
+            .iter()
+            .flat_map(|txid| links.descendants(txid))
+            .filter(|txid| scores.contains_key(txid))
+            .collect();
+        for txid in descendants {
+            let entry = candidate(&txid, &package(&txid, &selected));
+            scores.insert(txid, (entry.fee, entry.vsize));
+            heap.push(entry);
+        }
+    }
+    if !block.is_empty() {
+        blocks.push(MempoolBlock::new(block));
+    }
+    blocks
+}
+
+impl MempoolBlock {
+    fn new(mut txs: Vec<Selected>) -> Self {
+        txs.sort_unstable_by(|a, b| a.fee_rate.total_cmp(&b.fee_rate));
+        let block_vsize: u64 = txs.iter().map(|tx| tx.vsize).sum();
+
+        // the fee rate paid by the transaction at the given vsize percentile
+        let percentile = |p: u64| {
+            let target = block_vsize * p / 100;
+            let mut cumulative = 0;
+            txs.iter()
+                .find(|tx| {
+                    cumulative += tx.vsize;
+                    cumulative > target
+                })
+                .or_else(|| txs.last())
+                .map_or(0.0, |tx| tx.fee_rate)
+        };
+        let mut fee_range = vec![txs.first().map_or(0.0, |tx| tx.fee_rate)];
+        fee_range.extend(FEE_RANGE_PERCENTILES.iter().map(|p| percentile(*p)));
+        fee_range.push(txs.last().map_or(0.0, |tx| tx.fee_rate));
+
+        MempoolBlock {
+            block_vsize,
+            n_tx: txs.len(),
+            total_fees: txs.iter().map(|tx| tx.fee).sum(),
+            median_fee: percentile(50),
+            fee_range,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{project_blocks, BLOCK_MAX_VSIZE};
+    use crate::chain::Txid;
+    use crate::new_index::package::TxLinks;
+    use crate::util::fees::TxFeeInfo;
+    use bitcoin::hashes::Hash;
+    use std::collections::HashMap;
+
+    fn txid(n: u8) -> Txid {
+        Txid::from_slice(&[n; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_project_blocks() {
+        let half_block = BLOCK_MAX_VSIZE as u32 / 2;
+        let mut feeinfo = HashMap::new();
+        for (n, fee, vsize) in [
+            // a low fee parent with a high fee child
+            (1, 100, 100),
+            (2, 10_000, 100),
+            // fills most of a block
+            (3, 2 * half_block as u64, half_block),
+            // goes to the next block
+            (4, half_block as u64, half_block),
+            (5, 50, 100),
+        ] {
+            let info = TxFeeInfo {
+                fee,
+                vsize,
+                fee_per_vbyte: fee as f32 / vsize as f32,
+            };
+            feeinfo.insert(txid(n), info);
+        }
+        let mut links = TxLinks::new();
+        links.add(txid(2), vec![txid(1)]);
+
+        let blocks = project_blocks(&feeinfo, &links, 8);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].n_tx, 3);
+        assert_eq!(blocks[0].total_fees, 10_100 + 2 * half_block as u64);
+        assert_eq!(blocks[0].fee_range[0], 2.0);
+        assert_eq!(blocks[0].median_fee, 2.0);
+        assert_eq!(blocks[0].fee_range[6], 50.5);
+        assert_eq!(blocks[1].n_tx, 2);
+        assert_eq!(blocks[1].fee_range[0], 0.5);
+
+        // the last block holds all the remaining transactions
+        let blocks = project_blocks(&feeinfo, &links, 1);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].n_tx, 5);
+        assert!(project_blocks(&feeinfo, &links, 0).is_empty());
+    }
+}
@@ -7,10 +7,10 @@ use std::time::{Duration, Instant};
 
 use crate::chain::{Network, OutPoint, Transaction, TxOut, Txid};
 use crate::config::Config;
-use crate::daemon::{Daemon, MempoolAcceptResult, SubmitPackageResult};
+use crate::daemon::{DaemonApi, MempoolAcceptResult, SubmitPackageResult};
 use crate::errors::*;
 use crate::new_index::{ChainQuery, Mempool, ScriptStats, SpendingInput, Utxo};
-use crate::util::{is_spendable, BlockId, Bytes, TransactionStatus};
+use crate::util::{has_prevout, is_spendable, BlockId, Bytes, TransactionStatus};
 
 #[cfg(not(feature = "opcat_layer"))]
 const FEE_ESTIMATES_TTL: u64 = 60; // seconds
@@ -21,14 +21,18 @@ const CONF_TARGETS: [u16; 28] = [
     16u16, 17u16, 18u16, 19u16, 20u16, 21u16, 22u16, 23u16, 24u16, 25u16, 144u16, 504u16, 1008u16,
 ];
 
+// the estimates by confirmation target, with the time they were fetched at
+#[cfg(not(feature = "opcat_layer"))]
+type FeeEstimates = (HashMap<u16, f64>, Option<Instant>);
+
 pub struct Query {
     chain: Arc<ChainQuery>, // TODO: should be used as read-only
     mempool: Arc<RwLock<Mempool>>,
-    daemon: Arc<Daemon>,
+    daemon: Arc<dyn DaemonApi>,
     config: Arc<Config>,
     #[cfg(not(feature = "opcat_layer"))]
-    cached_estimates: RwLock<(HashMap<u16, f64>, Option<Instant>)>,
-    cached_relayfee: RwLock<Option<f64>>,
+    cached_estimates: Arc<RwLock<FeeEstimates>>,
+    cached_relayfee: Arc<RwLock<Option<f64>>>,
     // #[cfg(feature = "opcat_layer")]
     // asset_db: Option<Arc<RwLock<AssetRegistry>>>,
 }
@@ -37,7 +41,7 @@ impl Query {
     pub fn new(
         chain: Arc<ChainQuery>,
         mempool: Arc<RwLock<Mempool>>,
-        daemon: Arc<Daemon>,
+        daemon: Arc<dyn DaemonApi>,
         config: Arc<Config>,
     ) -> Self {
         Query {
@@ -46,8 +50,22 @@ impl Query {
             daemon,
             config,
             #[cfg(not(feature = "opcat_layer"))]
-            cached_estimates: RwLock::new((HashMap::new(), None)),
-            cached_relayfee: RwLock::new(None),
+            cached_estimates: Arc::new(RwLock::new((HashMap::new(), None))),
+            cached_relayfee: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// A query that reads the chain from a single store snapshot, see
+    /// `ChainQuery::with_snapshot`. The mempool and the cached fee estimates are shared.
+    pub fn with_snapshot(&self) -> Query {
+        Query {
+            chain: Arc::new(self.chain.with_snapshot()),
+            mempool: Arc::clone(&self.mempool),
+            daemon: Arc::clone(&self.daemon),
+            config: Arc::clone(&self.config),
+            #[cfg(not(feature = "opcat_layer"))]
+            cached_estimates: Arc::clone(&self.cached_estimates),
+            cached_relayfee: Arc::clone(&self.cached_relayfee),
         }
     }
 
@@ -75,7 +93,7 @@ impl Query {
             .mempool
             .write()
             .unwrap()
-            .add_by_txid(&self.daemon, &txid)
+            .add_by_txid(&*self.daemon, &txid)
         {
             warn!(
                 "broadcast_raw of {txid} succeeded to broadcast \
@@ -170,6 +188,37 @@ impl Query {
             .or_else(|| self.mempool().lookup_spend(outpoint))
     }
 
+    /// The inputs of the transaction spending outpoints that are already spent by another
+    /// transaction, either confirmed or in the mempool (double spends)
+    pub fn lookup_tx_conflicts(&self, tx: &Transaction) -> Vec<(u32, OutPoint, SpendingInput)> {
+        let txid = tx.txid();
+        tx.input
+            .iter()
+            .enumerate()
+            .filter(|(_, txin)| has_prevout(txin))
+            .filter_map(|(vin, txin)| {
+                self.lookup_spend(&txin.previous_output)
+                    .filter(|spend| spend.txid != txid)
+                    .map(|spend| (vin as u32, txin.previous_output, spend))
+            })
+            .collect()
+    }
+
+    /// The txids of the transactions conflicting with the given one, including the
+    /// transaction that replaced it
+    pub fn conflicting_txids(&self, txid: &Txid) -> Vec<Txid> {
+        let mut txids: Vec<Txid> = self.lookup_txn(txid).map_or_else(Vec::new, |tx| {
+            self.lookup_tx_conflicts(&tx)
+                .into_iter()
+                .map(|(_, _, spend)| spend.txid)
+                .collect()
+        });
+        txids.extend(self.mempool().replaced_by(txid));
+        txids.sort();
+        txids.dedup();
+        txids
+    }
+
     pub fn lookup_tx_spends(&self, tx: Transaction) -> Vec<Option<SpendingInput>> {
         let txid = tx.txid();
 
@@ -190,7 +239,11 @@ impl Query {
     }
 
     pub fn get_tx_status(&self, txid: &Txid) -> TransactionStatus {
-        TransactionStatus::from(self.chain.tx_confirming_block(txid))
+        let mut status = TransactionStatus::from(self.chain.tx_confirming_block(txid));
+        if !status.confirmed {
+            status.first_seen = self.mempool().first_seen(txid);
+        }
+        status
     }
 
     pub fn get_mempool_tx_fee(&self, txid: &Txid) -> Option<u64> {
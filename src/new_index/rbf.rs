@@ -0,0 +1,261 @@
+use bounded_vec_deque::BoundedVecDeque;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::chain::{OutPoint, Transaction, Txid};
+use crate::util::fees::TxFeeInfo;
+
+// Number of replacement trees to keep, most recent first
+const MAX_REPLACEMENT_TREES: usize = 1000;
+// How long the spends of removed transactions are kept to detect their replacements, in seconds.
+// The removal and the addition may be applied in separate mempool updates (e.g. ZMQ batches).
+const REMOVED_SPENDS_TTL: u64 = 60;
+
+/// A transaction involved in a replacement
+#[derive(Clone, Copy)]
+pub struct ReplacementTx {
+    pub txid: Txid,
+    pub fee: u64,
+    pub vsize: u32,
+    /// BIP 125 opt-in signaling, otherwise the replacement was a full-RBF one
+    pub signals_rbf: bool,
+}
+
+impl ReplacementTx {
+    pub fn new(txid: Txid, tx: &Transaction, feeinfo: &TxFeeInfo) -> Self {
+        ReplacementTx {
+            txid,
+            fee: feeinfo.fee,
+            vsize: feeinfo.vsize,
+            signals_rbf: tx.input.iter().any(|txin| txin.sequence < 0xffff_fffe),
+        }
+    }
+}
+
+/// A replacement transaction and the transactions it replaced, recursively
+#[derive(Serialize)]
+pub struct ReplacementTree {
+    pub txid: Txid,
+    pub fee: u64,
+    pub vsize: u32,
+    pub fee_rate: f64,
+    pub signals_rbf: bool,
+    /// When the transaction replaced the ones in `replaces`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    /// The fee paid on top of the replaced transactions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_delta: Option<i64>,
+    /// Whether any of the replaced transactions didn't signal BIP 125
+    pub full_rbf: bool,
+    pub replaces: Vec<ReplacementTree>,
+}
+
+/// Tracks mempool transactions replaced by conflicting spends of the same outpoints.
+///
+/// Conflicts are detected when a transaction spends an outpoint that is still
+/// spent by another mempool transaction, or that was spent by a transaction
+/// removed from the mempool recently.
+pub(super) struct ReplacementTracker {
+    txs: HashMap<Txid, ReplacementTx>,
+    // replaced txid -> replacing txid
+    replaced_by: HashMap<Txid, Txid>,
+    // replacing txid -> (replacement time, replaced txids)
+    replaces: HashMap<Txid, (u64, Vec<Txid>)>,
+    // the roots of the replacement trees, most recent first
+    trees: BoundedVecDeque<Txid>,
+    // spends of the recently removed transactions, with their removal time
+    removed_spends: HashMap<OutPoint, (u64, ReplacementTx)>,
+}
+
+impl ReplacementTracker {
+    pub(super) fn new() -> Self {
+        ReplacementTracker {
+            txs: HashMap::new(),
+            replaced_by: HashMap::new(),
+            replaces: HashMap::new(),
+            trees: BoundedVecDeque::new(MAX_REPLACEMENT_TREES),
+            removed_spends: HashMap::new(),
+        }
+    }
+
+    /// Remember the spends of transactions removed from the mempool at `time`, for
+    /// `REMOVED_SPENDS_TTL` seconds
+    pub fn add_removed(&mut self, removed: Vec<(ReplacementTx, Vec<OutPoint>)>, time: u64) {
+        self.removed_spends
+            .retain(|_, (removed_time, _)| *removed_time + REMOVED_SPENDS_TTL > time);
+        self.removed_spends
+            .extend(removed.into_iter().flat_map(|(tx, outpoints)| {
+                outpoints
+                    .into_iter()
+                    .map(move |outpoint| (outpoint, (time, tx)))
+            }));
+    }
+
+    /// The removed transaction that spent the outpoint, if any
+    pub fn removed_spend(&self, outpoint: &OutPoint) -> Option<ReplacementTx> {
+        self.removed_spends.get(outpoint).map(|(_, tx)| *tx)
+    }
+
+    /// Record that `tx` replaced the `replaced` transactions
+    pub fn add(&mut self, tx: ReplacementTx, replaced: Vec<ReplacementTx>) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        // a transaction re-entering the mempool after being replaced starts a new tree
+        if self.txs.contains_key(&tx.txid) {
+            let root = self.root(&tx.txid);
+            if let Some(pos) = self.trees.iter().position(|txid| *txid == root) {
+                self.trees.remove(pos);
+            }
+            self.forget(&root);
+        }
+
+        for replaced_tx in &replaced {
+            self.txs.insert(replaced_tx.txid, *replaced_tx);
+            self.replaced_by.insert(replaced_tx.txid, tx.txid);
+            // the replaced transaction is no longer the root of its tree
+            if let Some(pos) = self.trees.iter().position(|root| *root == replaced_tx.txid) {
+                self.trees.remove(pos);
+            }
+        }
+        self.txs.insert(tx.txid, tx);
+        self.replaces.insert(
+            tx.txid,
+            (
+                time,
+                replaced
+                    .iter()
+                    .map(|replaced_tx| replaced_tx.txid)
+                    .collect(),
+            ),
+        );
+
+        if let Some(evicted) = self.trees.push_front(tx.txid) {
+            self.forget(&evicted);
+        }
+    }
+
+    /// The transaction that replaced the given one, if any
+    pub fn replaced_by(&self, txid: &Txid) -> Option<Txid> {
+        self.replaced_by.get(txid).copied()
+    }
+
+    /// The transactions directly replaced by the given one
+    pub fn replaces(&self, txid: &Txid) -> Vec<Txid> {
+        self.replaces
+            .get(txid)
+            .map_or_else(Vec::new, |(_, replaced)| replaced.clone())
+    }
+
+    /// The full replacement tree the transaction belongs to, starting at the latest replacement
+    pub fn tree(&self, txid: &Txid) -> Option<ReplacementTree> {
+        if !self.txs.contains_key(txid) {
+            return None;
+        }
+        self.subtree(&self.root(txid))
+    }
+
+    /// The most recent replacement trees
+    pub fn recent_trees(&self) -> Vec<ReplacementTree> {
+        self.trees
+            .iter()
+            .filter_map(|root| self.subtree(root))
+            .collect()
+    }
+
+    fn root(&self, txid: &Txid) -> Txid {
+        let mut root = *txid;
+        while let Some(replacing) = self.replaced_by.get(&root) {
+            root = *replacing;
+        }
+        root
+    }
+
+    fn subtree(&self, txid: &Txid) -> Option<ReplacementTree> {
+        let tx = self.txs.get(txid)?;
+        let (time, replaces): (_, Vec<ReplacementTree>) = match self.replaces.get(txid) {
+            Some((time, replaced)) => (
+                Some(*time),
+                replaced
+                    .iter()
+                    .filter_map(|txid| self.subtree(txid))
+                    .collect(),
+            ),
+            None => (None, vec![]),
+        };
+        let fee_delta = time.map(|_| {
+            let replaced_fees: u64 = replaces.iter().map(|child| child.fee).sum();
+            tx.fee as i64 - replaced_fees as i64
+        });
+        Some(ReplacementTree {
+            txid: tx.txid,
+            fee: tx.fee,
+            vsize: tx.vsize,
+            fee_rate: tx.fee as f64 / tx.vsize as f64,
+            signals_rbf: tx.signals_rbf,
+            time,
+            fee_delta,
+            full_rbf: replaces.iter().any(|child| !child.signals_rbf),
+            replaces,
+        })
+    }
+
+    // Drop an evicted tree
+    fn forget(&mut self, txid: &Txid) {
+        self.txs.remove(txid);
+        self.replaced_by.remove(txid);
+        if let Some((_, replaced)) = self.replaces.remove(txid) {
+            for replaced_txid in replaced {
+                self.forget(&replaced_txid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReplacementTracker, ReplacementTx};
+    use crate::chain::Txid;
+    use bitcoin::hashes::Hash;
+
+    fn tx(n: u8, fee: u64, signals_rbf: bool) -> ReplacementTx {
+        ReplacementTx {
+            txid: Txid::from_slice(&[n; 32]).unwrap(),
+            fee,
+            vsize: 100,
+            signals_rbf,
+        }
+    }
+
+    #[test]
+    fn test_replacement_tree() {
+        let mut tracker = ReplacementTracker::new();
+        tracker.add(tx(2, 2000, true), vec![tx(1, 1000, true)]);
+        tracker.add(
+            tx(4, 5000, false),
+            vec![tx(2, 2000, true), tx(3, 500, false)],
+        );
+
+        assert_eq!(
+            tracker.replaced_by(&tx(1, 0, true).txid),
+            Some(tx(2, 0, true).txid)
+        );
+        assert_eq!(tracker.replaces(&tx(4, 0, true).txid).len(), 2);
+
+        let tree = tracker.tree(&tx(1, 0, true).txid).unwrap();
+        assert_eq!(tree.txid, tx(4, 0, true).txid);
+        assert_eq!(tree.fee_delta, Some(2500));
+        assert!(tree.full_rbf);
+        assert_eq!(tree.replaces[0].fee_delta, Some(1000));
+        assert!(!tree.replaces[0].full_rbf);
+        assert_eq!(tree.replaces[0].replaces[0].fee_delta, None);
+
+        let recent = tracker.recent_trees();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].txid, tx(4, 0, true).txid);
+        assert!(tracker.tree(&tx(5, 0, true).txid).is_none());
+    }
+}
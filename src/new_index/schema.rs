@@ -4,6 +4,7 @@ use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 #[cfg(not(feature = "opcat_layer"))]
 use bitcoin::util::merkleblock::MerkleBlock;
 use bitcoin::VarInt;
+use error_chain::ChainedError;
 use itertools::Itertools;
 use rayon::prelude::*;
 use sha2::{Digest, Sha256};
@@ -16,26 +17,33 @@ use crate::opcat_layer::consensus::encode::{deserialize, serialize};
 
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::TryInto;
+use std::fs;
 use std::io;
-use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, TryLockError};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 use crate::chain::{
     BlockHash, BlockHeader, Network, OutPoint, Script, Transaction, TxOut, Txid, Value,
 };
 use crate::config::Config;
-use crate::daemon::Daemon;
+use crate::daemon::DaemonApi;
 use crate::errors::*;
-use crate::metrics::{Gauge, HistogramOpts, HistogramTimer, HistogramVec, MetricOpts, Metrics};
+use crate::metrics::{
+    Gauge, GaugeVec, HistogramOpts, HistogramTimer, HistogramVec, MetricOpts, Metrics,
+};
 use crate::util::{
-    bincode_util, full_hash, has_prevout, is_spendable, BlockHeaderMeta, BlockId, BlockMeta,
-    BlockStatus, Bytes, HeaderEntry, HeaderList, ScriptToAddr,
+    bincode_util, full_hash, has_prevout, is_spendable, spawn_thread, BlockHeaderMeta, BlockId,
+    BlockMeta, BlockStatus, Bytes, HeaderEntry, HeaderList, ScriptToAddr,
 };
 
 use crate::new_index::db::{
-    DBFlush, DBRow, ReverseScanGroupIterator, ReverseScanIterator, ScanIterator, DB,
+    open_raw_db, DBFlush, DBRead, DBRow, DBSnapshot, Migration, OpenMode, ReverseScanGroupIterator,
+    ReverseScanIterator, ScanIterator, CACHE, DB, DEFAULT_DB_MEMORY_MB, HISTORY, TXSTORE,
 };
 use crate::new_index::fetch::{bitcoind_sequential_fetcher, start_fetcher, BlockEntry, FetchFrom};
+use crate::new_index::sync::{SyncMetrics, SyncPhase, SyncProgress, SyncStatus};
 
 const MIN_HISTORY_ITEMS_TO_CACHE: usize = 100;
 
@@ -45,20 +53,63 @@ pub struct Store {
     cache_db: DB,
     added_blockhashes: RwLock<HashSet<BlockHash>>,
     indexed_blockhashes: RwLock<HashSet<BlockHash>>,
-    indexed_headers: RwLock<HeaderList>,
+    indexed_headers: RwLock<Arc<HeaderList>>,
+    snapshot: RwLock<Arc<StoreSnapshot>>,
+    sync: SyncProgress,
+    // held by the indexer while it updates the DBs, so that checkpoints see a complete update
+    update_lock: Mutex<()>,
+}
+
+/// The chain state as of the last completed indexer update, which `ChainQuery` reads from so
+/// that it never observes a partially applied block or reorg.
+pub struct StoreSnapshot {
+    txstore_db: DBSnapshot,
+    history_db: DBSnapshot,
+    headers: Arc<HeaderList>,
 }
 
 impl Store {
     pub fn open(path: &Path, config: &Config) -> Self {
-        let txstore_db = DB::open(&path.join("txstore"), config);
+        let db = Arc::new(open_raw_db(
+            &path.join("db"),
+            OpenMode::ReadWrite,
+            config.db_memory_mb,
+        ));
+        let txstore_db = DB::open(&db, &TXSTORE, path, config);
+        let history_db = DB::open(&db, &HISTORY, path, config);
+        let cache_db = DB::open(&db, &CACHE, path, config);
+        Store::new(txstore_db, history_db, cache_db)
+    }
+
+    /// Opens the txstore and history DBs of the primary at `primary_path` as a RocksDB
+    /// secondary, which `Store::catch_up` brings up to date. Replicas never index blocks.
+    /// Their own cache DB and the info logs of the secondary are kept in `path`.
+    pub fn open_replica(primary_path: &Path, path: &Path, config: &Config) -> Self {
+        let secondary_path = path.join("secondary");
+        let primary = Arc::new(open_raw_db(
+            &primary_path.join("db"),
+            OpenMode::Secondary(&secondary_path),
+            config.db_memory_mb,
+        ));
+        let txstore_db = DB::open_secondary(&primary, &TXSTORE, config);
+        let history_db = DB::open_secondary(&primary, &HISTORY, config);
+        let local = Arc::new(open_raw_db(
+            &path.join("db"),
+            OpenMode::ReadWrite,
+            config.db_memory_mb,
+        ));
+        let cache_db = DB::open(&local, &CACHE, path, config);
+        Store::new(txstore_db, history_db, cache_db)
+    }
+
+    fn new(txstore_db: DB, history_db: DB, cache_db: DB) -> Self {
         let added_blockhashes = load_blockhashes(&txstore_db, &BlockRow::done_filter());
         debug!("{} blocks were added", added_blockhashes.len());
 
-        let history_db = DB::open(&path.join("history"), config);
         let indexed_blockhashes = load_blockhashes(&history_db, &BlockRow::done_filter());
         debug!("{} blocks were indexed", indexed_blockhashes.len());
 
-        let cache_db = DB::open(&path.join("cache"), config);
+        let sync = SyncProgress::load(&txstore_db);
 
         let headers = if let Some(tip_hash) = txstore_db.get(b"t") {
             let tip_hash = deserialize(&tip_hash).expect("invalid chain tip in `t`");
@@ -73,6 +124,13 @@ impl Store {
             HeaderList::empty()
         };
 
+        let headers = Arc::new(headers);
+        let snapshot = StoreSnapshot {
+            txstore_db: txstore_db.snapshot(),
+            history_db: history_db.snapshot(),
+            headers: Arc::clone(&headers),
+        };
+
         Store {
             txstore_db,
             history_db,
@@ -80,6 +138,9 @@ impl Store {
             added_blockhashes: RwLock::new(added_blockhashes),
             indexed_blockhashes: RwLock::new(indexed_blockhashes),
             indexed_headers: RwLock::new(headers),
+            snapshot: RwLock::new(Arc::new(snapshot)),
+            sync,
+            update_lock: Mutex::new(()),
         }
     }
 
@@ -98,6 +159,249 @@ impl Store {
     pub fn done_initial_sync(&self) -> bool {
         self.txstore_db.get(b"t").is_some()
     }
+
+    pub fn sync_status(&self) -> SyncStatus {
+        self.sync.status(self.done_initial_sync())
+    }
+
+    /// Creates a RocksDB checkpoint of the three DBs in a new subdirectory of `dir`, named after
+    /// the indexed tip. The result can be restored with `Store::restore`.
+    ///
+    /// Only the RocksDB checkpoint is made while holding the update lock. It is staged next to
+    /// the DB, where its files are hard links, and copied to `dir` once the indexer is resumed.
+    pub fn checkpoint(&self, dir: &Path) -> Result<(PathBuf, BlockId)> {
+        let update = self.update_lock.lock().unwrap();
+        self.checkpoint_locked(dir, update)
+    }
+
+    /// Like `checkpoint`, but returns `None` instead of waiting when an index update is running.
+    pub fn try_checkpoint(&self, dir: &Path) -> Result<Option<(PathBuf, BlockId)>> {
+        let update = match self.update_lock.try_lock() {
+            Ok(update) => update,
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+        };
+        self.checkpoint_locked(dir, update).map(Some)
+    }
+
+    fn checkpoint_locked(&self, dir: &Path, update: MutexGuard<()>) -> Result<(PathBuf, BlockId)> {
+        let (path, tip, staging) = {
+            let _update = update;
+            let tip = match self.txstore_db.get(b"t") {
+                Some(tip) => deserialize(&tip).expect("invalid chain tip in `t`"),
+                None => bail!("cannot checkpoint the DB before the initial sync completes"),
+            };
+            let tip = BlockId::from(
+                self.indexed_headers
+                    .read()
+                    .unwrap()
+                    .header_by_blockhash(&tip)
+                    .chain_err(|| format!("missing header for the chain tip {}", tip))?,
+            );
+
+            let path = dir.join(format!("{}-{}", tip.height, tip.hash));
+            if path.exists() {
+                bail!("checkpoint {:?} already exists", path);
+            }
+            fs::create_dir_all(&path).chain_err(|| format!("failed to create {:?}", path))?;
+            let db_path = self.txstore_db.path();
+            let staging = db_path.with_file_name(format!("checkpoint-{}-{}", tip.height, tip.hash));
+            // left over by a crash while copying a previous checkpoint
+            if staging.exists() {
+                fs::remove_dir_all(&staging)
+                    .chain_err(|| format!("failed to remove {:?}", staging))?;
+            }
+            // the three DBs share a single RocksDB
+            if let Err(e) = self.txstore_db.checkpoint(&staging) {
+                let _ = fs::remove_dir_all(&path);
+                return Err(e);
+            }
+            (path, tip, staging)
+        };
+
+        let copied = copy_dir(&staging, &path.join("db"))
+            .chain_err(|| format!("failed to copy {:?} to {:?}", staging, path));
+        if let Err(e) = fs::remove_dir_all(&staging) {
+            warn!(
+                "failed to remove the staged checkpoint {:?}: {}",
+                staging, e
+            );
+        }
+        if let Err(e) = copied {
+            let _ = fs::remove_dir_all(&path);
+            return Err(e);
+        }
+        info!("checkpointed the DB at height {} to {:?}", tip.height, path);
+        Ok((path, tip))
+    }
+
+    /// Copies a checkpoint made by `Store::checkpoint` to `path`, after checking that the daemon
+    /// knows its tip. The indexer then resumes from that tip, like after a restart.
+    pub fn restore(checkpoint: &Path, path: &Path, daemon: &dyn DaemonApi) -> Result<BlockHash> {
+        if path.exists() {
+            bail!("refusing to restore over the existing DB at {:?}", path);
+        }
+        if !checkpoint.join("db").is_dir() {
+            bail!("{:?} is not a DB checkpoint, db is missing", checkpoint);
+        }
+
+        let tip: BlockHash = {
+            let db = open_raw_db(
+                &checkpoint.join("db"),
+                OpenMode::ReadOnly,
+                DEFAULT_DB_MEMORY_MB,
+            );
+            let txstore_db = DB::open_read_only(&Arc::new(db), &TXSTORE);
+            let tip = txstore_db
+                .get(b"t")
+                .chain_err(|| format!("checkpoint {:?} has no chain tip", checkpoint))?;
+            deserialize(&tip).expect("invalid chain tip in `t`")
+        };
+        let block = daemon
+            .getblock_raw(&tip, 1)
+            .chain_err(|| format!("the daemon doesn't know the checkpoint tip {}", tip))?;
+        if block["confirmations"].as_i64().is_some_and(|c| c < 0) {
+            warn!(
+                "the checkpoint tip {} is not in the best chain anymore, it will be reorged",
+                tip
+            );
+        }
+
+        let copied = copy_dir(checkpoint, path)
+            .chain_err(|| format!("failed to copy {:?} to {:?}", checkpoint, path));
+        if copied.is_err() {
+            // the next start would open the partial copy as a corrupted DB
+            if let Err(e) = fs::remove_dir_all(path) {
+                warn!("failed to remove the partial copy {:?}: {}", path, e);
+            }
+        }
+        copied?;
+        info!(
+            "restored the DB checkpoint {:?} with tip {}",
+            checkpoint, tip
+        );
+        Ok(tip)
+    }
+
+    /// Brings the DBs up to the current `DB_VERSION` in a background thread, see `Migration`.
+    pub fn start_migrations(store: &Arc<Store>, metrics: &Metrics) -> JoinHandle<()> {
+        let db_version = metrics.gauge_vec(
+            MetricOpts::new("db_version", "Format version of the index DBs"),
+            &["db"],
+        );
+        let rows_migrated = metrics.gauge(MetricOpts::new(
+            "db_migration_rows",
+            "Number of rows rewritten by the current DB migration",
+        ));
+        let store = Arc::clone(store);
+        spawn_thread("db-migration", move || {
+            let dbs: [(&str, &DB, &[Migration]); 3] = [
+                ("txstore", &store.txstore_db, TXSTORE_MIGRATIONS),
+                ("history", &store.history_db, &[]),
+                ("cache", &store.cache_db, &[]),
+            ];
+            for (name, db, migrations) in dbs {
+                db.migrate(migrations, &rows_migrated);
+                db_version
+                    .with_label_values(&[name])
+                    .set(db.version() as f64);
+            }
+        })
+    }
+
+    /// Brings a replica opened with `Store::open_replica` up to date with its primary.
+    /// Returns the new indexed tip, if it changed.
+    pub fn catch_up(&self) -> Result<Option<BlockHash>> {
+        // the txstore and history DBs share the secondary, which catches up with both at once
+        self.txstore_db.catch_up()?;
+        let tip: BlockHash = match self.txstore_db.get(b"t") {
+            Some(tip) => deserialize(&tip).expect("invalid chain tip in `t`"),
+            None => return Ok(None),
+        };
+
+        let mut headers = self.indexed_headers.write().unwrap();
+        if headers.is_empty() {
+            let headers_map = load_blockheaders(&self.txstore_db);
+            *headers = Arc::new(HeaderList::new(headers_map, tip));
+        } else if headers.header_by_blockhash(&tip).is_some() {
+            // either unchanged, or a rollback tip written by the primary during a reorg,
+            // in which case the reorg is applied once the primary writes the new tip
+            return Ok(None);
+        } else {
+            // walk back from the new tip to the last known block, which is the fork point on reorgs
+            let mut new_headers = vec![];
+            let mut blockhash = tip;
+            while headers.header_by_blockhash(&blockhash).is_none() {
+                let header: BlockHeader = self
+                    .txstore_db
+                    .get(&BlockRow::header_key(full_hash(&blockhash[..])))
+                    .map(|header| deserialize(&header).expect("failed to parse BlockHeader"))
+                    .chain_err(|| format!("missing header for block {}", blockhash))?;
+                blockhash = header.prev_blockhash;
+                new_headers.push(header);
+            }
+            new_headers.reverse();
+            let new_headers = headers.order(new_headers);
+            // copy-on-write, the previous headers may still be in use by the query snapshot
+            // (cheap, the clone shares most of the headers)
+            Arc::make_mut(&mut headers).apply(new_headers);
+        }
+        drop(headers);
+
+        self.update_snapshot();
+        Ok(Some(tip))
+    }
+
+    /// Catches up with the primary every `interval`, see `Store::catch_up`
+    pub fn start_replication(
+        store: &Arc<Store>,
+        interval: Duration,
+        metrics: &Metrics,
+    ) -> JoinHandle<()> {
+        let tip_metric = metrics.gauge(MetricOpts::new("tip_height", "Current chain tip height"));
+        let store = Arc::clone(store);
+        spawn_thread("replica-catch-up", move || loop {
+            match store.catch_up() {
+                Ok(Some(tip)) => {
+                    let height = store.snapshot().headers.len() - 1;
+                    debug!("caught up with the primary at {} ({})", tip, height);
+                    tip_metric.set(height as i64);
+                }
+                Ok(None) => (),
+                Err(e) => warn!("failed to catch up with the primary: {}", e.display_chain()),
+            }
+            std::thread::sleep(interval);
+        })
+    }
+
+    pub fn snapshot(&self) -> Arc<StoreSnapshot> {
+        Arc::clone(&self.snapshot.read().unwrap())
+    }
+
+    fn update_snapshot(&self) {
+        let headers = self.indexed_headers.read().unwrap();
+        let snapshot = StoreSnapshot {
+            txstore_db: self.txstore_db.snapshot(),
+            history_db: self.history_db.snapshot(),
+            headers: Arc::clone(&headers),
+        };
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
+    }
+}
+
+// checkpoint files are never modified in place, so they can be hard linked instead of copied
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
 }
 
 type UtxoMap = HashMap<OutPoint, (BlockId, Value)>;
@@ -168,6 +472,8 @@ pub struct Indexer {
     iconfig: IndexerConfig,
     duration: HistogramVec,
     tip_metric: Gauge,
+    sync_metrics: SyncMetrics,
+    compression_bytes: GaugeVec,
 }
 
 struct IndexerConfig {
@@ -194,10 +500,12 @@ impl From<&Config> for IndexerConfig {
 
 pub struct ChainQuery {
     store: Arc<Store>, // TODO: should be used as read-only
-    daemon: Arc<Daemon>,
+    daemon: Arc<dyn DaemonApi>,
     light_mode: bool,
     duration: HistogramVec,
     network: Network,
+    // pinned by with_snapshot(), otherwise every call reads the latest snapshot
+    snapshot: Option<Arc<StoreSnapshot>>,
 }
 
 #[derive(Debug, Clone)]
@@ -219,7 +527,7 @@ impl std::fmt::Display for Operation {
 // TODO: &[Block] should be an iterator / a queue.
 impl Indexer {
     pub fn open(store: Arc<Store>, from: FetchFrom, config: &Config, metrics: &Metrics) -> Self {
-        Indexer {
+        let indexer = Indexer {
             store,
             flush: DBFlush::Disable,
             from,
@@ -229,13 +537,55 @@ impl Indexer {
                 &["step"],
             ),
             tip_metric: metrics.gauge(MetricOpts::new("tip_height", "Current chain tip height")),
-        }
+            sync_metrics: SyncMetrics::new(metrics),
+            compression_bytes: metrics.gauge_vec(
+                MetricOpts::new(
+                    "txstore_compression_bytes",
+                    "Size of the compressed txstore rows (uncompressed, stored and saved)",
+                ),
+                &["type"],
+            ),
+        };
+        indexer.observe_compression();
+        indexer
     }
 
     fn start_timer(&self, name: &str) -> HistogramTimer {
         self.duration.with_label_values(&[name]).start_timer()
     }
 
+    fn start_sync_phase(&self, phase: SyncPhase, blocks: usize) {
+        let sync = &self.store.sync;
+        sync.start(&self.store.txstore_db, phase, blocks, self.flush);
+        self.sync_metrics.observe(&self.store.sync_status());
+    }
+
+    fn advance_sync(&self, blocks: usize) {
+        let sync = &self.store.sync;
+        sync.advance(&self.store.txstore_db, blocks, self.flush);
+        let status = self.store.sync_status();
+        debug!(
+            "{:?} phase: {}/{} blocks ({:.1} blocks/s)",
+            status.phase, status.blocks_done, status.blocks_total, status.blocks_per_second
+        );
+        self.sync_metrics.observe(&status);
+    }
+
+    fn observe_compression(&self) {
+        if let Some(stats) = self.store.txstore_db.compression_stats() {
+            let sizes = [
+                ("uncompressed", stats.raw_bytes),
+                ("stored", stats.stored_bytes),
+                ("saved", stats.raw_bytes.saturating_sub(stats.stored_bytes)),
+            ];
+            for (label, bytes) in sizes {
+                self.compression_bytes
+                    .with_label_values(&[label])
+                    .set(bytes as f64);
+            }
+        }
+    }
+
     fn headers_to_add(&self, new_headers: &[HeaderEntry]) -> Vec<HeaderEntry> {
         let added_blockhashes = self.store.added_blockhashes.read().unwrap();
         new_headers
@@ -264,7 +614,7 @@ impl Indexer {
         db.enable_auto_compaction();
     }
 
-    fn get_new_headers(&self, daemon: &Daemon, tip: &BlockHash) -> Result<Vec<HeaderEntry>> {
+    fn get_new_headers(&self, daemon: &dyn DaemonApi, tip: &BlockHash) -> Result<Vec<HeaderEntry>> {
         let headers = self.store.indexed_headers.read().unwrap();
         let new_headers = daemon.get_new_headers(&headers, tip)?;
         let result = headers.order(new_headers);
@@ -275,7 +625,7 @@ impl Indexer {
         Ok(result)
     }
 
-    fn reorg(&self, reorged: Vec<HeaderEntry>, daemon: &Daemon) -> Result<()> {
+    fn reorg(&self, reorged: Vec<HeaderEntry>, daemon: &dyn DaemonApi) -> Result<()> {
         if reorged.len() > 10 {
             warn!(
                 "reorg of over 10 blocks ({}) detected! Wonky stuff might happen!",
@@ -309,15 +659,19 @@ impl Indexer {
         Ok(())
     }
 
-    pub fn update(&mut self, daemon: &Daemon) -> Result<BlockHash> {
+    pub fn update(&mut self, daemon: &dyn DaemonApi) -> Result<BlockHash> {
+        let store = Arc::clone(&self.store);
+        let _update = store.update_lock.lock().unwrap();
         let daemon = daemon.reconnect()?;
         let tip = daemon.getbestblockhash()?;
-        let new_headers = self.get_new_headers(&daemon, &tip)?;
+        let new_headers = self.get_new_headers(&*daemon, &tip)?;
 
         // Must rollback blocks before rolling forward
         let headers_len = {
             let mut headers = self.store.indexed_headers.write().unwrap();
-            let (reorged, rollback_tip) = headers.apply(new_headers.clone());
+            // copy-on-write, the previous headers may still be in use by the query snapshot
+            // (cheap, the clone shares most of the headers)
+            let (reorged, rollback_tip) = Arc::make_mut(&mut headers).apply(new_headers.clone());
             assert_eq!(tip, *headers.tip());
             let headers_len = headers.len();
             drop(headers);
@@ -338,7 +692,7 @@ impl Indexer {
                     }
                 }
 
-                self.reorg(reorged, &daemon)?;
+                self.reorg(reorged, &*daemon)?;
             }
 
             headers_len
@@ -350,8 +704,11 @@ impl Indexer {
             to_add.len(),
             self.from
         );
-        start_fetcher(self.from, &daemon, to_add)?
-            .map(|blocks| self.add(&blocks, Operation::AddBlocks));
+        self.start_sync_phase(SyncPhase::Add, to_add.len());
+        start_fetcher(self.from, &*daemon, to_add)?.map(|blocks| {
+            self.add(&blocks, Operation::AddBlocks);
+            self.advance_sync(blocks.len());
+        });
         self.start_auto_compactions(&self.store.txstore_db);
 
         let to_index = self.headers_to_index(&new_headers);
@@ -360,8 +717,11 @@ impl Indexer {
             to_index.len(),
             self.from
         );
-        start_fetcher(self.from, &daemon, to_index)?
-            .map(|blocks| self.index(&blocks, Operation::AddBlocks));
+        self.start_sync_phase(SyncPhase::Index, to_index.len());
+        start_fetcher(self.from, &*daemon, to_index)?.map(|blocks| {
+            self.index(&blocks, Operation::AddBlocks);
+            self.advance_sync(blocks.len());
+        });
         self.start_auto_compactions(&self.store.history_db);
 
         if let DBFlush::Disable = self.flush {
@@ -375,6 +735,11 @@ impl Indexer {
         debug!("updating synced tip to {:?}", tip);
         self.store.txstore_db.put_sync(b"t", &serialize(&tip));
 
+        // only expose the new chain state to queries once it is fully indexed
+        self.store.update_snapshot();
+        self.store.sync.finish(&self.store.txstore_db, self.flush);
+        self.sync_metrics.observe(&self.store.sync_status());
+
         if let FetchFrom::BlkFiles = self.from {
             self.from = FetchFrom::Bitcoind;
         }
@@ -400,6 +765,7 @@ impl Indexer {
             let _timer = self.start_timer(write_label);
             if let Operation::AddBlocks = op {
                 self.store.txstore_db.write(rows, self.flush);
+                self.observe_compression();
             } else {
                 self.store
                     .txstore_db
@@ -464,7 +830,12 @@ impl Indexer {
 }
 
 impl ChainQuery {
-    pub fn new(store: Arc<Store>, daemon: Arc<Daemon>, config: &Config, metrics: &Metrics) -> Self {
+    pub fn new(
+        store: Arc<Store>,
+        daemon: Arc<dyn DaemonApi>,
+        config: &Config,
+        metrics: &Metrics,
+    ) -> Self {
         ChainQuery {
             store,
             daemon,
@@ -474,6 +845,20 @@ impl ChainQuery {
                 HistogramOpts::new("query_duration", "Index query duration (in seconds)"),
                 &["name"],
             ),
+            snapshot: None,
+        }
+    }
+
+    /// A view of the chain that reads every query from the current store snapshot,
+    /// so that the lookups made for one request all see the same chain state.
+    pub fn with_snapshot(&self) -> ChainQuery {
+        ChainQuery {
+            store: Arc::clone(&self.store),
+            daemon: Arc::clone(&self.daemon),
+            light_mode: self.light_mode,
+            duration: self.duration.clone(),
+            network: self.network,
+            snapshot: Some(self.snapshot()),
         }
     }
 
@@ -485,6 +870,13 @@ impl ChainQuery {
         &self.store
     }
 
+    fn snapshot(&self) -> Arc<StoreSnapshot> {
+        match self.snapshot {
+            Some(ref snapshot) => Arc::clone(snapshot),
+            None => self.store.snapshot(),
+        }
+    }
+
     fn start_timer(&self, name: &str) -> HistogramTimer {
         self.duration.with_label_values(&[name]).start_timer()
     }
@@ -497,7 +889,7 @@ impl ChainQuery {
             let mut blockinfo = self.daemon.getblock_raw(hash, 1).ok()?;
             Some(serde_json::from_value(blockinfo["tx"].take()).unwrap())
         } else {
-            self.store
+            self.snapshot()
                 .txstore_db
                 .get(&BlockRow::txids_key(full_hash(&hash[..])))
                 .map(|val| {
@@ -514,7 +906,7 @@ impl ChainQuery {
             let mut blockinfo = self.daemon.getblock_raw(hash, 1).ok()?;
             Some(serde_json::from_value(blockinfo["tx"].take()).unwrap())
         } else {
-            self.store
+            self.snapshot()
                 .txstore_db
                 .get(&BlockRow::txids_key(full_hash(&hash[..])))
                 .map(|val| {
@@ -543,7 +935,7 @@ impl ChainQuery {
             let blockinfo = self.daemon.getblock_raw(hash, 1).ok()?;
             Some(serde_json::from_value(blockinfo).unwrap())
         } else {
-            self.store
+            self.snapshot()
                 .txstore_db
                 .get(&BlockRow::meta_key(full_hash(&hash[..])))
                 .map(|val| {
@@ -587,7 +979,7 @@ impl ChainQuery {
 
     pub fn get_mtp(&self, height: usize) -> u32 {
         let _timer = self.start_timer("get_block_mtp");
-        self.store.indexed_headers.read().unwrap().get_mtp(height)
+        self.snapshot().headers.get_mtp(height)
     }
 
     pub fn get_block_with_meta(&self, hash: &BlockHash) -> Option<BlockHeaderMeta> {
@@ -600,8 +992,13 @@ impl ChainQuery {
         })
     }
 
-    pub fn history_iter_scan(&self, code: u8, hash: &[u8], start_height: usize) -> ScanIterator {
-        self.store.history_db.iter_scan_from(
+    pub fn history_iter_scan(
+        &self,
+        code: u8,
+        hash: &[u8],
+        start_height: usize,
+    ) -> ScanIterator<'static> {
+        self.snapshot().history_db.iter_scan_from(
             &TxHistoryRow::filter(code, hash),
             &TxHistoryRow::prefix_height(code, hash, start_height as u32),
         )
@@ -611,8 +1008,8 @@ impl ChainQuery {
         code: u8,
         hash: &[u8],
         start_height: Option<u32>,
-    ) -> ReverseScanIterator {
-        self.store.history_db.iter_scan_reverse(
+    ) -> ReverseScanIterator<'static> {
+        self.snapshot().history_db.iter_scan_reverse(
             &TxHistoryRow::filter(code, hash),
             &start_height.map_or(TxHistoryRow::prefix_end(code, hash), |start_height| {
                 TxHistoryRow::prefix_height_end(code, hash, start_height)
@@ -624,8 +1021,8 @@ impl ChainQuery {
         code: u8,
         hashes: &[[u8; 32]],
         start_height: Option<u32>,
-    ) -> ReverseScanGroupIterator {
-        self.store.history_db.iter_scan_group_reverse(
+    ) -> ReverseScanGroupIterator<'static> {
+        self.snapshot().history_db.iter_scan_group_reverse(
             hashes.iter().map(|hash| {
                 let prefix = TxHistoryRow::filter(code, &hash[..]);
                 let prefix_max = start_height
@@ -1132,7 +1529,7 @@ impl ChainQuery {
 
     pub fn address_search(&self, prefix: &str, limit: usize) -> Vec<String> {
         let _timer_scan = self.start_timer("address_search");
-        self.store
+        self.snapshot()
             .history_db
             .iter_scan(&addr_search_filter(prefix))
             .take(limit)
@@ -1141,71 +1538,54 @@ impl ChainQuery {
     }
 
     fn header_by_hash(&self, hash: &BlockHash) -> Option<HeaderEntry> {
-        self.store
-            .indexed_headers
-            .read()
-            .unwrap()
-            .header_by_blockhash(hash)
-            .cloned()
+        self.snapshot().headers.header_by_blockhash(hash).cloned()
     }
 
     // Get the height of a blockhash, only if its part of the best chain
     pub fn height_by_hash(&self, hash: &BlockHash) -> Option<usize> {
-        self.store
-            .indexed_headers
-            .read()
-            .unwrap()
+        self.snapshot()
+            .headers
             .header_by_blockhash(hash)
             .map(|header| header.height())
     }
 
     pub fn header_by_height(&self, height: usize) -> Option<HeaderEntry> {
-        self.store
-            .indexed_headers
-            .read()
-            .unwrap()
-            .header_by_height(height)
-            .cloned()
+        self.snapshot().headers.header_by_height(height).cloned()
     }
 
     pub fn hash_by_height(&self, height: usize) -> Option<BlockHash> {
-        self.store
-            .indexed_headers
-            .read()
-            .unwrap()
+        self.snapshot()
+            .headers
             .header_by_height(height)
             .map(|entry| *entry.hash())
     }
 
     pub fn blockid_by_height(&self, height: usize) -> Option<BlockId> {
-        self.store
-            .indexed_headers
-            .read()
-            .unwrap()
+        self.snapshot()
+            .headers
             .header_by_height(height)
             .map(BlockId::from)
     }
 
     // returns None for orphaned blocks
     pub fn blockid_by_hash(&self, hash: &BlockHash) -> Option<BlockId> {
-        self.store
-            .indexed_headers
-            .read()
-            .unwrap()
+        self.snapshot()
+            .headers
             .header_by_blockhash(hash)
             .map(BlockId::from)
     }
 
     pub fn best_height(&self) -> usize {
-        self.store.indexed_headers.read().unwrap().len() - 1
+        self.snapshot().headers.len() - 1
     }
 
     pub fn best_hash(&self) -> BlockHash {
-        *self.store.indexed_headers.read().unwrap().tip()
+        *self.snapshot().headers.tip()
     }
 
     pub fn best_header(&self) -> HeaderEntry {
-        let headers = self.store.indexed_headers.read().unwrap();
+        let snapshot = self.snapshot();
+        let headers = &snapshot.headers;
         headers
             .header_by_blockhash(headers.tip())
             .expect("missing chain tip")
@@ -1251,35 +1631,30 @@ impl ChainQuery {
             let queried_blockhash =
                 blockhash.map_or_else(|| self.tx_confirming_block(txid).map(|b| b.hash), |_| None);
             let blockhash = blockhash.or(queried_blockhash.as_ref())?;
-            // TODO fetch transaction as binary from REST API instead of as hex
-            let txhex = self
-                .daemon
-                .gettransaction_raw(txid, blockhash, false)
-                .ok()?;
-            Some(hex::decode(txhex.as_str().unwrap()).unwrap())
+            self.daemon.gettransaction_bin(txid, blockhash).ok()
         } else {
-            self.store.txstore_db.get(&TxRow::key(&txid[..]))
+            self.snapshot().txstore_db.get(&TxRow::key(&txid[..]))
         }
     }
 
     pub fn lookup_txo(&self, outpoint: &OutPoint) -> Option<TxOut> {
         let _timer = self.start_timer("lookup_txo");
-        lookup_txo(&self.store.txstore_db, outpoint)
+        lookup_txo(&self.snapshot().txstore_db, outpoint)
     }
 
     pub fn lookup_txos(&self, outpoints: &BTreeSet<OutPoint>) -> HashMap<OutPoint, TxOut> {
         let _timer = self.start_timer("lookup_txos");
-        lookup_txos(&self.store.txstore_db, outpoints, false)
+        lookup_txos(&self.snapshot().txstore_db, outpoints, false)
     }
 
     pub fn lookup_avail_txos(&self, outpoints: &BTreeSet<OutPoint>) -> HashMap<OutPoint, TxOut> {
         let _timer = self.start_timer("lookup_available_txos");
-        lookup_txos(&self.store.txstore_db, outpoints, true)
+        lookup_txos(&self.snapshot().txstore_db, outpoints, true)
     }
 
     pub fn lookup_spend(&self, outpoint: &OutPoint) -> Option<SpendingInput> {
         let _timer = self.start_timer("lookup_spend");
-        self.store
+        self.snapshot()
             .history_db
             .iter_scan(&TxEdgeRow::filter(outpoint))
             .map(TxEdgeRow::from_row)
@@ -1294,8 +1669,9 @@ impl ChainQuery {
     }
     pub fn tx_confirming_block(&self, txid: &Txid) -> Option<BlockId> {
         let _timer = self.start_timer("tx_confirming_block");
-        let headers = self.store.indexed_headers.read().unwrap();
-        self.store
+        let snapshot = self.snapshot();
+        let headers = &snapshot.headers;
+        snapshot
             .txstore_db
             .iter_scan(&TxConfRow::filter(&txid[..]))
             .map(TxConfRow::from_row)
@@ -1312,7 +1688,8 @@ impl ChainQuery {
         // TODO differentiate orphaned and non-existing blocks? telling them apart requires
         // an additional db read.
 
-        let headers = self.store.indexed_headers.read().unwrap();
+        let snapshot = self.snapshot();
+        let headers = &snapshot.headers;
 
         // header_by_blockhash only returns blocks that are part of the best chain,
         // or None for orphaned blocks.
@@ -1408,17 +1785,40 @@ fn add_transaction(
     let txid = full_hash(&tx.txid()[..]);
     for (txo_index, txo) in tx.output.iter().enumerate() {
         if is_spendable(txo) {
-            let txo_row = TxOutRow::new(&txid, txo_index, txo);
-            let has_script_ref = txo_row.has_script_ref();
-            rows.push(txo_row.into_row());
-            if has_script_ref {
-                let (script_row, _) = ScriptRow::new(&txo.script_pubkey);
-                rows.push(script_row.into_row());
-            }
+            add_txout(&txid, txo_index, txo, rows);
         }
     }
 }
 
+fn add_txout(txid: &FullHash, vout: usize, txo: &TxOut, rows: &mut Vec<DBRow>) {
+    let txo_row = TxOutRow::new(txid, vout, txo);
+    let has_script_ref = txo_row.has_script_ref();
+    rows.push(txo_row.into_row());
+    if has_script_ref {
+        let (script_row, _) = ScriptRow::new(&txo.script_pubkey);
+        rows.push(script_row.into_row());
+    }
+}
+
+static TXSTORE_MIGRATIONS: &[Migration] = &[Migration {
+    version: 3,
+    description: "rewrite legacy TxOut rows as HybridTxOut",
+    prefix: b"O",
+    rewrite: migrate_legacy_txout,
+}];
+
+// lookup_txo() reads both formats, so queries are unaffected while this runs
+fn migrate_legacy_txout(row: &DBRow) -> Option<Vec<DBRow>> {
+    if deserialize::<HybridTxOut>(&row.value).is_ok() {
+        return None;
+    }
+    let txo: TxOut = deserialize(&row.value).ok()?;
+    let key: TxOutKey = bincode_util::deserialize_little(&row.key).ok()?;
+    let mut rows = vec![];
+    add_txout(&key.txid, key.vout as usize, &txo, &mut rows);
+    Some(rows)
+}
+
 fn get_previous_txos(block_entries: &[BlockEntry]) -> BTreeSet<OutPoint> {
     block_entries
         .iter()
@@ -1433,7 +1833,7 @@ fn get_previous_txos(block_entries: &[BlockEntry]) -> BTreeSet<OutPoint> {
 }
 
 fn lookup_txos(
-    txstore_db: &DB,
+    txstore_db: &impl DBRead,
     outpoints: &BTreeSet<OutPoint>,
     allow_missing: bool,
 ) -> HashMap<OutPoint, TxOut> {
@@ -1473,7 +1873,7 @@ fn lookup_txos(
 }
 
 fn lookup_txos_sequential(
-    txstore_db: &DB,
+    txstore_db: &impl DBRead,
     outpoints: &BTreeSet<OutPoint>,
     allow_missing: bool,
 ) -> HashMap<OutPoint, TxOut> {
@@ -1493,7 +1893,7 @@ fn lookup_txos_sequential(
         .collect()
 }
 
-fn lookup_txo(txstore_db: &DB, outpoint: &OutPoint) -> Option<TxOut> {
+fn lookup_txo(txstore_db: &impl DBRead, outpoint: &OutPoint) -> Option<TxOut> {
     txstore_db
         .get(&TxOutRow::get_key(outpoint))
         .and_then(|val| {
@@ -2005,6 +2405,10 @@ impl BlockRow {
         b"B".to_vec()
     }
 
+    fn header_key(hash: FullHash) -> Bytes {
+        [b"B", &hash[..]].concat()
+    }
+
     fn txids_key(hash: FullHash) -> Bytes {
         [b"X", &hash[..]].concat()
     }
@@ -0,0 +1,242 @@
+use std::sync::RwLock;
+use std::time::Instant;
+
+use crate::metrics::{Gauge, GaugeVec, MetricOpts, Metrics};
+use crate::new_index::db::{DBFlush, DBRow, DB};
+use crate::util::bincode_util;
+
+/// Where the checkpoint of the running sync phase is persisted, in the txstore DB
+const CHECKPOINT_KEY: &[u8] = b"p";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncPhase {
+    /// Storing the transactions of new blocks in the txstore DB
+    Add,
+    /// Indexing the history of new blocks in the history DB
+    Index,
+    Done,
+}
+
+impl SyncPhase {
+    fn label(self) -> &'static str {
+        match self {
+            SyncPhase::Add => "add",
+            SyncPhase::Index => "index",
+            SyncPhase::Done => "done",
+        }
+    }
+}
+
+/// Written after every batch of blocks, so that a restarted sync knows where it stopped.
+/// The blocks themselves are resumed from their done markers, this is only for reporting.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct SyncCheckpoint {
+    phase: SyncPhase,
+    blocks_done: usize,
+    blocks_total: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SyncStatus {
+    /// Whether the initial sync has completed, before that the REST API only serves this status
+    pub synced: bool,
+    pub phase: SyncPhase,
+    pub blocks_done: usize,
+    pub blocks_total: usize,
+    pub blocks_per_second: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_seconds: Option<u64>,
+}
+
+/// The progress of the indexer through the blocks of the running `Indexer::update`
+pub struct SyncProgress {
+    state: RwLock<SyncState>,
+}
+
+struct SyncState {
+    checkpoint: SyncCheckpoint,
+    started: Instant,
+    /// The blocks already done when this process took over the phase, which were not
+    /// processed since `started` and are left out of the rate
+    blocks_done_at_start: usize,
+}
+
+impl SyncProgress {
+    pub fn load(txstore_db: &DB) -> Self {
+        let checkpoint = txstore_db
+            .get(CHECKPOINT_KEY)
+            .map(|value| {
+                bincode_util::deserialize_little::<SyncCheckpoint>(&value)
+                    .expect("invalid sync checkpoint")
+            })
+            .unwrap_or(SyncCheckpoint {
+                phase: SyncPhase::Done,
+                blocks_done: 0,
+                blocks_total: 0,
+            });
+        if checkpoint.phase != SyncPhase::Done {
+            info!(
+                "resuming sync interrupted in the {} phase at {}/{} blocks",
+                checkpoint.phase.label(),
+                checkpoint.blocks_done,
+                checkpoint.blocks_total
+            );
+        }
+        SyncProgress {
+            state: RwLock::new(SyncState {
+                blocks_done_at_start: checkpoint.blocks_done,
+                checkpoint,
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn start(&self, txstore_db: &DB, phase: SyncPhase, blocks_total: usize, flush: DBFlush) {
+        let mut state = self.state.write().unwrap();
+        *state = SyncState {
+            checkpoint: SyncCheckpoint {
+                phase,
+                blocks_done: 0,
+                blocks_total,
+            },
+            started: Instant::now(),
+            blocks_done_at_start: 0,
+        };
+        save(txstore_db, &state.checkpoint, flush);
+    }
+
+    pub fn advance(&self, txstore_db: &DB, blocks: usize, flush: DBFlush) {
+        let mut state = self.state.write().unwrap();
+        state.checkpoint.blocks_done += blocks;
+        save(txstore_db, &state.checkpoint, flush);
+    }
+
+    pub fn finish(&self, txstore_db: &DB, flush: DBFlush) {
+        self.start(txstore_db, SyncPhase::Done, 0, flush);
+    }
+
+    pub fn status(&self, synced: bool) -> SyncStatus {
+        let state = self.state.read().unwrap();
+        let checkpoint = &state.checkpoint;
+        let elapsed = state.started.elapsed().as_secs_f64();
+        let blocks_per_second = if elapsed > 0.0 {
+            checkpoint
+                .blocks_done
+                .saturating_sub(state.blocks_done_at_start) as f64
+                / elapsed
+        } else {
+            0.0
+        };
+        let blocks_left = checkpoint
+            .blocks_total
+            .saturating_sub(checkpoint.blocks_done);
+        let eta_seconds = if blocks_left == 0 {
+            Some(0)
+        } else if blocks_per_second > 0.0 {
+            Some((blocks_left as f64 / blocks_per_second) as u64)
+        } else {
+            None
+        };
+        SyncStatus {
+            synced,
+            phase: checkpoint.phase,
+            blocks_done: checkpoint.blocks_done,
+            blocks_total: checkpoint.blocks_total,
+            blocks_per_second,
+            eta_seconds,
+        }
+    }
+}
+
+fn save(txstore_db: &DB, checkpoint: &SyncCheckpoint, flush: DBFlush) {
+    let row = DBRow {
+        key: CHECKPOINT_KEY.to_vec(),
+        value: bincode_util::serialize_little(checkpoint).unwrap(),
+    };
+    // written like the blocks themselves, so that it never gets ahead of them
+    txstore_db.write(vec![row], flush);
+}
+
+pub struct SyncMetrics {
+    blocks: GaugeVec,
+    blocks_per_second: GaugeVec,
+    eta: Gauge,
+}
+
+impl SyncMetrics {
+    pub fn new(metrics: &Metrics) -> Self {
+        SyncMetrics {
+            blocks: metrics.gauge_vec(
+                MetricOpts::new("sync_blocks", "Number of blocks in the running sync phase"),
+                &["phase", "type"],
+            ),
+            blocks_per_second: metrics.gauge_vec(
+                MetricOpts::new(
+                    "sync_blocks_per_second",
+                    "Number of blocks processed per second by the running sync phase",
+                ),
+                &["phase"],
+            ),
+            eta: metrics.gauge(MetricOpts::new(
+                "sync_eta_seconds",
+                "Estimated time left until the running sync phase completes",
+            )),
+        }
+    }
+
+    pub fn observe(&self, status: &SyncStatus) {
+        let phase = status.phase.label();
+        self.blocks
+            .with_label_values(&[phase, "done"])
+            .set(status.blocks_done as f64);
+        self.blocks
+            .with_label_values(&[phase, "total"])
+            .set(status.blocks_total as f64);
+        self.blocks_per_second
+            .with_label_values(&[phase])
+            .set(status.blocks_per_second);
+        self.eta.set(status.eta_seconds.unwrap_or(0) as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn progress(blocks_done: usize, blocks_total: usize) -> SyncProgress {
+        SyncProgress {
+            state: RwLock::new(SyncState {
+                checkpoint: SyncCheckpoint {
+                    phase: SyncPhase::Index,
+                    blocks_done,
+                    blocks_total,
+                },
+                started: Instant::now() - Duration::from_secs(10),
+                blocks_done_at_start: blocks_done,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_status_after_restart() {
+        let sync = progress(900, 1000);
+        // the blocks done before the restart are not counted towards the rate
+        let status = sync.status(false);
+        assert_eq!(status.blocks_done, 900);
+        assert_eq!(status.blocks_per_second, 0.0);
+        assert_eq!(status.eta_seconds, None);
+
+        sync.state.write().unwrap().checkpoint.blocks_done += 50;
+        let status = sync.status(false);
+        assert!(status.blocks_per_second > 4.9 && status.blocks_per_second <= 5.0);
+        assert_eq!(status.eta_seconds, Some(10));
+    }
+
+    #[test]
+    fn test_status_past_total() {
+        let status = progress(1001, 1000).status(false);
+        assert_eq!(status.eta_seconds, Some(0));
+    }
+}
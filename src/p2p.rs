@@ -0,0 +1,210 @@
+//! A minimal Bitcoin P2P client, to download blocks from the daemon in binary form.
+//!
+//! Only the version handshake, `ping`/`pong` and block `getdata` requests are supported.
+
+use bitcoin::consensus::encode::Encodable;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::VarInt;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpStream};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "opcat_layer")]
+use crate::opcat_layer::consensus::encode::deserialize;
+#[cfg(not(feature = "opcat_layer"))]
+use bitcoin::consensus::encode::deserialize;
+
+use crate::chain::{Block, BlockHash};
+use crate::config::{APP_NAME, ELECTRS_VERSION};
+use crate::errors::*;
+
+const PROTOCOL_VERSION: u32 = 70016;
+
+// Witness blocks, so that segwit transactions are complete
+#[cfg(not(feature = "opcat_layer"))]
+const INV_BLOCK: u32 = 0x4000_0002;
+#[cfg(feature = "opcat_layer")]
+const INV_BLOCK: u32 = 2;
+
+const HEADER_SIZE: usize = 24;
+// The daemon's limit (MAX_SIZE)
+const MAX_PAYLOAD_SIZE: usize = 0x0200_0000;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Peer {
+    stream: TcpStream,
+    magic: u32,
+}
+
+impl Peer {
+    /// Connect to the daemon's P2P port and do the version handshake
+    pub fn connect(addr: SocketAddr, magic: u32) -> Result<Peer> {
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)
+            .chain_err(|| format!("failed to connect to p2p peer {}", addr))?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .chain_err(|| "failed to set p2p read timeout")?;
+        let mut peer = Peer { stream, magic };
+
+        peer.send("version", &version_payload(addr))?;
+        let (mut version, mut verack) = (false, false);
+        while !(version && verack) {
+            let (command, _) = peer.recv()?;
+            match command.as_str() {
+                "version" => {
+                    version = true;
+                    peer.send("verack", &[])?;
+                }
+                "verack" => verack = true,
+                _ => (),
+            }
+        }
+        debug!("connected to p2p peer {}", addr);
+        Ok(peer)
+    }
+
+    /// Download the blocks, along with their serialized size
+    pub fn get_blocks(&mut self, blockhashes: &[BlockHash]) -> Result<Vec<(Block, u32)>> {
+        let mut getdata = vec![];
+        VarInt(blockhashes.len() as u64)
+            .consensus_encode(&mut getdata)
+            .chain_err(|| "failed to encode getdata")?;
+        for blockhash in blockhashes {
+            getdata.extend_from_slice(&INV_BLOCK.to_le_bytes());
+            getdata.extend_from_slice(&blockhash.into_inner());
+        }
+        self.send("getdata", &getdata)?;
+
+        let mut wanted: HashSet<&BlockHash> = blockhashes.iter().collect();
+        let mut blocks = HashMap::new();
+        while !wanted.is_empty() {
+            let (command, payload) = self.recv()?;
+            match command.as_str() {
+                "block" => {
+                    let block: Block = deserialize(&payload).chain_err(|| "invalid p2p block")?;
+                    let blockhash = block.block_hash();
+                    if wanted.remove(&blockhash) {
+                        blocks.insert(blockhash, (block, payload.len() as u32));
+                    }
+                }
+                "notfound" => bail!("p2p peer doesn't have the requested blocks"),
+                _ => (),
+            }
+        }
+        Ok(blockhashes
+            .iter()
+            .map(|blockhash| blocks.remove(blockhash).unwrap())
+            .collect())
+    }
+
+    fn send(&mut self, command: &str, payload: &[u8]) -> Result<()> {
+        let message = encode_message(self.magic, command, payload);
+        self.stream
+            .write_all(&message)
+            .chain_err(|| format!("failed to send p2p {}", command))
+    }
+
+    // The next message, answering pings in the meantime
+    fn recv(&mut self) -> Result<(String, Vec<u8>)> {
+        loop {
+            let (command, payload) = decode_message(&mut self.stream, self.magic)?;
+            if command == "ping" {
+                self.send("pong", &payload)?;
+                continue;
+            }
+            return Ok((command, payload));
+        }
+    }
+}
+
+fn version_payload(addr: SocketAddr) -> Vec<u8> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let user_agent = format!("/{}:{}/", APP_NAME, ELECTRS_VERSION);
+
+    let mut payload = vec![];
+    payload.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    payload.extend_from_slice(&0u64.to_le_bytes()); // no services
+    payload.extend_from_slice(&now.as_secs().to_le_bytes());
+    payload.extend_from_slice(&net_addr(addr));
+    payload.extend_from_slice(&net_addr(SocketAddr::new(
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        0,
+    )));
+    payload.extend_from_slice(&(now.subsec_nanos() as u64 ^ now.as_secs()).to_le_bytes()); // nonce
+    payload.push(user_agent.len() as u8);
+    payload.extend_from_slice(user_agent.as_bytes());
+    payload.extend_from_slice(&0u32.to_le_bytes()); // start height
+    payload.push(0); // don't relay transactions
+    payload
+}
+
+fn net_addr(addr: SocketAddr) -> [u8; 26] {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let mut net_addr = [0u8; 26];
+    net_addr[8..24].copy_from_slice(&ip.octets());
+    net_addr[24..].copy_from_slice(&addr.port().to_be_bytes());
+    net_addr
+}
+
+fn encode_message(magic: u32, command: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_SIZE + payload.len());
+    message.extend_from_slice(&magic.to_le_bytes());
+    let mut name = [0u8; 12];
+    name[..command.len()].copy_from_slice(command.as_bytes());
+    message.extend_from_slice(&name);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&sha256d::Hash::hash(payload)[..4]);
+    message.extend_from_slice(payload);
+    message
+}
+
+fn decode_message(stream: &mut impl Read, magic: u32) -> Result<(String, Vec<u8>)> {
+    let mut header = [0u8; HEADER_SIZE];
+    stream
+        .read_exact(&mut header)
+        .chain_err(|| "failed to read p2p message")?;
+    if header[..4] != magic.to_le_bytes() {
+        bail!("invalid p2p message magic");
+    }
+    let command = String::from_utf8_lossy(&header[4..16])
+        .trim_end_matches('\0')
+        .to_string();
+    let size = u32::from_le_bytes([header[16], header[17], header[18], header[19]]) as usize;
+    if size > MAX_PAYLOAD_SIZE {
+        bail!("p2p {} message too large ({} bytes)", command, size);
+    }
+    let mut payload = vec![0; size];
+    stream
+        .read_exact(&mut payload)
+        .chain_err(|| format!("failed to read p2p {}", command))?;
+    if sha256d::Hash::hash(&payload)[..4] != header[20..] {
+        bail!("invalid p2p {} checksum", command);
+    }
+    Ok((command, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_message, encode_message};
+
+    #[test]
+    fn test_p2p_messages() {
+        let magic = 0xd9b4bef9;
+        let message = encode_message(magic, "ping", &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(message.len(), 24 + 8);
+
+        let (command, payload) = decode_message(&mut &message[..], magic).unwrap();
+        assert_eq!(command, "ping");
+        assert_eq!(payload, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+
+        assert!(decode_message(&mut &message[..], magic + 1).is_err());
+        let mut corrupted = message.clone();
+        corrupted[30] ^= 1;
+        assert!(decode_message(&mut &corrupted[..], magic).is_err());
+    }
+}
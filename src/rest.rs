@@ -3,6 +3,7 @@ use crate::config::{Config, VERSION_STRING};
 use crate::errors;
 use crate::metrics::Metrics;
 use crate::new_index::{compute_script_hash, Query, SpendingInput, Utxo};
+use crate::util::psbt::{self, Psbt};
 use crate::util::{
     create_socket, electrum_merkle, extract_tx_prevouts, full_hash, get_innerscripts, get_tx_fee,
     has_prevout, is_coinbase, transaction_sigop_count, BlockHeaderMeta, BlockId, FullHash,
@@ -15,7 +16,10 @@ use {bitcoin::consensus::encode, std::str::FromStr};
 use bitcoin::blockdata::opcodes;
 use bitcoin::hashes::hex::{FromHex, ToHex};
 use bitcoin::hashes::Error as HashError;
+use bitcoin::VarInt;
 use hex::{self, FromHexError};
+use hyper::header::HeaderMap;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Response, Server, StatusCode};
 use prometheus::{HistogramOpts, HistogramVec};
@@ -27,7 +31,8 @@ use std::{cmp, fs};
 
 use serde::Serialize;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::num::ParseIntError;
 use std::os::unix::fs::FileTypeExt;
 use std::sync::Arc;
@@ -37,12 +42,27 @@ use url::form_urlencoded;
 const ADDRESS_SEARCH_LIMIT: usize = 10;
 // Limit to 300 addresses
 const MULTI_ADDRESS_LIMIT: usize = 300;
+// Standard transactions are limited to 400k weight units
+const MAX_DECODE_TX_SIZE: usize = 400_000;
+const MAX_PSBT_SIZE: usize = 4_000_000;
 
 const TTL_SHORT: u32 = 10; // 10 seconds
 const TTL_LONG: u32 = 157784760; // 5 years
 const TTL_MEMPOOL_RECENT: u32 = 60; // 1 minute
 const CONF_FINAL: usize = 100; // consider transactions final after 100 confirmations
 const INTERNAL_PREFIX: &str = "internal";
+const CHECKPOINT_RETRY_AFTER: u32 = 10; // seconds to wait when an index update is running
+
+mod auth;
+mod cache;
+mod encoding;
+mod openapi;
+mod routes;
+
+use self::auth::{Access, ApiKeys};
+use self::cache::CacheValidator;
+use self::encoding::{compress_response, ContentEncoding, ResponseFormat};
+use self::routes::{Request, Route, Schema};
 
 #[derive(Serialize)]
 struct BlockValue {
@@ -159,6 +179,9 @@ struct TransactionValue {
     fee: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     status: Option<TransactionStatus>,
+    // for mempool transactions, accounting for their ancestors and descendants (CPFP)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effective_fee_rate: Option<f64>,
 }
 
 impl TransactionValue {
@@ -171,7 +194,26 @@ impl TransactionValue {
         let prevouts = extract_tx_prevouts(&tx, txos)?;
         let sigops = transaction_sigop_count(&tx, &prevouts)
             .map_err(|_| errors::Error::from("Couldn't count sigops"))? as u32;
+        let fee = get_tx_fee(&tx, &prevouts, config.network_type);
+
+        Ok(TransactionValue::with_prevouts(
+            &tx,
+            &prevouts,
+            sigops,
+            fee,
+            Some(TransactionStatus::from(blockid)),
+            config,
+        ))
+    }
 
+    fn with_prevouts(
+        tx: &Transaction,
+        prevouts: &HashMap<u32, &TxOut>,
+        sigops: u32,
+        fee: u64,
+        status: Option<TransactionStatus>,
+        config: &Config,
+    ) -> Self {
         let vins: Vec<TxInValue> = tx
             .input
             .iter()
@@ -186,10 +228,8 @@ impl TransactionValue {
             .map(|txout| TxOutValue::new(txout, config))
             .collect();
 
-        let fee = get_tx_fee(&tx, &prevouts, config.network_type);
-
         #[allow(clippy::unnecessary_cast)]
-        Ok(TransactionValue {
+        TransactionValue {
             txid: tx.txid(),
             version: tx.version as u32,
             locktime: tx.lock_time,
@@ -199,11 +239,383 @@ impl TransactionValue {
             weight: tx.weight() as u32,
             sigops,
             fee,
-            status: Some(TransactionStatus::from(blockid)),
+            status,
+            effective_fee_rate: None,
+        }
+    }
+}
+
+/// A transaction submitted to `POST /tx/decode`, with its validation report.
+#[derive(Serialize)]
+struct DecodedTxValue {
+    #[serde(flatten)]
+    tx: TransactionValue,
+    validation: TxValidation,
+}
+
+#[derive(Serialize, Default)]
+struct TxValidation {
+    valid: bool,
+    // whether the transaction is already confirmed or in the mempool
+    known: bool,
+    missing_prevouts: Vec<InputRef>,
+    spent_prevouts: Vec<SpentPrevout>,
+    errors: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct InputRef {
+    vin: u32,
+    txid: Txid,
+    vout: u32,
+}
+
+#[derive(Serialize)]
+struct SpentPrevout {
+    #[serde(flatten)]
+    input: InputRef,
+    spent_by: SpendingValue,
+}
+
+impl DecodedTxValue {
+    fn new(tx: Transaction, query: &Query, config: &Config) -> Self {
+        let txid = tx.txid();
+        let outpoints = tx
+            .input
+            .iter()
+            .filter(|txin| has_prevout(txin))
+            .map(|txin| txin.previous_output)
+            .collect();
+        let txos = query.lookup_txos(&outpoints);
+
+        let mut validation = TxValidation::default();
+        let mut prevouts = HashMap::new();
+        let mut seen = HashSet::new();
+        for (vin, txin) in tx.input.iter().enumerate() {
+            if !has_prevout(txin) {
+                continue;
+            }
+            let outpoint = txin.previous_output;
+            let input = InputRef {
+                vin: vin as u32,
+                txid: outpoint.txid,
+                vout: outpoint.vout,
+            };
+            if !seen.insert(outpoint) {
+                validation
+                    .errors
+                    .push(format!("input {} spends a duplicate outpoint", vin));
+            }
+            match txos.get(&outpoint) {
+                Some(txo) => {
+                    prevouts.insert(vin as u32, txo);
+                }
+                None => {
+                    validation.missing_prevouts.push(input);
+                    continue;
+                }
+            }
+            match query.lookup_spend(&outpoint) {
+                // the transaction itself, if it was already broadcast
+                Some(spend) if spend.txid == txid => (),
+                Some(spend) => validation.spent_prevouts.push(SpentPrevout {
+                    input,
+                    spent_by: SpendingValue::from(spend),
+                }),
+                None => (),
+            }
+        }
+
+        if tx.input.is_empty() {
+            validation.errors.push("transaction has no inputs".into());
+        }
+        if tx.output.is_empty() {
+            validation.errors.push("transaction has no outputs".into());
+        }
+
+        // the fee and sigops can only be computed once all prevouts are known
+        let (sigops, fee) = if validation.missing_prevouts.is_empty() {
+            let total_in: u64 = prevouts.values().map(|prevout| prevout.value).sum();
+            let total_out: u64 = tx.output.iter().map(|vout| vout.value).sum();
+            let sigops = transaction_sigop_count(&tx, &prevouts).unwrap_or_else(|_| {
+                validation.errors.push("couldn't count sigops".into());
+                0
+            });
+            if tx.is_coin_base() || total_in >= total_out {
+                (
+                    sigops as u32,
+                    get_tx_fee(&tx, &prevouts, config.network_type),
+                )
+            } else {
+                validation.errors.push(format!(
+                    "outputs ({}) exceed inputs ({})",
+                    total_out, total_in
+                ));
+                (sigops as u32, 0)
+            }
+        } else {
+            (0, 0)
+        };
+
+        let blockid = query.chain().tx_confirming_block(&txid);
+        validation.known = blockid.is_some() || query.mempool().lookup_txn(&txid).is_some();
+        validation.valid = validation.missing_prevouts.is_empty()
+            && validation.spent_prevouts.is_empty()
+            && validation.errors.is_empty();
+
+        DecodedTxValue {
+            tx: TransactionValue::with_prevouts(
+                &tx,
+                &prevouts,
+                sigops,
+                fee,
+                Some(TransactionStatus::from(blockid)),
+                config,
+            ),
+            validation,
+        }
+    }
+}
+
+/// The result of `POST /psbt/analyze`.
+#[derive(Serialize)]
+struct PsbtAnalysisValue {
+    txid: Txid,
+    inputs: Vec<PsbtInputValue>,
+    // all inputs are finalized
+    complete: bool,
+    fee: Option<u64>,
+    estimated_vsize: Option<u32>,
+    estimated_feerate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    psbt: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PsbtInputValue {
+    txid: Txid,
+    vout: u32,
+    status: PsbtInputStatus,
+    utxo_source: UtxoSource,
+    prevout: Option<TxOutValue>,
+    partial_sigs: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spent_by: Option<SpendingValue>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum PsbtInputStatus {
+    Finalized,
+    PartiallySigned,
+    Unsigned,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum UtxoSource {
+    // provided in the PSBT
+    Psbt,
+    // looked up in the chain or mempool
+    Index,
+    Missing,
+}
+
+impl PsbtAnalysisValue {
+    fn new(
+        mut psbt: Psbt,
+        update: bool,
+        query: &Query,
+        config: &Config,
+    ) -> Result<Self, HttpError> {
+        let tx = &psbt.unsigned_tx;
+        let outpoints = tx.input.iter().map(|txin| txin.previous_output).collect();
+        let txos = query.lookup_txos(&outpoints);
+
+        let mut prevouts = vec![];
+        let mut inputs = vec![];
+        for (index, txin) in tx.input.iter().enumerate() {
+            let outpoint = txin.previous_output;
+            let (prevout, utxo_source) = match psbt.input_utxo(index)? {
+                Some(txout) => (Some(txout), UtxoSource::Psbt),
+                None => match txos.get(&outpoint) {
+                    Some(txout) => (Some(txout.clone()), UtxoSource::Index),
+                    None => (None, UtxoSource::Missing),
+                },
+            };
+            let partial_sigs = psbt.inputs[index].count(psbt::IN_PARTIAL_SIG);
+            let status = if psbt.is_finalized(index) {
+                PsbtInputStatus::Finalized
+            } else if partial_sigs > 0 {
+                PsbtInputStatus::PartiallySigned
+            } else {
+                PsbtInputStatus::Unsigned
+            };
+            inputs.push(PsbtInputValue {
+                txid: outpoint.txid,
+                vout: outpoint.vout,
+                status,
+                utxo_source,
+                prevout: prevout.as_ref().map(|txout| TxOutValue::new(txout, config)),
+                partial_sigs,
+                spent_by: query.lookup_spend(&outpoint).map(SpendingValue::from),
+            });
+            prevouts.push(prevout);
+        }
+
+        let fee = prevouts
+            .iter()
+            .map(|prevout| prevout.as_ref().map(|txout| txout.value))
+            .sum::<Option<u64>>()
+            .and_then(|total_in| {
+                total_in.checked_sub(tx.output.iter().map(|txout| txout.value).sum::<u64>())
+            });
+        let estimated_vsize = estimate_psbt_vsize(&psbt, &prevouts);
+        let estimated_feerate = fee
+            .zip(estimated_vsize)
+            .map(|(fee, vsize)| fee as f64 / vsize as f64);
+
+        if update {
+            for (index, input) in inputs.iter().enumerate() {
+                if input.utxo_source != UtxoSource::Index {
+                    continue;
+                }
+                let txout = prevouts[index].as_ref().unwrap();
+                if let Some(prev_tx) = query.lookup_txn(&input.txid) {
+                    psbt.inputs[index]
+                        .insert(psbt::IN_NON_WITNESS_UTXO, encode::serialize(&prev_tx));
+                }
+                if txout.script_pubkey.is_witness_program() {
+                    psbt.inputs[index].insert(psbt::IN_WITNESS_UTXO, encode::serialize(txout));
+                }
+            }
+        }
+
+        Ok(PsbtAnalysisValue {
+            txid: psbt.unsigned_tx.txid(),
+            complete: inputs
+                .iter()
+                .all(|input| input.status == PsbtInputStatus::Finalized),
+            inputs,
+            fee,
+            estimated_vsize,
+            estimated_feerate,
+            psbt: if update {
+                Some(base64::encode(psbt.serialize()))
+            } else {
+                None
+            },
         })
     }
 }
 
+/// Estimate the virtual size of the final transaction, using the final scripts of
+/// finalized inputs and typical signature sizes for the others.
+fn estimate_psbt_vsize(psbt: &Psbt, prevouts: &[Option<TxOut>]) -> Option<u32> {
+    let mut base_size = psbt.unsigned_tx.size();
+    let mut witness_sizes = vec![];
+    for (index, prevout) in prevouts.iter().enumerate() {
+        let (scriptsig_len, witness_len) = estimate_input_size(psbt, index, prevout.as_ref())?;
+        // the unsigned transaction already has an empty scriptSig
+        base_size += VarInt(scriptsig_len as u64).len() - 1 + scriptsig_len;
+        witness_sizes.push(witness_len);
+    }
+
+    #[cfg(not(feature = "opcat_layer"))]
+    let weight = if witness_sizes.iter().any(|len| *len > 0) {
+        // marker and flag, plus an empty stack for inputs without a witness
+        let witness_size: usize = witness_sizes.iter().map(|len| cmp::max(*len, 1)).sum();
+        base_size * 4 + 2 + witness_size
+    } else {
+        base_size * 4
+    };
+
+    // there are no witnesses in the OPCAT layer
+    #[cfg(feature = "opcat_layer")]
+    let weight = if witness_sizes.iter().any(|len| *len > 0) {
+        return None;
+    } else {
+        base_size * 4
+    };
+
+    Some(weight.div_ceil(4) as u32)
+}
+
+// The scriptSig and serialized witness sizes of the input, once finalized
+fn estimate_input_size(
+    psbt: &Psbt,
+    index: usize,
+    prevout: Option<&TxOut>,
+) -> Option<(usize, usize)> {
+    const SIG_LEN: usize = 73; // with the sighash flag
+    const PUBKEY_LEN: usize = 33;
+
+    let input = &psbt.inputs[index];
+    if psbt.is_finalized(index) {
+        return Some((
+            input
+                .get(psbt::IN_FINAL_SCRIPTSIG)
+                .map_or(0, |script| script.len()),
+            input
+                .get(psbt::IN_FINAL_SCRIPTWITNESS)
+                .map_or(0, |witness| witness.len()),
+        ));
+    }
+
+    let script = &prevout?.script_pubkey;
+    // the pushed stack items, each prefixed with its length
+    let stack = |items: &[usize]| -> usize { items.iter().map(|len| 1 + len).sum() };
+    // the signatures of an m-of-n CHECKMULTISIG script
+    let multisig_sigs = |script: &[u8]| -> Option<usize> {
+        match (script.first(), script.last()) {
+            (Some(m @ 0x51..=0x60), Some(0xae)) => Some((*m - 0x50) as usize * (1 + SIG_LEN)),
+            _ => None,
+        }
+    };
+    // item count, the dummy element, the signatures and the witness script
+    let p2wsh_witness = |witness_script: &[u8]| -> Option<usize> {
+        let script_len = witness_script.len();
+        Some(2 + multisig_sigs(witness_script)? + VarInt(script_len as u64).len() + script_len)
+    };
+
+    if script.is_p2pkh() {
+        Some((stack(&[SIG_LEN, PUBKEY_LEN]), 0))
+    } else if script.is_v0_p2wpkh() {
+        Some((0, 1 + stack(&[SIG_LEN, PUBKEY_LEN])))
+    } else if is_v1_p2tr(script) {
+        // key path spend with the default sighash
+        Some((0, 1 + stack(&[64])))
+    } else if script.is_v0_p2wsh() {
+        Some((0, p2wsh_witness(input.get(psbt::IN_WITNESS_SCRIPT)?)?))
+    } else if script.is_p2sh() {
+        let redeem_script = input.get(psbt::IN_REDEEM_SCRIPT)?;
+        let redeem_push = 1 + redeem_script.len();
+        if redeem_script.len() == 22 && redeem_script[..2] == [0x00, 0x14] {
+            // P2SH-P2WPKH
+            Some((redeem_push, 1 + stack(&[SIG_LEN, PUBKEY_LEN])))
+        } else if redeem_script.len() == 34 && redeem_script[..2] == [0x00, 0x20] {
+            // P2SH-P2WSH
+            Some((
+                redeem_push,
+                p2wsh_witness(input.get(psbt::IN_WITNESS_SCRIPT)?)?,
+            ))
+        } else {
+            // OP_0, the signatures and the redeem script push
+            let push_len = match redeem_script.len() {
+                0..=75 => 1,
+                76..=255 => 2,
+                _ => 3,
+            };
+            Some((
+                1 + multisig_sigs(redeem_script)? + push_len + redeem_script.len(),
+                0,
+            ))
+        }
+    } else {
+        None
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 struct TxInValue {
     txid: Txid,
@@ -496,15 +908,38 @@ fn prepare_txs(
 
     let prevouts = query.lookup_txos(&outpoints);
 
-    txs.into_iter()
+    let mut values: Vec<TransactionValue> = txs
+        .into_iter()
         .filter_map(|(tx, blockid)| TransactionValue::new(tx, blockid, &prevouts, config).ok())
-        .collect()
+        .collect();
+
+    // only lock the mempool when there are unconfirmed transactions
+    let mut unconfirmed = values
+        .iter_mut()
+        .filter(|value| {
+            value
+                .status
+                .as_ref()
+                .is_some_and(|status| !status.confirmed)
+        })
+        .peekable();
+    if unconfirmed.peek().is_some() {
+        let mempool = query.mempool();
+        for value in unconfirmed {
+            value.effective_fee_rate = mempool.effective_fee_rate(&value.txid);
+            if let Some(status) = value.status.as_mut() {
+                status.first_seen = mempool.first_seen(&value.txid);
+            }
+        }
+    }
+    values
 }
 
 #[tokio::main]
 async fn run_server(
     config: Arc<Config>,
     query: Arc<Query>,
+    api_keys: Arc<ApiKeys>,
     rx: oneshot::Receiver<()>,
     metric: HistogramVec,
 ) {
@@ -514,38 +949,49 @@ async fn run_server(
     let config = Arc::clone(&config);
     let query = Arc::clone(&query);
 
-    let make_service_fn_inn = || {
+    let make_service_fn_inn = |client: Option<IpAddr>| {
         let query = Arc::clone(&query);
         let config = Arc::clone(&config);
+        let api_keys = Arc::clone(&api_keys);
         let metric = metric.clone();
 
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
                 let query = Arc::clone(&query);
                 let config = Arc::clone(&config);
+                let api_keys = Arc::clone(&api_keys);
                 let timer = metric.with_label_values(&["all_methods"]).start_timer();
 
                 async move {
                     let method = req.method().clone();
                     let uri = req.uri().clone();
+                    let headers = req.headers().clone();
+                    let access = api_keys.authorize(&headers, uri.query(), client);
                     let body = hyper::body::to_bytes(req.into_body()).await?;
 
-                    let mut resp = tokio::task::block_in_place(|| {
-                        handle_request(method, uri, body, &query, &config)
-                    })
-                    .unwrap_or_else(|err| {
-                        warn!("{:?}", err);
-                        Response::builder()
-                            .status(err.0)
-                            .header("Content-Type", "text/plain")
-                            .header("X-Powered-By", &**VERSION_STRING)
-                            .body(Body::from(err.1))
-                            .unwrap()
-                    });
+                    let mut resp = access
+                        .and_then(|access| {
+                            tokio::task::block_in_place(|| {
+                                handle_request(
+                                    method, uri, &headers, body, &query, &config, &access,
+                                )
+                            })
+                        })
+                        .unwrap_or_else(|err| {
+                            warn!("{:?}", err);
+                            Response::builder()
+                                .status(err.0)
+                                .header("Content-Type", "text/plain")
+                                .header("X-Powered-By", &**VERSION_STRING)
+                                .body(Body::from(err.1))
+                                .unwrap()
+                        });
                     if let Some(ref origins) = config.cors {
                         resp.headers_mut()
                             .insert("Access-Control-Allow-Origin", origins.parse().unwrap());
                     }
+                    let resp =
+                        compress_response(resp, ContentEncoding::from_headers(&headers)).await?;
                     timer.observe_duration();
                     Ok::<_, hyper::Error>(resp)
                 }
@@ -562,7 +1008,9 @@ async fn run_server(
 
             Server::from_tcp(socket.into())
                 .expect("Server::from_tcp failed")
-                .serve(make_service_fn(move |_| make_service_fn_inn()))
+                .serve(make_service_fn(move |conn: &AddrStream| {
+                    make_service_fn_inn(Some(conn.remote_addr().ip()))
+                }))
                 .with_graceful_shutdown(async {
                     rx.await.ok();
                 })
@@ -580,7 +1028,7 @@ async fn run_server(
 
             Server::bind_unix(path)
                 .expect("Server::bind_unix failed")
-                .serve(make_service_fn(move |_| make_service_fn_inn()))
+                .serve(make_service_fn(move |_| make_service_fn_inn(None)))
                 .with_graceful_shutdown(async {
                     rx.await.ok();
                 })
@@ -599,11 +1047,12 @@ pub fn start(config: Arc<Config>, query: Arc<Query>, metrics: &Metrics) -> Handl
         HistogramOpts::new("electrs_rest_api", "Electrs REST API response timings"),
         &["method"],
     );
+    let api_keys = ApiKeys::start(&config, metrics);
 
     Handle {
         tx,
         thread: crate::util::spawn_thread("rest-server", move || {
-            run_server(config, query, rx, response_timer);
+            run_server(config, query, api_keys, rx, response_timer);
         }),
     }
 }
@@ -623,12 +1072,17 @@ impl Handle {
 fn handle_request(
     method: Method,
     uri: hyper::Uri,
+    headers: &HeaderMap,
     body: hyper::body::Bytes,
     query: &Query,
     config: &Config,
+    access: &Access,
 ) -> Result<Response<Body>, HttpError> {
-    // TODO it looks hyper does not have routing and query parsing :(
+    // hyper has no routing, requests are dispatched using the ROUTES table below
     let path: Vec<&str> = uri.path().split('/').skip(1).collect();
+    if path.first() == Some(&INTERNAL_PREFIX) && !access.internal {
+        return http_message(StatusCode::FORBIDDEN, "Access denied", 0);
+    }
     let query_params = match uri.query() {
         Some(value) => form_urlencoded::parse(value.as_bytes())
             .into_owned()
@@ -636,1146 +1090,1832 @@ fn handle_request(
         None => HashMap::new(),
     };
 
+    // the validator and the handler read the chain from the same snapshot, so that a
+    // block connected mid-request can't mix two chain states into one response
+    let query = &query.with_snapshot();
+    let format = ResponseFormat::from_headers(headers);
+    let validator = CacheValidator::for_request(&method, &path, &query_params, query, format);
+    if let Some(ref validator) = validator {
+        if validator.matches(headers) {
+            return Ok(validator.not_modified());
+        }
+    }
+
     info!("handle {:?} {:?}", method, uri);
-    match (
+    let (route, params) = routes::find(&ROUTES, &method, &path)
+        .ok_or_else(|| HttpError::not_found(format!("endpoint does not exist {:?}", uri.path())))?;
+
+    // nothing but the sync status is served until the initial sync completes, as the
+    // index would only return partial data
+    if route.path != SYNC_STATUS_PATH && !query.chain().store().done_initial_sync() {
+        let status = query.chain().store().sync_status();
+        return Err(HttpError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!(
+                "Initial sync in progress ({}/{} blocks in this phase), see {}",
+                status.blocks_done, status.blocks_total, SYNC_STATUS_PATH
+            ),
+        ));
+    }
+    let req = Request::new(
         &method,
-        path.first(),
-        path.get(1),
-        path.get(2),
-        path.get(3),
-        path.get(4),
-    ) {
-        (&Method::GET, Some(&"blocks"), Some(&"tip"), Some(&"hash"), None, None) => http_message(
-            StatusCode::OK,
-            query.chain().best_hash().to_hex(),
-            TTL_SHORT,
-        ),
+        path,
+        params,
+        query_params,
+        body,
+        query,
+        config,
+        access,
+        format,
+    );
+    let mut resp = (route.handler)(&req)?;
+
+    if let Some(validator) = validator {
+        validator.apply(&mut resp);
+    }
+    Ok(resp)
+}
+
+lazy_static! {
+    static ref ROUTES: Vec<Route> = routes();
+}
 
-        (&Method::GET, Some(&"blocks"), Some(&"tip"), Some(&"height"), None, None) => http_message(
-            StatusCode::OK,
-            query.chain().best_height().to_string(),
-            TTL_SHORT,
+const SYNC_STATUS_PATH: &str = "/sync/status";
+
+const TXID: Schema = Schema::String;
+const TXIDS: Schema = Schema::Array(&Schema::String);
+const TRANSACTIONS: Schema = Schema::Array(&Schema::Ref("Transaction"));
+const OUTSPENDS: Schema = Schema::Array(&Schema::Ref("Outspend"));
+const TX_SUMMARIES: Schema = Schema::Array(&Schema::Ref("TxSummary"));
+
+/// The REST API route table, used both for dispatching requests and for
+/// generating the OpenAPI document.
+fn routes() -> Vec<Route> {
+    #[allow(unused_mut)]
+    let mut routes = vec![
+        Route::get(
+            "/blocks/tip/hash",
+            blocks_tip_hash,
+            "Get the hash of the last block",
         ),
+        Route::get(
+            "/blocks/tip/height",
+            blocks_tip_height,
+            "Get the height of the last block",
+        ),
+        Route::get("/blocks", recent_blocks, "Get the most recent blocks")
+            .returns(Schema::Array(&Schema::Ref("Block"))),
+        Route::get(
+            "/blocks/:start_height",
+            recent_blocks,
+            "Get the blocks preceding start_height (inclusive)",
+        )
+        .returns(Schema::Array(&Schema::Ref("Block"))),
+        Route::get(
+            "/block-height/:height",
+            block_at_height,
+            "Get the hash of the block at the given height",
+        ),
+        Route::get("/block/:hash", block, "Get block information")
+            .returns(Schema::Ref("Block")),
+        Route::get("/block/:hash/status", block_status, "Get the block status")
+            .returns(Schema::Ref("BlockStatus")),
+        Route::get(
+            "/block/:hash/txids",
+            block_txids,
+            "Get the txids of all the transactions in the block",
+        )
+        .returns(TXIDS),
+        Route::get(
+            "/internal/block/:hash/txs",
+            internal_block_txs,
+            "Get all the transactions in the block",
+        )
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/block/:hash/header",
+            block_header,
+            "Get the hex-encoded block header",
+        ),
+        Route::get("/block/:hash/raw", block_raw, "Get the raw block")
+            .returns(Schema::Binary),
+        Route::get(
+            "/block/:hash/txid/:index",
+            block_txid_at_index,
+            "Get the txid of the transaction at the given index in the block",
+        ),
+        Route::get(
+            "/block/:hash/txs",
+            block_txs,
+            "Get the first page of transactions in the block",
+        )
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/block/:hash/txs/:start_index",
+            block_txs,
+            "Get a page of transactions in the block, starting at start_index",
+        )
+        .returns(TRANSACTIONS),
+        Route::get("/address/:address", address_stats, "Get address statistics")
+            .returns(Schema::Ref("AddressStats")),
+        Route::get(
+            "/scripthash/:hash",
+            address_stats,
+            "Get scripthash statistics",
+        )
+        .returns(Schema::Ref("AddressStats")),
+        Route::get(
+            "/address/:address/txs",
+            address_txs,
+            "Get the address transaction history, mempool transactions first",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .query("after_txid", TXID, "Return transactions after this txid")
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/scripthash/:hash/txs",
+            address_txs,
+            "Get the scripthash transaction history, mempool transactions first",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .query("after_txid", TXID, "Return transactions after this txid")
+        .returns(TRANSACTIONS),
+        Route::post(
+            "/addresses/txs",
+            multi_address_txs,
+            "Get the transaction history of multiple addresses",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .query("after_txid", TXID, "Return transactions after this txid")
+        .body(Schema::Array(&Schema::String))
+        .returns(TRANSACTIONS),
+        Route::post(
+            "/scripthashes/txs",
+            multi_address_txs,
+            "Get the transaction history of multiple scripthashes",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .query("after_txid", TXID, "Return transactions after this txid")
+        .body(Schema::Array(&Schema::String))
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/address/:address/txs/chain",
+            address_chain_txs,
+            "Get the confirmed address transaction history",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/address/:address/txs/chain/:last_seen_txid",
+            address_chain_txs,
+            "Get the confirmed address transaction history after last_seen_txid",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/scripthash/:hash/txs/chain",
+            address_chain_txs,
+            "Get the confirmed scripthash transaction history",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/scripthash/:hash/txs/chain/:last_seen_txid",
+            address_chain_txs,
+            "Get the confirmed scripthash transaction history after last_seen_txid",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/address/:address/txs/summary",
+            address_txs_summary,
+            "Get a summary of the confirmed address transaction history",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TX_SUMMARIES),
+        Route::get(
+            "/address/:address/txs/summary/:last_seen_txid",
+            address_txs_summary,
+            "Get a summary of the confirmed address transaction history after last_seen_txid",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TX_SUMMARIES),
+        Route::get(
+            "/scripthash/:hash/txs/summary",
+            address_txs_summary,
+            "Get a summary of the confirmed scripthash transaction history",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TX_SUMMARIES),
+        Route::get(
+            "/scripthash/:hash/txs/summary/:last_seen_txid",
+            address_txs_summary,
+            "Get a summary of the confirmed scripthash transaction history after last_seen_txid",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TX_SUMMARIES),
+        Route::post(
+            "/addresses/txs/summary",
+            multi_address_txs_summary,
+            "Get a summary of the confirmed transaction history of multiple addresses",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .body(Schema::Array(&Schema::String))
+        .returns(TX_SUMMARIES),
+        Route::post(
+            "/addresses/txs/summary/:last_seen_txid",
+            multi_address_txs_summary,
+            "Get a summary of the confirmed transaction history of multiple addresses after last_seen_txid",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .body(Schema::Array(&Schema::String))
+        .returns(TX_SUMMARIES),
+        Route::post(
+            "/scripthashes/txs/summary",
+            multi_address_txs_summary,
+            "Get a summary of the confirmed transaction history of multiple scripthashes",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .body(Schema::Array(&Schema::String))
+        .returns(TX_SUMMARIES),
+        Route::post(
+            "/scripthashes/txs/summary/:last_seen_txid",
+            multi_address_txs_summary,
+            "Get a summary of the confirmed transaction history of multiple scripthashes after last_seen_txid",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .body(Schema::Array(&Schema::String))
+        .returns(TX_SUMMARIES),
+        Route::get(
+            "/address/:address/txs/mempool",
+            address_mempool_txs,
+            "Get the unconfirmed address transaction history",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/scripthash/:hash/txs/mempool",
+            address_mempool_txs,
+            "Get the unconfirmed scripthash transaction history",
+        )
+        .query("max_txs", Schema::Integer, "Maximum number of transactions")
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/address/:address/utxo",
+            address_utxo,
+            "Get the unspent outputs of the address",
+        )
+        .query("max_utxos", Schema::Integer, "Page size (10 to 500)")
+        .query("after_txid", TXID, "Pagination cursor txid")
+        .query("after_vout", Schema::Integer, "Pagination cursor vout")
+        .returns(Schema::Array(&Schema::Ref("Utxo"))),
+        Route::get(
+            "/scripthash/:hash/utxo",
+            address_utxo,
+            "Get the unspent outputs of the scripthash",
+        )
+        .query("max_utxos", Schema::Integer, "Page size (10 to 500)")
+        .query("after_txid", TXID, "Pagination cursor txid")
+        .query("after_vout", Schema::Integer, "Pagination cursor vout")
+        .returns(Schema::Array(&Schema::Ref("Utxo"))),
+        Route::get(
+            "/address-prefix/:prefix",
+            address_search,
+            "Search for addresses starting with the given prefix",
+        )
+        .returns(Schema::Array(&Schema::String)),
+        Route::get("/tx/:txid", tx, "Get transaction information")
+            .returns(Schema::Ref("Transaction")),
+        Route::post(
+            "/internal/txs",
+            internal_txs,
+            "Get multiple transactions by txid",
+        )
+        .body(TXIDS)
+        .returns(TRANSACTIONS),
+        Route::get("/tx/:txid/hex", tx_raw, "Get the hex-encoded transaction"),
+        Route::get("/tx/:txid/raw", tx_raw, "Get the raw transaction").returns(Schema::Binary),
+        Route::get(
+            "/tx/:txid/status",
+            tx_status,
+            "Get the transaction confirmation status",
+        )
+        .returns(Schema::Ref("TxStatus")),
+        Route::get(
+            "/tx/:txid/merkle-proof",
+            tx_merkle_proof,
+            "Get the merkle inclusion proof of the transaction, in Electrum's format",
+        )
+        .returns(Schema::Ref("MerkleProof")),
+        Route::get(
+            "/tx/:txid/out/:vout",
+            tx_output,
+            "Get a transaction output",
+        )
+        .returns(Schema::Ref("TxOutput")),
+        Route::get(
+            "/tx/:txid/outspend/:vout",
+            tx_outspend,
+            "Get the spending status of a transaction output",
+        )
+        .returns(Schema::Ref("Outspend")),
+        Route::get(
+            "/tx/:txid/outspends",
+            tx_outspends,
+            "Get the spending status of all the transaction outputs",
+        )
+        .returns(OUTSPENDS),
+        Route::get(
+            "/tx/:txid/rbf",
+            tx_rbf,
+            "Get the replacement (RBF) history of the transaction",
+        )
+        .returns(Schema::Ref("TxReplacements")),
+        Route::get(
+            "/tx/:txid/conflicts",
+            tx_conflicts,
+            "Get the confirmed and mempool transactions double spending the transaction inputs",
+        )
+        .returns(Schema::Ref("TxConflicts")),
+        Route::get(
+            "/tx/:txid/lifecycle",
+            tx_lifecycle,
+            "Get when the transaction was first seen in the mempool, and when and why it left",
+        )
+        .returns(Schema::Ref("TxLifecycle")),
+        Route::get(
+            "/tx/:txid/package",
+            tx_package,
+            "Get the in-mempool ancestors and descendants of the transaction (CPFP package)",
+        )
+        .returns(Schema::Ref("TxPackage")),
+        Route::get(
+            "/broadcast",
+            broadcast,
+            "Broadcast a raw transaction (deprecated, use POST /tx)",
+        )
+        .required_query("tx", Schema::String, "The hex-encoded transaction"),
+        Route::post(
+            "/tx",
+            broadcast,
+            "Broadcast a raw transaction, returning its txid",
+        )
+        .body(Schema::Text),
+        Route::post(
+            "/tx/decode",
+            tx_decode,
+            "Decode a hex or binary encoded transaction and validate its inputs, without broadcasting it",
+        )
+        .body(Schema::Text)
+        .returns(Schema::Ref("DecodedTransaction")),
+        Route::post(
+            "/psbt/analyze",
+            psbt_analyze,
+            "Analyze a PSBT: input signing status, fee and estimated size",
+        )
+        .query(
+            "update",
+            Schema::Boolean,
+            "Return the PSBT with the UTXOs found in the chain or mempool filled in",
+        )
+        .body(Schema::Text)
+        .returns(Schema::Ref("PsbtAnalysis")),
+        Route::post(
+            "/txs/test",
+            txs_test,
+            "Test whether the transactions would be accepted to the mempool",
+        )
+        .query("maxfeerate", Schema::Number, "Reject transactions with a higher fee rate (BTC/kvB)")
+        .body(Schema::Array(&Schema::String))
+        .returns(Schema::Array(&Schema::Ref("MempoolAcceptResult"))),
+        Route::post(
+            "/txs/package",
+            txs_package,
+            "Submit a package of transactions",
+        )
+        .query("maxfeerate", Schema::Number, "Reject transactions with a higher fee rate (BTC/kvB)")
+        .query("maxburnamount", Schema::Number, "Reject transactions with a higher unspendable output value (BTC)")
+        .body(Schema::Array(&Schema::String))
+        .returns(Schema::Ref("SubmitPackageResult")),
+        Route::get(
+            "/txs/outspends",
+            txs_outspends,
+            "Get the spending status of the outputs of multiple transactions",
+        )
+        .required_query("txids", Schema::String, "Comma-separated list of up to 50 txids")
+        .returns(Schema::Array(&OUTSPENDS)),
+        Route::post(
+            "/internal/txs/outspends/by-txid",
+            internal_txs_outspends_by_txid,
+            "Get the spending status of the outputs of multiple transactions",
+        )
+        .body(TXIDS)
+        .returns(Schema::Array(&OUTSPENDS)),
+        Route::post(
+            "/internal/txs/outspends/by-outpoint",
+            internal_txs_outspends_by_outpoint,
+            "Get the spending status of multiple outputs, given as txid:vout",
+        )
+        .body(Schema::Array(&Schema::String))
+        .returns(OUTSPENDS),
+        Route::get("/mempool", mempool, "Get mempool backlog statistics")
+            .returns(Schema::Ref("MempoolStats")),
+        Route::get(
+            "/mempool/blocks",
+            mempool_blocks,
+            "Get the next blocks projected from the mempool",
+        )
+        .returns(Schema::Array(&Schema::Ref("MempoolBlock"))),
+        Route::get("/mempool/txids", mempool_txids, "Get the txids of all mempool transactions")
+            .returns(TXIDS),
+        Route::get(
+            "/mempool/txids/page",
+            mempool_txids_page,
+            "Get the first page of mempool txids",
+        )
+        .query("max_txs", Schema::Integer, "Page size")
+        .returns(TXIDS),
+        Route::get(
+            "/mempool/txids/page/:last_seen_txid",
+            mempool_txids_page,
+            "Get a page of mempool txids after last_seen_txid",
+        )
+        .query("max_txs", Schema::Integer, "Page size")
+        .returns(TXIDS),
+        Route::get(
+            "/internal/mempool/txs/all",
+            internal_mempool_txs_all,
+            "Get all mempool transactions",
+        )
+        .returns(TRANSACTIONS),
+        Route::post(
+            "/internal/mempool/txs",
+            internal_mempool_txs_by_txid,
+            "Get multiple mempool transactions by txid",
+        )
+        .body(TXIDS)
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/internal/mempool/txs",
+            internal_mempool_txs,
+            "Get the first page of mempool transactions",
+        )
+        .query("max_txs", Schema::Integer, "Page size")
+        .returns(TRANSACTIONS),
+        Route::get(
+            "/internal/mempool/txs/:last_seen_txid",
+            internal_mempool_txs,
+            "Get a page of mempool transactions after last_seen_txid",
+        )
+        .query("max_txs", Schema::Integer, "Page size")
+        .returns(TRANSACTIONS),
+        Route::post(
+            "/internal/db/checkpoint",
+            internal_db_checkpoint,
+            "Create a checkpoint of the index DB in the --db-checkpoint-dir directory",
+        )
+        .returns(Schema::Ref("DBCheckpoint")),
+        Route::get(
+            "/mempool/recent",
+            mempool_recent,
+            "Get the most recent mempool transactions",
+        )
+        .returns(Schema::Array(&Schema::Ref("MempoolRecentTx"))),
+        Route::get(
+            "/replacements",
+            replacements,
+            "Get the most recent mempool replacement trees",
+        )
+        .returns(Schema::Array(&Schema::Ref("ReplacementTree"))),
+        Route::get("/fee-estimates", fee_estimates, "Get fee rate estimates")
+            .returns(Schema::Ref("FeeEstimates")),
+        Route::get(
+            SYNC_STATUS_PATH,
+            sync_status,
+            "Get the progress of the index sync",
+        )
+        .returns(Schema::Ref("SyncStatus")),
+        Route::get(
+            "/openapi.json",
+            openapi_document,
+            "Get the OpenAPI document describing this API",
+        )
+        .returns(Schema::Ref("OpenApi")),
+    ];
 
-        (&Method::GET, Some(&"blocks"), start_height, None, None, None) => {
-            let start_height = start_height.and_then(|height| height.parse::<usize>().ok());
-            blocks(query, config, start_height)
-        }
-        (&Method::GET, Some(&"block-height"), Some(height), None, None, None) => {
-            let height = height.parse::<usize>()?;
-            let header = query
-                .chain()
-                .header_by_height(height)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-            let ttl = ttl_by_depth(Some(height), query);
-            http_message(StatusCode::OK, header.hash().to_hex(), ttl)
-        }
-        (&Method::GET, Some(&"block"), Some(hash), None, None, None) => {
-            let hash = BlockHash::from_hex(hash)?;
-            let blockhm = query
-                .chain()
-                .get_block_with_meta(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-            let block_value = BlockValue::new(blockhm);
-            json_response(block_value, TTL_LONG)
-        }
-        (&Method::GET, Some(&"block"), Some(hash), Some(&"status"), None, None) => {
-            let hash = BlockHash::from_hex(hash)?;
-            let status = query.chain().get_block_status(&hash);
-            let ttl = ttl_by_depth(status.height, query);
-            json_response(status, ttl)
-        }
-        (&Method::GET, Some(&"block"), Some(hash), Some(&"txids"), None, None) => {
-            let hash = BlockHash::from_hex(hash)?;
-            let txids = query
-                .chain()
-                .get_block_txids(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-            json_response(txids, TTL_LONG)
-        }
-        (&Method::GET, Some(&INTERNAL_PREFIX), Some(&"block"), Some(hash), Some(&"txs"), None) => {
-            let hash = BlockHash::from_hex(hash)?;
-            let block_id = query.chain().blockid_by_hash(&hash);
-            let txs = query
-                .chain()
-                .get_block_txs(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?
-                .into_iter()
-                .map(|tx| (tx, block_id.clone()))
-                .collect();
+    #[cfg(not(feature = "opcat_layer"))]
+    routes.push(Route::get(
+        "/tx/:txid/merkleblock-proof",
+        tx_merkleblock_proof,
+        "Get the merkle inclusion proof of the transaction, in bitcoind's merkleblock format",
+    ));
 
-            let ttl = ttl_by_depth(block_id.map(|b| b.height), query);
-            json_response(prepare_txs(txs, query, config), ttl)
-        }
-        (&Method::GET, Some(&"block"), Some(hash), Some(&"header"), None, None) => {
-            let hash = BlockHash::from_hex(hash)?;
-            let header = query
-                .chain()
-                .get_block_header(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-
-            let header_hex = hex::encode(encode::serialize(&header));
-            http_message(StatusCode::OK, header_hex, TTL_LONG)
-        }
-        (&Method::GET, Some(&"block"), Some(hash), Some(&"raw"), None, None) => {
-            let hash = BlockHash::from_hex(hash)?;
-            let raw = query
-                .chain()
-                .get_block_raw(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", "application/octet-stream")
-                .header("Cache-Control", format!("public, max-age={:}", TTL_LONG))
-                .header("X-Powered-By", &**VERSION_STRING)
-                .body(Body::from(raw))
-                .unwrap())
-        }
-        (&Method::GET, Some(&"block"), Some(hash), Some(&"txid"), Some(index), None) => {
-            let hash = BlockHash::from_hex(hash)?;
-            let index: usize = index.parse()?;
-            let txids = query
-                .chain()
-                .get_block_txids(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-            if index >= txids.len() {
-                bail!(HttpError::not_found("tx index out of range".to_string()));
-            }
-            http_message(StatusCode::OK, txids[index].to_hex(), TTL_LONG)
-        }
-        (&Method::GET, Some(&"block"), Some(hash), Some(&"txs"), start_index, None) => {
-            let hash = BlockHash::from_hex(hash)?;
-            let txids = query
-                .chain()
-                .get_block_txids(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-
-            let start_index = start_index.map_or(0u32, |el| el.parse().unwrap_or(0)) as usize;
-            if start_index >= txids.len() {
-                bail!(HttpError::not_found("start index out of range".to_string()));
-            } else if start_index % config.rest_default_chain_txs_per_page != 0 {
-                bail!(HttpError::from(format!(
-                    "start index must be a multipication of {}",
-                    config.rest_default_chain_txs_per_page
-                )));
-            }
+    routes
+}
 
-            // blockid_by_hash() only returns the BlockId for non-orphaned blocks,
-            // or None for orphaned
-            let confirmed_blockid = query.chain().blockid_by_hash(&hash);
+fn openapi_document(req: &Request) -> Result<Response<Body>, HttpError> {
+    json_response(
+        openapi::document(&ROUTES, req.access.internal),
+        TTL_LONG,
+        req.format,
+    )
+}
 
-            let txs = txids
-                .iter()
-                .skip(start_index)
-                .take(config.rest_default_chain_txs_per_page)
-                .map(|txid| {
-                    query
-                        .lookup_txn(txid)
-                        .map(|tx| (tx, confirmed_blockid.clone()))
-                        .ok_or_else(|| "missing tx".to_string())
-                })
-                .collect::<Result<Vec<(Transaction, Option<BlockId>)>, _>>()?;
+fn blocks_tip_hash(req: &Request) -> Result<Response<Body>, HttpError> {
+    let query = req.query;
+    http_message(
+        StatusCode::OK,
+        query.chain().best_hash().to_hex(),
+        TTL_SHORT,
+    )
+}
 
-            // XXX orphaned blocks always get TTL_SHORT
-            let ttl = ttl_by_depth(confirmed_blockid.map(|b| b.height), query);
+fn blocks_tip_height(req: &Request) -> Result<Response<Body>, HttpError> {
+    let query = req.query;
+    http_message(
+        StatusCode::OK,
+        query.chain().best_height().to_string(),
+        TTL_SHORT,
+    )
+}
 
-            json_response(prepare_txs(txs, query, config), ttl)
-        }
-        (&Method::GET, Some(script_type @ &"address"), Some(script_str), None, None, None)
-        | (&Method::GET, Some(script_type @ &"scripthash"), Some(script_str), None, None, None) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-            let stats = query.stats(&script_hash[..]);
-            json_response(
-                json!({
-                    *script_type: script_str,
-                    "chain_stats": stats.0,
-                    "mempool_stats": stats.1,
-                }),
-                TTL_SHORT,
-            )
-        }
-        (
-            &Method::GET,
-            Some(script_type @ &"address"),
-            Some(script_str),
-            Some(&"txs"),
-            None,
-            None,
-        )
-        | (
-            &Method::GET,
-            Some(script_type @ &"scripthash"),
-            Some(script_str),
-            Some(&"txs"),
-            None,
-            None,
-        ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-            let max_txs = query_params
-                .get("max_txs")
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_default_max_mempool_txs);
-            let after_txid = query_params
-                .get("after_txid")
-                .and_then(|s| s.parse::<Txid>().ok());
-
-            let mut txs = vec![];
-
-            let after_txid_location = if let Some(txid) = &after_txid {
-                find_txid(txid, &query.mempool(), query.chain())
-            } else {
-                TxidLocation::Mempool
-            };
+fn recent_blocks(req: &Request) -> Result<Response<Body>, HttpError> {
+    let start_height = req.optional_param("start_height");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let start_height = start_height.and_then(|height| height.parse::<usize>().ok());
+    blocks(query, config, start_height, format)
+}
 
-            let confirmed_block_height = match after_txid_location {
-                TxidLocation::Mempool => {
-                    txs.extend(
-                        query
-                            .mempool()
-                            .history(&script_hash[..], after_txid.as_ref(), max_txs)
-                            .into_iter()
-                            .map(|tx| (tx, None)),
-                    );
-                    None
-                }
-                TxidLocation::None => {
-                    return Err(HttpError(
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                        String::from("after_txid not found"),
-                    ));
-                }
-                TxidLocation::Chain(height) => Some(height),
-            };
+fn block_at_height(req: &Request) -> Result<Response<Body>, HttpError> {
+    let height = req.param("height");
+    let query = req.query;
+    let height = height.parse::<usize>()?;
+    let header = query
+        .chain()
+        .header_by_height(height)
+        .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+    let ttl = ttl_by_depth(Some(height), query);
+    http_message(StatusCode::OK, header.hash().to_hex(), ttl)
+}
 
-            if txs.len() < max_txs {
-                let after_txid_ref = if !txs.is_empty() {
-                    // If there are any txs, we know mempool found the
-                    // after_txid IF it exists... so always return None.
-                    None
-                } else {
-                    after_txid.as_ref()
-                };
-                let mut confirmed_txs = query
-                    .chain()
-                    .history(
-                        &script_hash[..],
-                        after_txid_ref,
-                        confirmed_block_height,
-                        max_txs - txs.len(),
-                    )
-                    .map(|res| {
-                        res.map(|(tx, blockid, tx_position)| (tx, Some(blockid), tx_position))
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-                confirmed_txs.sort_unstable_by(
-                    |(_, blockid1, tx_position1), (_, blockid2, tx_position2)| {
-                        blockid2
-                            .as_ref()
-                            .map(|b| b.height)
-                            .cmp(&blockid1.as_ref().map(|b| b.height))
-                            .then_with(|| tx_position2.cmp(tx_position1))
-                    },
-                );
-                txs.extend(
-                    confirmed_txs
-                        .into_iter()
-                        .map(|(tx, blockid, _)| (tx, blockid)),
-                );
-            }
+fn block(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("hash");
+    let (query, format) = (req.query, req.format);
+    let hash = BlockHash::from_hex(hash)?;
+    let blockhm = query
+        .chain()
+        .get_block_with_meta(&hash)
+        .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+    let block_value = BlockValue::new(blockhm);
+    json_response(block_value, TTL_LONG, format)
+}
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
-        }
+fn block_status(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("hash");
+    let (query, format) = (req.query, req.format);
+    let hash = BlockHash::from_hex(hash)?;
+    let status = query.chain().get_block_status(&hash);
+    let ttl = ttl_by_depth(status.height, query);
+    json_response(status, ttl, format)
+}
 
-        (&Method::POST, Some(script_types @ &"addresses"), Some(&"txs"), None, None, None)
-        | (&Method::POST, Some(script_types @ &"scripthashes"), Some(&"txs"), None, None, None) => {
-            let script_type = match *script_types {
-                "addresses" => "address",
-                "scripthashes" => "scripthash",
-                _ => "",
-            };
+fn block_txids(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("hash");
+    let (query, format) = (req.query, req.format);
+    let hash = BlockHash::from_hex(hash)?;
+    let txids = query
+        .chain()
+        .get_block_txids(&hash)
+        .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+    json_response(txids, TTL_LONG, format)
+}
 
-            if multi_address_too_long(&body) {
-                return Err(HttpError(
-                    StatusCode::UNPROCESSABLE_ENTITY,
-                    String::from("body too long"),
-                ));
-            }
+fn internal_block_txs(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("hash");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let hash = BlockHash::from_hex(hash)?;
+    let block_id = query.chain().blockid_by_hash(&hash);
+    let txs = query
+        .chain()
+        .get_block_txs(&hash)
+        .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?
+        .into_iter()
+        .map(|tx| (tx, block_id.clone()))
+        .collect();
 
-            let script_hashes: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+    let ttl = ttl_by_depth(block_id.map(|b| b.height), query);
+    json_response(prepare_txs(txs, query, config), ttl, format)
+}
 
-            if script_hashes.len() > MULTI_ADDRESS_LIMIT {
-                return Err(HttpError(
-                    StatusCode::UNPROCESSABLE_ENTITY,
-                    String::from("body too long"),
-                ));
-            }
+fn block_header(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("hash");
+    let query = req.query;
+    let hash = BlockHash::from_hex(hash)?;
+    let header = query
+        .chain()
+        .get_block_header(&hash)
+        .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+
+    let header_hex = hex::encode(encode::serialize(&header));
+    http_message(StatusCode::OK, header_hex, TTL_LONG)
+}
 
-            let script_hashes: Vec<[u8; 32]> = script_hashes
-                .iter()
-                .filter_map(|script_str| {
-                    to_scripthash(script_type, script_str, config.network_type).ok()
-                })
-                .collect();
+fn block_raw(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("hash");
+    let query = req.query;
+    let hash = BlockHash::from_hex(hash)?;
+    let raw = query
+        .chain()
+        .get_block_raw(&hash)
+        .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
 
-            let max_txs = query_params
-                .get("max_txs")
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_default_max_mempool_txs);
-            let after_txid = query_params
-                .get("after_txid")
-                .and_then(|s| s.parse::<Txid>().ok());
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/octet-stream")
+        .header("Cache-Control", format!("public, max-age={:}", TTL_LONG))
+        .header("X-Powered-By", &**VERSION_STRING)
+        .body(Body::from(raw))
+        .unwrap())
+}
 
-            let mut txs = vec![];
+fn block_txid_at_index(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("hash");
+    let index = req.param("index");
+    let query = req.query;
+    let hash = BlockHash::from_hex(hash)?;
+    let index: usize = index.parse()?;
+    let txids = query
+        .chain()
+        .get_block_txids(&hash)
+        .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+    if index >= txids.len() {
+        bail!(HttpError::not_found("tx index out of range".to_string()));
+    }
+    http_message(StatusCode::OK, txids[index].to_hex(), TTL_LONG)
+}
 
-            let after_txid_location = if let Some(txid) = &after_txid {
-                find_txid(txid, &query.mempool(), query.chain())
-            } else {
-                TxidLocation::Mempool
-            };
+fn block_txs(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("hash");
+    let start_index = req.optional_param("start_index");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let hash = BlockHash::from_hex(hash)?;
+    let txids = query
+        .chain()
+        .get_block_txids(&hash)
+        .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+
+    let start_index = start_index.map_or(0u32, |el| el.parse().unwrap_or(0)) as usize;
+    if start_index >= txids.len() {
+        bail!(HttpError::not_found("start index out of range".to_string()));
+    } else if start_index % config.rest_default_chain_txs_per_page != 0 {
+        bail!(HttpError::from(format!(
+            "start index must be a multipication of {}",
+            config.rest_default_chain_txs_per_page
+        )));
+    }
 
-            let confirmed_block_height = match after_txid_location {
-                TxidLocation::Mempool => {
-                    txs.extend(
-                        query
-                            .mempool()
-                            .history_group(&script_hashes, after_txid.as_ref(), max_txs)
-                            .into_iter()
-                            .map(|tx| (tx, None)),
-                    );
-                    None
-                }
-                TxidLocation::None => {
-                    return Err(HttpError(
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                        String::from("after_txid not found"),
-                    ));
-                }
-                TxidLocation::Chain(height) => Some(height),
-            };
+    // blockid_by_hash() only returns the BlockId for non-orphaned blocks,
+    // or None for orphaned
+    let confirmed_blockid = query.chain().blockid_by_hash(&hash);
 
-            if txs.len() < max_txs {
-                let after_txid_ref = if !txs.is_empty() {
-                    // If there are any txs, we know mempool found the
-                    // after_txid IF it exists... so always return None.
-                    None
-                } else {
-                    after_txid.as_ref()
-                };
-                let mut confirmed_txs = query
-                    .chain()
-                    .history_group(
-                        &script_hashes,
-                        after_txid_ref,
-                        confirmed_block_height,
-                        max_txs - txs.len(),
-                    )
-                    .map(|res| {
-                        res.map(|(tx, blockid, tx_position)| (tx, Some(blockid), tx_position))
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-                confirmed_txs.sort_unstable_by(
-                    |(_, blockid1, tx_position1), (_, blockid2, tx_position2)| {
-                        blockid2
-                            .as_ref()
-                            .map(|b| b.height)
-                            .cmp(&blockid1.as_ref().map(|b| b.height))
-                            .then_with(|| tx_position2.cmp(tx_position1))
-                    },
-                );
-                txs.extend(
-                    confirmed_txs
-                        .into_iter()
-                        .map(|(tx, blockid, _)| (tx, blockid)),
-                );
-            }
+    let txs = txids
+        .iter()
+        .skip(start_index)
+        .take(config.rest_default_chain_txs_per_page)
+        .map(|txid| {
+            query
+                .lookup_txn(txid)
+                .map(|tx| (tx, confirmed_blockid.clone()))
+                .ok_or_else(|| "missing tx".to_string())
+        })
+        .collect::<Result<Vec<(Transaction, Option<BlockId>)>, _>>()?;
+
+    // XXX orphaned blocks always get TTL_SHORT
+    let ttl = ttl_by_depth(confirmed_blockid.map(|b| b.height), query);
+
+    json_response(prepare_txs(txs, query, config), ttl, format)
+}
+
+fn address_stats(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (script_type, script_str) = req.script();
+    let (query, config, format) = (req.query, req.config, req.format);
+    let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+    let stats = query.stats(&script_hash[..]);
+    json_response(
+        json!({
+            script_type: script_str,
+            "chain_stats": stats.0,
+            "mempool_stats": stats.1,
+        }),
+        TTL_SHORT,
+        format,
+    )
+}
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+fn address_txs(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (script_type, script_str) = req.script();
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+    let max_txs = query_params
+        .get("max_txs")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(config.rest_default_max_mempool_txs);
+    let after_txid = query_params
+        .get("after_txid")
+        .and_then(|s| s.parse::<Txid>().ok());
+
+    let mut txs = vec![];
+
+    let after_txid_location = if let Some(txid) = &after_txid {
+        find_txid(txid, &query.mempool(), query.chain())
+    } else {
+        TxidLocation::Mempool
+    };
+
+    let confirmed_block_height = match after_txid_location {
+        TxidLocation::Mempool => {
+            txs.extend(
+                query
+                    .mempool()
+                    .history(&script_hash[..], after_txid.as_ref(), max_txs)
+                    .into_iter()
+                    .map(|tx| (tx, None)),
+            );
+            None
         }
+        TxidLocation::None => {
+            return Err(HttpError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                String::from("after_txid not found"),
+            ));
+        }
+        TxidLocation::Chain(height) => Some(height),
+    };
 
-        (
-            &Method::GET,
-            Some(script_type @ &"address"),
-            Some(script_str),
-            Some(&"txs"),
-            Some(&"chain"),
-            last_seen_txid,
-        )
-        | (
-            &Method::GET,
-            Some(script_type @ &"scripthash"),
-            Some(script_str),
-            Some(&"txs"),
-            Some(&"chain"),
-            last_seen_txid,
-        ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-            let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
-            let max_txs = query_params
-                .get("max_txs")
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_default_chain_txs_per_page);
-
-            let mut txs = query
-                .chain()
-                .history(&script_hash[..], last_seen_txid.as_ref(), None, max_txs)
-                .map(|res| res.map(|(tx, blockid, tx_position)| (tx, Some(blockid), tx_position)))
-                .collect::<Result<Vec<_>, _>>()?;
-            txs.sort_unstable_by(|(_, blockid1, tx_position1), (_, blockid2, tx_position2)| {
+    if txs.len() < max_txs {
+        let after_txid_ref = if !txs.is_empty() {
+            // If there are any txs, we know mempool found the
+            // after_txid IF it exists... so always return None.
+            None
+        } else {
+            after_txid.as_ref()
+        };
+        let mut confirmed_txs = query
+            .chain()
+            .history(
+                &script_hash[..],
+                after_txid_ref,
+                confirmed_block_height,
+                max_txs - txs.len(),
+            )
+            .map(|res| res.map(|(tx, blockid, tx_position)| (tx, Some(blockid), tx_position)))
+            .collect::<Result<Vec<_>, _>>()?;
+        confirmed_txs.sort_unstable_by(
+            |(_, blockid1, tx_position1), (_, blockid2, tx_position2)| {
                 blockid2
                     .as_ref()
                     .map(|b| b.height)
                     .cmp(&blockid1.as_ref().map(|b| b.height))
                     .then_with(|| tx_position2.cmp(tx_position1))
-            });
-            json_response(
-                prepare_txs(
-                    txs.into_iter()
-                        .map(|(tx, blockid, _)| (tx, blockid))
-                        .collect(),
-                    query,
-                    config,
-                ),
-                TTL_SHORT,
-            )
-        }
-        (
-            &Method::GET,
-            Some(script_type @ &"address"),
-            Some(script_str),
-            Some(&"txs"),
-            Some(&"summary"),
-            last_seen_txid,
-        )
-        | (
-            &Method::GET,
-            Some(script_type @ &"scripthash"),
-            Some(script_str),
-            Some(&"txs"),
-            Some(&"summary"),
-            last_seen_txid,
-        ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-            let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
-            let max_txs = cmp::min(
-                config.rest_default_max_address_summary_txs,
-                query_params
-                    .get("max_txs")
-                    .and_then(|s| s.parse::<usize>().ok())
-                    .unwrap_or(config.rest_default_max_address_summary_txs),
-            );
-
-            let last_seen_txid_location = if let Some(txid) = &last_seen_txid {
-                find_txid(txid, &query.mempool(), query.chain())
-            } else {
-                TxidLocation::Mempool
-            };
+            },
+        );
+        txs.extend(
+            confirmed_txs
+                .into_iter()
+                .map(|(tx, blockid, _)| (tx, blockid)),
+        );
+    }
 
-            let confirmed_block_height = match last_seen_txid_location {
-                TxidLocation::Mempool => None,
-                TxidLocation::None => {
-                    return Err(HttpError(
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                        String::from("after_txid not found"),
-                    ));
-                }
-                TxidLocation::Chain(height) => Some(height),
-            };
+    json_response(prepare_txs(txs, query, config), TTL_SHORT, format)
+}
 
-            let summary = query.chain().summary(
-                &script_hash[..],
-                last_seen_txid.as_ref(),
-                confirmed_block_height,
-                max_txs,
-            );
+fn multi_address_txs(req: &Request) -> Result<Response<Body>, HttpError> {
+    let script_types = req.path[0];
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let body = &req.body;
+    let script_type = match script_types {
+        "addresses" => "address",
+        "scripthashes" => "scripthash",
+        _ => "",
+    };
 
-            json_response(summary, TTL_SHORT)
-        }
-        (
-            &Method::POST,
-            Some(script_types @ &"addresses"),
-            Some(&"txs"),
-            Some(&"summary"),
-            last_seen_txid,
-            None,
-        )
-        | (
-            &Method::POST,
-            Some(script_types @ &"scripthashes"),
-            Some(&"txs"),
-            Some(&"summary"),
-            last_seen_txid,
-            None,
-        ) => {
-            let script_type = match *script_types {
-                "addresses" => "address",
-                "scripthashes" => "scripthash",
-                _ => "",
-            };
+    if multi_address_too_long(body) {
+        return Err(HttpError(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            String::from("body too long"),
+        ));
+    }
 
-            if multi_address_too_long(&body) {
-                return Err(HttpError(
-                    StatusCode::UNPROCESSABLE_ENTITY,
-                    String::from("body too long"),
-                ));
-            }
+    let script_hashes: Vec<String> =
+        serde_json::from_slice(body).map_err(|err| HttpError::from(err.to_string()))?;
 
-            let script_hashes: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+    if script_hashes.len() > MULTI_ADDRESS_LIMIT {
+        return Err(HttpError(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            String::from("body too long"),
+        ));
+    }
 
-            if script_hashes.len() > MULTI_ADDRESS_LIMIT {
-                return Err(HttpError(
-                    StatusCode::UNPROCESSABLE_ENTITY,
-                    String::from("body too long"),
-                ));
-            }
+    let script_hashes: Vec<[u8; 32]> = script_hashes
+        .iter()
+        .filter_map(|script_str| to_scripthash(script_type, script_str, config.network_type).ok())
+        .collect();
 
-            let script_hashes: Vec<[u8; 32]> = script_hashes
-                .iter()
-                .filter_map(|script_str| {
-                    to_scripthash(script_type, script_str, config.network_type).ok()
-                })
-                .collect();
+    let max_txs = query_params
+        .get("max_txs")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(config.rest_default_max_mempool_txs);
+    let after_txid = query_params
+        .get("after_txid")
+        .and_then(|s| s.parse::<Txid>().ok());
 
-            let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
-            let max_txs = cmp::min(
-                config.rest_default_max_address_summary_txs,
-                query_params
-                    .get("max_txs")
-                    .and_then(|s| s.parse::<usize>().ok())
-                    .unwrap_or(config.rest_default_max_address_summary_txs),
-            );
+    let mut txs = vec![];
 
-            let last_seen_txid_location = if let Some(txid) = &last_seen_txid {
-                find_txid(txid, &query.mempool(), query.chain())
-            } else {
-                TxidLocation::Mempool
-            };
+    let after_txid_location = if let Some(txid) = &after_txid {
+        find_txid(txid, &query.mempool(), query.chain())
+    } else {
+        TxidLocation::Mempool
+    };
 
-            let confirmed_block_height = match last_seen_txid_location {
-                TxidLocation::Mempool => None,
-                TxidLocation::None => {
-                    return Err(HttpError(
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                        String::from("after_txid not found"),
-                    ));
-                }
-                TxidLocation::Chain(height) => Some(height),
-            };
+    let confirmed_block_height = match after_txid_location {
+        TxidLocation::Mempool => {
+            txs.extend(
+                query
+                    .mempool()
+                    .history_group(&script_hashes, after_txid.as_ref(), max_txs)
+                    .into_iter()
+                    .map(|tx| (tx, None)),
+            );
+            None
+        }
+        TxidLocation::None => {
+            return Err(HttpError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                String::from("after_txid not found"),
+            ));
+        }
+        TxidLocation::Chain(height) => Some(height),
+    };
 
-            let summary = query.chain().summary_group(
+    if txs.len() < max_txs {
+        let after_txid_ref = if !txs.is_empty() {
+            // If there are any txs, we know mempool found the
+            // after_txid IF it exists... so always return None.
+            None
+        } else {
+            after_txid.as_ref()
+        };
+        let mut confirmed_txs = query
+            .chain()
+            .history_group(
                 &script_hashes,
-                last_seen_txid.as_ref(),
+                after_txid_ref,
                 confirmed_block_height,
-                max_txs,
-            );
-
-            json_response(summary, TTL_SHORT)
-        }
-        (
-            &Method::GET,
-            Some(script_type @ &"address"),
-            Some(script_str),
-            Some(&"txs"),
-            Some(&"mempool"),
-            None,
-        )
-        | (
-            &Method::GET,
-            Some(script_type @ &"scripthash"),
-            Some(script_str),
-            Some(&"txs"),
-            Some(&"mempool"),
-            None,
-        ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-            let max_txs = query_params
-                .get("max_txs")
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_default_max_mempool_txs);
-
-            let txs = query
-                .mempool()
-                .history(&script_hash[..], None, max_txs)
+                max_txs - txs.len(),
+            )
+            .map(|res| res.map(|(tx, blockid, tx_position)| (tx, Some(blockid), tx_position)))
+            .collect::<Result<Vec<_>, _>>()?;
+        confirmed_txs.sort_unstable_by(
+            |(_, blockid1, tx_position1), (_, blockid2, tx_position2)| {
+                blockid2
+                    .as_ref()
+                    .map(|b| b.height)
+                    .cmp(&blockid1.as_ref().map(|b| b.height))
+                    .then_with(|| tx_position2.cmp(tx_position1))
+            },
+        );
+        txs.extend(
+            confirmed_txs
                 .into_iter()
-                .map(|tx| (tx, None))
-                .collect();
+                .map(|(tx, blockid, _)| (tx, blockid)),
+        );
+    }
+
+    json_response(prepare_txs(txs, query, config), TTL_SHORT, format)
+}
+
+fn address_chain_txs(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (script_type, script_str) = req.script();
+    let last_seen_txid = req.optional_param("last_seen_txid");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+    let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
+    let max_txs = query_params
+        .get("max_txs")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(config.rest_default_chain_txs_per_page);
+
+    let mut txs = query
+        .chain()
+        .history(&script_hash[..], last_seen_txid.as_ref(), None, max_txs)
+        .map(|res| res.map(|(tx, blockid, tx_position)| (tx, Some(blockid), tx_position)))
+        .collect::<Result<Vec<_>, _>>()?;
+    txs.sort_unstable_by(|(_, blockid1, tx_position1), (_, blockid2, tx_position2)| {
+        blockid2
+            .as_ref()
+            .map(|b| b.height)
+            .cmp(&blockid1.as_ref().map(|b| b.height))
+            .then_with(|| tx_position2.cmp(tx_position1))
+    });
+    json_response(
+        prepare_txs(
+            txs.into_iter()
+                .map(|(tx, blockid, _)| (tx, blockid))
+                .collect(),
+            query,
+            config,
+        ),
+        TTL_SHORT,
+        format,
+    )
+}
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+fn address_txs_summary(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (script_type, script_str) = req.script();
+    let last_seen_txid = req.optional_param("last_seen_txid");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+    let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
+    let max_txs = cmp::min(
+        config.rest_default_max_address_summary_txs,
+        query_params
+            .get("max_txs")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(config.rest_default_max_address_summary_txs),
+    );
+
+    let last_seen_txid_location = if let Some(txid) = &last_seen_txid {
+        find_txid(txid, &query.mempool(), query.chain())
+    } else {
+        TxidLocation::Mempool
+    };
+
+    let confirmed_block_height = match last_seen_txid_location {
+        TxidLocation::Mempool => None,
+        TxidLocation::None => {
+            return Err(HttpError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                String::from("after_txid not found"),
+            ));
         }
+        TxidLocation::Chain(height) => Some(height),
+    };
 
-        (
-            &Method::GET,
-            Some(script_type @ &"address"),
-            Some(script_str),
-            Some(&"utxo"),
-            None,
-            None,
-        )
-        | (
-            &Method::GET,
-            Some(script_type @ &"scripthash"),
-            Some(script_str),
-            Some(&"utxo"),
-            None,
-            None,
-        ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-
-            // Parse pagination parameters
-            const MIN_UTXOS: usize = 10;
-            const MAX_UTXOS: usize = 500;
-
-            let max_utxos = query_params
-                .get("max_utxos")
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_default_max_mempool_txs); // Reuse mempool txs config for now
-
-            // Validate max_utxos range
-            if max_utxos < MIN_UTXOS {
-                return Err(HttpError(
-                    StatusCode::BAD_REQUEST,
-                    format!("max_utxos must be at least {}", MIN_UTXOS),
-                ));
-            }
-            if max_utxos > MAX_UTXOS {
-                return Err(HttpError(
-                    StatusCode::BAD_REQUEST,
-                    format!("max_utxos must not exceed {}", MAX_UTXOS),
-                ));
-            }
+    let summary = query.chain().summary(
+        &script_hash[..],
+        last_seen_txid.as_ref(),
+        confirmed_block_height,
+        max_txs,
+    );
 
-            let after_txid = query_params
-                .get("after_txid")
-                .and_then(|s| s.parse::<Txid>().ok());
-
-            let after_vout = query_params
-                .get("after_vout")
-                .and_then(|s| s.parse::<u32>().ok());
-
-            // Construct after_outpoint if both txid and vout are provided
-            let after_outpoint = match (after_txid, after_vout) {
-                (Some(txid), Some(vout)) => Some(OutPoint { txid, vout }),
-                (Some(_), None) => {
-                    return Err(HttpError(
-                        StatusCode::BAD_REQUEST,
-                        String::from("after_txid requires after_vout parameter"),
-                    ));
-                }
-                (None, Some(_)) => {
-                    return Err(HttpError(
-                        StatusCode::BAD_REQUEST,
-                        String::from("after_vout requires after_txid parameter"),
-                    ));
-                }
-                (None, None) => None,
-            };
+    json_response(summary, TTL_SHORT, format)
+}
 
-            // Validate after_outpoint exists if provided
-            if let Some(ref outpoint) = after_outpoint {
-                let location = find_outpoint(outpoint, &query.mempool(), query.chain());
-                if matches!(location, OutPointLocation::None) {
-                    return Err(HttpError(
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                        String::from("after_txid:after_vout not found"),
-                    ));
-                }
-            }
+fn multi_address_txs_summary(req: &Request) -> Result<Response<Body>, HttpError> {
+    let script_types = req.path[0];
+    let last_seen_txid = req.optional_param("last_seen_txid");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let body = &req.body;
+    let script_type = match script_types {
+        "addresses" => "address",
+        "scripthashes" => "scripthash",
+        _ => "",
+    };
 
-            let utxos: Vec<UtxoValue> = query
-                .utxo(&script_hash[..], after_outpoint.as_ref(), max_utxos)?
-                .into_iter()
-                .map(UtxoValue::from)
-                .collect();
+    if multi_address_too_long(body) {
+        return Err(HttpError(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            String::from("body too long"),
+        ));
+    }
 
-            json_response(utxos, TTL_SHORT)
-        }
-        (&Method::GET, Some(&"address-prefix"), Some(prefix), None, None, None) => {
-            if !config.address_search {
-                return Err(HttpError::from("address search disabled".to_string()));
-            }
-            let results = query.chain().address_search(prefix, ADDRESS_SEARCH_LIMIT);
-            json_response(results, TTL_SHORT)
-        }
-        (&Method::GET, Some(&"tx"), Some(hash), None, None, None) => {
-            let hash = Txid::from_hex(hash)?;
-            let tx = query
-                .lookup_txn(&hash)
-                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
-            let blockid = query.chain().tx_confirming_block(&hash);
-            let ttl = ttl_by_depth(blockid.as_ref().map(|b| b.height), query);
-
-            let mut tx = prepare_txs(vec![(tx, blockid)], query, config);
-
-            if tx.is_empty() {
-                http_message(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Transaction missing prevouts",
-                    0,
-                )
-            } else {
-                json_response(tx.remove(0), ttl)
-            }
-        }
-        (&Method::POST, Some(&INTERNAL_PREFIX), Some(&"txs"), None, None, None) => {
-            let txid_strings: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+    let script_hashes: Vec<String> =
+        serde_json::from_slice(body).map_err(|err| HttpError::from(err.to_string()))?;
 
-            match txid_strings
-                .into_iter()
-                .map(|txid| Txid::from_hex(&txid))
-                .collect::<Result<Vec<Txid>, _>>()
-            {
-                Ok(txids) => {
-                    let txs: Vec<(Transaction, Option<BlockId>)> = txids
-                        .iter()
-                        .filter_map(|txid| {
-                            query
-                                .lookup_txn(txid)
-                                .map(|tx| (tx, query.chain().tx_confirming_block(txid)))
-                        })
-                        .collect();
-                    json_response(prepare_txs(txs, query, config), 0)
-                }
-                Err(err) => http_message(StatusCode::BAD_REQUEST, err.to_string(), 0),
-            }
+    if script_hashes.len() > MULTI_ADDRESS_LIMIT {
+        return Err(HttpError(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            String::from("body too long"),
+        ));
+    }
+
+    let script_hashes: Vec<[u8; 32]> = script_hashes
+        .iter()
+        .filter_map(|script_str| to_scripthash(script_type, script_str, config.network_type).ok())
+        .collect();
+
+    let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
+    let max_txs = cmp::min(
+        config.rest_default_max_address_summary_txs,
+        query_params
+            .get("max_txs")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(config.rest_default_max_address_summary_txs),
+    );
+
+    let last_seen_txid_location = if let Some(txid) = &last_seen_txid {
+        find_txid(txid, &query.mempool(), query.chain())
+    } else {
+        TxidLocation::Mempool
+    };
+
+    let confirmed_block_height = match last_seen_txid_location {
+        TxidLocation::Mempool => None,
+        TxidLocation::None => {
+            return Err(HttpError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                String::from("after_txid not found"),
+            ));
         }
-        (&Method::GET, Some(&"tx"), Some(hash), Some(out_type @ &"hex"), None, None)
-        | (&Method::GET, Some(&"tx"), Some(hash), Some(out_type @ &"raw"), None, None) => {
-            let hash = Txid::from_hex(hash)?;
-            let rawtx = query
-                .lookup_raw_txn(&hash)
-                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
-
-            let (content_type, body) = match *out_type {
-                "raw" => ("application/octet-stream", Body::from(rawtx)),
-                "hex" => ("text/plain", Body::from(hex::encode(rawtx))),
-                _ => unreachable!(),
-            };
-            let ttl = ttl_by_depth(query.get_tx_status(&hash).block_height, query);
-
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", content_type)
-                .header("Cache-Control", format!("public, max-age={:}", ttl))
-                .header("X-Powered-By", &**VERSION_STRING)
-                .body(body)
-                .unwrap())
+        TxidLocation::Chain(height) => Some(height),
+    };
+
+    let summary = query.chain().summary_group(
+        &script_hashes,
+        last_seen_txid.as_ref(),
+        confirmed_block_height,
+        max_txs,
+    );
+
+    json_response(summary, TTL_SHORT, format)
+}
+
+fn address_mempool_txs(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (script_type, script_str) = req.script();
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+    let max_txs = query_params
+        .get("max_txs")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(config.rest_default_max_mempool_txs);
+
+    let txs = query
+        .mempool()
+        .history(&script_hash[..], None, max_txs)
+        .into_iter()
+        .map(|tx| (tx, None))
+        .collect();
+
+    json_response(prepare_txs(txs, query, config), TTL_SHORT, format)
+}
+
+fn address_utxo(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (script_type, script_str) = req.script();
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+
+    // Parse pagination parameters
+    const MIN_UTXOS: usize = 10;
+    const MAX_UTXOS: usize = 500;
+
+    let max_utxos = query_params
+        .get("max_utxos")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(config.rest_default_max_mempool_txs); // Reuse mempool txs config for now
+
+    // Validate max_utxos range
+    if max_utxos < MIN_UTXOS {
+        return Err(HttpError(
+            StatusCode::BAD_REQUEST,
+            format!("max_utxos must be at least {}", MIN_UTXOS),
+        ));
+    }
+    if max_utxos > MAX_UTXOS {
+        return Err(HttpError(
+            StatusCode::BAD_REQUEST,
+            format!("max_utxos must not exceed {}", MAX_UTXOS),
+        ));
+    }
+
+    let after_txid = query_params
+        .get("after_txid")
+        .and_then(|s| s.parse::<Txid>().ok());
+
+    let after_vout = query_params
+        .get("after_vout")
+        .and_then(|s| s.parse::<u32>().ok());
+
+    // Construct after_outpoint if both txid and vout are provided
+    let after_outpoint = match (after_txid, after_vout) {
+        (Some(txid), Some(vout)) => Some(OutPoint { txid, vout }),
+        (Some(_), None) => {
+            return Err(HttpError(
+                StatusCode::BAD_REQUEST,
+                String::from("after_txid requires after_vout parameter"),
+            ));
         }
-        (&Method::GET, Some(&"tx"), Some(hash), Some(&"status"), None, None) => {
-            let hash = Txid::from_hex(hash)?;
-            let status = query.get_tx_status(&hash);
-            // Avoid raw transaction lookup here: status only needs confirmation state.
-            // Return 404 only if tx is neither confirmed nor present in mempool.
-            if !status.confirmed && query.mempool().lookup_txn(&hash).is_none() {
-                return Err(HttpError::not_found("Transaction not found".to_string()));
-            }
-            let ttl = ttl_by_depth(status.block_height, query);
-            json_response(status, ttl)
+        (None, Some(_)) => {
+            return Err(HttpError(
+                StatusCode::BAD_REQUEST,
+                String::from("after_vout requires after_txid parameter"),
+            ));
         }
+        (None, None) => None,
+    };
 
-        (&Method::GET, Some(&"tx"), Some(hash), Some(&"merkle-proof"), None, None) => {
-            let hash = Txid::from_hex(hash)?;
-            let blockid = query.chain().tx_confirming_block(&hash).ok_or_else(|| {
-                HttpError::not_found("Transaction not found or is unconfirmed".to_string())
-            })?;
-            let (merkle, pos) =
-                electrum_merkle::get_tx_merkle_proof(query.chain(), &hash, &blockid.hash)?;
-            let merkle: Vec<String> = merkle.into_iter().map(|txid| txid.to_hex()).collect();
-            let ttl = ttl_by_depth(Some(blockid.height), query);
-            json_response(
-                json!({ "block_height": blockid.height, "merkle": merkle, "pos": pos }),
-                ttl,
-            )
-        }
-        #[cfg(all(not(feature = "opcat_layer"), not(feature = "opcat_layer")))]
-        (&Method::GET, Some(&"tx"), Some(hash), Some(&"merkleblock-proof"), None, None) => {
-            let hash = Txid::from_hex(hash)?;
-
-            let merkleblock = query.chain().get_merkleblock_proof(&hash).ok_or_else(|| {
-                HttpError::not_found("Transaction not found or is unconfirmed".to_string())
-            })?;
-
-            let height = query
-                .chain()
-                .height_by_hash(&merkleblock.header.block_hash());
-
-            http_message(
-                StatusCode::OK,
-                hex::encode(encode::serialize(&merkleblock)),
-                ttl_by_depth(height, query),
-            )
+    // Validate after_outpoint exists if provided
+    if let Some(ref outpoint) = after_outpoint {
+        let location = find_outpoint(outpoint, &query.mempool(), query.chain());
+        if matches!(location, OutPointLocation::None) {
+            return Err(HttpError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                String::from("after_txid:after_vout not found"),
+            ));
         }
-        (&Method::GET, Some(&"tx"), Some(hash), Some(&"out"), Some(index), None) => {
-            let txid = Txid::from_hex(hash)?;
-            let vout = index.parse::<u32>()?;
-            let outpoint = OutPoint { txid, vout };
-
-            // Look up TxOut from chain or mempool
-            let txout = query
-                .chain()
-                .lookup_txo(&outpoint)
-                .or_else(|| {
-                    let mut outpoints = std::collections::BTreeSet::new();
-                    outpoints.insert(outpoint);
-                    query
-                        .mempool()
-                        .lookup_txos(&outpoints)
-                        .get(&outpoint)
-                        .cloned()
-                })
-                .ok_or_else(|| HttpError::not_found("Output not found".to_string()))?;
+    }
 
-            // Build response
-            #[cfg(not(feature = "opcat_layer"))]
-            let response = serde_json::json!({
-                "scriptpubkey": txout.script_pubkey.to_hex(),
-                "value": txout.value,
-            });
+    let utxos: Vec<UtxoValue> = query
+        .utxo(&script_hash[..], after_outpoint.as_ref(), max_utxos)?
+        .into_iter()
+        .map(UtxoValue::from)
+        .collect();
 
-            #[cfg(feature = "opcat_layer")]
-            let response = serde_json::json!({
-                "scriptpubkey": txout.script_pubkey.to_hex(),
-                "value": txout.value.as_sat(),
-                "data": hex::encode(&txout.data),
-            });
+    json_response(utxos, TTL_SHORT, format)
+}
 
-            // Determine TTL based on confirmation status
-            let block_id = query.chain().tx_confirming_block(&txid);
-            let ttl = ttl_by_depth(block_id.as_ref().map(|b| b.height), query);
+fn address_search(req: &Request) -> Result<Response<Body>, HttpError> {
+    let prefix = req.param("prefix");
+    let (query, config, format) = (req.query, req.config, req.format);
+    if !config.address_search {
+        return Err(HttpError::from("address search disabled".to_string()));
+    }
+    let results = query.chain().address_search(prefix, ADDRESS_SEARCH_LIMIT);
+    json_response(results, TTL_SHORT, format)
+}
 
-            json_response(response, ttl)
-        }
-        (&Method::GET, Some(&"tx"), Some(hash), Some(&"outspend"), Some(index), None) => {
-            let hash = Txid::from_hex(hash)?;
-            let outpoint = OutPoint {
-                txid: hash,
-                vout: index.parse::<u32>()?,
-            };
-            let spend = query
-                .lookup_spend(&outpoint)
-                .map_or_else(SpendingValue::default, SpendingValue::from);
-            let ttl = ttl_by_depth(
-                spend.status.as_ref().and_then(|status| status.block_height),
-                query,
-            );
-            json_response(spend, ttl)
-        }
-        (&Method::GET, Some(&"tx"), Some(hash), Some(&"outspends"), None, None) => {
-            let hash = Txid::from_hex(hash)?;
-            let tx = query
-                .lookup_txn(&hash)
-                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
-            let spends: Vec<SpendingValue> = query
-                .lookup_tx_spends(tx)
-                .into_iter()
-                .map(|spend| spend.map_or_else(SpendingValue::default, SpendingValue::from))
+fn tx(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let hash = Txid::from_hex(hash)?;
+    let tx = query
+        .lookup_txn(&hash)
+        .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+    let blockid = query.chain().tx_confirming_block(&hash);
+    let ttl = ttl_by_depth(blockid.as_ref().map(|b| b.height), query);
+
+    let mut tx = prepare_txs(vec![(tx, blockid)], query, config);
+
+    if tx.is_empty() {
+        http_message(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Transaction missing prevouts",
+            0,
+        )
+    } else {
+        json_response(tx.remove(0), ttl, format)
+    }
+}
+
+fn internal_txs(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, config, format) = (req.query, req.config, req.format);
+    let body = &req.body;
+    let txid_strings: Vec<String> =
+        serde_json::from_slice(body).map_err(|err| HttpError::from(err.to_string()))?;
+
+    match txid_strings
+        .into_iter()
+        .map(|txid| Txid::from_hex(&txid))
+        .collect::<Result<Vec<Txid>, _>>()
+    {
+        Ok(txids) => {
+            let txs: Vec<(Transaction, Option<BlockId>)> = txids
+                .iter()
+                .filter_map(|txid| {
+                    query
+                        .lookup_txn(txid)
+                        .map(|tx| (tx, query.chain().tx_confirming_block(txid)))
+                })
                 .collect();
-            // @TODO long ttl if all outputs are either spent long ago or unspendable
-            json_response(spends, TTL_SHORT)
+            json_response(prepare_txs(txs, query, config), 0, format)
         }
-        (&Method::GET, Some(&"broadcast"), None, None, None, None)
-        | (&Method::POST, Some(&"tx"), None, None, None, None) => {
-            // accept both POST and GET for backward compatibility.
-            // GET will eventually be removed in favor of POST.
-            let txhex = match method {
-                Method::POST => String::from_utf8(body.to_vec())?,
-                Method::GET => query_params
-                    .get("tx")
-                    .cloned()
-                    .ok_or_else(|| HttpError::from("Missing tx".to_string()))?,
-                _ => return http_message(StatusCode::METHOD_NOT_ALLOWED, "Invalid method", 0),
-            };
-            let txid = query
-                .broadcast_raw(&txhex)
-                .map_err(|err| HttpError::from(err.description().to_string()))?;
-            http_message(StatusCode::OK, txid.to_hex(), 0)
-        }
-        (&Method::POST, Some(&"txs"), Some(&"test"), None, None, None) => {
-            let txhexes: Vec<String> =
-                serde_json::from_str(String::from_utf8(body.to_vec())?.as_str())?;
-
-            if txhexes.len() > 25 {
-                Result::Err(HttpError::from(
-                    "Exceeded maximum of 25 transactions".to_string(),
-                ))?
-            }
+        Err(err) => http_message(StatusCode::BAD_REQUEST, err.to_string(), 0),
+    }
+}
 
-            let maxfeerate = query_params
-                .get("maxfeerate")
-                .map(|s| {
-                    s.parse::<f64>()
-                        .map_err(|_| HttpError::from("Invalid maxfeerate".to_string()))
-                })
-                .transpose()?;
-
-            // pre-checks
-            txhexes.iter().enumerate().try_for_each(|(index, txhex)| {
-                // each transaction must be of reasonable size (more than 60 bytes, within 400kWU standardness limit)
-                if !(120..800_000).contains(&txhex.len()) {
-                    Result::Err(HttpError::from(format!(
-                        "Invalid transaction size for item {}",
-                        index
-                    )))
-                } else {
-                    // must be a valid hex string
-                    Vec::<u8>::from_hex(txhex)
-                        .map_err(|_| {
-                            HttpError::from(format!("Invalid transaction hex for item {}", index))
-                        })
-                        .map(|_| ())
-                }
-            })?;
+fn tx_raw(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let out_type = req.path[2];
+    let query = req.query;
+    let hash = Txid::from_hex(hash)?;
+    let rawtx = query
+        .lookup_raw_txn(&hash)
+        .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+
+    let (content_type, body) = match out_type {
+        "raw" => ("application/octet-stream", Body::from(rawtx)),
+        "hex" => ("text/plain", Body::from(hex::encode(rawtx))),
+        _ => unreachable!(),
+    };
+    let ttl = ttl_by_depth(query.get_tx_status(&hash).block_height, query);
 
-            let result = query
-                .test_mempool_accept(txhexes, maxfeerate)
-                .map_err(|err| HttpError::from(err.description().to_string()))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .header("Cache-Control", format!("public, max-age={:}", ttl))
+        .header("X-Powered-By", &**VERSION_STRING)
+        .body(body)
+        .unwrap())
+}
 
-            json_response(result, TTL_SHORT)
-        }
-        (&Method::POST, Some(&"txs"), Some(&"package"), None, None, None) => {
-            let txhexes: Vec<String> =
-                serde_json::from_str(String::from_utf8(body.to_vec())?.as_str())?;
-
-            if txhexes.len() > 25 {
-                Result::Err(HttpError::from(
-                    "Exceeded maximum of 25 transactions".to_string(),
-                ))?
-            }
+fn tx_status(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let (query, format) = (req.query, req.format);
+    let hash = Txid::from_hex(hash)?;
+    let status = query.get_tx_status(&hash);
+    // Avoid raw transaction lookup here: status only needs confirmation state.
+    // Return 404 only if tx is neither confirmed nor present in mempool.
+    if !status.confirmed && query.mempool().lookup_txn(&hash).is_none() {
+        return Err(HttpError::not_found("Transaction not found".to_string()));
+    }
+    let ttl = ttl_by_depth(status.block_height, query);
+    json_response(status, ttl, format)
+}
 
-            let maxfeerate = query_params
-                .get("maxfeerate")
-                .map(|s| {
-                    s.parse::<f64>()
-                        .map_err(|_| HttpError::from("Invalid maxfeerate".to_string()))
-                })
-                .transpose()?;
+fn tx_merkle_proof(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let (query, format) = (req.query, req.format);
+    let hash = Txid::from_hex(hash)?;
+    let blockid = query.chain().tx_confirming_block(&hash).ok_or_else(|| {
+        HttpError::not_found("Transaction not found or is unconfirmed".to_string())
+    })?;
+    let (merkle, pos) = electrum_merkle::get_tx_merkle_proof(query.chain(), &hash, &blockid.hash)?;
+    let merkle: Vec<String> = merkle.into_iter().map(|txid| txid.to_hex()).collect();
+    let ttl = ttl_by_depth(Some(blockid.height), query);
+    json_response(
+        json!({ "block_height": blockid.height, "merkle": merkle, "pos": pos }),
+        ttl,
+        format,
+    )
+}
 
-            let maxburnamount = query_params
-                .get("maxburnamount")
-                .map(|s| {
-                    s.parse::<f64>()
-                        .map_err(|_| HttpError::from("Invalid maxburnamount".to_string()))
-                })
-                .transpose()?;
-
-            // pre-checks
-            txhexes.iter().enumerate().try_for_each(|(index, txhex)| {
-                // each transaction must be of reasonable size (more than 60 bytes, within 400kWU standardness limit)
-                if !(120..800_000).contains(&txhex.len()) {
-                    Result::Err(HttpError::from(format!(
-                        "Invalid transaction size for item {}",
-                        index
-                    )))
-                } else {
-                    // must be a valid hex string
-                    Vec::<u8>::from_hex(txhex)
-                        .map_err(|_| {
-                            HttpError::from(format!("Invalid transaction hex for item {}", index))
-                        })
-                        .map(|_| ())
-                }
-            })?;
+#[cfg(not(feature = "opcat_layer"))]
+fn tx_merkleblock_proof(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let query = req.query;
+    let hash = Txid::from_hex(hash)?;
+
+    let merkleblock = query.chain().get_merkleblock_proof(&hash).ok_or_else(|| {
+        HttpError::not_found("Transaction not found or is unconfirmed".to_string())
+    })?;
+
+    let height = query
+        .chain()
+        .height_by_hash(&merkleblock.header.block_hash());
+
+    http_message(
+        StatusCode::OK,
+        hex::encode(encode::serialize(&merkleblock)),
+        ttl_by_depth(height, query),
+    )
+}
 
-            let result = query
-                .submit_package(txhexes, maxfeerate, maxburnamount)
-                .map_err(|err| HttpError::from(err.description().to_string()))?;
+fn tx_output(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let index = req.param("vout");
+    let (query, format) = (req.query, req.format);
+    let txid = Txid::from_hex(hash)?;
+    let vout = index.parse::<u32>()?;
+    let outpoint = OutPoint { txid, vout };
+
+    // Look up TxOut from chain or mempool
+    let txout = query
+        .chain()
+        .lookup_txo(&outpoint)
+        .or_else(|| {
+            let mut outpoints = std::collections::BTreeSet::new();
+            outpoints.insert(outpoint);
+            query
+                .mempool()
+                .lookup_txos(&outpoints)
+                .get(&outpoint)
+                .cloned()
+        })
+        .ok_or_else(|| HttpError::not_found("Output not found".to_string()))?;
 
-            json_response(result, TTL_SHORT)
-        }
-        (&Method::GET, Some(&"txs"), Some(&"outspends"), None, None, None) => {
-            let txid_strings: Vec<&str> = query_params
-                .get("txids")
-                .ok_or(HttpError::from("No txids specified".to_string()))?
-                .as_str()
-                .split(',')
-                .collect();
+    // Build response
+    #[cfg(not(feature = "opcat_layer"))]
+    let response = serde_json::json!({
+        "scriptpubkey": txout.script_pubkey.to_hex(),
+        "value": txout.value,
+    });
 
-            if txid_strings.len() > 50 {
-                return http_message(StatusCode::BAD_REQUEST, "Too many txids requested", 0);
-            }
+    #[cfg(feature = "opcat_layer")]
+    let response = serde_json::json!({
+        "scriptpubkey": txout.script_pubkey.to_hex(),
+        "value": txout.value.as_sat(),
+        "data": hex::encode(&txout.data),
+    });
 
-            let spends: Vec<Vec<SpendingValue>> = txid_strings
-                .into_iter()
-                .map(|txid_str| {
-                    Txid::from_hex(txid_str)
-                        .ok()
-                        .and_then(|txid| query.lookup_txn(&txid))
-                        .map_or_else(Vec::new, |tx| {
-                            query
-                                .lookup_tx_spends(tx)
-                                .into_iter()
-                                .map(|spend| {
-                                    spend.map_or_else(SpendingValue::default, SpendingValue::from)
-                                })
-                                .collect()
-                        })
-                })
-                .collect();
+    // Determine TTL based on confirmation status
+    let block_id = query.chain().tx_confirming_block(&txid);
+    let ttl = ttl_by_depth(block_id.as_ref().map(|b| b.height), query);
+
+    json_response(response, ttl, format)
+}
+
+fn tx_outspend(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let index = req.param("vout");
+    let (query, format) = (req.query, req.format);
+    let hash = Txid::from_hex(hash)?;
+    let outpoint = OutPoint {
+        txid: hash,
+        vout: index.parse::<u32>()?,
+    };
+    let spend = query
+        .lookup_spend(&outpoint)
+        .map_or_else(SpendingValue::default, SpendingValue::from);
+    let ttl = ttl_by_depth(
+        spend.status.as_ref().and_then(|status| status.block_height),
+        query,
+    );
+    json_response(spend, ttl, format)
+}
+
+fn tx_outspends(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let (query, format) = (req.query, req.format);
+    let hash = Txid::from_hex(hash)?;
+    let tx = query
+        .lookup_txn(&hash)
+        .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+    let spends: Vec<SpendingValue> = query
+        .lookup_tx_spends(tx)
+        .into_iter()
+        .map(|spend| spend.map_or_else(SpendingValue::default, SpendingValue::from))
+        .collect();
+    // @TODO long ttl if all outputs are either spent long ago or unspendable
+    json_response(spends, TTL_SHORT, format)
+}
+
+fn broadcast(req: &Request) -> Result<Response<Body>, HttpError> {
+    let query = req.query;
+    let query_params = &req.query_params;
+    let body = &req.body;
+    // accept both POST and GET for backward compatibility.
+    // GET will eventually be removed in favor of POST.
+    let txhex = match *req.method {
+        Method::POST => String::from_utf8(body.to_vec())?,
+        Method::GET => query_params
+            .get("tx")
+            .cloned()
+            .ok_or_else(|| HttpError::from("Missing tx".to_string()))?,
+        _ => return http_message(StatusCode::METHOD_NOT_ALLOWED, "Invalid method", 0),
+    };
+    let txid = query
+        .broadcast_raw(&txhex)
+        .map_err(|err| HttpError::from(err.description().to_string()))?;
+    http_message(StatusCode::OK, txid.to_hex(), 0)
+}
+
+fn tx_decode(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, config, format) = (req.query, req.config, req.format);
+    // hex encoded transactions are twice their size, anything longer isn't decoded at all
+    if req.body.len() > 2 * MAX_DECODE_TX_SIZE {
+        return Err(HttpError::from("Transaction too large".to_string()));
+    }
+    let rawtx = decode_rawtx(&req.body);
+    if rawtx.len() > MAX_DECODE_TX_SIZE {
+        return Err(HttpError::from("Transaction too large".to_string()));
+    }
+    let tx: Transaction = encode::deserialize(&rawtx)?;
+    json_response(DecodedTxValue::new(tx, query, config), TTL_SHORT, format)
+}
+
+// Accept both hex and binary encoded transactions. Binary transactions can be valid UTF-8,
+// so the body is only assumed to be binary when it doesn't decode as hex.
+fn decode_rawtx(body: &[u8]) -> Vec<u8> {
+    std::str::from_utf8(body)
+        .ok()
+        .and_then(|txhex| Vec::<u8>::from_hex(txhex.trim()).ok())
+        .unwrap_or_else(|| body.to_vec())
+}
+
+fn psbt_analyze(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, config, format) = (req.query, req.config, req.format);
+    if req.body.len() > MAX_PSBT_SIZE {
+        return Err(HttpError::from("PSBT too large".to_string()));
+    }
+    // accept binary, base64 and hex encoded PSBTs
+    let data = if req.body.starts_with(b"psbt\xff") {
+        req.body.to_vec()
+    } else {
+        let encoded = String::from_utf8(req.body.to_vec())?;
+        let encoded = encoded.trim();
+        base64::decode(encoded)
+            .or_else(|_| Vec::<u8>::from_hex(encoded))
+            .map_err(|_| HttpError::from("Invalid PSBT encoding".to_string()))?
+    };
+    let psbt =
+        Psbt::deserialize(&data).map_err(|e| HttpError::from(format!("Invalid PSBT: {}", e)))?;
+    let update = req
+        .query_params
+        .get("update")
+        .is_some_and(|update| update == "true" || update == "1");
+
+    json_response(
+        PsbtAnalysisValue::new(psbt, update, query, config)?,
+        TTL_SHORT,
+        format,
+    )
+}
+
+fn tx_rbf(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let (query, format) = (req.query, req.format);
+    let txid = Txid::from_hex(hash)?;
+    let mempool = query.mempool();
+    json_response(
+        json!({
+            "replacements": mempool.replacement_tree(&txid),
+            "replaces": mempool.replaces(&txid),
+            "replaced_by": mempool.replaced_by(&txid),
+        }),
+        TTL_SHORT,
+        format,
+    )
+}
+
+fn tx_conflicts(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let (query, format) = (req.query, req.format);
+    let txid = Txid::from_hex(hash)?;
+
+    let tx = query.lookup_txn(&txid);
+    let (replaced_by, replaces) = {
+        let mempool = query.mempool();
+        (mempool.replaced_by(&txid), mempool.replaces(&txid))
+    };
+    if tx.is_none() && replaced_by.is_none() {
+        return Err(HttpError::not_found("Transaction not found".to_string()));
+    }
+
+    let conflicts: Vec<SpentPrevout> = tx
+        .map_or_else(Vec::new, |tx| query.lookup_tx_conflicts(&tx))
+        .into_iter()
+        .map(|(vin, outpoint, spend)| SpentPrevout {
+            input: InputRef {
+                vin,
+                txid: outpoint.txid,
+                vout: outpoint.vout,
+            },
+            spent_by: SpendingValue::from(spend),
+        })
+        .collect();
+    json_response(
+        json!({
+            "txid": txid,
+            "conflicted": !conflicts.is_empty() || replaced_by.is_some(),
+            "conflicts": conflicts,
+            "replaced_by": replaced_by,
+            "replaces": replaces,
+        }),
+        TTL_SHORT,
+        format,
+    )
+}
+
+fn tx_lifecycle(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let (query, format) = (req.query, req.format);
+    let txid = Txid::from_hex(hash)?;
+    let lifecycle = query
+        .mempool()
+        .lifecycle(&txid)
+        .ok_or_else(|| HttpError::not_found("Transaction not seen in mempool".to_string()))?;
+    json_response(lifecycle, TTL_SHORT, format)
+}
+
+fn tx_package(req: &Request) -> Result<Response<Body>, HttpError> {
+    let hash = req.param("txid");
+    let (query, format) = (req.query, req.format);
+    let txid = Txid::from_hex(hash)?;
+    let package = query
+        .mempool()
+        .package(&txid)
+        .ok_or_else(|| HttpError::not_found("Transaction not found in mempool".to_string()))?;
+    json_response(package, TTL_SHORT, format)
+}
+
+fn replacements(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    json_response(query.mempool().recent_replacements(), TTL_SHORT, format)
+}
+
+fn txs_test(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    let query_params = &req.query_params;
+    let body = &req.body;
+    let txhexes: Vec<String> = serde_json::from_str(String::from_utf8(body.to_vec())?.as_str())?;
 
-            json_response(spends, TTL_SHORT)
+    if txhexes.len() > 25 {
+        Result::Err(HttpError::from(
+            "Exceeded maximum of 25 transactions".to_string(),
+        ))?
+    }
+
+    let maxfeerate = query_params
+        .get("maxfeerate")
+        .map(|s| {
+            s.parse::<f64>()
+                .map_err(|_| HttpError::from("Invalid maxfeerate".to_string()))
+        })
+        .transpose()?;
+
+    // pre-checks
+    txhexes.iter().enumerate().try_for_each(|(index, txhex)| {
+        // each transaction must be of reasonable size (more than 60 bytes, within 400kWU standardness limit)
+        if !(120..800_000).contains(&txhex.len()) {
+            Result::Err(HttpError::from(format!(
+                "Invalid transaction size for item {}",
+                index
+            )))
+        } else {
+            // must be a valid hex string
+            Vec::<u8>::from_hex(txhex)
+                .map_err(|_| HttpError::from(format!("Invalid transaction hex for item {}", index)))
+                .map(|_| ())
         }
-        (
-            &Method::POST,
-            Some(&INTERNAL_PREFIX),
-            Some(&"txs"),
-            Some(&"outspends"),
-            Some(&"by-txid"),
-            None,
-        ) => {
-            let txid_strings: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
-
-            let spends: Vec<Vec<SpendingValue>> = txid_strings
-                .into_iter()
-                .map(|txid_str| {
-                    Txid::from_hex(&txid_str)
-                        .ok()
-                        .and_then(|txid| query.lookup_txn(&txid))
-                        .map_or_else(Vec::new, |tx| {
-                            query
-                                .lookup_tx_spends(tx)
-                                .into_iter()
-                                .map(|spend| {
-                                    spend.map_or_else(SpendingValue::default, SpendingValue::from)
-                                })
-                                .collect()
-                        })
-                })
-                .collect();
+    })?;
+
+    let result = query
+        .test_mempool_accept(txhexes, maxfeerate)
+        .map_err(|err| HttpError::from(err.description().to_string()))?;
+
+    json_response(result, TTL_SHORT, format)
+}
+
+fn txs_package(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    let query_params = &req.query_params;
+    let body = &req.body;
+    let txhexes: Vec<String> = serde_json::from_str(String::from_utf8(body.to_vec())?.as_str())?;
 
-            json_response(spends, TTL_SHORT)
+    if txhexes.len() > 25 {
+        Result::Err(HttpError::from(
+            "Exceeded maximum of 25 transactions".to_string(),
+        ))?
+    }
+
+    let maxfeerate = query_params
+        .get("maxfeerate")
+        .map(|s| {
+            s.parse::<f64>()
+                .map_err(|_| HttpError::from("Invalid maxfeerate".to_string()))
+        })
+        .transpose()?;
+
+    let maxburnamount = query_params
+        .get("maxburnamount")
+        .map(|s| {
+            s.parse::<f64>()
+                .map_err(|_| HttpError::from("Invalid maxburnamount".to_string()))
+        })
+        .transpose()?;
+
+    // pre-checks
+    txhexes.iter().enumerate().try_for_each(|(index, txhex)| {
+        // each transaction must be of reasonable size (more than 60 bytes, within 400kWU standardness limit)
+        if !(120..800_000).contains(&txhex.len()) {
+            Result::Err(HttpError::from(format!(
+                "Invalid transaction size for item {}",
+                index
+            )))
+        } else {
+            // must be a valid hex string
+            Vec::<u8>::from_hex(txhex)
+                .map_err(|_| HttpError::from(format!("Invalid transaction hex for item {}", index)))
+                .map(|_| ())
         }
-        (
-            &Method::POST,
-            Some(&INTERNAL_PREFIX),
-            Some(&"txs"),
-            Some(&"outspends"),
-            Some(&"by-outpoint"),
-            None,
-        ) => {
-            let outpoint_strings: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
-
-            let spends: Vec<SpendingValue> = outpoint_strings
-                .into_iter()
-                .map(|outpoint_str| {
-                    let mut parts = outpoint_str.split(':');
-                    let hash_part = parts.next();
-                    let index_part = parts.next();
-
-                    if let (Some(hash), Some(index)) = (hash_part, index_part) {
-                        if let (Ok(txid), Ok(vout)) = (Txid::from_hex(hash), index.parse::<u32>()) {
-                            let outpoint = OutPoint { txid, vout };
-                            return query
-                                .lookup_spend(&outpoint)
-                                .map_or_else(SpendingValue::default, SpendingValue::from);
-                        }
-                    }
-                    SpendingValue::default()
+    })?;
+
+    let result = query
+        .submit_package(txhexes, maxfeerate, maxburnamount)
+        .map_err(|err| HttpError::from(err.description().to_string()))?;
+
+    json_response(result, TTL_SHORT, format)
+}
+
+fn txs_outspends(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    let query_params = &req.query_params;
+    let txid_strings: Vec<&str> = query_params
+        .get("txids")
+        .ok_or(HttpError::from("No txids specified".to_string()))?
+        .as_str()
+        .split(',')
+        .collect();
+
+    if txid_strings.len() > 50 {
+        return http_message(StatusCode::BAD_REQUEST, "Too many txids requested", 0);
+    }
+
+    let spends: Vec<Vec<SpendingValue>> = txid_strings
+        .into_iter()
+        .map(|txid_str| {
+            Txid::from_hex(txid_str)
+                .ok()
+                .and_then(|txid| query.lookup_txn(&txid))
+                .map_or_else(Vec::new, |tx| {
+                    query
+                        .lookup_tx_spends(tx)
+                        .into_iter()
+                        .map(|spend| spend.map_or_else(SpendingValue::default, SpendingValue::from))
+                        .collect()
                 })
-                .collect();
+        })
+        .collect();
 
-            json_response(spends, TTL_SHORT)
-        }
+    json_response(spends, TTL_SHORT, format)
+}
 
-        (&Method::GET, Some(&"mempool"), None, None, None, None) => {
-            json_response(query.mempool().backlog_stats(), TTL_SHORT)
-        }
-        (&Method::GET, Some(&"mempool"), Some(&"txids"), None, None, None) => {
-            json_response(query.mempool().txids(), TTL_SHORT)
-        }
-        (&Method::GET, Some(&"mempool"), Some(&"txids"), Some(&"page"), last_seen_txid, None) => {
-            let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
-            let max_txs = query_params
-                .get("max_txs")
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_max_mempool_txid_page_size);
-            json_response(
-                query.mempool().txids_page(max_txs, last_seen_txid),
-                TTL_SHORT,
-            )
-        }
-        (
-            &Method::GET,
-            Some(&INTERNAL_PREFIX),
-            Some(&"mempool"),
-            Some(&"txs"),
-            Some(&"all"),
-            None,
-        ) => {
-            let txs = query
-                .mempool()
-                .txs()
-                .into_iter()
-                .map(|tx| (tx, None))
-                .collect();
+fn internal_txs_outspends_by_txid(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    let body = &req.body;
+    let txid_strings: Vec<String> =
+        serde_json::from_slice(body).map_err(|err| HttpError::from(err.to_string()))?;
+
+    let spends: Vec<Vec<SpendingValue>> = txid_strings
+        .into_iter()
+        .map(|txid_str| {
+            Txid::from_hex(&txid_str)
+                .ok()
+                .and_then(|txid| query.lookup_txn(&txid))
+                .map_or_else(Vec::new, |tx| {
+                    query
+                        .lookup_tx_spends(tx)
+                        .into_iter()
+                        .map(|spend| spend.map_or_else(SpendingValue::default, SpendingValue::from))
+                        .collect()
+                })
+        })
+        .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
-        }
-        (&Method::POST, Some(&INTERNAL_PREFIX), Some(&"mempool"), Some(&"txs"), None, None) => {
-            let txid_strings: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+    json_response(spends, TTL_SHORT, format)
+}
 
-            match txid_strings
-                .into_iter()
-                .map(|txid| Txid::from_hex(&txid))
-                .collect::<Result<Vec<Txid>, _>>()
-            {
-                Ok(txids) => {
-                    let txs: Vec<(Transaction, Option<BlockId>)> = {
-                        let mempool = query.mempool();
-                        txids
-                            .iter()
-                            .filter_map(|txid| mempool.lookup_txn(txid).map(|tx| (tx, None)))
-                            .collect()
-                    };
-
-                    json_response(prepare_txs(txs, query, config), 0)
+fn internal_txs_outspends_by_outpoint(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    let body = &req.body;
+    let outpoint_strings: Vec<String> =
+        serde_json::from_slice(body).map_err(|err| HttpError::from(err.to_string()))?;
+
+    let spends: Vec<SpendingValue> = outpoint_strings
+        .into_iter()
+        .map(|outpoint_str| {
+            let mut parts = outpoint_str.split(':');
+            let hash_part = parts.next();
+            let index_part = parts.next();
+
+            if let (Some(hash), Some(index)) = (hash_part, index_part) {
+                if let (Ok(txid), Ok(vout)) = (Txid::from_hex(hash), index.parse::<u32>()) {
+                    let outpoint = OutPoint { txid, vout };
+                    return query
+                        .lookup_spend(&outpoint)
+                        .map_or_else(SpendingValue::default, SpendingValue::from);
                 }
-                Err(err) => http_message(StatusCode::BAD_REQUEST, err.to_string(), 0),
             }
-        }
-        (
-            &Method::GET,
-            Some(&INTERNAL_PREFIX),
-            Some(&"mempool"),
-            Some(&"txs"),
-            last_seen_txid,
-            None,
-        ) => {
-            let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
-            let max_txs = query_params
-                .get("max_txs")
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_max_mempool_page_size);
-            let txs = query
-                .mempool()
-                .txs_page(max_txs, last_seen_txid)
-                .into_iter()
-                .map(|tx| (tx, None))
-                .collect();
+            SpendingValue::default()
+        })
+        .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
-        }
-        (&Method::GET, Some(&"mempool"), Some(&"recent"), None, None, None) => {
-            let mempool = query.mempool();
-            let recent = mempool.recent_txs_overview();
-            json_response(recent, TTL_MEMPOOL_RECENT)
-        }
+    json_response(spends, TTL_SHORT, format)
+}
 
-        (&Method::GET, Some(&"fee-estimates"), None, None, None, None) => {
-            json_response(query.estimate_fee_map(), TTL_SHORT)
-        }
+fn sync_status(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    json_response(query.chain().store().sync_status(), 0, format)
+}
+
+fn mempool(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    json_response(query.mempool().backlog_stats(), TTL_SHORT, format)
+}
+
+fn mempool_blocks(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    json_response(query.mempool().projected_blocks(), TTL_SHORT, format)
+}
+
+fn mempool_txids(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    json_response(query.mempool().txids(), TTL_SHORT, format)
+}
+
+fn mempool_txids_page(req: &Request) -> Result<Response<Body>, HttpError> {
+    let last_seen_txid = req.optional_param("last_seen_txid");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
+    let max_txs = req.access.mempool_txid_page_size(
+        query_params
+            .get("max_txs")
+            .and_then(|s| s.parse::<usize>().ok()),
+        config,
+    );
+    json_response(
+        query.mempool().txids_page(max_txs, last_seen_txid),
+        TTL_SHORT,
+        format,
+    )
+}
+
+fn internal_mempool_txs_all(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, config, format) = (req.query, req.config, req.format);
+    let txs = query
+        .mempool()
+        .txs()
+        .into_iter()
+        .map(|tx| (tx, None))
+        .collect();
 
-        _ => Err(HttpError::not_found(format!(
-            "endpoint does not exist {:?}",
-            uri.path()
-        ))),
+    json_response(prepare_txs(txs, query, config), TTL_SHORT, format)
+}
+
+fn internal_db_checkpoint(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, config, format) = (req.query, req.config, req.format);
+    let dir = config.db_checkpoint_dir.as_ref().ok_or_else(|| {
+        HttpError::not_found("DB checkpoints are disabled, see --db-checkpoint-dir".to_string())
+    })?;
+    // not waiting for the running index update, which would hold this connection
+    match query.chain().store().try_checkpoint(dir)? {
+        Some((path, tip)) => json_response(json!({ "path": path, "tip": tip }), 0, format),
+        None => Ok(Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header("Content-Type", "text/plain")
+            .header("Retry-After", CHECKPOINT_RETRY_AFTER.to_string())
+            .header("X-Powered-By", &**VERSION_STRING)
+            .body(Body::from("An index update is in progress, retry later"))
+            .unwrap()),
+    }
+}
+
+fn internal_mempool_txs_by_txid(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, config, format) = (req.query, req.config, req.format);
+    let body = &req.body;
+    let txid_strings: Vec<String> =
+        serde_json::from_slice(body).map_err(|err| HttpError::from(err.to_string()))?;
+
+    match txid_strings
+        .into_iter()
+        .map(|txid| Txid::from_hex(&txid))
+        .collect::<Result<Vec<Txid>, _>>()
+    {
+        Ok(txids) => {
+            let txs: Vec<(Transaction, Option<BlockId>)> = {
+                let mempool = query.mempool();
+                txids
+                    .iter()
+                    .filter_map(|txid| mempool.lookup_txn(txid).map(|tx| (tx, None)))
+                    .collect()
+            };
+
+            json_response(prepare_txs(txs, query, config), 0, format)
+        }
+        Err(err) => http_message(StatusCode::BAD_REQUEST, err.to_string(), 0),
     }
 }
 
+fn internal_mempool_txs(req: &Request) -> Result<Response<Body>, HttpError> {
+    let last_seen_txid = req.optional_param("last_seen_txid");
+    let (query, config, format) = (req.query, req.config, req.format);
+    let query_params = &req.query_params;
+    let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_hex(txid).ok());
+    let max_txs = req.access.mempool_page_size(
+        query_params
+            .get("max_txs")
+            .and_then(|s| s.parse::<usize>().ok()),
+        config,
+    );
+    let txs = query
+        .mempool()
+        .txs_page(max_txs, last_seen_txid)
+        .into_iter()
+        .map(|tx| (tx, None))
+        .collect();
+
+    json_response(prepare_txs(txs, query, config), TTL_SHORT, format)
+}
+
+fn mempool_recent(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    let mempool = query.mempool();
+    let recent = mempool.recent_txs_overview();
+    json_response(recent, TTL_MEMPOOL_RECENT, format)
+}
+
+fn fee_estimates(req: &Request) -> Result<Response<Body>, HttpError> {
+    let (query, format) = (req.query, req.format);
+    json_response(query.estimate_fee_map(), TTL_SHORT, format)
+}
+
 fn http_message<T>(status: StatusCode, message: T, ttl: u32) -> Result<Response<Body>, HttpError>
 where
     T: Into<Body>,
@@ -1789,10 +2929,14 @@ where
         .unwrap())
 }
 
-fn json_response<T: Serialize>(value: T, ttl: u32) -> Result<Response<Body>, HttpError> {
-    let value = serde_json::to_string(&value)?;
+fn json_response<T: Serialize>(
+    value: T,
+    ttl: u32,
+    format: ResponseFormat,
+) -> Result<Response<Body>, HttpError> {
+    let value = format.serialize(&value)?;
     Ok(Response::builder()
-        .header("Content-Type", "application/json")
+        .header("Content-Type", format.content_type())
         .header("Cache-Control", format!("public, max-age={:}", ttl))
         .header("X-Powered-By", &**VERSION_STRING)
         .body(Body::from(value))
@@ -1824,6 +2968,7 @@ fn blocks(
     query: &Query,
     config: &Config,
     start_height: Option<usize>,
+    format: ResponseFormat,
 ) -> Result<Response<Body>, HttpError> {
     let mut values = Vec::new();
     let mut current_hash = match start_height {
@@ -1852,7 +2997,7 @@ fn blocks(
             break;
         }
     }
-    json_response(values, TTL_SHORT)
+    json_response(values, TTL_SHORT, format)
 }
 
 fn to_scripthash(
@@ -2181,4 +3326,91 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_decode_rawtx() {
+        use super::decode_rawtx;
+        use bitcoin::hashes::hex::FromHex;
+
+        // a binary transaction that is also valid UTF-8
+        let txhex = format!(
+            "0200000001{}0000000000000000000130300000000000000000000000",
+            "00".repeat(32)
+        );
+        let rawtx = Vec::<u8>::from_hex(&txhex).unwrap();
+        assert!(std::str::from_utf8(&rawtx).is_ok());
+        assert_eq!(decode_rawtx(&rawtx), rawtx);
+        assert_eq!(decode_rawtx(txhex.as_bytes()), rawtx);
+        assert_eq!(decode_rawtx(format!("{}\n", txhex).as_bytes()), rawtx);
+    }
+
+    #[test]
+    #[cfg(not(feature = "opcat_layer"))]
+    fn test_estimate_psbt_vsize() {
+        use super::estimate_psbt_vsize;
+        use crate::chain::{Script, Transaction, TxIn, TxOut};
+        use crate::util::psbt::{Psbt, PsbtMap, IN_FINAL_SCRIPTWITNESS};
+        use bitcoin::hashes::Hash;
+
+        let p2wpkh = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::from_slice(&[0; 20]).unwrap());
+        let txout = TxOut {
+            value: 10_000,
+            script_pubkey: p2wpkh,
+        };
+        let mut psbt = Psbt {
+            unsigned_tx: Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![TxIn::default()],
+                output: vec![txout.clone(), txout.clone()],
+            },
+            global: PsbtMap::default(),
+            inputs: vec![PsbtMap::default()],
+            outputs: vec![PsbtMap::default(), PsbtMap::default()],
+        };
+        let prevouts = vec![Some(txout)];
+        // the typical 1-input 2-output P2WPKH transaction
+        assert_eq!(estimate_psbt_vsize(&psbt, &prevouts), Some(141));
+        assert_eq!(estimate_psbt_vsize(&psbt, &[None]), None);
+
+        // finalized inputs use their actual witness
+        psbt.inputs[0].insert(IN_FINAL_SCRIPTWITNESS, vec![0; 108]);
+        assert_eq!(estimate_psbt_vsize(&psbt, &[None]), Some(141));
+    }
+
+    #[test]
+    #[cfg(feature = "opcat_layer")]
+    fn test_estimate_psbt_vsize() {
+        use super::estimate_psbt_vsize;
+        use crate::chain::{Transaction, TxIn, TxOut, Value};
+        use crate::util::psbt::{Psbt, PsbtMap};
+        use bitcoin::hashes::Hash;
+        use bitcoin::Script;
+
+        let txout = |script_pubkey: Script| TxOut {
+            value: Value::from_sat(10_000),
+            script_pubkey,
+            data: vec![],
+        };
+        let p2pkh = Script::new_p2pkh(&bitcoin::PubkeyHash::from_slice(&[0; 20]).unwrap());
+        let p2wpkh = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::from_slice(&[0; 20]).unwrap());
+        let psbt = Psbt {
+            unsigned_tx: Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: Default::default(),
+                    script_sig: Script::new(),
+                    sequence: 0xffff_ffff,
+                }],
+                output: vec![txout(p2pkh.clone())],
+            },
+            global: PsbtMap::default(),
+            inputs: vec![PsbtMap::default()],
+            outputs: vec![PsbtMap::default()],
+        };
+        assert!(estimate_psbt_vsize(&psbt, &[Some(txout(p2pkh))]).is_some());
+        // witness inputs can't be spent in the OPCAT layer
+        assert_eq!(estimate_psbt_vsize(&psbt, &[Some(txout(p2wpkh))]), None);
+    }
 }
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use error_chain::ChainedError;
+use hyper::header::HeaderMap;
+use hyper::StatusCode;
+use url::form_urlencoded;
+
+use crate::config::Config;
+use crate::errors::{Result, ResultExt};
+use crate::metrics::{CounterVec, MetricOpts, Metrics};
+use crate::util::spawn_thread;
+
+use super::HttpError;
+
+const API_KEY_HEADER: &str = "X-API-Key";
+pub(super) const API_KEY_PARAM: &str = "api_key";
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+const ANONYMOUS_TIER: &str = "anonymous";
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// An access tier, as defined in the API keys file.
+///
+/// Requests without a key use the `anonymous` tier (if defined), which by
+/// default has no access to the internal endpoints.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Tier {
+    /// Allow access to the `/internal/...` endpoints
+    #[serde(default)]
+    pub internal: bool,
+    /// Ceiling for the paginated /internal/mempool/txs endpoint
+    pub max_mempool_page_size: Option<usize>,
+    /// Ceiling for the paginated /mempool/txids/page endpoint
+    pub max_mempool_txid_page_size: Option<usize>,
+    /// Maximum number of requests per minute, per key. For the anonymous tier, per client IP.
+    pub rate_limit: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    name: String,
+    tier: String,
+}
+
+/// The on-disk format of the API keys file:
+///
+/// ```json
+/// {
+///   "tiers": {
+///     "anonymous": { "max_mempool_txid_page_size": 1000 },
+///     "partner": { "internal": true, "rate_limit": 600 }
+///   },
+///   "keys": {
+///     "5e1f...c0de": { "name": "acme", "tier": "partner" }
+///   }
+/// }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct KeyFile {
+    #[serde(default)]
+    tiers: HashMap<String, Tier>,
+    #[serde(default)]
+    keys: HashMap<String, KeyEntry>,
+}
+
+impl KeyFile {
+    fn load(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(path).chain_err(|| format!("failed to read API keys from {:?}", path))?;
+        let keyfile: KeyFile = serde_json::from_slice(&contents)
+            .chain_err(|| format!("invalid API keys file {:?}", path))?;
+        for (key, entry) in &keyfile.keys {
+            if key.is_empty() {
+                bail!("empty API key for {}", entry.name);
+            }
+            if !keyfile.tiers.contains_key(&entry.tier) {
+                bail!("unknown tier {} for API key {}", entry.tier, entry.name);
+            }
+        }
+        Ok(keyfile)
+    }
+
+    fn anonymous_tier(&self) -> Tier {
+        self.tiers.get(ANONYMOUS_TIER).cloned().unwrap_or_default()
+    }
+}
+
+/// The access level granted to a single request.
+pub struct Access {
+    pub internal: bool,
+    max_mempool_page_size: Option<usize>,
+    max_mempool_txid_page_size: Option<usize>,
+}
+
+impl Access {
+    fn unrestricted() -> Self {
+        Access {
+            internal: true,
+            max_mempool_page_size: None,
+            max_mempool_txid_page_size: None,
+        }
+    }
+
+    fn from_tier(tier: &Tier) -> Self {
+        Access {
+            internal: tier.internal,
+            max_mempool_page_size: tier.max_mempool_page_size,
+            max_mempool_txid_page_size: tier.max_mempool_txid_page_size,
+        }
+    }
+
+    pub fn mempool_page_size(&self, requested: Option<usize>, config: &Config) -> usize {
+        let size = requested.unwrap_or(config.rest_max_mempool_page_size);
+        self.max_mempool_page_size.map_or(size, |max| size.min(max))
+    }
+
+    pub fn mempool_txid_page_size(&self, requested: Option<usize>, config: &Config) -> usize {
+        let size = requested.unwrap_or(config.rest_max_mempool_txid_page_size);
+        self.max_mempool_txid_page_size
+            .map_or(size, |max| size.min(max))
+    }
+}
+
+struct KeyState {
+    keyfile: KeyFile,
+    modified: Option<SystemTime>,
+}
+
+/// What a rate limit window is counted for
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum RateKey {
+    ApiKey(String),
+    /// An anonymous client, `None` if its address is unknown
+    Client(Option<IpAddr>),
+}
+
+pub struct ApiKeys {
+    path: Option<PathBuf>,
+    state: RwLock<KeyState>,
+    // key => (start of the current window, requests made within it)
+    windows: Mutex<HashMap<RateKey, (Instant, u32)>>,
+
+    // Metrics
+    requests: CounterVec,
+    rejected: CounterVec,
+}
+
+impl ApiKeys {
+    pub fn start(config: &Config, metrics: &Metrics) -> Arc<ApiKeys> {
+        let state = match config.rest_api_keys {
+            Some(ref path) => {
+                let keyfile = KeyFile::load(path).expect("cannot load REST API keys");
+                info!("loaded {} REST API keys", keyfile.keys.len());
+                KeyState {
+                    keyfile,
+                    modified: modified_time(path),
+                }
+            }
+            None => KeyState {
+                keyfile: KeyFile {
+                    tiers: HashMap::new(),
+                    keys: HashMap::new(),
+                },
+                modified: None,
+            },
+        };
+
+        let api_keys = Arc::new(ApiKeys {
+            path: config.rest_api_keys.clone(),
+            state: RwLock::new(state),
+            windows: Mutex::new(HashMap::new()),
+            requests: metrics.counter_vec(
+                MetricOpts::new("rest_api_key_requests", "# of REST requests per API key"),
+                &["key", "tier"],
+            ),
+            rejected: metrics.counter_vec(
+                MetricOpts::new(
+                    "rest_api_key_rejected",
+                    "# of REST requests rejected per API key",
+                ),
+                &["key", "reason"],
+            ),
+        });
+
+        if api_keys.path.is_some() {
+            let api_keys = Arc::clone(&api_keys);
+            spawn_thread("api-keys-reload", move || loop {
+                std::thread::sleep(RELOAD_INTERVAL);
+                api_keys.reload_if_modified();
+                api_keys.prune_windows();
+            });
+        }
+
+        api_keys
+    }
+
+    fn reload_if_modified(&self) {
+        let path = self.path.as_ref().expect("reloading without API keys file");
+        let modified = modified_time(path);
+        if modified == self.state.read().unwrap().modified {
+            return;
+        }
+        match KeyFile::load(path) {
+            Ok(keyfile) => {
+                info!("reloaded {} REST API keys", keyfile.keys.len());
+                *self.state.write().unwrap() = KeyState { keyfile, modified };
+                self.windows.lock().unwrap().clear();
+            }
+            Err(e) => warn!("keeping previous REST API keys: {}", e.display_chain()),
+        }
+    }
+
+    // Forget the windows of the clients that made no request within the last minute,
+    // there is one per anonymous client IP.
+    fn prune_windows(&self) {
+        self.windows
+            .lock()
+            .unwrap()
+            .retain(|_, (started, _)| started.elapsed() < RATE_LIMIT_WINDOW);
+    }
+
+    /// Resolve the access level for a request, based on the key passed in
+    /// the `X-API-Key` header or the `api_key` query parameter.
+    ///
+    /// `client` is the address of the peer, `None` on a unix socket. There, the
+    /// anonymous requests are told apart by the `X-Forwarded-For` header of the proxy.
+    pub fn authorize(
+        &self,
+        headers: &HeaderMap,
+        query: Option<&str>,
+        client: Option<IpAddr>,
+    ) -> std::result::Result<Access, HttpError> {
+        if self.path.is_none() {
+            return Ok(Access::unrestricted());
+        }
+
+        let key = headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+            .or_else(|| {
+                form_urlencoded::parse(query?.as_bytes())
+                    .find(|(name, _)| name == API_KEY_PARAM)
+                    .map(|(_, value)| value.into_owned())
+            });
+
+        let state = self.state.read().unwrap();
+        let key = match key {
+            Some(ref key) => key,
+            None => {
+                let tier = state.keyfile.anonymous_tier();
+                let client = client.or_else(|| forwarded_for(headers));
+                self.check_rate_limit(RateKey::Client(client), ANONYMOUS_TIER, &tier)?;
+                self.requests
+                    .with_label_values(&[ANONYMOUS_TIER, ANONYMOUS_TIER])
+                    .inc();
+                return Ok(Access::from_tier(&tier));
+            }
+        };
+
+        let entry = state.keyfile.keys.get(key).ok_or_else(|| {
+            self.rejected
+                .with_label_values(&["unknown", "invalid_key"])
+                .inc();
+            HttpError(StatusCode::UNAUTHORIZED, "Invalid API key".to_string())
+        })?;
+        let tier = &state.keyfile.tiers[&entry.tier];
+        self.check_rate_limit(RateKey::ApiKey(key.clone()), &entry.name, tier)?;
+
+        self.requests
+            .with_label_values(&[&entry.name, &entry.tier])
+            .inc();
+        Ok(Access::from_tier(tier))
+    }
+
+    fn check_rate_limit(
+        &self,
+        key: RateKey,
+        name: &str,
+        tier: &Tier,
+    ) -> std::result::Result<(), HttpError> {
+        match tier.rate_limit {
+            Some(limit) if !self.take_request(key, limit) => {
+                self.rejected.with_label_values(&[name, "rate_limit"]).inc();
+                Err(HttpError(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!("Rate limit of {} requests per minute exceeded", limit),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Count a request against the key's fixed one-minute window.
+    // Returns false if the limit was already reached.
+    fn take_request(&self, key: RateKey, limit: u32) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+        let (started, count) = windows.entry(key).or_insert((now, 0));
+        if now.duration_since(*started) >= RATE_LIMIT_WINDOW {
+            *started = now;
+            *count = 0;
+        }
+        if *count >= limit {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+// The client that connected to the reverse proxy. The proxy appends it to the header, the
+// entries before it come from the client and can't be trusted.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    let value = headers.get_all(FORWARDED_FOR_HEADER).iter().next_back()?;
+    value.to_str().ok()?.rsplit(',').next()?.trim().parse().ok()
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_file() {
+        let keyfile: KeyFile = serde_json::from_str(
+            r#"{
+                "tiers": {
+                    "anonymous": { "max_mempool_txid_page_size": 100 },
+                    "partner": { "internal": true, "rate_limit": 600 }
+                },
+                "keys": { "secret": { "name": "acme", "tier": "partner" } }
+            }"#,
+        )
+        .unwrap();
+
+        let anonymous = keyfile.anonymous_tier();
+        assert!(!anonymous.internal);
+        assert_eq!(anonymous.max_mempool_txid_page_size, Some(100));
+
+        let entry = &keyfile.keys["secret"];
+        assert_eq!(entry.name, "acme");
+        let partner: &Tier = &keyfile.tiers[&entry.tier];
+        assert!(partner.internal);
+        assert_eq!(partner.rate_limit, Some(600));
+        assert_eq!(partner.max_mempool_page_size, None);
+    }
+
+    #[test]
+    fn test_default_anonymous_tier() {
+        let keyfile: KeyFile = serde_json::from_str(r#"{ "keys": {} }"#).unwrap();
+        let anonymous = keyfile.anonymous_tier();
+        assert!(!anonymous.internal);
+        assert_eq!(anonymous.rate_limit, None);
+    }
+
+    #[test]
+    fn test_forwarded_for() {
+        let forwarded = |values: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for value in values {
+                headers.append(FORWARDED_FOR_HEADER, value.parse().unwrap());
+            }
+            forwarded_for(&headers)
+        };
+        let proxy_client = Some("198.51.100.7".parse().unwrap());
+        assert_eq!(forwarded(&["198.51.100.7"]), proxy_client);
+        // a spoofed leading entry is ignored
+        assert_eq!(forwarded(&["203.0.113.1, 198.51.100.7"]), proxy_client);
+        assert_eq!(forwarded(&["203.0.113.1", "198.51.100.7"]), proxy_client);
+        assert_eq!(forwarded(&["198.51.100.7, garbage"]), None);
+        assert_eq!(forwarded(&[]), None);
+    }
+
+    #[test]
+    fn test_anonymous_rate_limit() {
+        let keyfile: KeyFile = serde_json::from_str(
+            r#"{ "tiers": { "anonymous": { "rate_limit": 2 } }, "keys": {} }"#,
+        )
+        .unwrap();
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let api_keys = ApiKeys {
+            path: Some(PathBuf::from("keys.json")),
+            state: RwLock::new(KeyState {
+                keyfile,
+                modified: None,
+            }),
+            windows: Mutex::new(HashMap::new()),
+            requests: metrics.counter_vec(MetricOpts::new("requests", "-"), &["key", "tier"]),
+            rejected: metrics.counter_vec(MetricOpts::new("rejected", "-"), &["key", "reason"]),
+        };
+        let client = |ip: &str| Some(ip.parse().unwrap());
+        let headers = HeaderMap::new();
+
+        for _ in 0..2 {
+            assert!(api_keys
+                .authorize(&headers, None, client("10.0.0.1"))
+                .is_ok());
+        }
+        let err = api_keys
+            .authorize(&headers, None, client("10.0.0.1"))
+            .err()
+            .unwrap();
+        assert_eq!(err.0, StatusCode::TOO_MANY_REQUESTS);
+        // counted per client IP
+        assert!(api_keys
+            .authorize(&headers, None, client("10.0.0.2"))
+            .is_ok());
+
+        // behind a proxy on a unix socket, by the forwarded address
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_FOR_HEADER, "10.0.0.9, 10.0.0.1".parse().unwrap());
+        assert!(api_keys.authorize(&headers, None, None).is_err());
+        headers.insert(FORWARDED_FOR_HEADER, "10.0.0.3".parse().unwrap());
+        assert!(api_keys.authorize(&headers, None, None).is_ok());
+    }
+}
@@ -0,0 +1,234 @@
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Method, Response, StatusCode};
+use time::OffsetDateTime as DateTime;
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::chain::BlockHash;
+use crate::config::VERSION_STRING;
+use crate::new_index::Query;
+
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{sha256, Hash};
+
+use super::auth::API_KEY_PARAM;
+use super::encoding::ResponseFormat;
+use super::{ttl_by_depth, INTERNAL_PREFIX, TTL_SHORT};
+
+/// Validators for conditional GET requests.
+///
+/// Block resources are identified by the block hash and the requested
+/// sub-resource (e.g. the txs page), while address resources change with
+/// every new block or mempool update and are identified by the chain tip
+/// and the mempool sequence. The mempool sequence restarts with the process, so address tags
+/// also include a random per-process epoch, and a hash of the path and query params to tell
+/// apart the sub-resources and pages of the address. Non-JSON formats get their own tags.
+pub struct CacheValidator {
+    etag: String,
+    last_modified: Option<u32>,
+    ttl: u32,
+}
+
+impl CacheValidator {
+    pub fn for_request(
+        method: &Method,
+        path: &[&str],
+        query_params: &HashMap<String, String>,
+        query: &Query,
+        format: ResponseFormat,
+    ) -> Option<Self> {
+        if method != Method::GET {
+            return None;
+        }
+        let mut validator = match path {
+            // The block status changes with the chain tip and is not cacheable by hash
+            [INTERNAL_PREFIX, "block", _, "status", ..] | ["block", _, "status", ..] => None,
+            [INTERNAL_PREFIX, "block", hash, rest @ ..] | ["block", hash, rest @ ..] => {
+                let hash = BlockHash::from_hex(hash).ok()?;
+                let header = query.chain().get_block_header(&hash)?;
+                let height = query.chain().blockid_by_hash(&hash).map(|b| b.height);
+                Some(CacheValidator {
+                    // Transactions of a stale block are reported as unconfirmed
+                    etag: format!(
+                        "\"{}{}/{}\"",
+                        hash,
+                        if height.is_some() { "" } else { "-stale" },
+                        rest.join("/")
+                    ),
+                    last_modified: Some(header.time),
+                    ttl: ttl_by_depth(height, query),
+                })
+            }
+            ["address", _, ..] | ["scripthash", _, ..] => Some(CacheValidator {
+                etag: format!(
+                    "\"{}-{:016x}-{}-{}\"",
+                    query.chain().best_hash(),
+                    *EPOCH,
+                    query.mempool().sequence(),
+                    request_hash(path, query_params)
+                ),
+                last_modified: None,
+                ttl: TTL_SHORT,
+            }),
+            _ => None,
+        }?;
+        match format {
+            ResponseFormat::Json => (),
+            ResponseFormat::Cbor => validator.etag.insert_str(validator.etag.len() - 1, "+cbor"),
+            ResponseFormat::MessagePack => validator
+                .etag
+                .insert_str(validator.etag.len() - 1, "+msgpack"),
+        }
+        Some(validator)
+    }
+
+    /// Check the request's If-None-Match header against our ETag
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(hyper::header::IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == self.etag)
+    }
+
+    pub fn not_modified(&self) -> Response<Body> {
+        let mut resp = Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("Cache-Control", format!("public, max-age={:}", self.ttl))
+            .header("X-Powered-By", &**VERSION_STRING)
+            .body(Body::empty())
+            .unwrap();
+        self.apply(&mut resp);
+        resp
+    }
+
+    /// Attach the validators to a successful response
+    pub fn apply(&self, resp: &mut Response<Body>) {
+        if resp.status() != StatusCode::OK {
+            return;
+        }
+        let headers = resp.headers_mut();
+        headers.insert(
+            hyper::header::ETAG,
+            HeaderValue::from_str(&self.etag).unwrap(),
+        );
+        if let Some(last_modified) = self.last_modified.and_then(http_date) {
+            headers.insert(
+                hyper::header::LAST_MODIFIED,
+                HeaderValue::from_str(&last_modified).unwrap(),
+            );
+        }
+    }
+}
+
+lazy_static! {
+    // Random for every process, std's RandomState is seeded from the OS
+    static ref EPOCH: u64 = RandomState::new().build_hasher().finish();
+}
+
+// The API key only identifies the client, it doesn't select what is returned
+fn request_hash(path: &[&str], query_params: &HashMap<String, String>) -> String {
+    let mut params: Vec<_> = query_params
+        .iter()
+        .filter(|(name, _)| *name != API_KEY_PARAM)
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    params.sort();
+    let request = format!("{}?{}", path.join("/"), params.join("&"));
+    sha256::Hash::hash(request.as_bytes())[..8].to_hex()
+}
+
+// IMF-fixdate, as required for HTTP dates (RFC 7231 section 7.1.1.1)
+fn http_date(timestamp: u32) -> Option<String> {
+    let date = DateTime::from_unix_timestamp(timestamp as i64).ok()?;
+    Some(format!(
+        "{:.3}, {:02} {:.3} {} {:02}:{:02}:{:02} GMT",
+        date.weekday().to_string(),
+        date.day(),
+        date.month().to_string(),
+        date.year(),
+        date.hour(),
+        date.minute(),
+        date.second()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{http_date, request_hash, CacheValidator};
+    use hyper::header::{HeaderMap, HeaderValue, IF_NONE_MATCH};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(
+            http_date(1231006505).unwrap(),
+            "Sat, 03 Jan 2009 18:15:05 GMT"
+        );
+    }
+
+    #[test]
+    fn test_request_hash() {
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        let utxo = request_hash(&["address", "bc1qxyz", "utxo"], &params(&[]));
+        assert_eq!(utxo.len(), 16);
+        assert_eq!(
+            utxo,
+            request_hash(&["address", "bc1qxyz", "utxo"], &params(&[]))
+        );
+        assert_ne!(
+            utxo,
+            request_hash(&["address", "bc1qxyz", "txs"], &params(&[]))
+        );
+
+        let page = request_hash(
+            &["address", "bc1qxyz", "utxo"],
+            &params(&[("max_utxos", "10"), ("after_txid", "ab")]),
+        );
+        assert_ne!(utxo, page);
+        assert_ne!(
+            page,
+            request_hash(
+                &["address", "bc1qxyz", "utxo"],
+                &params(&[("max_utxos", "10"), ("after_txid", "cd")]),
+            )
+        );
+        // the api key doesn't change the response
+        assert_eq!(
+            page,
+            request_hash(
+                &["address", "bc1qxyz", "utxo"],
+                &params(&[("after_txid", "ab"), ("api_key", "k"), ("max_utxos", "10")]),
+            )
+        );
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let validator = CacheValidator {
+            etag: "\"abc-1\"".to_string(),
+            last_modified: None,
+            ttl: 10,
+        };
+        let check = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+            validator.matches(&headers)
+        };
+        assert!(check("\"abc-1\""));
+        assert!(check("W/\"abc-1\""));
+        assert!(check("\"abc-0\", \"abc-1\""));
+        assert!(check("*"));
+        assert!(!check("\"abc-2\""));
+        assert!(!validator.matches(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,240 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Response};
+use serde::Serialize;
+
+use super::HttpError;
+
+// Responses smaller than this are sent uncompressed
+const MIN_COMPRESS_SIZE: usize = 1024;
+const ZSTD_LEVEL: i32 = 3;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_LGWIN: u32 = 22;
+
+/// The serialization format for structured responses, negotiated using the `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+impl ResponseFormat {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get_all(hyper::header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|media_range| match media_type(media_range) {
+                "application/cbor" => Some(ResponseFormat::Cbor),
+                "application/msgpack" | "application/x-msgpack" => {
+                    Some(ResponseFormat::MessagePack)
+                }
+                "application/json" => Some(ResponseFormat::Json),
+                _ => None,
+            })
+            .next()
+            .unwrap_or(ResponseFormat::Json)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ResponseFormat::Json => "application/json",
+            ResponseFormat::Cbor => "application/cbor",
+            ResponseFormat::MessagePack => "application/msgpack",
+        }
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>, HttpError> {
+        match self {
+            ResponseFormat::Json => serde_json::to_vec(value).map_err(HttpError::from),
+            ResponseFormat::Cbor => serde_cbor::to_vec(&human_readable(value)?)
+                .map_err(|e| HttpError::from(e.to_string())),
+            // Encode structs as maps, to keep the same field names as the JSON format
+            ResponseFormat::MessagePack => rmp_serde::to_vec_named(&human_readable(value)?)
+                .map_err(|e| HttpError::from(e.to_string())),
+        }
+    }
+}
+
+// The binary formats are not human-readable, which makes types like hashes serialize as raw
+// (reversed) bytes. Going through the JSON representation keeps the same values as JSON.
+fn human_readable<T: Serialize>(value: &T) -> Result<serde_json::Value, HttpError> {
+    serde_json::to_value(value).map_err(HttpError::from)
+}
+
+/// The content coding used to compress the response body, negotiated using
+/// the `Accept-Encoding` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl ContentEncoding {
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let accepted: Vec<&str> = headers
+            .get_all(hyper::header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter(|coding| !is_rejected(coding))
+            .map(media_type)
+            .collect();
+
+        // in order of preference, regardless of the client's ordering
+        [
+            ContentEncoding::Zstd,
+            ContentEncoding::Brotli,
+            ContentEncoding::Gzip,
+        ]
+        .iter()
+        .find(|encoding| accepted.contains(&encoding.name()))
+        .copied()
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ContentEncoding::Zstd => "zstd",
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            ContentEncoding::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+            ContentEncoding::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_LGWIN);
+                writer.write_all(data)?;
+                Ok(writer.into_inner())
+            }
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Compress the response body using the negotiated content coding, if any.
+pub async fn compress_response(
+    resp: Response<Body>,
+    encoding: Option<ContentEncoding>,
+) -> Result<Response<Body>, hyper::Error> {
+    let (mut parts, body) = resp.into_parts();
+    parts.headers.insert(
+        hyper::header::VARY,
+        HeaderValue::from_static("Accept, Accept-Encoding"),
+    );
+
+    let encoding = match encoding {
+        Some(encoding) if !parts.headers.contains_key(hyper::header::CONTENT_ENCODING) => encoding,
+        _ => return Ok(Response::from_parts(parts, body)),
+    };
+
+    let data = hyper::body::to_bytes(body).await?;
+    if data.len() < MIN_COMPRESS_SIZE {
+        return Ok(Response::from_parts(parts, Body::from(data)));
+    }
+
+    let compressed = match tokio::task::block_in_place(|| encoding.compress(&data)) {
+        Ok(compressed) => compressed,
+        Err(e) => {
+            warn!(
+                "failed compressing response with {}: {}",
+                encoding.name(),
+                e
+            );
+            return Ok(Response::from_parts(parts, Body::from(data)));
+        }
+    };
+
+    parts.headers.insert(
+        hyper::header::CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.name()),
+    );
+    parts.headers.remove(hyper::header::CONTENT_LENGTH);
+    // The compressed body is no longer byte-for-byte identical to the uncompressed one
+    if let Some(etag) = parts.headers.get(hyper::header::ETAG) {
+        if !etag.as_bytes().starts_with(b"W/") {
+            let weak = format!("W/{}", etag.to_str().unwrap_or_default());
+            parts
+                .headers
+                .insert(hyper::header::ETAG, HeaderValue::from_str(&weak).unwrap());
+        }
+    }
+
+    Ok(Response::from_parts(parts, Body::from(compressed)))
+}
+
+// Strip parameters (like the q-value) from a media range or content coding
+fn media_type(value: &str) -> &str {
+    value.split(';').next().unwrap_or_default().trim()
+}
+
+// Check for an explicit "q=0", which marks the coding as not acceptable
+fn is_rejected(coding: &str) -> bool {
+    coding.split(';').skip(1).any(|param| {
+        let param = param.trim();
+        param
+            .strip_prefix("q=")
+            .and_then(|q| q.parse::<f32>().ok())
+            .is_some_and(|q| q == 0.0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContentEncoding, ResponseFormat};
+    use hyper::header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING};
+
+    fn headers(name: hyper::header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_response_format() {
+        assert_eq!(
+            ResponseFormat::from_headers(&HeaderMap::new()),
+            ResponseFormat::Json
+        );
+        assert_eq!(
+            ResponseFormat::from_headers(&headers(ACCEPT, "application/cbor")),
+            ResponseFormat::Cbor
+        );
+        assert_eq!(
+            ResponseFormat::from_headers(&headers(ACCEPT, "text/html, application/msgpack;q=0.9")),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            ResponseFormat::from_headers(&headers(ACCEPT, "*/*")),
+            ResponseFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_content_encoding() {
+        assert_eq!(ContentEncoding::from_headers(&HeaderMap::new()), None);
+        assert_eq!(
+            ContentEncoding::from_headers(&headers(ACCEPT_ENCODING, "gzip, deflate, br")),
+            Some(ContentEncoding::Brotli)
+        );
+        assert_eq!(
+            ContentEncoding::from_headers(&headers(ACCEPT_ENCODING, "gzip;q=1.0, zstd;q=0")),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(
+            ContentEncoding::from_headers(&headers(ACCEPT_ENCODING, "identity")),
+            None
+        );
+    }
+}
@@ -0,0 +1,567 @@
+use serde_json::{Map, Value};
+
+use crate::config::ELECTRS_VERSION;
+
+use super::routes::{Route, Schema};
+
+/// Build the OpenAPI 3.0 document describing the given routes.
+///
+/// The component schemas describe the responses of this build, so OPCAT
+/// layer builds document their extra fields and omit the ones they lack.
+pub fn document(routes: &[Route], include_internal: bool) -> Value {
+    let mut paths = Map::new();
+    for route in routes {
+        if route.is_internal() && !include_internal {
+            continue;
+        }
+        let path = route
+            .segments()
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => format!("/{{{}}}", name),
+                None => format!("/{}", segment),
+            })
+            .collect::<String>();
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[route.method.as_str().to_lowercase()] = operation(route);
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Electrs REST API",
+            "version": ELECTRS_VERSION,
+        },
+        "paths": paths,
+        "components": { "schemas": components() },
+    })
+}
+
+fn operation(route: &Route) -> Value {
+    let mut parameters: Vec<Value> = route
+        .segments()
+        .filter_map(|segment| segment.strip_prefix(':'))
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": path_param_schema(name),
+            })
+        })
+        .collect();
+    parameters.extend(route.query_params.iter().map(|param| {
+        json!({
+            "name": param.name,
+            "in": "query",
+            "required": param.required,
+            "description": param.description,
+            "schema": schema(param.schema),
+        })
+    }));
+
+    let mut operation = json!({
+        "operationId": operation_id(route),
+        "summary": route.summary,
+        "parameters": parameters,
+        "responses": {
+            "200": { "description": "Success", "content": content(route.response) },
+            "4XX": {
+                "description": "Invalid request or resource not found",
+                "content": { "text/plain": { "schema": { "type": "string" } } },
+            },
+        },
+    });
+    if let Some(body) = route.body {
+        operation["requestBody"] = json!({ "required": true, "content": content(body) });
+    }
+    operation
+}
+
+// e.g. "getBlockHashTxsStartIndex" for GET /block/:hash/txs/:start_index
+fn operation_id(route: &Route) -> String {
+    let mut id = route.method.as_str().to_lowercase();
+    for word in route
+        .segments()
+        .flat_map(|segment| segment.trim_start_matches(':').split(['-', '_']))
+    {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            id.extend(first.to_uppercase());
+            id.extend(chars);
+        }
+    }
+    id
+}
+
+fn path_param_schema(name: &str) -> Value {
+    match name {
+        "hash" | "txid" | "last_seen_txid" => {
+            json!({ "type": "string", "pattern": "^[0-9a-f]{64}$" })
+        }
+        "height" | "start_height" | "index" | "start_index" | "vout" => {
+            json!({ "type": "integer", "minimum": 0 })
+        }
+        _ => json!({ "type": "string" }),
+    }
+}
+
+fn content(body: Schema) -> Value {
+    match body {
+        Schema::Text => json!({ "text/plain": { "schema": { "type": "string" } } }),
+        Schema::Binary => json!({
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } }
+        }),
+        // structured responses are also available as CBOR and MessagePack (see encoding.rs)
+        _ => {
+            let schema = schema(body);
+            json!({
+                "application/json": { "schema": schema },
+                "application/cbor": { "schema": schema },
+                "application/msgpack": { "schema": schema },
+            })
+        }
+    }
+}
+
+fn schema(schema: Schema) -> Value {
+    match schema {
+        Schema::Text | Schema::String => json!({ "type": "string" }),
+        Schema::Binary => json!({ "type": "string", "format": "binary" }),
+        Schema::Integer => json!({ "type": "integer" }),
+        Schema::Number => json!({ "type": "number" }),
+        Schema::Boolean => json!({ "type": "boolean" }),
+        Schema::Ref(name) => json!({ "$ref": format!("#/components/schemas/{}", name) }),
+        Schema::Array(items) => json!({ "type": "array", "items": self::schema(*items) }),
+    }
+}
+
+fn object(properties: Vec<(&str, Value)>, required: &[&str]) -> Value {
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+fn reference(name: &'static str) -> Value {
+    schema(Schema::Ref(name))
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn components() -> Value {
+    let string = || json!({ "type": "string" });
+    let integer = || json!({ "type": "integer" });
+    let number = || json!({ "type": "number" });
+    let boolean = || json!({ "type": "boolean" });
+
+    #[allow(unused_mut)]
+    let mut block = vec![
+        ("id", string()),
+        ("height", integer()),
+        ("version", integer()),
+        ("timestamp", integer()),
+        ("tx_count", integer()),
+        ("size", integer()),
+        ("weight", integer()),
+        ("merkle_root", string()),
+        ("previousblockhash", string()),
+        ("mediantime", integer()),
+    ];
+    #[cfg(not(feature = "opcat_layer"))]
+    block.extend(vec![
+        ("bits", integer()),
+        ("nonce", integer()),
+        ("difficulty", number()),
+    ]);
+
+    #[allow(unused_mut)]
+    let mut txin = vec![
+        ("txid", string()),
+        ("vout", integer()),
+        ("prevout", reference("TxOut")),
+        ("scriptsig", string()),
+        ("scriptsig_asm", string()),
+        ("is_coinbase", boolean()),
+        ("sequence", integer()),
+        ("inner_redeemscript_asm", string()),
+        ("inner_witnessscript_asm", string()),
+    ];
+    #[cfg(not(feature = "opcat_layer"))]
+    txin.push(("witness", array(string())));
+
+    #[allow(unused_mut)]
+    let mut txout = vec![
+        ("scriptpubkey", string()),
+        ("scriptpubkey_asm", string()),
+        ("scriptpubkey_type", string()),
+        ("scriptpubkey_address", string()),
+        ("value", integer()),
+    ];
+    #[allow(unused_mut)]
+    let mut utxo = vec![
+        ("txid", string()),
+        ("vout", integer()),
+        ("status", reference("TxStatus")),
+        ("value", integer()),
+    ];
+    #[allow(unused_mut)]
+    let mut tx_output = vec![("scriptpubkey", string()), ("value", integer())];
+    #[cfg(feature = "opcat_layer")]
+    {
+        txout.push(("data", string()));
+        utxo.push(("data", string()));
+        tx_output.push(("data", string()));
+    }
+
+    #[allow(unused_mut)]
+    let mut recent_tx = vec![("txid", string()), ("fee", integer()), ("vsize", integer())];
+    #[cfg(not(feature = "opcat_layer"))]
+    recent_tx.push(("value", integer()));
+    recent_tx.push(("first_seen", integer()));
+    recent_tx.push(("effective_fee_rate", number()));
+
+    let script_stats = object(
+        vec![
+            ("tx_count", integer()),
+            ("funded_txo_count", integer()),
+            ("spent_txo_count", integer()),
+            ("funded_txo_sum", integer()),
+            ("spent_txo_sum", integer()),
+        ],
+        &[],
+    );
+
+    let input_ref = object(
+        vec![("vin", integer()), ("txid", string()), ("vout", integer())],
+        &["vin", "txid", "vout"],
+    );
+    let spent_prevout = json!({
+        "allOf": [
+            input_ref,
+            object(vec![("spent_by", reference("Outspend"))], &["spent_by"]),
+        ],
+    });
+
+    json!({
+        "Block": object(block, &["id", "height"]),
+        "BlockStatus": object(
+            vec![
+                ("in_best_chain", boolean()),
+                ("height", integer()),
+                ("next_best", string()),
+            ],
+            &["in_best_chain"],
+        ),
+        "TxStatus": object(
+            vec![
+                ("confirmed", boolean()),
+                ("block_height", integer()),
+                ("block_hash", string()),
+                ("block_time", integer()),
+                ("first_seen", integer()),
+            ],
+            &["confirmed"],
+        ),
+        "Transaction": object(
+            vec![
+                ("txid", string()),
+                ("version", integer()),
+                ("locktime", integer()),
+                ("vin", array(reference("TxIn"))),
+                ("vout", array(reference("TxOut"))),
+                ("size", integer()),
+                ("weight", integer()),
+                ("sigops", integer()),
+                ("fee", integer()),
+                ("status", reference("TxStatus")),
+                ("effective_fee_rate", number()),
+            ],
+            &["txid", "vin", "vout"],
+        ),
+        "DecodedTransaction": {
+            "allOf": [
+                reference("Transaction"),
+                object(
+                    vec![(
+                        "validation",
+                        object(
+                            vec![
+                                ("valid", boolean()),
+                                ("known", boolean()),
+                                ("missing_prevouts", array(input_ref)),
+                                ("spent_prevouts", array(spent_prevout.clone())),
+                                ("errors", array(string())),
+                            ],
+                            &["valid", "known", "missing_prevouts", "spent_prevouts", "errors"],
+                        ),
+                    )],
+                    &["validation"],
+                ),
+            ],
+        },
+        "TxIn": object(txin, &["txid", "vout", "is_coinbase"]),
+        "TxOut": object(txout, &["scriptpubkey"]),
+        "Utxo": object(utxo, &["txid", "vout", "status"]),
+        "TxOutput": object(tx_output, &["scriptpubkey"]),
+        "Outspend": object(
+            vec![
+                ("spent", boolean()),
+                ("txid", string()),
+                ("vin", integer()),
+                ("status", reference("TxStatus")),
+            ],
+            &["spent"],
+        ),
+        "AddressStats": object(
+            vec![
+                ("address", string()),
+                ("scripthash", string()),
+                ("chain_stats", script_stats.clone()),
+                ("mempool_stats", script_stats),
+            ],
+            &["chain_stats", "mempool_stats"],
+        ),
+        "DBCheckpoint": object(
+            vec![
+                ("path", string()),
+                (
+                    "tip",
+                    object(
+                        vec![
+                            ("height", integer()),
+                            ("hash", string()),
+                            ("time", integer()),
+                        ],
+                        &["height", "hash", "time"],
+                    ),
+                ),
+            ],
+            &["path", "tip"],
+        ),
+        "SyncStatus": object(
+            vec![
+                ("synced", boolean()),
+                (
+                    "phase",
+                    json!({ "type": "string", "enum": ["add", "index", "done"] }),
+                ),
+                ("blocks_done", integer()),
+                ("blocks_total", integer()),
+                ("blocks_per_second", number()),
+                ("eta_seconds", integer()),
+            ],
+            &["synced", "phase", "blocks_done", "blocks_total", "blocks_per_second"],
+        ),
+        "TxSummary": object(
+            vec![
+                ("txid", string()),
+                ("height", integer()),
+                ("value", integer()),
+                ("time", integer()),
+                ("tx_position", integer()),
+            ],
+            &["txid"],
+        ),
+        "MerkleProof": object(
+            vec![
+                ("block_height", integer()),
+                ("merkle", array(string())),
+                ("pos", integer()),
+            ],
+            &["block_height", "merkle", "pos"],
+        ),
+        "MempoolStats": object(
+            vec![
+                ("count", integer()),
+                ("vsize", integer()),
+                ("total_fee", integer()),
+                ("fee_histogram", array(array(number()))),
+            ],
+            &["count", "vsize", "total_fee", "fee_histogram"],
+        ),
+        "MempoolRecentTx": object(recent_tx, &["txid"]),
+        "MempoolBlock": object(
+            vec![
+                ("block_vsize", integer()),
+                ("n_tx", integer()),
+                ("total_fees", integer()),
+                ("median_fee", number()),
+                ("fee_range", array(number())),
+            ],
+            &["block_vsize", "n_tx", "total_fees", "median_fee", "fee_range"],
+        ),
+        "FeeEstimates": {
+            "type": "object",
+            "description": "Fee rate estimates (sat/vB), keyed by confirmation target",
+            "additionalProperties": number(),
+        },
+        "MempoolAcceptResult": object(
+            vec![
+                ("txid", string()),
+                ("wtxid", string()),
+                ("allowed", boolean()),
+                ("vsize", integer()),
+                ("fees", json!({ "type": "object" })),
+                ("reject-reason", string()),
+            ],
+            &["txid", "wtxid"],
+        ),
+        "SubmitPackageResult": object(
+            vec![
+                ("package_msg", string()),
+                ("tx-results", json!({ "type": "object" })),
+                ("replaced-transactions", array(string())),
+            ],
+            &["package_msg", "tx-results"],
+        ),
+        "PsbtAnalysis": object(
+            vec![
+                ("txid", string()),
+                (
+                    "inputs",
+                    array(object(
+                        vec![
+                            ("txid", string()),
+                            ("vout", integer()),
+                            (
+                                "status",
+                                json!({
+                                    "type": "string",
+                                    "enum": ["finalized", "partially_signed", "unsigned"],
+                                }),
+                            ),
+                            (
+                                "utxo_source",
+                                json!({ "type": "string", "enum": ["psbt", "index", "missing"] }),
+                            ),
+                            ("prevout", reference("TxOut")),
+                            ("partial_sigs", integer()),
+                            ("spent_by", reference("Outspend")),
+                        ],
+                        &["txid", "vout", "status", "utxo_source", "partial_sigs"],
+                    )),
+                ),
+                ("complete", boolean()),
+                ("fee", integer()),
+                ("estimated_vsize", integer()),
+                ("estimated_feerate", number()),
+                ("psbt", string()),
+            ],
+            &["txid", "inputs", "complete"],
+        ),
+        "ReplacementTree": object(
+            vec![
+                ("txid", string()),
+                ("fee", integer()),
+                ("vsize", integer()),
+                ("fee_rate", number()),
+                ("signals_rbf", boolean()),
+                ("time", integer()),
+                ("fee_delta", integer()),
+                ("full_rbf", boolean()),
+                ("replaces", array(reference("ReplacementTree"))),
+            ],
+            &["txid", "fee", "vsize", "replaces"],
+        ),
+        "TxReplacements": object(
+            vec![
+                ("replacements", reference("ReplacementTree")),
+                ("replaces", array(string())),
+                ("replaced_by", string()),
+            ],
+            &["replaces"],
+        ),
+        "TxConflicts": object(
+            vec![
+                ("txid", string()),
+                ("conflicted", boolean()),
+                ("conflicts", array(spent_prevout)),
+                ("replaced_by", string()),
+                ("replaces", array(string())),
+            ],
+            &["txid", "conflicted", "conflicts", "replaces"],
+        ),
+        "TxLifecycle": object(
+            vec![
+                ("txid", string()),
+                ("first_seen", integer()),
+                ("in_mempool", boolean()),
+                (
+                    "removed",
+                    object(
+                        vec![
+                            ("time", integer()),
+                            (
+                                "reason",
+                                json!({
+                                    "type": "string",
+                                    "enum": ["confirmed", "replaced", "expired", "evicted"],
+                                }),
+                            ),
+                            ("block_hash", string()),
+                            ("block_height", integer()),
+                            ("replaced_by", string()),
+                        ],
+                        &["time", "reason"],
+                    ),
+                ),
+            ],
+            &["txid", "first_seen", "in_mempool"],
+        ),
+        "TxPackage": object(
+            vec![
+                ("txid", string()),
+                ("fee", integer()),
+                ("vsize", integer()),
+                ("fee_rate", number()),
+                ("ancestors", array(string())),
+                ("descendants", array(string())),
+                ("ancestor_fee", integer()),
+                ("ancestor_vsize", integer()),
+                ("ancestor_fee_rate", number()),
+                ("descendant_fee", integer()),
+                ("descendant_vsize", integer()),
+                ("descendant_fee_rate", number()),
+                ("effective_fee_rate", number()),
+            ],
+            &["txid", "ancestors", "descendants", "effective_fee_rate"],
+        ),
+        "OpenApi": { "type": "object" },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::document;
+    use crate::rest::routes::{Request, Route, Schema};
+    use crate::rest::HttpError;
+    use hyper::{Body, Response};
+
+    fn handler(_req: &Request) -> Result<Response<Body>, HttpError> {
+        Ok(Response::new(Body::empty()))
+    }
+
+    #[test]
+    fn test_document() {
+        let routes = vec![
+            Route::get("/block/:hash/txs/:start_index", handler, "Get block txs")
+                .returns(Schema::Array(&Schema::Ref("Transaction"))),
+            Route::post("/internal/txs", handler, "Get txs").body(Schema::Text),
+        ];
+        let doc = document(&routes, false);
+        let op = &doc["paths"]["/block/{hash}/txs/{start_index}"]["get"];
+        assert_eq!(op["operationId"], "getBlockHashTxsStartIndex");
+        assert_eq!(op["parameters"][1]["name"], "start_index");
+        assert_eq!(
+            op["responses"]["200"]["content"]["application/json"]["schema"]["items"]["$ref"],
+            "#/components/schemas/Transaction"
+        );
+        assert!(doc["paths"].get("/internal/txs").is_none());
+
+        let doc = document(&routes, true);
+        assert!(doc["paths"]["/internal/txs"]["post"]["requestBody"].is_object());
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use hyper::{Body, Method, Response};
+
+use crate::config::Config;
+use crate::new_index::Query;
+
+use super::auth::Access;
+use super::encoding::ResponseFormat;
+use super::HttpError;
+
+pub type Handler = fn(&Request) -> Result<Response<Body>, HttpError>;
+
+/// A request matched against a route, passed to the route's handler.
+pub struct Request<'a> {
+    pub method: &'a Method,
+    pub path: Vec<&'a str>,
+    params: Vec<(&'static str, &'a str)>,
+    pub query_params: HashMap<String, String>,
+    pub body: hyper::body::Bytes,
+    pub query: &'a Query,
+    pub config: &'a Config,
+    pub access: &'a Access,
+    pub format: ResponseFormat,
+}
+
+impl<'a> Request<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        method: &'a Method,
+        path: Vec<&'a str>,
+        params: Vec<(&'static str, &'a str)>,
+        query_params: HashMap<String, String>,
+        body: hyper::body::Bytes,
+        query: &'a Query,
+        config: &'a Config,
+        access: &'a Access,
+        format: ResponseFormat,
+    ) -> Self {
+        Request {
+            method,
+            path,
+            params,
+            query_params,
+            body,
+            query,
+            config,
+            access,
+            format,
+        }
+    }
+
+    /// A path parameter declared by the route (e.g. `:hash`)
+    pub fn param(&self, name: &str) -> &'a str {
+        self.optional_param(name)
+            .unwrap_or_else(|| panic!("route has no path parameter {}", name))
+    }
+
+    /// A path parameter declared by only some of the handler's routes
+    pub fn optional_param(&self, name: &str) -> Option<&'a str> {
+        self.params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// The script type and address/scripthash of `/address/:address/...`
+    /// and `/scripthash/:hash/...` routes
+    pub fn script(&self) -> (&'a str, &'a str) {
+        (self.path[0], self.path[1])
+    }
+}
+
+/// The schema of a request or response body, used for the OpenAPI document.
+#[derive(Clone, Copy)]
+pub enum Schema {
+    /// A plain text response
+    Text,
+    /// An `application/octet-stream` response
+    Binary,
+    String,
+    Integer,
+    Number,
+    Boolean,
+    /// A schema defined in the OpenAPI components (see `openapi::components`)
+    Ref(&'static str),
+    Array(&'static Schema),
+}
+
+pub struct QueryParam {
+    pub name: &'static str,
+    pub schema: Schema,
+    pub description: &'static str,
+    pub required: bool,
+}
+
+/// A REST endpoint: the path pattern it matches, its handler and its documentation.
+///
+/// Path patterns are made of literal segments and `:name` parameters, which
+/// match any single segment. Routes are tried in order and the first match wins.
+pub struct Route {
+    pub method: Method,
+    pub path: &'static str,
+    pub handler: Handler,
+    pub summary: &'static str,
+    pub query_params: Vec<QueryParam>,
+    pub body: Option<Schema>,
+    pub response: Schema,
+}
+
+impl Route {
+    pub fn get(path: &'static str, handler: Handler, summary: &'static str) -> Self {
+        Route::new(Method::GET, path, handler, summary)
+    }
+
+    pub fn post(path: &'static str, handler: Handler, summary: &'static str) -> Self {
+        Route::new(Method::POST, path, handler, summary)
+    }
+
+    fn new(method: Method, path: &'static str, handler: Handler, summary: &'static str) -> Self {
+        Route {
+            method,
+            path,
+            handler,
+            summary,
+            query_params: vec![],
+            body: None,
+            response: Schema::Text,
+        }
+    }
+
+    pub fn query(mut self, name: &'static str, schema: Schema, description: &'static str) -> Self {
+        self.query_params.push(QueryParam {
+            name,
+            schema,
+            description,
+            required: false,
+        });
+        self
+    }
+
+    pub fn required_query(
+        mut self,
+        name: &'static str,
+        schema: Schema,
+        description: &'static str,
+    ) -> Self {
+        self.query_params.push(QueryParam {
+            name,
+            schema,
+            description,
+            required: true,
+        });
+        self
+    }
+
+    pub fn body(mut self, schema: Schema) -> Self {
+        self.body = Some(schema);
+        self
+    }
+
+    pub fn returns(mut self, schema: Schema) -> Self {
+        self.response = schema;
+        self
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = &'static str> {
+        self.path.split('/').skip(1)
+    }
+
+    pub fn is_internal(&self) -> bool {
+        self.segments().next() == Some(super::INTERNAL_PREFIX)
+    }
+
+    /// Match the request path, returning the values of the path parameters
+    fn matches<'a>(
+        &self,
+        method: &Method,
+        path: &[&'a str],
+    ) -> Option<Vec<(&'static str, &'a str)>> {
+        if *method != self.method || self.segments().count() != path.len() {
+            return None;
+        }
+        let mut params = vec![];
+        for (pattern, segment) in self.segments().zip(path) {
+            match pattern.strip_prefix(':') {
+                Some(name) => params.push((name, *segment)),
+                None if pattern == *segment => (),
+                None => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+/// Find the first route matching the request
+pub fn find<'a>(
+    routes: &'static [Route],
+    method: &Method,
+    path: &[&'a str],
+) -> Option<(&'static Route, Vec<(&'static str, &'a str)>)> {
+    routes
+        .iter()
+        .find_map(|route| Some((route, route.matches(method, path)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Request, Route};
+    use crate::rest::HttpError;
+    use hyper::{Body, Method, Response};
+
+    fn handler(_req: &Request) -> Result<Response<Body>, HttpError> {
+        Ok(Response::new(Body::empty()))
+    }
+
+    #[test]
+    fn test_route_matching() {
+        let route = Route::get("/block/:hash/txs/:start_index", handler, "");
+        assert_eq!(
+            route.matches(&Method::GET, &["block", "00ff", "txs", "25"]),
+            Some(vec![("hash", "00ff"), ("start_index", "25")])
+        );
+        assert_eq!(
+            route.matches(&Method::POST, &["block", "00ff", "txs", "25"]),
+            None
+        );
+        assert_eq!(route.matches(&Method::GET, &["block", "00ff", "txs"]), None);
+        assert_eq!(
+            route.matches(&Method::GET, &["block", "00ff", "txids", "25"]),
+            None
+        );
+        assert!(!route.is_internal());
+        assert!(Route::get("/internal/mempool/txs", handler, "").is_internal());
+    }
+}
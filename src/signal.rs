@@ -40,6 +40,32 @@ impl Waiter {
         self.wait_deadline(Instant::now() + duration, accept_sigusr)
     }
 
+    /// Like `wait()`, but returns early with the next event received from `events`
+    pub fn wait_for<T>(
+        &self,
+        duration: Duration,
+        accept_sigusr: bool,
+        events: &channel::Receiver<T>,
+    ) -> Result<Option<T>> {
+        let deadline = Instant::now() + duration;
+        loop {
+            channel::select! {
+                recv(self.receiver) -> sig => match sig {
+                    Ok(sig) if sig == SIGUSR1 => {
+                        trace!("notified via SIGUSR1");
+                        if accept_sigusr {
+                            return Ok(None);
+                        }
+                    }
+                    Ok(sig) => bail!(ErrorKind::Interrupt(sig)),
+                    Err(_) => bail!("signal hook channel disconnected"),
+                },
+                recv(events) -> event => return Ok(event.ok()),
+                default(deadline.saturating_duration_since(Instant::now())) => return Ok(None),
+            }
+        }
+    }
+
     fn wait_deadline(&self, deadline: Instant, accept_sigusr: bool) -> Result<()> {
         match self.receiver.recv_deadline(deadline) {
             Ok(sig) if sig == SIGUSR1 => {
@@ -5,11 +5,13 @@ use crate::new_index::BlockEntry;
 use std::collections::HashMap;
 use std::fmt;
 use std::iter::FromIterator;
-use std::slice;
+use std::sync::Arc;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime as DateTime;
 
 const MTP_SPAN: usize = 11;
+// The headers are stored in chunks of this many entries (about two weeks of blocks)
+const CHUNK_SIZE: usize = 2016;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BlockId {
@@ -62,17 +64,28 @@ impl fmt::Debug for HeaderEntry {
     }
 }
 
+/// The best chain headers, indexed by height and by hash.
+///
+/// Clones are cheap, so that a copy can be updated while others are still being read: the
+/// headers are kept in append-only chunks and the heights in a map, all shared between the
+/// clones. Only the last chunk and the heights added since the map was last extended are
+/// copied when a clone is updated.
+#[derive(Clone)]
 pub struct HeaderList {
-    headers: Vec<HeaderEntry>,
-    heights: HashMap<BlockHash, usize>,
+    chunks: Vec<Arc<Vec<HeaderEntry>>>,
+    len: usize,
+    heights: Arc<HashMap<BlockHash, usize>>,
+    new_heights: HashMap<BlockHash, usize>, // not merged into `heights` yet
     tip: BlockHash,
 }
 
 impl HeaderList {
     pub fn empty() -> HeaderList {
         HeaderList {
-            headers: vec![],
-            heights: HashMap::new(),
+            chunks: vec![],
+            len: 0,
+            heights: Arc::new(HashMap::new()),
+            new_heights: HashMap::new(),
             tip: BlockHash::default(),
         }
     }
@@ -173,7 +186,7 @@ impl HeaderList {
             Some(entry) => {
                 let height = entry.height();
                 let expected_prev_blockhash = if height > 0 {
-                    *self.headers[height - 1].hash()
+                    *self.get(height - 1).expect("missing parent header").hash()
                 } else {
                     BlockHash::default()
                 };
@@ -187,29 +200,69 @@ impl HeaderList {
             new_headers.len(),
             new_height
         );
-        let mut removed = self.headers.split_off(new_height); // keep [0..new_height) entries
+        let mut removed = self.truncate(new_height); // keep [0..new_height) entries
 
         // If we reorged, we should return the last blockhash before adding the new chain's blockheaders.
         let reorged_tip = if !removed.is_empty() {
-            self.headers.last().map(|be| be.hash()).cloned()
+            self.last().map(|be| be.hash()).cloned()
         } else {
             None
         };
 
         for new_header in new_headers {
             let height = new_header.height();
-            assert_eq!(height, self.headers.len());
+            assert_eq!(height, self.len);
             self.tip = *new_header.hash();
-            self.headers.push(new_header);
-            self.heights.insert(self.tip, height);
+            self.push(new_header);
+            // a block hash always maps to the same height, even after a reorg
+            self.new_heights.insert(self.tip, height);
+        }
+        if self.new_heights.len() >= CHUNK_SIZE {
+            Arc::make_mut(&mut self.heights).extend(self.new_heights.drain());
         }
         removed.reverse();
         (removed, reorged_tip)
     }
 
+    fn get(&self, height: usize) -> Option<&HeaderEntry> {
+        self.chunks
+            .get(height / CHUNK_SIZE)
+            .and_then(|chunk| chunk.get(height % CHUNK_SIZE))
+    }
+
+    fn last(&self) -> Option<&HeaderEntry> {
+        self.get(self.len.checked_sub(1)?)
+    }
+
+    fn push(&mut self, entry: HeaderEntry) {
+        if self.len % CHUNK_SIZE == 0 {
+            self.chunks.push(Arc::new(Vec::with_capacity(CHUNK_SIZE)));
+        }
+        // copy-on-write, the chunk may be shared with clones
+        Arc::make_mut(self.chunks.last_mut().unwrap()).push(entry);
+        self.len += 1;
+    }
+
+    // Remove the entries from `height`, returning them
+    fn truncate(&mut self, height: usize) -> Vec<HeaderEntry> {
+        if height >= self.len {
+            return vec![];
+        }
+        let removed = self.iter().skip(height).cloned().collect();
+        self.chunks.truncate(height.div_ceil(CHUNK_SIZE));
+        if height % CHUNK_SIZE != 0 {
+            Arc::make_mut(self.chunks.last_mut().unwrap()).truncate(height % CHUNK_SIZE);
+        }
+        self.len = height;
+        removed
+    }
+
     pub fn header_by_blockhash(&self, blockhash: &BlockHash) -> Option<&HeaderEntry> {
-        let height = self.heights.get(blockhash)?;
-        let header = self.headers.get(*height)?;
+        let height = self
+            .new_heights
+            .get(blockhash)
+            .or_else(|| self.heights.get(blockhash))?;
+        let header = self.get(*height)?;
         if *blockhash == *header.hash() {
             Some(header)
         } else {
@@ -218,33 +271,30 @@ impl HeaderList {
     }
 
     pub fn header_by_height(&self, height: usize) -> Option<&HeaderEntry> {
-        self.headers.get(height).inspect(|entry| {
+        self.get(height).inspect(|entry| {
             assert_eq!(entry.height(), height);
         })
     }
 
     pub fn equals(&self, other: &HeaderList) -> bool {
-        self.headers.last() == other.headers.last()
+        self.last() == other.last()
     }
 
     pub fn tip(&self) -> &BlockHash {
-        assert_eq!(
-            self.tip,
-            self.headers.last().map(|h| *h.hash()).unwrap_or_default()
-        );
+        assert_eq!(self.tip, self.last().map(|h| *h.hash()).unwrap_or_default());
         &self.tip
     }
 
     pub fn len(&self) -> usize {
-        self.headers.len()
+        self.len
     }
 
     pub fn is_empty(&self) -> bool {
-        self.headers.is_empty()
+        self.len == 0
     }
 
-    pub fn iter(&self) -> slice::Iter<HeaderEntry> {
-        self.headers.iter()
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &HeaderEntry> + '_ {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
     }
 
     /// Get the Median Time Past
@@ -252,12 +302,12 @@ impl HeaderList {
         // Use the timestamp as the mtp of the genesis block.
         // Matches bitcoind's behaviour: bitcoin-cli getblock `bitcoin-cli getblockhash 0` | jq '.time == .mediantime'
         if height == 0 {
-            self.headers.first().unwrap().header.time
+            self.get(0).unwrap().header.time
         } else if height > self.len() - 1 {
             0
         } else {
             let mut timestamps = (height.saturating_sub(MTP_SPAN - 1)..=height)
-                .map(|p_height| self.headers.get(p_height).unwrap().header.time)
+                .map(|p_height| self.get(p_height).unwrap().header.time)
                 .collect::<Vec<_>>();
             timestamps.sort_unstable();
             timestamps[timestamps.len() / 2]
@@ -335,3 +385,52 @@ impl BlockMeta {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{HeaderList, CHUNK_SIZE};
+    use crate::chain::{BlockHash, BlockHeader};
+    use bitcoin::TxMerkleNode;
+
+    fn chain(prev_blockhash: BlockHash, count: usize, nonce: u32) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = vec![];
+        for i in 0..count {
+            headers.push(BlockHeader {
+                version: 0x2000_0000,
+                prev_blockhash: headers.last().map_or(prev_blockhash, |h| h.block_hash()),
+                merkle_root: TxMerkleNode::default(),
+                time: i as u32,
+                bits: 0x207f_ffff,
+                nonce,
+            });
+        }
+        headers
+    }
+
+    #[test]
+    fn test_header_list_chunks() {
+        let mut headers = HeaderList::empty();
+        let new_headers = headers.order(chain(BlockHash::default(), CHUNK_SIZE + 10, 0));
+        headers.apply(new_headers);
+        assert_eq!(headers.len(), CHUNK_SIZE + 10);
+        let first = headers.header_by_height(CHUNK_SIZE).unwrap().clone();
+        assert_eq!(headers.header_by_blockhash(first.hash()), Some(&first));
+
+        // reorg across the chunk boundary, without changing the clone
+        let previous = headers.clone();
+        let fork = *headers.header_by_height(CHUNK_SIZE - 6).unwrap().hash();
+        let new_headers = headers.order(chain(fork, 20, 1));
+        let (removed, reorged_tip) = headers.apply(new_headers);
+        assert_eq!(removed.len(), 15);
+        assert_eq!(removed.last().unwrap().height(), CHUNK_SIZE - 5);
+        assert_eq!(reorged_tip, Some(fork));
+        assert_eq!(headers.len(), CHUNK_SIZE + 15);
+        assert_eq!(headers.iter().count(), headers.len());
+        assert!(headers.header_by_blockhash(first.hash()).is_none());
+        assert_eq!(*headers.tip(), *headers.iter().last().unwrap().hash());
+
+        assert_eq!(previous.len(), CHUNK_SIZE + 10);
+        assert_eq!(previous.header_by_blockhash(first.hash()), Some(&first));
+        assert!(!previous.equals(&headers));
+    }
+}
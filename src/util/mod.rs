@@ -5,6 +5,7 @@ mod transaction;
 pub mod bincode_util;
 pub mod electrum_merkle;
 pub mod fees;
+pub mod psbt;
 
 pub use self::block::{BlockHeaderMeta, BlockId, BlockMeta, BlockStatus, HeaderEntry, HeaderList};
 pub use self::fees::get_tx_fee;
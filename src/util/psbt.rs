@@ -0,0 +1,297 @@
+use std::io::{Cursor, Read};
+
+use bitcoin::consensus::encode::{self, Decodable, Encodable};
+use bitcoin::VarInt;
+
+use crate::chain::{Transaction, TxOut};
+use crate::errors::*;
+
+const MAGIC: &[u8] = b"psbt\xff";
+
+// Key types (BIP 174)
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+pub const IN_NON_WITNESS_UTXO: u8 = 0x00;
+pub const IN_WITNESS_UTXO: u8 = 0x01;
+pub const IN_PARTIAL_SIG: u8 = 0x02;
+pub const IN_REDEEM_SCRIPT: u8 = 0x04;
+pub const IN_WITNESS_SCRIPT: u8 = 0x05;
+pub const IN_FINAL_SCRIPTSIG: u8 = 0x07;
+pub const IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+
+/// A key-value map of a PSBT section, in serialization order.
+///
+/// Keys include their type byte. Unknown keys are kept as-is, so that the PSBT
+/// can be re-serialized without losing data.
+#[derive(Default, Debug)]
+pub struct PsbtMap(Vec<(Vec<u8>, Vec<u8>)>);
+
+impl PsbtMap {
+    /// The value of the key made of just the key type, if any
+    pub fn get(&self, key_type: u8) -> Option<&[u8]> {
+        self.0
+            .iter()
+            .find(|(key, _)| key[..] == [key_type])
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Number of entries of the given key type (e.g. partial signatures, keyed by pubkey)
+    pub fn count(&self, key_type: u8) -> usize {
+        self.0.iter().filter(|(key, _)| key[0] == key_type).count()
+    }
+
+    pub fn insert(&mut self, key_type: u8, value: Vec<u8>) {
+        match self.0.iter_mut().find(|(key, _)| key[..] == [key_type]) {
+            Some(entry) => entry.1 = value,
+            None => self.0.push((vec![key_type], value)),
+        }
+    }
+
+    fn decode(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+        let mut map = PsbtMap::default();
+        loop {
+            let key = read_bytes(reader)?;
+            if key.is_empty() {
+                return Ok(map);
+            }
+            if map.0.iter().any(|(existing, _)| *existing == key) {
+                bail!("duplicate PSBT key {}", hex::encode(&key));
+            }
+            let value = read_bytes(reader)?;
+            map.0.push((key, value));
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        for (key, value) in &self.0 {
+            write_bytes(out, key);
+            write_bytes(out, value);
+        }
+        out.push(0x00);
+    }
+}
+
+/// A BIP 174 partially signed transaction.
+///
+/// The unsigned transaction, UTXOs and final scripts are decoded using the chain's
+/// consensus encoding, so OPCAT layer PSBTs carry OPCAT transactions and outputs
+/// (including their `data`).
+pub struct Psbt {
+    pub unsigned_tx: Transaction,
+    pub global: PsbtMap,
+    pub inputs: Vec<PsbtMap>,
+    pub outputs: Vec<PsbtMap>,
+}
+
+impl Psbt {
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(MAGIC) {
+            bail!("invalid PSBT magic");
+        }
+        let mut reader = Cursor::new(data);
+        reader.set_position(MAGIC.len() as u64);
+
+        let global = PsbtMap::decode(&mut reader)?;
+        let unsigned_tx: Transaction = global
+            .get(GLOBAL_UNSIGNED_TX)
+            .chain_err(|| "missing unsigned transaction")
+            .and_then(|tx| encode::deserialize(tx).chain_err(|| "invalid unsigned transaction"))?;
+        if unsigned_tx
+            .input
+            .iter()
+            .any(|txin| !txin.script_sig.is_empty())
+        {
+            bail!("unsigned transaction has non-empty scriptSigs");
+        }
+
+        let inputs = (0..unsigned_tx.input.len())
+            .map(|_| PsbtMap::decode(&mut reader))
+            .collect::<Result<Vec<_>>>()?;
+        let outputs = (0..unsigned_tx.output.len())
+            .map(|_| PsbtMap::decode(&mut reader))
+            .collect::<Result<Vec<_>>>()?;
+        if reader.position() != data.len() as u64 {
+            bail!("trailing data after PSBT");
+        }
+
+        Ok(Psbt {
+            unsigned_tx,
+            global,
+            inputs,
+            outputs,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        self.global.encode(&mut out);
+        for map in self.inputs.iter().chain(&self.outputs) {
+            map.encode(&mut out);
+        }
+        out
+    }
+
+    /// The UTXO spent by the given input, as provided in the PSBT
+    pub fn input_utxo(&self, index: usize) -> Result<Option<TxOut>> {
+        let input = &self.inputs[index];
+        if let Some(txout) = input.get(IN_WITNESS_UTXO) {
+            return Ok(Some(
+                encode::deserialize(txout).chain_err(|| "invalid witness UTXO")?,
+            ));
+        }
+        if let Some(prev_tx) = input.get(IN_NON_WITNESS_UTXO) {
+            let prev_tx: Transaction =
+                encode::deserialize(prev_tx).chain_err(|| "invalid non-witness UTXO")?;
+            let outpoint = self.unsigned_tx.input[index].previous_output;
+            if prev_tx.txid() != outpoint.txid {
+                bail!("non-witness UTXO does not match the spent outpoint");
+            }
+            return Ok(prev_tx.output.get(outpoint.vout as usize).cloned());
+        }
+        Ok(None)
+    }
+
+    pub fn is_finalized(&self, index: usize) -> bool {
+        let input = &self.inputs[index];
+        input.get(IN_FINAL_SCRIPTSIG).is_some() || input.get(IN_FINAL_SCRIPTWITNESS).is_some()
+    }
+}
+
+fn read_bytes(reader: &mut Cursor<&[u8]>) -> Result<Vec<u8>> {
+    let len = VarInt::consensus_decode(&mut *reader)
+        .chain_err(|| "truncated PSBT")?
+        .0;
+    let remaining = reader.get_ref().len() as u64 - reader.position();
+    if len > remaining {
+        bail!("truncated PSBT");
+    }
+    let mut bytes = vec![0; len as usize];
+    reader
+        .read_exact(&mut bytes)
+        .chain_err(|| "truncated PSBT")?;
+    Ok(bytes)
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    VarInt(bytes.len() as u64)
+        .consensus_encode(&mut *out)
+        .expect("in-memory writers don't error");
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "opcat_layer"))]
+mod tests {
+    use super::{Psbt, PsbtMap, IN_FINAL_SCRIPTSIG, IN_PARTIAL_SIG, IN_WITNESS_UTXO};
+    use crate::chain::{Script, Transaction, TxIn, TxOut};
+    use bitcoin::consensus::encode::serialize;
+
+    fn psbt() -> Psbt {
+        let unsigned_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut global = PsbtMap::default();
+        global.insert(0x00, serialize(&unsigned_tx));
+        let mut signed = PsbtMap::default();
+        signed.insert(IN_WITNESS_UTXO, serialize(&unsigned_tx.output[0]));
+        signed
+            .0
+            .push((vec![IN_PARTIAL_SIG, 0x02, 0x03], vec![0x30]));
+        Psbt {
+            unsigned_tx,
+            global,
+            inputs: vec![signed, PsbtMap::default()],
+            outputs: vec![PsbtMap::default()],
+        }
+    }
+
+    #[test]
+    fn test_psbt_roundtrip() {
+        let mut psbt = psbt();
+        psbt.inputs[1].insert(IN_FINAL_SCRIPTSIG, vec![0x00]);
+        let data = psbt.serialize();
+        let psbt = Psbt::deserialize(&data).unwrap();
+        assert_eq!(psbt.serialize(), data);
+        assert_eq!(psbt.inputs.len(), 2);
+        assert_eq!(psbt.inputs[0].count(IN_PARTIAL_SIG), 1);
+        assert_eq!(psbt.input_utxo(0).unwrap().unwrap().value, 1000);
+        assert!(psbt.input_utxo(1).unwrap().is_none());
+        assert!(!psbt.is_finalized(0));
+        assert!(psbt.is_finalized(1));
+
+        assert!(Psbt::deserialize(&data[..data.len() - 1]).is_err());
+        assert!(Psbt::deserialize(&data[1..]).is_err());
+        let mut trailing = data;
+        trailing.push(0x00);
+        assert!(Psbt::deserialize(&trailing).is_err());
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "opcat_layer")]
+mod opcat_tests {
+    use super::{Psbt, PsbtMap, IN_NON_WITNESS_UTXO, IN_WITNESS_UTXO};
+    use crate::chain::{OutPoint, Transaction, TxIn, TxOut, Value};
+    use bitcoin::consensus::encode::serialize;
+    use bitcoin::Script;
+
+    fn txout(value: u64, data: &[u8]) -> TxOut {
+        TxOut {
+            value: Value::from_sat(value),
+            script_pubkey: Script::new(),
+            data: data.to_vec(),
+        }
+    }
+
+    fn txin(previous_output: OutPoint) -> TxIn {
+        TxIn {
+            previous_output,
+            script_sig: Script::new(),
+            sequence: 0xffff_ffff,
+        }
+    }
+
+    #[test]
+    fn test_psbt_data_roundtrip() {
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin(OutPoint::null())],
+            output: vec![txout(2000, b"\x01\x02\x03")],
+        };
+        let witness_utxo = txout(3000, b"\x04\x05");
+        let unsigned_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                txin(OutPoint::new(prev_tx.txid(), 0)),
+                txin(OutPoint::default()),
+            ],
+            output: vec![txout(4500, b"\x06")],
+        };
+        let mut global = PsbtMap::default();
+        global.insert(0x00, serialize(&unsigned_tx));
+        let mut non_witness = PsbtMap::default();
+        non_witness.insert(IN_NON_WITNESS_UTXO, serialize(&prev_tx));
+        let mut witness = PsbtMap::default();
+        witness.insert(IN_WITNESS_UTXO, serialize(&witness_utxo));
+        let data = Psbt {
+            unsigned_tx,
+            global,
+            inputs: vec![non_witness, witness],
+            outputs: vec![PsbtMap::default()],
+        }
+        .serialize();
+
+        let psbt = Psbt::deserialize(&data).unwrap();
+        assert_eq!(psbt.serialize(), data);
+        assert_eq!(psbt.unsigned_tx.output[0].data, b"\x06");
+        assert_eq!(psbt.input_utxo(0).unwrap().unwrap(), prev_tx.output[0]);
+        assert_eq!(psbt.input_utxo(1).unwrap().unwrap(), witness_utxo);
+    }
+}
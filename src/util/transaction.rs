@@ -13,6 +13,9 @@ pub struct TransactionStatus {
     pub block_hash: Option<BlockHash>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub block_time: Option<u32>,
+    // when an unconfirmed transaction was first seen in the mempool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<u64>,
 }
 
 impl From<Option<BlockId>> for TransactionStatus {
@@ -23,12 +26,14 @@ impl From<Option<BlockId>> for TransactionStatus {
                 block_height: Some(b.height),
                 block_hash: Some(b.hash),
                 block_time: Some(b.time),
+                first_seen: None,
             },
             None => TransactionStatus {
                 confirmed: false,
                 block_height: None,
                 block_hash: None,
                 block_time: None,
+                first_seen: None,
             },
         }
     }
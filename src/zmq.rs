@@ -0,0 +1,295 @@
+//! A minimal ZeroMQ subscriber for bitcoind's ZMQ notifications.
+//!
+//! Implements just enough of ZMTP 3.0 (NULL security, SUB socket) to receive the
+//! `hashblock`, `rawtx` and `sequence` topics, see bitcoind's doc/zmq.md.
+
+use bitcoin::hashes::Hash;
+use crossbeam_channel::{self as channel, Receiver, Sender};
+use socket2::{SockRef, TcpKeepalive};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+#[cfg(feature = "opcat_layer")]
+use crate::opcat_layer::consensus::encode::deserialize;
+#[cfg(not(feature = "opcat_layer"))]
+use bitcoin::consensus::encode::deserialize;
+
+use crate::chain::{BlockHash, Transaction, Txid};
+use crate::errors::*;
+use crate::util::spawn_thread;
+
+const TOPICS: &[&str] = &["hashblock", "rawtx", "sequence"];
+
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+// Larger than any standard transaction or notification
+const MAX_FRAME_SIZE: u64 = 16_000_000;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// The connection is assumed to be dead after this long without notifications, since a half-open
+// TCP connection would otherwise never be noticed by a subscriber that only reads
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+const KEEPALIVE_TIME: Duration = Duration::from_secs(60);
+
+pub enum ZmqEvent {
+    /// A block was connected or disconnected
+    Block(BlockHash),
+    /// A transaction was announced, either in a block or entering the mempool
+    RawTx(Transaction),
+    /// A transaction was added to the mempool, with the new mempool sequence number
+    TxAdded(Txid, u64),
+    /// A transaction was removed from the mempool for another reason than being mined, with
+    /// the new mempool sequence number
+    TxRemoved(Txid, u64),
+    /// Notifications may have been missed (on (re)connection or a sequence gap)
+    Resync,
+}
+
+/// Subscribes to the daemon's ZMQ notifications from a background thread,
+/// reconnecting when the connection is lost.
+pub struct ZmqSubscriber {
+    events: Receiver<ZmqEvent>,
+}
+
+impl ZmqSubscriber {
+    pub fn start(addr: SocketAddr) -> Self {
+        let (sender, events) = channel::unbounded();
+        spawn_thread("zmq", move || loop {
+            if let Err(e) = subscribe(&addr, &sender) {
+                warn!("ZMQ subscription to {} failed: {}", addr, e);
+            }
+            std::thread::sleep(RECONNECT_DELAY);
+        });
+        ZmqSubscriber { events }
+    }
+
+    pub fn events(&self) -> &Receiver<ZmqEvent> {
+        &self.events
+    }
+}
+
+// Receive notifications until the connection fails
+fn subscribe(addr: &SocketAddr, sender: &Sender<ZmqEvent>) -> Result<()> {
+    let mut stream = connect(addr)?;
+    info!("subscribed to ZMQ notifications from {}", addr);
+    // anything published while we weren't connected is lost
+    sender
+        .send(ZmqEvent::Resync)
+        .chain_err(|| "ZMQ receiver dropped")?;
+
+    let mut sequences: HashMap<Vec<u8>, u32> = HashMap::new();
+    loop {
+        let parts = read_message(&mut stream)?;
+        let (topic, body) = match &parts[..] {
+            [topic, body, sequence] if sequence.len() == 4 => {
+                let sequence =
+                    u32::from_le_bytes([sequence[0], sequence[1], sequence[2], sequence[3]]);
+                let expected = sequences
+                    .insert(topic.clone(), sequence)
+                    .map(|s| s.wrapping_add(1));
+                if expected.is_some_and(|expected| expected != sequence) {
+                    debug!("ZMQ {} notifications gap", String::from_utf8_lossy(topic));
+                    sender
+                        .send(ZmqEvent::Resync)
+                        .chain_err(|| "ZMQ receiver dropped")?;
+                }
+                (topic, body)
+            }
+            _ => bail!("unexpected ZMQ message with {} parts", parts.len()),
+        };
+        if let Some(event) = parse_event(topic, body)? {
+            sender.send(event).chain_err(|| "ZMQ receiver dropped")?;
+        }
+    }
+}
+
+fn parse_event(topic: &[u8], body: &[u8]) -> Result<Option<ZmqEvent>> {
+    Ok(match topic {
+        b"hashblock" => Some(ZmqEvent::Block(BlockHash::from_inner(reversed_hash(body)?))),
+        b"rawtx" => Some(ZmqEvent::RawTx(
+            deserialize(body).chain_err(|| "invalid ZMQ rawtx")?,
+        )),
+        // <32-byte hash><label>[<8-byte mempool sequence>]
+        b"sequence" => {
+            let hash = reversed_hash(body)?;
+            let mempool_sequence = || -> Result<u64> {
+                let bytes = body
+                    .get(33..41)
+                    .chain_err(|| "missing ZMQ mempool sequence")?;
+                let mut sequence = [0u8; 8];
+                sequence.copy_from_slice(bytes);
+                Ok(u64::from_le_bytes(sequence))
+            };
+            match body.get(32) {
+                Some(b'C') | Some(b'D') => Some(ZmqEvent::Block(BlockHash::from_inner(hash))),
+                Some(b'A') => Some(ZmqEvent::TxAdded(
+                    Txid::from_inner(hash),
+                    mempool_sequence()?,
+                )),
+                Some(b'R') => Some(ZmqEvent::TxRemoved(
+                    Txid::from_inner(hash),
+                    mempool_sequence()?,
+                )),
+                _ => bail!("invalid ZMQ sequence notification"),
+            }
+        }
+        _ => None,
+    })
+}
+
+// Hashes are published in reversed (display) byte order
+fn reversed_hash(body: &[u8]) -> Result<[u8; 32]> {
+    if body.len() < 32 {
+        bail!("truncated ZMQ hash");
+    }
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&body[..32]);
+    hash.reverse();
+    Ok(hash)
+}
+
+fn connect(addr: &SocketAddr) -> Result<TcpStream> {
+    let mut stream =
+        TcpStream::connect_timeout(addr, CONNECT_TIMEOUT).chain_err(|| "failed to connect")?;
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .chain_err(|| "failed to set the read timeout")?;
+    SockRef::from(&stream)
+        .set_tcp_keepalive(&TcpKeepalive::new().with_time(KEEPALIVE_TIME))
+        .chain_err(|| "failed to enable TCP keepalive")?;
+
+    // signature, version 3.0, NULL mechanism, as-server = false
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream
+        .write_all(&greeting)
+        .chain_err(|| "failed to send ZMTP greeting")?;
+    let mut peer = [0u8; 64];
+    stream
+        .read_exact(&mut peer)
+        .chain_err(|| "failed to read ZMTP greeting")?;
+    if peer[0] != 0xff || peer[9] != 0x7f || peer[10] < 3 || &peer[12..16] != b"NULL" {
+        bail!("unsupported ZMTP peer");
+    }
+
+    write_frame(&mut stream, FLAG_COMMAND, &ready_command("SUB"))?;
+    let (flags, ready) = read_frame(&mut stream)?;
+    if flags & FLAG_COMMAND == 0 || !ready.starts_with(b"\x05READY") {
+        bail!("unexpected ZMTP handshake");
+    }
+
+    // ZMTP 3.0 subscriptions are messages starting with 0x01
+    for topic in TOPICS {
+        write_frame(&mut stream, 0, &[&[0x01], topic.as_bytes()].concat())?;
+    }
+    Ok(stream)
+}
+
+fn ready_command(socket_type: &str) -> Vec<u8> {
+    let mut command = b"\x05READY".to_vec();
+    command.push(11);
+    command.extend_from_slice(b"Socket-Type");
+    command.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    command.extend_from_slice(socket_type.as_bytes());
+    command
+}
+
+fn write_frame(stream: &mut impl Write, flags: u8, body: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(body.len() + 9);
+    if body.len() > 255 {
+        frame.push(flags | FLAG_LONG);
+        frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+    } else {
+        frame.push(flags);
+        frame.push(body.len() as u8);
+    }
+    frame.extend_from_slice(body);
+    stream
+        .write_all(&frame)
+        .chain_err(|| "failed to send ZMTP frame")
+}
+
+fn read_frame(stream: &mut impl Read) -> Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    stream
+        .read_exact(&mut flags)
+        .chain_err(|| "failed to read ZMTP frame")?;
+    let flags = flags[0];
+    let size = if flags & FLAG_LONG != 0 {
+        let mut size = [0u8; 8];
+        stream
+            .read_exact(&mut size)
+            .chain_err(|| "failed to read ZMTP frame")?;
+        u64::from_be_bytes(size)
+    } else {
+        let mut size = [0u8; 1];
+        stream
+            .read_exact(&mut size)
+            .chain_err(|| "failed to read ZMTP frame")?;
+        size[0] as u64
+    };
+    if size > MAX_FRAME_SIZE {
+        bail!("ZMTP frame too large ({} bytes)", size);
+    }
+    let mut body = vec![0; size as usize];
+    stream
+        .read_exact(&mut body)
+        .chain_err(|| "failed to read ZMTP frame")?;
+    Ok((flags, body))
+}
+
+// Read the parts of the next message, skipping commands
+fn read_message(stream: &mut impl Read) -> Result<Vec<Vec<u8>>> {
+    let mut parts = vec![];
+    loop {
+        let (flags, body) = read_frame(stream)?;
+        if flags & FLAG_COMMAND != 0 {
+            continue;
+        }
+        parts.push(body);
+        if flags & FLAG_MORE == 0 {
+            return Ok(parts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_event, read_message, write_frame, ZmqEvent, FLAG_MORE};
+
+    #[test]
+    fn test_zmq_messages() {
+        let mut hash = [0u8; 32];
+        hash[0] = 0xaa;
+        let mut sequence = hash.to_vec();
+        sequence.push(b'R');
+        sequence.extend_from_slice(&7u64.to_le_bytes());
+
+        let mut data = vec![];
+        write_frame(&mut data, FLAG_MORE, b"sequence").unwrap();
+        write_frame(&mut data, FLAG_MORE, &sequence).unwrap();
+        write_frame(&mut data, 0, &1u32.to_le_bytes()).unwrap();
+        let parts = read_message(&mut &data[..]).unwrap();
+        assert_eq!(parts.len(), 3);
+
+        match parse_event(&parts[0], &parts[1]).unwrap() {
+            // display order, the first published byte is the last internal one
+            Some(ZmqEvent::TxRemoved(txid, sequence)) => {
+                assert_eq!(txid[31], 0xaa);
+                assert_eq!(sequence, 7);
+            }
+            _ => panic!("expected a removed tx"),
+        }
+        assert!(parse_event(b"sequence", &hash).is_err());
+        assert!(parse_event(b"sequence", &sequence[..33]).is_err());
+        assert!(parse_event(b"rawblock", &[]).unwrap().is_none());
+    }
+}
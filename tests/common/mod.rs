@@ -0,0 +1,666 @@
+//! An in-memory fake daemon with a scriptable chain and mempool, and a harness running the
+//! indexer, the mempool and the REST and Electrum servers against it.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::Hash;
+use bitcoin::{PubkeyHash, TxMerkleNode};
+use error_chain::bail;
+use serde_json::{json, Value};
+
+#[cfg(not(feature = "opcat_layer"))]
+use electrs::chain::Witness;
+use electrs::chain::{
+    Block, BlockHash, BlockHeader, Network, OutPoint, Script, Transaction, TxIn, TxOut, Txid,
+};
+use electrs::config::Config;
+use electrs::daemon::{DaemonApi, MempoolAcceptResult, SubmitPackageResult};
+use electrs::electrum::RPC as ElectrumRPC;
+use electrs::errors::*;
+use electrs::metrics::Metrics;
+use electrs::new_index::{
+    compute_script_hash, ChainQuery, FetchFrom, Indexer, Mempool, MempoolChange, Query, Store,
+};
+use electrs::rest;
+use electrs::util::HeaderList;
+
+pub const COINBASE_VALUE: u64 = 50 * 100_000_000;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A P2PKH output script, distinct for every seed
+pub fn script(seed: u8) -> Script {
+    Script::new_p2pkh(&PubkeyHash::hash(&[seed]))
+}
+
+/// The script hash, as used by the REST API
+pub fn scripthash_hex(script: &Script) -> String {
+    hex::encode(compute_script_hash(script))
+}
+
+/// The script hash, as used by the Electrum protocol (reversed)
+pub fn electrum_scripthash(script: &Script) -> String {
+    let mut hash = compute_script_hash(script);
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// A transaction spending the outpoints (with empty scriptSigs) to the outputs
+#[allow(clippy::useless_conversion)] // the values are `Amount`s with the opcat_layer feature
+pub fn tx(inputs: &[OutPoint], outputs: &[(Script, u64)]) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: 0,
+        input: inputs
+            .iter()
+            .map(|outpoint| TxIn {
+                previous_output: *outpoint,
+                script_sig: Script::new(),
+                sequence: 0xffff_fffd,
+                #[cfg(not(feature = "opcat_layer"))]
+                witness: Witness::default(),
+            })
+            .collect(),
+        output: outputs
+            .iter()
+            .map(|(script_pubkey, value)| TxOut {
+                value: (*value).into(),
+                script_pubkey: script_pubkey.clone(),
+                #[cfg(feature = "opcat_layer")]
+                data: vec![],
+            })
+            .collect(),
+    }
+}
+
+fn coinbase(height: usize, script_pubkey: &Script) -> Transaction {
+    let mut coinbase = tx(
+        &[OutPoint::null()],
+        &[(script_pubkey.clone(), COINBASE_VALUE)],
+    );
+    // the height makes the coinbases unique (BIP34)
+    coinbase.input[0].script_sig = bitcoin::blockdata::script::Builder::new()
+        .push_int(height as i64)
+        .into_script();
+    coinbase
+}
+
+fn decode_tx(txhex: &str) -> Result<Transaction> {
+    hex::decode(txhex)
+        .ok()
+        .and_then(|bytes| deserialize(&bytes).ok())
+        .chain_err(|| "TX decode failed")
+}
+
+#[derive(Default)]
+struct Chain {
+    best: Vec<BlockHash>, // from genesis to the tip
+    blocks: HashMap<BlockHash, Block>,
+    mempool: HashMap<Txid, Transaction>,
+    mempool_sequence: u64,
+    time: u32,
+}
+
+impl Chain {
+    fn tip(&self) -> BlockHash {
+        *self.best.last().expect("empty chain")
+    }
+
+    fn find_tx(&self, txid: &Txid) -> Option<Transaction> {
+        self.mempool.get(txid).cloned().or_else(|| {
+            self.best
+                .iter()
+                .flat_map(|hash| &self.blocks[hash].txdata)
+                .find(|tx| tx.txid() == *txid)
+                .cloned()
+        })
+    }
+
+    fn add_to_mempool(&mut self, tx: Transaction) -> Txid {
+        let txid = tx.txid();
+        self.mempool.insert(txid, tx);
+        self.mempool_sequence += 1;
+        txid
+    }
+}
+
+/// A daemon serving a chain built by the test, the clones share the same chain and mempool.
+#[derive(Clone, Default)]
+pub struct FakeDaemon {
+    chain: Arc<Mutex<Chain>>,
+}
+
+impl FakeDaemon {
+    /// A chain with just a genesis block
+    pub fn new() -> Self {
+        let daemon = FakeDaemon::default();
+        daemon.mine_to(&script(0), vec![]);
+        daemon
+    }
+
+    /// Mine a block paying the coinbase to the script, with the given (and no mempool)
+    /// transactions
+    pub fn mine_to(&self, script_pubkey: &Script, txs: Vec<Transaction>) -> BlockHash {
+        let mut chain = self.chain.lock().unwrap();
+        let height = chain.best.len();
+        let mut txdata = vec![coinbase(height, script_pubkey)];
+        txdata.extend(txs);
+        for tx in &txdata {
+            if chain.mempool.remove(&tx.txid()).is_some() {
+                chain.mempool_sequence += 1;
+            }
+        }
+        chain.time += 600;
+        let mut block = Block {
+            header: BlockHeader {
+                version: 0x2000_0000,
+                prev_blockhash: chain.best.last().copied().unwrap_or_default(),
+                merkle_root: TxMerkleNode::default(),
+                time: 1_600_000_000 + chain.time,
+                bits: 0x207f_ffff,
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        let blockhash = block.block_hash();
+        chain.best.push(blockhash);
+        chain.blocks.insert(blockhash, block);
+        blockhash
+    }
+
+    /// Mine a block with all the mempool transactions
+    pub fn mine(&self, script_pubkey: &Script) -> BlockHash {
+        let txs = self
+            .chain
+            .lock()
+            .unwrap()
+            .mempool
+            .values()
+            .cloned()
+            .collect();
+        self.mine_to(script_pubkey, txs)
+    }
+
+    /// Disconnect the last blocks, their transactions go back to the mempool
+    pub fn invalidate(&self, count: usize) {
+        let mut chain = self.chain.lock().unwrap();
+        for _ in 0..count {
+            let blockhash = chain.best.pop().expect("empty chain");
+            let txs = chain.blocks[&blockhash].txdata.clone();
+            for tx in txs.into_iter().skip(1) {
+                chain.add_to_mempool(tx);
+            }
+        }
+    }
+
+    /// Add a transaction to the mempool
+    pub fn send(&self, tx: Transaction) -> Txid {
+        self.chain.lock().unwrap().add_to_mempool(tx)
+    }
+
+    /// Drop a transaction from the mempool (as if it was evicted)
+    pub fn evict(&self, txid: &Txid) {
+        let mut chain = self.chain.lock().unwrap();
+        if chain.mempool.remove(txid).is_some() {
+            chain.mempool_sequence += 1;
+        }
+    }
+
+    pub fn block(&self, height: usize) -> Block {
+        let chain = self.chain.lock().unwrap();
+        chain.blocks[&chain.best[height]].clone()
+    }
+}
+
+impl DaemonApi for FakeDaemon {
+    fn reconnect(&self) -> Result<Box<dyn DaemonApi>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn list_blk_files(&self) -> Result<Vec<PathBuf>> {
+        Ok(vec![])
+    }
+
+    fn magic(&self) -> u32 {
+        network().magic()
+    }
+
+    #[cfg(feature = "opcat_layer")]
+    fn get_mempoolminfee(&self) -> Result<f64> {
+        Ok(0.00001)
+    }
+
+    fn getbestblockhash(&self) -> Result<BlockHash> {
+        Ok(self.chain.lock().unwrap().tip())
+    }
+
+    fn getblock_raw(&self, blockhash: &BlockHash, verbose: u32) -> Result<Value> {
+        let chain = self.chain.lock().unwrap();
+        let block = chain
+            .blocks
+            .get(blockhash)
+            .chain_err(|| format!("block {} not found", blockhash))?;
+        if verbose == 0 {
+            return Ok(json!(hex::encode(serialize(block))));
+        }
+        Ok(json!({
+            "hash": blockhash.to_hex(),
+            "height": chain.best.iter().position(|hash| hash == blockhash),
+            "nTx": block.txdata.len(),
+            "size": block.size(),
+            "weight": block.weight(),
+            "tx": block.txdata.iter().map(|tx| tx.txid().to_hex()).collect::<Vec<_>>(),
+        }))
+    }
+
+    fn getblocks(&self, blockhashes: &[BlockHash]) -> Result<Vec<Block>> {
+        let chain = self.chain.lock().unwrap();
+        blockhashes
+            .iter()
+            .map(|blockhash| {
+                chain
+                    .blocks
+                    .get(blockhash)
+                    .cloned()
+                    .chain_err(|| format!("block {} not found", blockhash))
+            })
+            .collect()
+    }
+
+    fn gettransactions(&self, txhashes: &[&Txid]) -> Result<Vec<Transaction>> {
+        let chain = self.chain.lock().unwrap();
+        Ok(txhashes
+            .iter()
+            .filter_map(|txid| chain.find_tx(txid))
+            .collect())
+    }
+
+    fn gettransaction_bin(&self, txid: &Txid, _blockhash: &BlockHash) -> Result<Vec<u8>> {
+        let chain = self.chain.lock().unwrap();
+        let tx = chain
+            .find_tx(txid)
+            .chain_err(|| format!("transaction {} not found", txid))?;
+        Ok(serialize(&tx))
+    }
+
+    fn getmempooltx(&self, txhash: &Txid) -> Result<Transaction> {
+        let chain = self.chain.lock().unwrap();
+        chain
+            .mempool
+            .get(txhash)
+            .cloned()
+            .chain_err(|| format!("transaction {} not in mempool", txhash))
+    }
+
+    fn getmempooltxids(&self) -> Result<HashSet<Txid>> {
+        Ok(self.chain.lock().unwrap().mempool.keys().copied().collect())
+    }
+
+    fn getmempooltxids_sequence(&self) -> Result<(HashSet<Txid>, u64)> {
+        let chain = self.chain.lock().unwrap();
+        Ok((
+            chain.mempool.keys().copied().collect(),
+            chain.mempool_sequence,
+        ))
+    }
+
+    fn broadcast_raw(&self, txhex: &str) -> Result<Txid> {
+        Ok(self.send(decode_tx(txhex)?))
+    }
+
+    fn test_mempool_accept(
+        &self,
+        txhex: Vec<String>,
+        _maxfeerate: Option<f64>,
+    ) -> Result<Vec<MempoolAcceptResult>> {
+        let mut results = vec![];
+        for txhex in txhex {
+            let tx = decode_tx(&txhex)?;
+            results.push(json!({
+                "txid": tx.txid().to_hex(),
+                "wtxid": tx.txid().to_hex(),
+                "allowed": true,
+                "vsize": tx.size(),
+            }));
+        }
+        serde_json::from_value(json!(results)).chain_err(|| "invalid testmempoolaccept results")
+    }
+
+    fn submit_package(
+        &self,
+        _txhex: Vec<String>,
+        _maxfeerate: Option<f64>,
+        _maxburnamount: Option<f64>,
+    ) -> Result<SubmitPackageResult> {
+        bail!("submitpackage is not supported by the fake daemon")
+    }
+
+    fn estimatesmartfee_batch(&self, conf_targets: &[u16]) -> Result<HashMap<u16, f64>> {
+        Ok(conf_targets.iter().map(|t| (*t, 1.0)).collect())
+    }
+
+    fn get_new_headers(
+        &self,
+        indexed_headers: &HeaderList,
+        bestblockhash: &BlockHash,
+    ) -> Result<Vec<BlockHeader>> {
+        let chain = self.chain.lock().unwrap();
+        let mut new_headers = vec![];
+        let mut blockhash = *bestblockhash;
+        while blockhash != BlockHash::default()
+            && indexed_headers.header_by_blockhash(&blockhash).is_none()
+        {
+            let header = chain.blocks[&blockhash].header;
+            blockhash = header.prev_blockhash;
+            new_headers.push(header);
+        }
+        new_headers.reverse();
+        Ok(new_headers)
+    }
+
+    fn get_relayfee(&self) -> Result<f64> {
+        Ok(1.0)
+    }
+}
+
+pub fn network() -> Network {
+    #[cfg(not(feature = "opcat_layer"))]
+    return Network::Regtest;
+    #[cfg(feature = "opcat_layer")]
+    return Network::OpcatLayerRegtest;
+}
+
+fn free_addr() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let start = Instant::now();
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return stream,
+            Err(e) if start.elapsed() > CONNECT_TIMEOUT => panic!("cannot connect: {}", e),
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+pub fn config(db_path: PathBuf) -> Config {
+    Config {
+        log: stderrlog::new(),
+        network_type: network(),
+        magic: None,
+        db_path,
+        daemon_dir: PathBuf::new(),
+        blocks_dir: PathBuf::new(),
+        daemon_rpc_addr: free_addr(),
+        daemon_rpc_fallback_addrs: vec![],
+        daemon_p2p_addr: None,
+        daemon_rest: false,
+        zmq_addr: None,
+        cookie: None,
+        electrum_rpc_addr: free_addr(),
+        http_addr: free_addr(),
+        http_socket_file: None,
+        rpc_socket_file: None,
+        monitoring_addr: free_addr(),
+        jsonrpc_import: false,
+        light_mode: false,
+        main_loop_delay: 500,
+        address_search: false,
+        index_unspendables: false,
+        compress_txstore: false,
+        import_legacy_db: false,
+        db_memory_mb: 64,
+        cors: None,
+        precache_scripts: None,
+        precache_threads: 1,
+        utxos_limit: 500,
+        electrum_txs_limit: 500,
+        electrum_banner: "".into(),
+        mempool_backlog_stats_ttl: 10,
+        mempool_recent_txs_size: 10,
+        mempool_snapshot: None,
+        mempool_snapshot_interval: 0,
+        db_checkpoint_dir: None,
+        create_db_checkpoint: false,
+        restore_db_checkpoint: None,
+        replica_of: None,
+        rest_default_block_limit: 10,
+        rest_default_chain_txs_per_page: 25,
+        rest_default_max_mempool_txs: 50,
+        rest_default_max_address_summary_txs: 5000,
+        rest_max_mempool_page_size: 1000,
+        rest_max_mempool_txid_page_size: 10000,
+        rest_api_keys: None,
+        #[cfg(feature = "electrum-discovery")]
+        electrum_public_hosts: None,
+        #[cfg(feature = "electrum-discovery")]
+        electrum_announce: false,
+        #[cfg(feature = "electrum-discovery")]
+        tor_proxy: None,
+    }
+}
+
+/// An index of the fake daemon's chain in a temporary directory, for the tests that use the
+/// store directly rather than through the servers
+pub struct TestIndex {
+    pub config: Config,
+    pub metrics: Metrics,
+    pub store: Arc<Store>,
+    pub indexer: Indexer,
+    pub dir: tempfile::TempDir,
+}
+
+impl TestIndex {
+    pub fn new(daemon: &FakeDaemon) -> Self {
+        TestIndex::with_config(daemon, |_| ())
+    }
+
+    /// Like `new`, with the config adjusted before the store is opened
+    pub fn with_config(daemon: &FakeDaemon, configure: impl FnOnce(&mut Config)) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(dir.path().join("db"));
+        configure(&mut config);
+        let metrics = Metrics::new(config.monitoring_addr);
+
+        let store = Arc::new(Store::open(&config.db_path.join("newindex"), &config));
+        let mut indexer = Indexer::open(Arc::clone(&store), FetchFrom::Bitcoind, &config, &metrics);
+        indexer.update(daemon).unwrap();
+
+        TestIndex {
+            config,
+            metrics,
+            store,
+            indexer,
+            dir,
+        }
+    }
+
+    /// The path the store was opened at
+    pub fn path(&self) -> PathBuf {
+        self.config.db_path.join("newindex")
+    }
+
+    pub fn chain(&self, daemon: &FakeDaemon) -> ChainQuery {
+        ChainQuery::new(
+            Arc::clone(&self.store),
+            Arc::new(daemon.clone()),
+            &self.config,
+            &self.metrics,
+        )
+    }
+}
+
+/// Indexes the fake daemon's chain and mempool, and serves them over REST and Electrum
+pub struct TestRunner {
+    daemon: FakeDaemon,
+    config: Arc<Config>,
+    indexer: Indexer,
+    mempool: Arc<RwLock<Mempool>>,
+    rest_server: Option<rest::Handle>,
+    electrum_server: ElectrumRPC,
+    electrum: Option<BufReader<TcpStream>>,
+    _db_dir: tempfile::TempDir,
+}
+
+impl TestRunner {
+    pub fn new(daemon: FakeDaemon) -> Self {
+        let mut runner = TestRunner::new_unsynced(daemon);
+        runner.sync();
+        runner
+    }
+
+    /// Start the servers without indexing anything yet, like at startup before the initial sync
+    pub fn new_unsynced(daemon: FakeDaemon) -> Self {
+        let db_dir = tempfile::tempdir().unwrap();
+        let config = Arc::new(config(db_dir.path().to_path_buf()));
+        let metrics = Metrics::new(config.monitoring_addr);
+
+        let store = Arc::new(Store::open(&config.db_path.join("newindex"), &config));
+        let indexer = Indexer::open(Arc::clone(&store), FetchFrom::Bitcoind, &config, &metrics);
+
+        let chain = Arc::new(ChainQuery::new(
+            store,
+            Arc::new(daemon.clone()),
+            &config,
+            &metrics,
+        ));
+        let mempool = Arc::new(RwLock::new(Mempool::new(
+            Arc::clone(&chain),
+            &metrics,
+            Arc::clone(&config),
+        )));
+        let query = Arc::new(Query::new(
+            chain,
+            Arc::clone(&mempool),
+            Arc::new(daemon.clone()),
+            Arc::clone(&config),
+        ));
+
+        TestRunner {
+            rest_server: Some(rest::start(
+                Arc::clone(&config),
+                Arc::clone(&query),
+                &metrics,
+            )),
+            electrum_server: ElectrumRPC::start(Arc::clone(&config), Arc::clone(&query), &metrics),
+            electrum: None,
+            daemon,
+            config,
+            indexer,
+            mempool,
+            _db_dir: db_dir,
+        }
+    }
+
+    /// Index the new blocks and update the mempool, like the main loop does
+    pub fn sync(&mut self) {
+        self.indexer.update(&self.daemon).unwrap();
+        Mempool::update(&self.mempool, &self.daemon, None).unwrap();
+        self.electrum_server.notify();
+    }
+
+    /// Update the mempool alone, with the daemon's mempool sequence when known from ZMQ
+    pub fn update_mempool(&mut self, daemon_sequence: Option<u64>) {
+        Mempool::update(&self.mempool, &self.daemon, daemon_sequence).unwrap();
+    }
+
+    /// Apply the mempool changes announced by the daemon, like the ZMQ listener does
+    pub fn apply_mempool_changes(&mut self, changes: Vec<MempoolChange>) {
+        Mempool::apply_changes(&self.mempool, &self.daemon, changes, HashMap::new()).unwrap();
+        self.electrum_server.notify();
+    }
+
+    /// GET the REST path, returning the status code and the body
+    pub fn rest_get(&self, path: &str) -> (u16, String) {
+        let (status, body) = self.rest_get_accept(path, "application/json");
+        (status, String::from_utf8(body).unwrap())
+    }
+
+    /// GET the REST path in the format of the `Accept` media type, returning the status code
+    /// and the raw body
+    pub fn rest_get_accept(&self, path: &str, accept: &str) -> (u16, Vec<u8>) {
+        let (status, _, body) = self.rest_get_with_headers(path, &[("Accept", accept)]);
+        (status, body)
+    }
+
+    /// GET the REST path with extra request headers, returning the status code, the response
+    /// headers (with lowercase names) and the body
+    pub fn rest_get_with_headers(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> (u16, HashMap<String, String>, Vec<u8>) {
+        let mut stream = connect(self.config.http_addr);
+        let mut request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n", path);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("Connection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("invalid response");
+        let head = std::str::from_utf8(&response[..split]).unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        let headers = head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_lowercase(), value.to_string()))
+            .collect();
+        (status, headers, response[split + 4..].to_vec())
+    }
+
+    /// GET the REST path, expecting a successful JSON response
+    pub fn rest_get_json(&self, path: &str) -> Value {
+        let (status, body) = self.rest_get(path);
+        assert_eq!(status, 200, "GET {} failed: {}", path, body);
+        serde_json::from_str(&body).unwrap()
+    }
+
+    /// Call an Electrum method, returning its result (or error)
+    pub fn electrum_call(&mut self, method: &str, params: Value) -> Value {
+        let addr = self.config.electrum_rpc_addr;
+        let electrum = self
+            .electrum
+            .get_or_insert_with(|| BufReader::new(connect(addr)));
+        let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        writeln!(electrum.get_mut(), "{}", request).unwrap();
+        loop {
+            let mut line = String::new();
+            electrum.read_line(&mut line).unwrap();
+            let mut reply: Value = serde_json::from_str(&line).unwrap();
+            // skip the subscription notifications
+            if reply.get("id").is_none() {
+                continue;
+            }
+            return match reply.get("error") {
+                Some(error) => json!({ "error": error }),
+                None => reply["result"].take(),
+            };
+        }
+    }
+}
+
+impl Drop for TestRunner {
+    fn drop(&mut self) {
+        if let Some(rest_server) = self.rest_server.take() {
+            rest_server.stop();
+        }
+    }
+}
@@ -0,0 +1,399 @@
+mod common;
+
+use std::sync::Arc;
+
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::hex::ToHex;
+use electrs::chain::{OutPoint, Script};
+use electrs::daemon::DaemonApi;
+use electrs::metrics::Metrics;
+use electrs::new_index::{
+    compute_script_hash, ChainQuery, FetchFrom, Indexer, MempoolChange, Store,
+};
+use serde_json::json;
+
+use common::{electrum_scripthash, script, scripthash_hex, tx, FakeDaemon, TestIndex, TestRunner};
+
+// A chain where the coinbase of block 1 is split in two outputs in block 2
+fn spending_chain() -> (FakeDaemon, OutPoint) {
+    let daemon = FakeDaemon::new();
+    daemon.mine_to(&script(1), vec![]);
+    let funding = daemon.block(1).txdata[0].txid();
+    let spend = tx(
+        &[OutPoint::new(funding, 0)],
+        &[(script(2), 30_0000_0000), (script(3), 19_9999_0000)],
+    );
+    daemon.mine_to(&script(4), vec![spend.clone()]);
+    (daemon, OutPoint::new(spend.txid(), 0))
+}
+
+#[test]
+fn test_index_blocks() {
+    let (daemon, outpoint) = spending_chain();
+    let runner = TestRunner::new(daemon.clone());
+
+    let tip = daemon.block(2).block_hash();
+    assert_eq!(runner.rest_get("/blocks/tip/height").1, "2");
+    assert_eq!(runner.rest_get("/blocks/tip/hash").1, tip.to_hex());
+    assert_eq!(
+        runner.rest_get("/block-height/1").1,
+        daemon.block(1).block_hash().to_hex()
+    );
+
+    let block = runner.rest_get_json(&format!("/block/{}", tip));
+    assert_eq!(block["height"], 2);
+    assert_eq!(block["tx_count"], 2);
+    let txids = runner.rest_get_json(&format!("/block/{}/txids", tip));
+    assert_eq!(txids[1], outpoint.txid.to_hex());
+
+    let tx = runner.rest_get_json(&format!("/tx/{}", outpoint.txid));
+    assert_eq!(tx["status"]["confirmed"], true);
+    assert_eq!(tx["status"]["block_height"], 2);
+    assert_eq!(tx["vout"][0]["value"], 30_0000_0000u64);
+    assert_eq!(tx["fee"], 1_0000);
+
+    // the coinbase of block 1 is spent, its funds moved to scripts 2 and 3
+    let stats = runner.rest_get_json(&format!("/scripthash/{}", scripthash_hex(&script(1))));
+    assert_eq!(stats["chain_stats"]["funded_txo_sum"], 50_0000_0000u64);
+    assert_eq!(stats["chain_stats"]["spent_txo_sum"], 50_0000_0000u64);
+    let utxos = runner.rest_get_json(&format!("/scripthash/{}/utxo", scripthash_hex(&script(2))));
+    assert_eq!(utxos[0]["txid"], outpoint.txid.to_hex());
+    assert_eq!(utxos[0]["value"], 30_0000_0000u64);
+
+    let (status, _) = runner.rest_get(&format!("/tx/{}", "00".repeat(32)));
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn test_address_etag_pages() {
+    let daemon = FakeDaemon::new();
+    for _ in 0..3 {
+        daemon.mine(&script(1));
+    }
+    let runner = TestRunner::new(daemon.clone());
+    let scripthash = scripthash_hex(&script(1));
+
+    let page1 = format!("/scripthash/{}/txs?max_txs=1", scripthash);
+    let (status, headers, _) = runner.rest_get_with_headers(&page1, &[]);
+    assert_eq!(status, 200);
+    let etag1 = headers["etag"].clone();
+
+    let after = daemon.block(3).txdata[0].txid();
+    let page2 = format!("{}&after_txid={}", page1, after);
+    let (status, headers, _) = runner.rest_get_with_headers(&page2, &[]);
+    assert_eq!(status, 200);
+    assert_ne!(headers["etag"], etag1);
+
+    // the tag of a page only revalidates that page
+    let if_none_match = [("If-None-Match", etag1.as_str())];
+    assert_eq!(runner.rest_get_with_headers(&page1, &if_none_match).0, 304);
+    assert_eq!(runner.rest_get_with_headers(&page2, &if_none_match).0, 200);
+}
+
+#[test]
+fn test_binary_formats() {
+    let (daemon, outpoint) = spending_chain();
+    let runner = TestRunner::new(daemon.clone());
+    let path = format!("/tx/{}", outpoint.txid);
+
+    let (status, body) = runner.rest_get_accept(&path, "application/cbor");
+    assert_eq!(status, 200);
+    let tx: serde_json::Value = serde_cbor::from_slice(&body).unwrap();
+    assert_eq!(tx["txid"], outpoint.txid.to_hex());
+    assert_eq!(tx, runner.rest_get_json(&path));
+
+    let (status, body) = runner.rest_get_accept(&path, "application/msgpack");
+    assert_eq!(status, 200);
+    let tx: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(tx["txid"], outpoint.txid.to_hex());
+    assert_eq!(
+        tx["status"]["block_hash"],
+        daemon.block(2).block_hash().to_hex()
+    );
+}
+
+#[test]
+fn test_mempool() {
+    let (daemon, outpoint) = spending_chain();
+    let mut runner = TestRunner::new(daemon.clone());
+
+    let unconfirmed = daemon.send(tx(&[outpoint], &[(script(5), 29_9999_0000)]));
+    runner.sync();
+    let txids = runner.rest_get_json("/mempool/txids");
+    assert_eq!(txids, json!([unconfirmed.to_hex()]));
+    let status = runner.rest_get_json(&format!("/tx/{}/status", unconfirmed));
+    assert_eq!(status["confirmed"], false);
+    let stats = runner.rest_get_json(&format!("/scripthash/{}", scripthash_hex(&script(2))));
+    assert_eq!(stats["mempool_stats"]["spent_txo_sum"], 30_0000_0000u64);
+
+    // evicted from the mempool
+    daemon.evict(&unconfirmed);
+    runner.sync();
+    assert_eq!(runner.rest_get_json("/mempool/txids"), json!([]));
+
+    // and confirmed after all
+    daemon.send(tx(&[outpoint], &[(script(5), 29_9999_0000)]));
+    daemon.mine(&script(4));
+    runner.sync();
+    assert_eq!(runner.rest_get_json("/mempool/txids"), json!([]));
+    let status = runner.rest_get_json(&format!("/tx/{}/status", unconfirmed));
+    assert_eq!(status["confirmed"], true);
+    assert_eq!(status["block_height"], 3);
+}
+
+#[test]
+fn test_replacement_across_updates() {
+    let (daemon, outpoint) = spending_chain();
+    let mut runner = TestRunner::new(daemon.clone());
+    let replaced = daemon.send(tx(&[outpoint], &[(script(5), 29_9999_0000)]));
+    runner.sync();
+
+    // the removal and the replacement are announced in separate batches
+    daemon.evict(&replaced);
+    let replacing = daemon.send(tx(&[outpoint], &[(script(6), 29_9998_0000)]));
+    let (_, sequence) = daemon.getmempooltxids_sequence().unwrap();
+    runner.apply_mempool_changes(vec![MempoolChange::Removed(replaced, sequence - 1)]);
+    runner.apply_mempool_changes(vec![MempoolChange::Added(replacing, sequence)]);
+
+    let rbf = runner.rest_get_json(&format!("/tx/{}/rbf", replaced));
+    assert_eq!(rbf["replaced_by"], replacing.to_hex());
+    let txids = runner.rest_get_json("/mempool/txids");
+    assert_eq!(txids, json!([replacing.to_hex()]));
+}
+
+#[test]
+fn test_mempool_known_sequence() {
+    let (daemon, outpoint) = spending_chain();
+    let mut runner = TestRunner::new(daemon.clone());
+    let (_, synced) = daemon.getmempooltxids_sequence().unwrap();
+    let unconfirmed = daemon.send(tx(&[outpoint], &[(script(5), 29_9999_0000)]));
+
+    // the daemon isn't asked for its txids while its sequence is known to be the synced one
+    runner.update_mempool(Some(synced));
+    assert_eq!(runner.rest_get_json("/mempool/txids"), json!([]));
+
+    let (_, sequence) = daemon.getmempooltxids_sequence().unwrap();
+    runner.update_mempool(Some(sequence));
+    let txids = runner.rest_get_json("/mempool/txids");
+    assert_eq!(txids, json!([unconfirmed.to_hex()]));
+}
+
+#[test]
+fn test_electrum() {
+    let (daemon, outpoint) = spending_chain();
+    let mut runner = TestRunner::new(daemon.clone());
+
+    let version = runner.electrum_call("server.version", json!(["test", "1.4"]));
+    assert_eq!(version[1], "1.4");
+    let header = runner.electrum_call("blockchain.headers.subscribe", json!([]));
+    assert_eq!(header["height"], 2);
+
+    let scripthash = electrum_scripthash(&script(2));
+    let balance = runner.electrum_call("blockchain.scripthash.get_balance", json!([scripthash]));
+    assert_eq!(
+        balance,
+        json!({"confirmed": 30_0000_0000u64, "unconfirmed": 0})
+    );
+    let unspent = runner.electrum_call("blockchain.scripthash.listunspent", json!([scripthash]));
+    assert_eq!(unspent[0]["tx_hash"], outpoint.txid.to_hex());
+    assert_eq!(unspent[0]["height"], 2);
+
+    let unconfirmed_tx = tx(&[outpoint], &[(script(5), 29_9999_0000)]);
+    let unconfirmed = daemon.send(unconfirmed_tx.clone());
+    runner.sync();
+    let balance = runner.electrum_call("blockchain.scripthash.get_balance", json!([scripthash]));
+    assert_eq!(balance["unconfirmed"], -30_0000_0000i64);
+    let history = runner.electrum_call("blockchain.scripthash.get_history", json!([scripthash]));
+    assert_eq!(history[0]["tx_hash"], outpoint.txid.to_hex());
+    assert_eq!(history[1]["tx_hash"], unconfirmed.to_hex());
+    assert_eq!(history[1]["height"], 0);
+
+    let raw = runner.electrum_call("blockchain.transaction.get", json!([unconfirmed.to_hex()]));
+    assert_eq!(raw, hex::encode(serialize(&unconfirmed_tx)));
+    let raw = runner.electrum_call("blockchain.transaction.get", json!([outpoint.txid]));
+    assert_eq!(raw, hex::encode(serialize(&daemon.block(2).txdata[1])));
+}
+
+#[test]
+fn test_reorg() {
+    let (daemon, outpoint) = spending_chain();
+    let mut runner = TestRunner::new(daemon.clone());
+    let stale = daemon.block(2).block_hash();
+
+    // replace block 2 by two empty blocks, its transaction goes back to the mempool
+    daemon.invalidate(1);
+    daemon.mine_to(&script(6), vec![]);
+    let tip = daemon.mine_to(&script(6), vec![]);
+    runner.sync();
+
+    assert_eq!(runner.rest_get("/blocks/tip/height").1, "3");
+    assert_eq!(runner.rest_get("/blocks/tip/hash").1, tip.to_hex());
+    let status = runner.rest_get_json(&format!("/block/{}/status", stale));
+    assert_eq!(status["in_best_chain"], false);
+
+    let status = runner.rest_get_json(&format!("/tx/{}/status", outpoint.txid));
+    assert_eq!(status["confirmed"], false);
+    assert_eq!(
+        runner.rest_get_json("/mempool/txids"),
+        json!([outpoint.txid.to_hex()])
+    );
+    let stats = runner.rest_get_json(&format!("/scripthash/{}", scripthash_hex(&script(2))));
+    assert_eq!(stats["chain_stats"]["funded_txo_sum"], 0);
+    assert_eq!(stats["mempool_stats"]["funded_txo_sum"], 30_0000_0000u64);
+    let stats = runner.rest_get_json(&format!("/scripthash/{}", scripthash_hex(&script(4))));
+    assert_eq!(stats["chain_stats"]["funded_txo_sum"], 0);
+
+    let scripthash = electrum_scripthash(&script(6));
+    let balance = runner.electrum_call("blockchain.scripthash.get_balance", json!([scripthash]));
+    assert_eq!(balance["confirmed"], 100_0000_0000u64);
+
+    // and mined again in the new chain
+    daemon.mine(&script(7));
+    runner.sync();
+    let status = runner.rest_get_json(&format!("/tx/{}/status", outpoint.txid));
+    assert_eq!(status["block_height"], 4);
+}
+
+#[test]
+fn test_sync_status() {
+    let (daemon, _) = spending_chain();
+    let mut runner = TestRunner::new_unsynced(daemon);
+
+    // only the sync status is served until the initial sync completes
+    let (status, body) = runner.rest_get("/blocks/tip/height");
+    assert_eq!(status, 503);
+    assert!(body.contains("/sync/status"), "{}", body);
+    let sync = runner.rest_get_json("/sync/status");
+    assert_eq!(sync["synced"], false);
+
+    runner.sync();
+    let sync = runner.rest_get_json("/sync/status");
+    assert_eq!(sync["synced"], true);
+    assert_eq!(sync["phase"], "done");
+    assert_eq!(runner.rest_get("/blocks/tip/height").1, "2");
+}
+
+#[test]
+fn test_db_checkpoint() {
+    let (daemon, outpoint) = spending_chain();
+    let index = TestIndex::new(&daemon);
+    let checkpoints = index.dir.path().join("checkpoints");
+    let (checkpoint, tip) = index.store.checkpoint(&checkpoints).unwrap();
+    assert_eq!(tip.height, 2);
+    assert_eq!(tip.hash, daemon.block(2).block_hash());
+    assert!(index.store.checkpoint(&checkpoints).is_err());
+    // without a running index update, the non-blocking variant checkpoints right away
+    let other = index.dir.path().join("other");
+    let (_, other_tip) = index.store.try_checkpoint(&other).unwrap().unwrap();
+    assert_eq!(other_tip.hash, tip.hash);
+
+    // a new node restored from the checkpoint resumes indexing from its tip
+    let restored = index.dir.path().join("restored");
+    Store::restore(&checkpoint, &restored, &daemon).unwrap();
+    assert!(Store::restore(&checkpoint, &restored, &daemon).is_err());
+    daemon.mine(&script(5));
+
+    let config = &index.config;
+    let store = Arc::new(Store::open(&restored, config));
+    let metrics = Metrics::new(config.monitoring_addr);
+    let mut indexer = Indexer::open(Arc::clone(&store), FetchFrom::Bitcoind, config, &metrics);
+    indexer.update(&daemon).unwrap();
+    let chain = ChainQuery::new(store, Arc::new(daemon.clone()), config, &metrics);
+    assert_eq!(chain.best_height(), 3);
+    assert!(chain.lookup_txn(&outpoint.txid, None).is_some());
+}
+
+#[test]
+fn test_db_replica() {
+    let (daemon, outpoint) = spending_chain();
+    let mut index = TestIndex::new(&daemon);
+
+    let replica = Arc::new(Store::open_replica(
+        &index.path(),
+        &index.dir.path().join("replica"),
+        &index.config,
+    ));
+    let chain = ChainQuery::new(
+        Arc::clone(&replica),
+        Arc::new(daemon.clone()),
+        &index.config,
+        &index.metrics,
+    );
+    assert_eq!(chain.best_height(), 2);
+    assert!(chain.lookup_txn(&outpoint.txid, None).is_some());
+    assert_eq!(replica.catch_up().unwrap(), None);
+
+    daemon.mine(&script(5));
+    index.indexer.update(&daemon).unwrap();
+    assert_eq!(
+        replica.catch_up().unwrap(),
+        Some(daemon.block(3).block_hash())
+    );
+    assert_eq!(chain.best_height(), 3);
+
+    // reorgs are followed from the fork point
+    daemon.invalidate(2);
+    daemon.mine(&script(6));
+    daemon.mine(&script(6));
+    daemon.mine(&script(6));
+    index.indexer.update(&daemon).unwrap();
+    assert_eq!(
+        replica.catch_up().unwrap(),
+        Some(daemon.block(4).block_hash())
+    );
+    assert_eq!(chain.best_height(), 4);
+    assert_eq!(chain.hash_by_height(2), Some(daemon.block(2).block_hash()));
+    assert!(chain.lookup_txn(&outpoint.txid, None).is_some());
+}
+
+#[test]
+fn test_snapshot_outlives_store() {
+    let (daemon, _) = spending_chain();
+    let index = TestIndex::new(&daemon);
+    let chain = index.chain(&daemon);
+    let scripthash = compute_script_hash(&script(2));
+    let iter = chain.history_iter_scan(b'H', &scripthash, 0);
+
+    // the iterator keeps its snapshot, and the DB it reads, alive on its own
+    let TestIndex { dir: _dir, .. } = index;
+    drop(chain);
+    assert_eq!(iter.count(), 1);
+}
+
+#[test]
+fn test_txstore_compression() {
+    let daemon = FakeDaemon::new();
+    daemon.mine_to(&script(1), vec![]);
+    let funding = daemon.block(1).txdata[0].txid();
+    // scripts longer than 32 bytes are stored in their own S rows
+    let long_script = Script::from(vec![0x51; 40]);
+    let spend = tx(
+        &[OutPoint::new(funding, 0)],
+        &[(long_script.clone(), 49_9999_0000)],
+    );
+    daemon.mine_to(&script(2), vec![spend.clone()]);
+
+    let index = TestIndex::with_config(&daemon, |config| config.compress_txstore = true);
+    let stats = index.store.txstore_db().compression_stats().unwrap();
+    assert!(stats.raw_bytes > 0);
+
+    let chain = index.chain(&daemon);
+    assert_eq!(
+        chain.lookup_raw_txn(&spend.txid(), None),
+        Some(serialize(&spend))
+    );
+    let txo = chain.lookup_txo(&OutPoint::new(spend.txid(), 0)).unwrap();
+    assert_eq!(txo.script_pubkey, long_script);
+
+    // the rows are encoded differently, so it can't be toggled on an existing index
+    let path = index.path();
+    let TestIndex {
+        mut config,
+        dir: _dir,
+        ..
+    } = index;
+    drop(chain);
+    config.compress_txstore = false;
+    let reopen = std::panic::AssertUnwindSafe(|| Store::open(&path, &config));
+    assert!(std::panic::catch_unwind(reopen).is_err());
+}